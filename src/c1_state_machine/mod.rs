@@ -7,9 +7,20 @@ mod p3_atm;
 pub mod p4_accounted_currency;
 mod p5_digital_cash;
 mod p6_open_ended;
+mod p7_signed_extrinsics;
+
+#[cfg(test)]
+pub use p4_accounted_currency::BalancesKey;
+pub use p4_accounted_currency::{
+    AccountedCurrency, AccountingError, AccountingEvent, AccountingTransaction, BalancesB,
+};
+#[cfg(test)]
+pub use p5_digital_cash::DigitalCashSystem;
+pub use p5_digital_cash::State as CashState;
+pub use p7_signed_extrinsics::{SignedAccountedCurrency, SignedExtrinsic};
+
+use crate::{Decode, DecodeError, Encode};
 
-pub use p4_accounted_currency::Balances;
-pub use p4_accounted_currency::{AccountedCurrency, AccountingTransaction, BalancesB};
 /// A state machine - Generic over the transition type
 pub trait StateMachine {
     /// The states that can be occupied by this machine
@@ -18,9 +29,98 @@ pub trait StateMachine {
     /// The transitions that can be made between states
     type Transition;
 
+    /// The reasons why a transition may be rejected. Machines in which every transition
+    /// is valid from every state can use `std::convert::Infallible`.
+    type Error;
+
+    /// The events a transition may emit to describe what it did. Machines that have
+    /// nothing interesting to report can use `()`.
+    type Event;
+
+    /// The accounts this machine keeps track of. Block authors are identified by one so
+    /// that machines which charge fees can pay them. Machines without accounts can use `()`.
+    type AccountId;
+
     /// Calculate the resulting state when this state undergoes the given transition
     fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State;
 
+    /// Calculate the resulting state when this state undergoes the given transition, or
+    /// explain why the transition is not valid from this state.
+    ///
+    /// Blockchain clients use this method rather than `next_state` so that they can reject
+    /// invalid transitions instead of silently including them as no-ops. The provided
+    /// implementation is suitable for machines whose transitions never fail.
+    fn try_next_state(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+    ) -> Result<Self::State, Self::Error> {
+        Ok(Self::next_state(starting_state, t))
+    }
+
+    /// Check the parts of a transition that do not depend on any state, such as its signature.
+    /// Clients run this before accepting a transaction into the pool, and machines that need
+    /// it should also run it as part of `try_next_state`.
+    ///
+    /// The provided implementation accepts every transition.
+    fn validate_transaction(_t: &Self::Transition) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// For machines that number each sender's transitions, the sender of the given transition
+    /// and its nonce. Senders are given as a hash, which is all that is needed to tell them
+    /// apart. Transaction pools use this to keep each sender's transitions in nonce order.
+    ///
+    /// The provided implementation returns None, meaning transitions are not ordered.
+    fn sender_and_nonce(_t: &Self::Transition) -> Option<(crate::H256, u64)> {
+        None
+    }
+
+    /// Whether the transition cannot be applied to the given state yet, but may become
+    /// valid once some other transitions are applied first. For example, its nonce may be
+    /// ahead of the sender's. Block authors keep such transitions in the pool instead of
+    /// dropping them.
+    ///
+    /// The provided implementation never considers a transition premature.
+    fn is_premature(_starting_state: &Self::State, _t: &Self::Transition) -> bool {
+        false
+    }
+
+    /// Whether the transition can never be applied to the given state, nor to any state that
+    /// follows from it. For example, its nonce may already have been used. Clients refuse
+    /// such transitions instead of letting them wait in the pool.
+    ///
+    /// The provided implementation never considers a transition stale.
+    fn is_stale(_starting_state: &Self::State, _t: &Self::Transition) -> bool {
+        false
+    }
+
+    /// How much of a block's capacity the transition uses up. Block authors stop adding
+    /// transitions once a block's weight limit is reached, and machines that charge fees
+    /// may charge in proportion to it.
+    ///
+    /// The provided implementation gives every transition a weight of 1.
+    fn weight(_t: &Self::Transition) -> u64 {
+        1
+    }
+
+    /// Called once all of a block's transitions have been executed, with the account of
+    /// whoever authored the block, if known. Machines that charge fees pay the block's
+    /// fees out to its author here.
+    ///
+    /// The provided implementation does nothing.
+    fn finalize_block(_state: &mut Self::State, _author: Option<&Self::AccountId>) {}
+
+    /// Like `try_next_state`, but also returns the events emitted by the transition,
+    /// in the order they happened.
+    ///
+    /// The provided implementation emits no events.
+    fn try_next_state_with_events(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+    ) -> TransitionOutcome<Self> {
+        Self::try_next_state(starting_state, t).map(|state| (state, Vec::new()))
+    }
+
     /// A human-readable name for this state machine. This may be used in user-facing
     /// programs such as the repl described below. This is not in any way related to
     /// the correctness of the state machine.
@@ -29,13 +129,52 @@ pub trait StateMachine {
     }
 }
 
+/// The outcome of a transition that reports its events: the new state and the events
+/// emitted on the way there, or the reason the transition was rejected.
+pub type TransitionOutcome<SM> = Result<
+    (
+        <SM as StateMachine>::State,
+        Vec<<SM as StateMachine>::Event>,
+    ),
+    <SM as StateMachine>::Error,
+>;
+
 /// A set of play users for experimenting with the multi-user state machines
-#[derive(Hash, Eq, PartialEq, Debug, Clone, Copy)]
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Clone, Copy)]
 pub enum User {
     Alice,
     Bob,
     Charlie,
 }
 
+impl Encode for User {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl Decode for User {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(User::Alice),
+            1 => Ok(User::Bob),
+            2 => Ok(User::Charlie),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+/// The identifier of an account in the multi-user state machines. Our play users are the
+/// default, but anything small enough to copy around and compare will do. Integers are
+/// handy when modelling thousands of accounts, and hashes of public keys are what real
+/// chains use.
+///
+/// Accounts must be ordered so that state made up of many accounts can be committed to in a
+/// canonical order, no matter how it is laid out in memory, and encodable so that such
+/// state can be hashed the same way by every node.
+pub trait Account: Copy + Ord + std::hash::Hash + std::fmt::Debug + Encode {}
+
+impl<T: Copy + Ord + std::hash::Hash + std::fmt::Debug + Encode> Account for T {}
+
 //TODO Some kind of main program that allows users to interact with their state machine in a repl-like way.
 // Might require From<String> implementation for the transition type.
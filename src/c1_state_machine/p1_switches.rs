@@ -3,6 +3,7 @@
 //! well, just the state of the switches.
 
 use super::StateMachine;
+use crate::{Decode, DecodeError, Encode};
 
 /// This state machine models a single light switch.
 /// The internal state, a bool, represents whether the switch is on or not.
@@ -13,6 +14,9 @@ pub struct LightSwitch;
 impl StateMachine for LightSwitch {
     type State = bool;
     type Transition = ();
+    type Error = std::convert::Infallible;
+    type Event = ();
+    type AccountId = ();
 
     fn next_state(starting_state: &bool, t: &()) -> bool {
         !starting_state
@@ -40,6 +44,9 @@ pub enum Toggle {
 impl StateMachine for WeirdSwitchMachine {
     type State = TwoSwitches;
     type Transition = Toggle;
+    type Error = std::convert::Infallible;
+    type Event = ();
+    type AccountId = ();
 
     fn next_state(starting_state: &TwoSwitches, t: &Toggle) -> TwoSwitches {
         match t {
@@ -55,6 +62,25 @@ impl StateMachine for WeirdSwitchMachine {
     }
 }
 
+impl Encode for Toggle {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            Toggle::FirstSwitch => 0,
+            Toggle::SecondSwitch => 1,
+        });
+    }
+}
+
+impl Decode for Toggle {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(Toggle::FirstSwitch),
+            1 => Ok(Toggle::SecondSwitch),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
 #[test]
 fn sm_1_light_switch_toggles_off() {
     assert!(!LightSwitch::next_state(&true, &()));
@@ -3,6 +3,7 @@
 //! eventually they get tattered.
 
 use super::StateMachine;
+use crate::{Decode, DecodeError, Encode};
 
 /// This state machine models the typical life cycle of clothes as they make their way through the laundry
 /// cycle several times before ultimately becoming tattered.
@@ -38,6 +39,9 @@ pub enum ClothesAction {
 impl StateMachine for ClothesMachine {
     type State = ClothesState;
     type Transition = ClothesAction;
+    type Error = std::convert::Infallible;
+    type Event = ();
+    type AccountId = ();
 
     fn next_state(starting_state: &ClothesState, t: &ClothesAction) -> ClothesState {
         if starting_state == &ClothesState::Tattered {
@@ -62,6 +66,27 @@ impl StateMachine for ClothesMachine {
     }
 }
 
+impl Encode for ClothesAction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            ClothesAction::Wear => 0,
+            ClothesAction::Wash => 1,
+            ClothesAction::Dry => 2,
+        });
+    }
+}
+
+impl Decode for ClothesAction {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(ClothesAction::Wear),
+            1 => Ok(ClothesAction::Wash),
+            2 => Ok(ClothesAction::Dry),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
 #[test]
 fn sm_2_wear_clean_clothes() {
     let start = ClothesState::Clean(4);
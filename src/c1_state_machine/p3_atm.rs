@@ -3,6 +3,7 @@
 //! entered the wrong pin.
 
 use super::StateMachine;
+use crate::{Decode, DecodeError, Encode};
 
 /// The keys on the ATM keypad
 #[derive(Hash, Debug, PartialEq, Eq, Clone)]
@@ -18,7 +19,7 @@ pub enum Key {
 pub enum Action {
     /// Swipe your card at the ATM. The attached value is the hash of the pin
     /// that should be keyed in on the keypad next.
-    SwipeCard(u64),
+    SwipeCard(crate::H256),
     /// Press a key on the keypad
     PressKey(Key),
 }
@@ -30,7 +31,7 @@ enum Auth {
     Waiting,
     /// The user has swiped their card, providing the enclosed PIN hash.
     /// Waiting for the user to key in their pin
-    Authenticating(u64),
+    Authenticating(crate::H256),
     /// The user has authenticated. Waiting for them to key in the amount
     /// of cash to withdraw
     Authenticated,
@@ -52,13 +53,49 @@ pub struct Atm {
     keystroke_register: Vec<Key>,
 }
 
+/// The reasons an interaction with the ATM may fail
+#[derive(Clone, Copy, Hash, Debug, PartialEq, Eq)]
+pub enum AtmError {
+    /// A key was pressed before any card was swiped
+    NoCardSwiped,
+    /// The keyed in pin does not match the swiped card
+    IncorrectPin,
+    /// The ATM does not hold enough cash for the requested withdrawal
+    InsufficientCash,
+}
+
 impl StateMachine for Atm {
     // Notice that we are using the same type for the state as we are using for the machine this time.
     type State = Self;
     type Transition = Action;
+    type Error = AtmError;
+    type Event = ();
+    type AccountId = ();
 
     fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
+        Self::transition(starting_state, t).0
+    }
+
+    /// Unlike the currency machines, a failed interaction with the ATM still changes its
+    /// state (the card is returned and the keystrokes are cleared). That resulting state
+    /// is available from `next_state`; here we only report that the interaction failed.
+    fn try_next_state(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+    ) -> Result<Self::State, Self::Error> {
+        match Self::transition(starting_state, t) {
+            (new_state, None) => Ok(new_state),
+            (_, Some(err)) => Err(err),
+        }
+    }
+}
+
+impl Atm {
+    /// Calculate the state the ATM ends up in, along with the reason the interaction
+    /// failed if it did.
+    fn transition(starting_state: &Self, t: &Action) -> (Self, Option<AtmError>) {
         let mut new_state = starting_state.clone();
+        let mut error = None;
         let auth_status = starting_state.auth_status.clone();
 
         match (auth_status, t) {
@@ -67,6 +104,7 @@ impl StateMachine for Atm {
             }
             (Auth::Waiting, Action::PressKey(key)) => {
                 new_state.auth_status = Auth::Waiting;
+                error = Some(AtmError::NoCardSwiped);
             }
             (Auth::Authenticating(_), Action::SwipeCard(pin_hash)) => {
                 new_state.auth_status = Auth::Authenticating(*pin_hash);
@@ -87,6 +125,7 @@ impl StateMachine for Atm {
                             new_state.auth_status = Auth::Authenticated;
                         } else {
                             new_state.auth_status = Auth::Waiting;
+                            error = Some(AtmError::IncorrectPin);
                         }
                         new_state.keystroke_register = Vec::new();
                     }
@@ -121,6 +160,8 @@ impl StateMachine for Atm {
 
                         if amount <= starting_state.cash_inside {
                             new_state.cash_inside = starting_state.cash_inside - amount
+                        } else {
+                            error = Some(AtmError::InsufficientCash);
                         }
                         new_state.keystroke_register = Vec::new();
                         new_state.auth_status = Auth::Waiting;
@@ -128,7 +169,57 @@ impl StateMachine for Atm {
                 }
             }
         }
-        new_state
+        (new_state, error)
+    }
+}
+
+impl Encode for Key {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(match self {
+            Key::One => 0,
+            Key::Two => 1,
+            Key::Three => 2,
+            Key::Four => 3,
+            Key::Enter => 4,
+        });
+    }
+}
+
+impl Decode for Key {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(Key::One),
+            1 => Ok(Key::Two),
+            2 => Ok(Key::Three),
+            3 => Ok(Key::Four),
+            4 => Ok(Key::Enter),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl Encode for Action {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            Action::SwipeCard(pin_hash) => {
+                out.push(0);
+                pin_hash.encode_to(out);
+            }
+            Action::PressKey(key) => {
+                out.push(1);
+                key.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for Action {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(Action::SwipeCard(Decode::decode(input)?)),
+            1 => Ok(Action::PressKey(Decode::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
     }
 }
 
@@ -139,10 +230,10 @@ fn sm_3_simple_swipe_card() {
         auth_status: Auth::Waiting,
         keystroke_register: Vec::new(),
     };
-    let end = Atm::next_state(&start, &Action::SwipeCard(1234));
+    let end = Atm::next_state(&start, &Action::SwipeCard(1234.into()));
     let expected = Atm {
         cash_inside: 10,
-        auth_status: Auth::Authenticating(1234),
+        auth_status: Auth::Authenticating(1234.into()),
         keystroke_register: Vec::new(),
     };
 
@@ -153,13 +244,13 @@ fn sm_3_simple_swipe_card() {
 fn sm_3_swipe_card_again_part_way_through() {
     let start = Atm {
         cash_inside: 10,
-        auth_status: Auth::Authenticating(1234),
+        auth_status: Auth::Authenticating(1234.into()),
         keystroke_register: Vec::new(),
     };
-    let end = Atm::next_state(&start, &Action::SwipeCard(1234));
+    let end = Atm::next_state(&start, &Action::SwipeCard(1234.into()));
     let expected = Atm {
         cash_inside: 10,
-        auth_status: Auth::Authenticating(1234),
+        auth_status: Auth::Authenticating(1234.into()),
         keystroke_register: Vec::new(),
     };
 
@@ -167,13 +258,13 @@ fn sm_3_swipe_card_again_part_way_through() {
 
     let start = Atm {
         cash_inside: 10,
-        auth_status: Auth::Authenticating(1234),
+        auth_status: Auth::Authenticating(1234.into()),
         keystroke_register: vec![Key::One, Key::Three],
     };
-    let end = Atm::next_state(&start, &Action::SwipeCard(1234));
+    let end = Atm::next_state(&start, &Action::SwipeCard(1234.into()));
     let expected = Atm {
         cash_inside: 10,
-        auth_status: Auth::Authenticating(1234),
+        auth_status: Auth::Authenticating(1234.into()),
         keystroke_register: vec![Key::One, Key::Three],
     };
 
@@ -201,13 +292,13 @@ fn sm_3_press_key_before_card_swipe() {
 fn sm_3_enter_single_digit_of_pin() {
     let start = Atm {
         cash_inside: 10,
-        auth_status: Auth::Authenticating(1234),
+        auth_status: Auth::Authenticating(1234.into()),
         keystroke_register: Vec::new(),
     };
     let end = Atm::next_state(&start, &Action::PressKey(Key::One));
     let expected = Atm {
         cash_inside: 10,
-        auth_status: Auth::Authenticating(1234),
+        auth_status: Auth::Authenticating(1234.into()),
         keystroke_register: vec![Key::One],
     };
 
@@ -215,13 +306,13 @@ fn sm_3_enter_single_digit_of_pin() {
 
     let start = Atm {
         cash_inside: 10,
-        auth_status: Auth::Authenticating(1234),
+        auth_status: Auth::Authenticating(1234.into()),
         keystroke_register: vec![Key::One],
     };
     let end1 = Atm::next_state(&start, &Action::PressKey(Key::Two));
     let expected1 = Atm {
         cash_inside: 10,
-        auth_status: Auth::Authenticating(1234),
+        auth_status: Auth::Authenticating(1234.into()),
         keystroke_register: vec![Key::One, Key::Two],
     };
 
@@ -334,3 +425,59 @@ fn sm_3_withdraw_acceptable_amount() {
 
     assert_eq!(end, expected);
 }
+
+#[test]
+fn sm_3_try_to_withdraw_too_much_errors() {
+    let start = Atm {
+        cash_inside: 10,
+        auth_status: Auth::Authenticated,
+        keystroke_register: vec![Key::One, Key::Four],
+    };
+    let end = Atm::try_next_state(&start, &Action::PressKey(Key::Enter));
+
+    assert_eq!(end, Err(AtmError::InsufficientCash));
+}
+
+#[test]
+fn sm_3_try_wrong_pin_errors() {
+    let pin = vec![Key::One, Key::Two, Key::Three, Key::Four];
+    let pin_hash = crate::hash(&pin);
+
+    let start = Atm {
+        cash_inside: 10,
+        auth_status: Auth::Authenticating(pin_hash),
+        keystroke_register: vec![Key::Three],
+    };
+    let end = Atm::try_next_state(&start, &Action::PressKey(Key::Enter));
+
+    assert_eq!(end, Err(AtmError::IncorrectPin));
+}
+
+#[test]
+fn sm_3_try_press_key_before_card_swipe_errors() {
+    let start = Atm {
+        cash_inside: 10,
+        auth_status: Auth::Waiting,
+        keystroke_register: Vec::new(),
+    };
+    let end = Atm::try_next_state(&start, &Action::PressKey(Key::One));
+
+    assert_eq!(end, Err(AtmError::NoCardSwiped));
+}
+
+#[test]
+fn sm_3_try_withdraw_acceptable_amount_succeeds() {
+    let start = Atm {
+        cash_inside: 10,
+        auth_status: Auth::Authenticated,
+        keystroke_register: vec![Key::One],
+    };
+    let end = Atm::try_next_state(&start, &Action::PressKey(Key::Enter));
+    let expected = Atm {
+        cash_inside: 9,
+        auth_status: Auth::Waiting,
+        keystroke_register: Vec::new(),
+    };
+
+    assert_eq!(end, Ok(expected));
+}
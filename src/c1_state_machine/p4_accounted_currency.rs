@@ -7,14 +7,29 @@
 //!
 //! In this module we design a state machine that tracks the currency balances of several users.
 //! Each user is associated with an account balance and users are able to send money to other users.
+//!
+//! The machine is generic over the type that identifies accounts. By default that is our
+//! three play users, but any `Account` works, such as plain integers when thousands of
+//! accounts are needed or hashes of public keys.
+//!
+//! Every transaction carries a nonce: the number of transactions its origin has had executed
+//! before it. A transaction only executes when its nonce is exactly the origin's next one, so
+//! the same transaction can never execute twice.
 
-use super::{StateMachine, User};
-use std::{collections::HashMap, hash::Hash};
+use super::{Account, StateMachine, User};
+use crate::{hash, Decode, DecodeError, Encode, TrieState};
+use std::{collections::HashMap, hash::Hash, marker::PhantomData};
 
 /// This state machine models a multi-user currency system. It tracks the balance of each
 /// user and allows users to send funds to one another.
 #[derive(Clone, Hash)]
-pub struct AccountedCurrency;
+pub struct AccountedCurrency<A = User>(PhantomData<A>);
+
+impl<A> Default for AccountedCurrency<A> {
+    fn default() -> Self {
+        AccountedCurrency(PhantomData)
+    }
+}
 
 /// The main balances mapping.
 ///
@@ -22,108 +37,488 @@ pub struct AccountedCurrency;
 /// There exists an existential deposit of at least 1. That is
 /// to say that an account gets removed from the map entirely
 /// when its balance falls back to 0.
-pub type Balances = HashMap<User, u64>;
+pub type Balances<A = User> = HashMap<A, u64>;
 
-#[derive(Clone, Default)]
-pub struct BalancesB {
-    pub balances: HashMap<User, u64>,
+#[derive(Clone)]
+pub struct BalancesB<A = User> {
+    pub balances: Balances<A>,
+    /// The number of transactions each user has had executed. A user's next transaction
+    /// must carry exactly this nonce, so no transaction can execute twice.
+    ///
+    /// Unlike balances, nonces are never reaped. Otherwise an emptied account that is
+    /// later refunded would start over at nonce 0 and its old transactions could be replayed.
+    pub nonces: HashMap<A, u64>,
+    /// Fees collected from the transactions of the block being executed. They are paid
+    /// out to the block's author once the block is finalized.
+    pub fees: u64,
 }
 
-impl Hash for BalancesB {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        for (user, balance) in &self.balances {
-            user.hash(state);
-            balance.hash(state);
+// Deriving this would needlessly require the account type to have a default.
+impl<A> Default for BalancesB<A> {
+    fn default() -> Self {
+        BalancesB {
+            balances: HashMap::new(),
+            nonces: HashMap::new(),
+            fees: 0,
         }
     }
 }
 
-/// The state transitions that users can make in an accounted currency system
+/// The encoding, and so the hash, of the state must not depend on the order in which a
+/// `HashMap` happens to iterate. Accounts are always encoded sorted by their id.
+impl<A: Account> Encode for BalancesB<A> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        let mut balances: Vec<_> = self.balances.iter().collect();
+        balances.sort();
+        balances.encode_to(out);
+
+        let mut nonces: Vec<_> = self.nonces.iter().collect();
+        nonces.sort();
+        nonces.encode_to(out);
+
+        self.fees.encode_to(out);
+    }
+}
+
+impl<A: Account> BalancesB<A> {
+    /// The nonce the given user's next transaction must carry.
+    pub fn nonce(&self, user: &A) -> u64 {
+        self.nonces.get(user).copied().unwrap_or(0)
+    }
+}
+
+/// Where each piece of the balances state is stored when it is kept in a trie.
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
+pub enum BalancesKey<A = User> {
+    Balance(A),
+    Nonce(A),
+    Fees,
+}
+
+/// Every balance and nonce is its own entry, so the balance of a single account can be
+/// proven against the state root.
+impl<A: Account> TrieState for BalancesB<A> {
+    type Key = BalancesKey<A>;
+    type Value = u64;
+
+    fn to_entries(&self) -> Vec<(BalancesKey<A>, u64)> {
+        let balances = self
+            .balances
+            .iter()
+            .map(|(account, balance)| (BalancesKey::Balance(*account), *balance));
+        let nonces = self
+            .nonces
+            .iter()
+            .map(|(account, nonce)| (BalancesKey::Nonce(*account), *nonce));
+        let fees = (self.fees > 0).then_some((BalancesKey::Fees, self.fees));
+        balances.chain(nonces).chain(fees).collect()
+    }
+
+    fn from_entries(entries: Vec<(BalancesKey<A>, u64)>) -> Self {
+        let mut state = BalancesB::default();
+        for (key, value) in entries {
+            match key {
+                BalancesKey::Balance(account) => {
+                    state.balances.insert(account, value);
+                }
+                BalancesKey::Nonce(account) => {
+                    state.nonces.insert(account, value);
+                }
+                BalancesKey::Fees => state.fees = value,
+            }
+        }
+        state
+    }
+}
+
+/// The state transitions that users can make in an accounted currency system.
+/// Each carries the nonce of the account it acts on behalf of.
 #[derive(Clone, Hash, PartialEq, Debug)]
-pub enum AccountingTransaction {
+pub enum AccountingTransaction<A = User> {
     /// Create some new money for the given minter in the given amount
-    Mint { minter: User, amount: u64 },
+    Mint { minter: A, amount: u64, nonce: u64 },
     /// Destroy some money from the given account in the given amount
     /// If the burn amount exceeds the account balance, burn the entire
     /// amount and remove the account from storage
-    Burn { burner: User, amount: u64 },
+    Burn { burner: A, amount: u64, nonce: u64 },
     /// Send some tokens from one account to another
     Transfer {
-        sender: User,
-        receiver: User,
+        sender: A,
+        receiver: A,
         amount: u64,
+        nonce: u64,
     },
 }
 
+impl<A: Account> AccountingTransaction<A> {
+    /// The user on whose behalf this transaction acts, and whose nonce it carries.
+    pub fn origin(&self) -> A {
+        match self {
+            AccountingTransaction::Mint { minter, .. } => *minter,
+            AccountingTransaction::Burn { burner, .. } => *burner,
+            AccountingTransaction::Transfer { sender, .. } => *sender,
+        }
+    }
+
+    /// The nonce of the origin this transaction must execute at.
+    pub fn nonce(&self) -> u64 {
+        match self {
+            AccountingTransaction::Mint { nonce, .. }
+            | AccountingTransaction::Burn { nonce, .. }
+            | AccountingTransaction::Transfer { nonce, .. } => *nonce,
+        }
+    }
+
+    /// The same transaction, but at another nonce.
+    pub fn with_nonce(mut self, new_nonce: u64) -> Self {
+        match &mut self {
+            AccountingTransaction::Mint { nonce, .. }
+            | AccountingTransaction::Burn { nonce, .. }
+            | AccountingTransaction::Transfer { nonce, .. } => *nonce = new_nonce,
+        }
+        self
+    }
+}
+
+/// The reasons an accounting transaction may be rejected
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
+pub enum AccountingError {
+    /// Minting, burning or transferring nothing is not allowed. A zero transfer
+    /// could otherwise create an account below the existential deposit.
+    ZeroAmount,
+    /// The burner or sender does not have an account
+    AccountNotFound,
+    /// The sender does not have enough funds to cover the transfer
+    InsufficientBalance,
+    /// The resulting balance would not fit in a u64
+    Overflow,
+    /// The nonce has already been used. This is what a replayed transaction looks like.
+    StaleNonce,
+    /// The nonce is ahead of the origin's. The transaction may become valid once the
+    /// origin's earlier transactions have executed.
+    FutureNonce,
+}
+
+/// The events emitted while executing accounting transactions. Together they describe
+/// exactly how the balances changed.
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
+pub enum AccountingEvent<A = User> {
+    /// An account received funds for the first time and was created
+    AccountCreated { account: A },
+    /// An account's balance fell to zero and it was removed
+    AccountReaped { account: A },
+    /// New money was created in the minter's account
+    Minted { minter: A, amount: u64 },
+    /// Money was destroyed from the burner's account. This may be less than the
+    /// requested amount if the account did not hold that much.
+    Burned { burner: A, amount: u64 },
+    /// Money was moved from one account to another
+    Transferred { sender: A, receiver: A, amount: u64 },
+    /// A transaction fee was paid. It is held until the block's author collects it.
+    FeePaid { payer: A, amount: u64 },
+}
+
 /// We model this system as a state machine with three possible transitions
-impl StateMachine for AccountedCurrency {
-    type State = BalancesB;
-    type Transition = AccountingTransaction;
+impl<A: Account> StateMachine for AccountedCurrency<A> {
+    type State = BalancesB<A>;
+    type Transition = AccountingTransaction<A>;
+    type Error = AccountingError;
+    type Event = AccountingEvent<A>;
+    type AccountId = A;
 
-    fn next_state(starting_state: &BalancesB, t: &AccountingTransaction) -> BalancesB {
+    /// Invalid transactions leave the balances untouched.
+    fn next_state(starting_state: &BalancesB<A>, t: &AccountingTransaction<A>) -> BalancesB<A> {
+        Self::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+    }
+
+    fn try_next_state(
+        starting_state: &BalancesB<A>,
+        t: &AccountingTransaction<A>,
+    ) -> Result<BalancesB<A>, AccountingError> {
+        Self::try_next_state_with_events(starting_state, t).map(|(state, _)| state)
+    }
+
+    fn sender_and_nonce(t: &AccountingTransaction<A>) -> Option<(crate::H256, u64)> {
+        Some((hash(&t.origin()), t.nonce()))
+    }
+
+    fn is_premature(starting_state: &BalancesB<A>, t: &AccountingTransaction<A>) -> bool {
+        t.nonce() > starting_state.nonce(&t.origin())
+    }
+
+    fn is_stale(starting_state: &BalancesB<A>, t: &AccountingTransaction<A>) -> bool {
+        t.nonce() < starting_state.nonce(&t.origin())
+    }
+
+    fn try_next_state_with_events(
+        starting_state: &BalancesB<A>,
+        t: &AccountingTransaction<A>,
+    ) -> Result<(BalancesB<A>, Vec<AccountingEvent<A>>), AccountingError> {
+        let origin = t.origin();
+        let expected_nonce = starting_state.nonce(&origin);
+        if t.nonce() < expected_nonce {
+            return Err(AccountingError::StaleNonce);
+        }
+        if t.nonce() > expected_nonce {
+            return Err(AccountingError::FutureNonce);
+        }
+
+        let mut new_state = starting_state.clone();
+        let mut events = Vec::new();
         match t {
-            AccountingTransaction::Mint { minter, amount } => {
-                let mut new_state = starting_state.clone();
-                if *amount <= 0 {
-                    return new_state;
+            AccountingTransaction::Mint { minter, amount, .. } => {
+                if *amount == 0 {
+                    return Err(AccountingError::ZeroAmount);
                 }
 
-                new_state
+                new_state.deposit(*minter, *amount, &mut events)?;
+                events.push(AccountingEvent::Minted {
+                    minter: *minter,
+                    amount: *amount,
+                });
+            }
+            AccountingTransaction::Burn { burner, amount, .. } => {
+                if *amount == 0 {
+                    return Err(AccountingError::ZeroAmount);
+                }
+
+                let balance = *new_state
                     .balances
-                    .entry(minter.clone())
-                    .and_modify(|e| *e += amount)
-                    .or_insert(*amount);
-                new_state
+                    .get(burner)
+                    .ok_or(AccountingError::AccountNotFound)?;
+                let burned = balance.min(*amount);
+                new_state.withdraw(*burner, burned, &mut events)?;
+                events.push(AccountingEvent::Burned {
+                    burner: *burner,
+                    amount: burned,
+                });
             }
-            AccountingTransaction::Burn { burner, amount } => {
-                let mut new_state = starting_state.clone();
-                let mut new_balances: HashMap<User, u64> = new_state.balances.clone();
-
-                if let Some(&value) = new_balances.get(&burner) {
-                    if value <= *amount {
-                        new_balances.remove(&burner);
-                        return new_state;
-                    }
+            AccountingTransaction::Transfer {
+                sender,
+                receiver,
+                amount,
+                ..
+            } => {
+                if *amount == 0 {
+                    return Err(AccountingError::ZeroAmount);
                 }
 
-                new_balances
-                    .entry(burner.clone())
-                    .and_modify(|e| *e -= amount);
-                new_state.balances = new_balances;
-                new_state
+                new_state.withdraw(*sender, *amount, &mut events)?;
+                new_state.deposit(*receiver, *amount, &mut events)?;
+                events.push(AccountingEvent::Transferred {
+                    sender: *sender,
+                    receiver: *receiver,
+                    amount: *amount,
+                });
+            }
+        }
+        new_state.nonces.insert(origin, expected_nonce + 1);
+        Ok((new_state, events))
+    }
+}
+
+impl<A: Account> BalancesB<A> {
+    /// Add funds to an account, creating it if necessary.
+    pub(super) fn deposit(
+        &mut self,
+        account: A,
+        amount: u64,
+        events: &mut Vec<AccountingEvent<A>>,
+    ) -> Result<(), AccountingError> {
+        match self.balances.get_mut(&account) {
+            Some(balance) => {
+                *balance = balance
+                    .checked_add(amount)
+                    .ok_or(AccountingError::Overflow)?;
+            }
+            None => {
+                self.balances.insert(account, amount);
+                events.push(AccountingEvent::AccountCreated { account });
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove funds from an account, reaping it if nothing is left.
+    pub(super) fn withdraw(
+        &mut self,
+        account: A,
+        amount: u64,
+        events: &mut Vec<AccountingEvent<A>>,
+    ) -> Result<(), AccountingError> {
+        let balance = *self
+            .balances
+            .get(&account)
+            .ok_or(AccountingError::AccountNotFound)?;
+        if balance < amount {
+            return Err(AccountingError::InsufficientBalance);
+        } else if balance == amount {
+            self.balances.remove(&account);
+            events.push(AccountingEvent::AccountReaped { account });
+        } else {
+            self.balances.insert(account, balance - amount);
+        }
+        Ok(())
+    }
+}
+
+impl<A: Encode> Encode for AccountingTransaction<A> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            AccountingTransaction::Mint {
+                minter,
+                amount,
+                nonce,
+            } => {
+                out.push(0);
+                minter.encode_to(out);
+                amount.encode_to(out);
+                nonce.encode_to(out);
+            }
+            AccountingTransaction::Burn {
+                burner,
+                amount,
+                nonce,
+            } => {
+                out.push(1);
+                burner.encode_to(out);
+                amount.encode_to(out);
+                nonce.encode_to(out);
             }
             AccountingTransaction::Transfer {
                 sender,
                 receiver,
                 amount,
+                nonce,
             } => {
-                let mut new_state = starting_state.clone();
-                let mut new_balances: HashMap<User, u64> = new_state.balances.clone();
+                out.push(2);
+                sender.encode_to(out);
+                receiver.encode_to(out);
+                amount.encode_to(out);
+                nonce.encode_to(out);
+            }
+        }
+    }
+}
 
-                if !new_balances.contains_key(sender) {
-                    return new_state;
-                }
+impl<A: Decode> Decode for AccountingTransaction<A> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(AccountingTransaction::Mint {
+                minter: A::decode(input)?,
+                amount: u64::decode(input)?,
+                nonce: u64::decode(input)?,
+            }),
+            1 => Ok(AccountingTransaction::Burn {
+                burner: A::decode(input)?,
+                amount: u64::decode(input)?,
+                nonce: u64::decode(input)?,
+            }),
+            2 => Ok(AccountingTransaction::Transfer {
+                sender: A::decode(input)?,
+                receiver: A::decode(input)?,
+                amount: u64::decode(input)?,
+                nonce: u64::decode(input)?,
+            }),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
 
-                if let Some(&value) = new_balances.get(&sender) {
-                    if value < *amount {
-                        return new_state;
-                    } else if value == *amount {
-                        new_balances.remove(&sender);
-                    } else {
-                        new_balances
-                            .entry(sender.clone())
-                            .and_modify(|e| *e -= amount);
-                    }
-                }
+impl<A: Encode> Encode for AccountingEvent<A> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            AccountingEvent::AccountCreated { account } => {
+                out.push(0);
+                account.encode_to(out);
+            }
+            AccountingEvent::AccountReaped { account } => {
+                out.push(1);
+                account.encode_to(out);
+            }
+            AccountingEvent::Minted { minter, amount } => {
+                out.push(2);
+                minter.encode_to(out);
+                amount.encode_to(out);
+            }
+            AccountingEvent::Burned { burner, amount } => {
+                out.push(3);
+                burner.encode_to(out);
+                amount.encode_to(out);
+            }
+            AccountingEvent::Transferred {
+                sender,
+                receiver,
+                amount,
+            } => {
+                out.push(4);
+                sender.encode_to(out);
+                receiver.encode_to(out);
+                amount.encode_to(out);
+            }
+            AccountingEvent::FeePaid { payer, amount } => {
+                out.push(5);
+                payer.encode_to(out);
+                amount.encode_to(out);
+            }
+        }
+    }
+}
 
-                new_balances
-                    .entry(receiver.clone())
-                    .and_modify(|e| *e += amount)
-                    .or_insert(*amount);
+impl<A: Decode> Decode for AccountingEvent<A> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(AccountingEvent::AccountCreated {
+                account: A::decode(input)?,
+            }),
+            1 => Ok(AccountingEvent::AccountReaped {
+                account: A::decode(input)?,
+            }),
+            2 => Ok(AccountingEvent::Minted {
+                minter: A::decode(input)?,
+                amount: u64::decode(input)?,
+            }),
+            3 => Ok(AccountingEvent::Burned {
+                burner: A::decode(input)?,
+                amount: u64::decode(input)?,
+            }),
+            4 => Ok(AccountingEvent::Transferred {
+                sender: A::decode(input)?,
+                receiver: A::decode(input)?,
+                amount: u64::decode(input)?,
+            }),
+            5 => Ok(AccountingEvent::FeePaid {
+                payer: A::decode(input)?,
+                amount: u64::decode(input)?,
+            }),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
 
-                new_state.balances = new_balances;
-                new_state
+impl<A: Encode> Encode for BalancesKey<A> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            BalancesKey::Balance(account) => {
+                out.push(0);
+                account.encode_to(out);
             }
+            BalancesKey::Nonce(account) => {
+                out.push(1);
+                account.encode_to(out);
+            }
+            BalancesKey::Fees => out.push(2),
+        }
+    }
+}
+
+impl<A: Decode> Decode for BalancesKey<A> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(BalancesKey::Balance(A::decode(input)?)),
+            1 => Ok(BalancesKey::Nonce(A::decode(input)?)),
+            2 => Ok(BalancesKey::Fees),
+            tag => Err(DecodeError::InvalidTag(tag)),
         }
     }
 }
@@ -136,6 +531,7 @@ fn sm_4_mint_creates_account() {
         &AccountingTransaction::Mint {
             minter: User::Alice,
             amount: 100,
+            nonce: 0,
         },
     );
     let expected = HashMap::from([(User::Alice, 100)]);
@@ -147,12 +543,14 @@ fn sm_4_mint_creates_account() {
 fn sm_4_mint_creates_second_account() {
     let start = BalancesB {
         balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
     };
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Mint {
             minter: User::Bob,
             amount: 50,
+            nonce: 0,
         },
     );
     let expected = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
@@ -164,12 +562,14 @@ fn sm_4_mint_creates_second_account() {
 fn sm_4_mint_increases_balance() {
     let start = BalancesB {
         balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
     };
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Mint {
             minter: User::Alice,
             amount: 50,
+            nonce: 0,
         },
     );
     let expected = HashMap::from([(User::Alice, 150)]);
@@ -186,6 +586,7 @@ fn sm_4_empty_mint() {
         &AccountingTransaction::Mint {
             minter: User::Alice,
             amount: 0,
+            nonce: 0,
         },
     );
     let expected = HashMap::new();
@@ -197,12 +598,14 @@ fn sm_4_empty_mint() {
 fn sm_4_simple_burn() {
     let start = BalancesB {
         balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
     };
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
             burner: User::Alice,
             amount: 50,
+            nonce: 0,
         },
     );
     let expected = HashMap::from([(User::Alice, 50)]);
@@ -214,12 +617,14 @@ fn sm_4_simple_burn() {
 fn sm_4_burn_no_existential_deposit_left() {
     let start = BalancesB {
         balances: HashMap::from([(User::Alice, 100), (User::Bob, 50)]),
+        ..Default::default()
     };
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
             burner: User::Bob,
             amount: 50,
+            nonce: 0,
         },
     );
     let expected = HashMap::from([(User::Alice, 100)]);
@@ -231,12 +636,14 @@ fn sm_4_burn_no_existential_deposit_left() {
 fn sm_4_non_registered_burner() {
     let start = BalancesB {
         balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
     };
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
             burner: User::Bob,
             amount: 50,
+            nonce: 0,
         },
     );
     let expected = HashMap::from([(User::Alice, 100)]);
@@ -248,12 +655,14 @@ fn sm_4_non_registered_burner() {
 fn sm_4_burn_more_than_balance() {
     let start = BalancesB {
         balances: HashMap::from([(User::Alice, 100), (User::Bob, 50)]),
+        ..Default::default()
     };
     let end2 = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
             burner: User::Bob,
             amount: 100,
+            nonce: 0,
         },
     );
     let expected2 = HashMap::from([(User::Alice, 100)]);
@@ -265,12 +674,14 @@ fn sm_4_burn_more_than_balance() {
 fn sm_4_empty_burn() {
     let start = BalancesB {
         balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
     };
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
             burner: User::Alice,
             amount: 0,
+            nonce: 0,
         },
     );
     let expected = HashMap::from([(User::Alice, 100)]);
@@ -282,12 +693,14 @@ fn sm_4_empty_burn() {
 fn sm_4_burner_does_not_exist() {
     let start = BalancesB {
         balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
     };
     let end = AccountedCurrency::next_state(
         &start,
         &AccountingTransaction::Burn {
             burner: User::Bob,
             amount: 50,
+            nonce: 0,
         },
     );
     let expected = HashMap::from([(User::Alice, 100)]);
@@ -299,6 +712,7 @@ fn sm_4_burner_does_not_exist() {
 fn sm_4_simple_transfer() {
     let start = BalancesB {
         balances: HashMap::from([(User::Alice, 100), (User::Bob, 50)]),
+        ..Default::default()
     };
     let end = AccountedCurrency::next_state(
         &start,
@@ -306,6 +720,7 @@ fn sm_4_simple_transfer() {
             sender: User::Alice,
             receiver: User::Bob,
             amount: 10,
+            nonce: 0,
         },
     );
     let expected = HashMap::from([(User::Alice, 90), (User::Bob, 60)]);
@@ -314,6 +729,7 @@ fn sm_4_simple_transfer() {
 
     let start = BalancesB {
         balances: HashMap::from([(User::Alice, 90), (User::Bob, 60)]),
+        ..Default::default()
     };
 
     let end1 = AccountedCurrency::next_state(
@@ -322,6 +738,7 @@ fn sm_4_simple_transfer() {
             sender: User::Bob,
             receiver: User::Alice,
             amount: 50,
+            nonce: 0,
         },
     );
     let expected1 = HashMap::from([(User::Alice, 140), (User::Bob, 10)]);
@@ -333,6 +750,7 @@ fn sm_4_simple_transfer() {
 fn sm_4_send_to_same_user() {
     let start = BalancesB {
         balances: HashMap::from([(User::Alice, 100), (User::Bob, 50)]),
+        ..Default::default()
     };
     let end = AccountedCurrency::next_state(
         &start,
@@ -340,6 +758,7 @@ fn sm_4_send_to_same_user() {
             sender: User::Bob,
             receiver: User::Bob,
             amount: 10,
+            nonce: 0,
         },
     );
     let expected = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
@@ -351,6 +770,7 @@ fn sm_4_send_to_same_user() {
 fn sm_4_insufficient_balance_transfer() {
     let start = BalancesB {
         balances: HashMap::from([(User::Alice, 100), (User::Bob, 50)]),
+        ..Default::default()
     };
     let end = AccountedCurrency::next_state(
         &start,
@@ -358,6 +778,7 @@ fn sm_4_insufficient_balance_transfer() {
             sender: User::Bob,
             receiver: User::Alice,
             amount: 60,
+            nonce: 0,
         },
     );
     let expected = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
@@ -369,6 +790,7 @@ fn sm_4_insufficient_balance_transfer() {
 fn sm_4_sender_not_registered() {
     let start = BalancesB {
         balances: HashMap::from([(User::Alice, 100), (User::Bob, 50)]),
+        ..Default::default()
     };
     let end = AccountedCurrency::next_state(
         &start,
@@ -376,6 +798,7 @@ fn sm_4_sender_not_registered() {
             sender: User::Charlie,
             receiver: User::Alice,
             amount: 50,
+            nonce: 0,
         },
     );
     let expected = HashMap::from([(User::Alice, 100), (User::Bob, 50)]);
@@ -387,6 +810,7 @@ fn sm_4_sender_not_registered() {
 fn sm_4_receiver_not_registered() {
     let start = BalancesB {
         balances: HashMap::from([(User::Alice, 100), (User::Bob, 50)]),
+        ..Default::default()
     };
     let end = AccountedCurrency::next_state(
         &start,
@@ -394,6 +818,7 @@ fn sm_4_receiver_not_registered() {
             sender: User::Alice,
             receiver: User::Charlie,
             amount: 50,
+            nonce: 0,
         },
     );
     let expected = HashMap::from([(User::Alice, 50), (User::Bob, 50), (User::Charlie, 50)]);
@@ -405,6 +830,7 @@ fn sm_4_receiver_not_registered() {
 fn sm_4_sender_to_empty_balance() {
     let start = BalancesB {
         balances: HashMap::from([(User::Alice, 100), (User::Bob, 50)]),
+        ..Default::default()
     };
     let end = AccountedCurrency::next_state(
         &start,
@@ -412,6 +838,7 @@ fn sm_4_sender_to_empty_balance() {
             sender: User::Bob,
             receiver: User::Alice,
             amount: 50,
+            nonce: 0,
         },
     );
     let expected = HashMap::from([(User::Alice, 150)]);
@@ -423,6 +850,7 @@ fn sm_4_sender_to_empty_balance() {
 fn sm_4_transfer() {
     let start = BalancesB {
         balances: HashMap::from([(User::Alice, 100), (User::Bob, 50)]),
+        ..Default::default()
     };
     let end = AccountedCurrency::next_state(
         &start,
@@ -430,9 +858,440 @@ fn sm_4_transfer() {
             sender: User::Bob,
             receiver: User::Charlie,
             amount: 50,
+            nonce: 0,
         },
     );
     let expected = HashMap::from([(User::Alice, 100), (User::Charlie, 50)]);
 
     assert_eq!(end.balances, expected);
 }
+
+#[test]
+fn sm_4_try_transfer_insufficient_balance_errors() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100), (User::Bob, 50)]),
+        ..Default::default()
+    };
+    let end = AccountedCurrency::try_next_state(
+        &start,
+        &AccountingTransaction::Transfer {
+            sender: User::Bob,
+            receiver: User::Alice,
+            amount: 60,
+            nonce: 0,
+        },
+    );
+
+    assert_eq!(end.err(), Some(AccountingError::InsufficientBalance));
+}
+
+#[test]
+fn sm_4_try_transfer_from_unknown_sender_errors() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
+    };
+    let end = AccountedCurrency::try_next_state(
+        &start,
+        &AccountingTransaction::Transfer {
+            sender: User::Charlie,
+            receiver: User::Alice,
+            amount: 10,
+            nonce: 0,
+        },
+    );
+
+    assert_eq!(end.err(), Some(AccountingError::AccountNotFound));
+}
+
+#[test]
+fn sm_4_try_zero_amounts_error() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
+    };
+    let transactions = [
+        AccountingTransaction::Mint {
+            minter: User::Alice,
+            amount: 0,
+            nonce: 0,
+        },
+        AccountingTransaction::Burn {
+            burner: User::Alice,
+            amount: 0,
+            nonce: 0,
+        },
+        AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Bob,
+            amount: 0,
+            nonce: 0,
+        },
+    ];
+
+    for t in &transactions {
+        assert_eq!(
+            AccountedCurrency::try_next_state(&start, t).err(),
+            Some(AccountingError::ZeroAmount)
+        );
+    }
+}
+
+#[test]
+fn sm_4_try_mint_overflow_errors() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, u64::MAX)]),
+        ..Default::default()
+    };
+    let end = AccountedCurrency::try_next_state(
+        &start,
+        &AccountingTransaction::Mint {
+            minter: User::Alice,
+            amount: 1,
+            nonce: 0,
+        },
+    );
+
+    assert_eq!(end.err(), Some(AccountingError::Overflow));
+}
+
+#[test]
+fn sm_4_try_valid_transfer_succeeds() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
+    };
+    let end = AccountedCurrency::try_next_state(
+        &start,
+        &AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Bob,
+            amount: 100,
+            nonce: 0,
+        },
+    )
+    .unwrap();
+
+    assert_eq!(end.balances, HashMap::from([(User::Bob, 100)]));
+}
+
+#[test]
+fn sm_4_transaction_increments_nonce() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
+    };
+    let end = AccountedCurrency::try_next_state(
+        &start,
+        &AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Bob,
+            amount: 10,
+            nonce: 0,
+        },
+    )
+    .unwrap();
+
+    assert_eq!(end.nonce(&User::Alice), 1);
+    assert_eq!(end.nonce(&User::Bob), 0);
+}
+
+#[test]
+fn sm_4_replayed_transfer_errors() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
+    };
+    let transfer = AccountingTransaction::Transfer {
+        sender: User::Alice,
+        receiver: User::Bob,
+        amount: 10,
+        nonce: 0,
+    };
+    let once = AccountedCurrency::try_next_state(&start, &transfer).unwrap();
+
+    assert_eq!(
+        AccountedCurrency::try_next_state(&once, &transfer).err(),
+        Some(AccountingError::StaleNonce)
+    );
+    assert!(AccountedCurrency::is_stale(&once, &transfer));
+    assert_eq!(
+        AccountedCurrency::next_state(&once, &transfer).balances,
+        once.balances
+    );
+}
+
+#[test]
+fn sm_4_future_nonce_errors_but_is_premature() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
+    };
+    let burn = AccountingTransaction::Burn {
+        burner: User::Alice,
+        amount: 10,
+        nonce: 1,
+    };
+
+    assert_eq!(
+        AccountedCurrency::try_next_state(&start, &burn).err(),
+        Some(AccountingError::FutureNonce)
+    );
+    assert!(AccountedCurrency::is_premature(&start, &burn));
+    assert!(!AccountedCurrency::is_stale(&start, &burn));
+}
+
+#[test]
+fn sm_4_nonce_survives_reaping() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 10)]),
+        ..Default::default()
+    };
+    let end = AccountedCurrency::try_next_state(
+        &start,
+        &AccountingTransaction::Burn {
+            burner: User::Alice,
+            amount: 10,
+            nonce: 0,
+        },
+    )
+    .unwrap();
+
+    // Otherwise a refunded account could replay its old transactions.
+    assert!(end.balances.is_empty());
+    assert_eq!(end.nonce(&User::Alice), 1);
+}
+
+#[test]
+fn sm_4_transfer_emits_events() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100), (User::Bob, 50)]),
+        ..Default::default()
+    };
+    let (end, events) = AccountedCurrency::try_next_state_with_events(
+        &start,
+        &AccountingTransaction::Transfer {
+            sender: User::Bob,
+            receiver: User::Charlie,
+            amount: 50,
+            nonce: 0,
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        end.balances,
+        HashMap::from([(User::Alice, 100), (User::Charlie, 50)])
+    );
+    assert_eq!(
+        events,
+        vec![
+            AccountingEvent::AccountReaped { account: User::Bob },
+            AccountingEvent::AccountCreated {
+                account: User::Charlie
+            },
+            AccountingEvent::Transferred {
+                sender: User::Bob,
+                receiver: User::Charlie,
+                amount: 50,
+            },
+        ]
+    );
+}
+
+#[test]
+fn sm_4_burn_more_than_balance_emits_actual_amount() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
+    };
+    let (_, events) = AccountedCurrency::try_next_state_with_events(
+        &start,
+        &AccountingTransaction::Burn {
+            burner: User::Alice,
+            amount: 150,
+            nonce: 0,
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        events,
+        vec![
+            AccountingEvent::AccountReaped {
+                account: User::Alice
+            },
+            AccountingEvent::Burned {
+                burner: User::Alice,
+                amount: 100,
+            },
+        ]
+    );
+}
+
+#[test]
+fn sm_4_mint_into_existing_account_emits_only_minted() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
+    };
+    let (_, events) = AccountedCurrency::try_next_state_with_events(
+        &start,
+        &AccountingTransaction::Mint {
+            minter: User::Alice,
+            amount: 5,
+            nonce: 0,
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        events,
+        vec![AccountingEvent::Minted {
+            minter: User::Alice,
+            amount: 5,
+        }]
+    );
+}
+
+#[test]
+fn sm_4_thousands_of_numeric_accounts() {
+    let mut state = BalancesB::<u32>::default();
+    for account in 0..5_000u32 {
+        state = AccountedCurrency::try_next_state(
+            &state,
+            &AccountingTransaction::Mint {
+                minter: account,
+                amount: 10,
+                nonce: 0,
+            },
+        )
+        .unwrap();
+    }
+    for account in 0..5_000u32 {
+        state = AccountedCurrency::try_next_state(
+            &state,
+            &AccountingTransaction::Transfer {
+                sender: account,
+                receiver: (account + 1) % 5_000,
+                amount: 10,
+                nonce: 1,
+            },
+        )
+        .unwrap();
+    }
+
+    assert_eq!(state.balances.len(), 5_000);
+    assert!(state.balances.values().all(|balance| *balance == 10));
+}
+
+#[test]
+fn sm_4_state_hash_is_independent_of_insertion_order() {
+    let mut forwards = BalancesB::<u32>::default();
+    for account in 0..100u32 {
+        forwards.balances.insert(account, account as u64 + 1);
+        forwards.nonces.insert(account, account as u64);
+    }
+    let mut backwards = BalancesB::<u32>::default();
+    for account in (0..100u32).rev() {
+        backwards.balances.insert(account, account as u64 + 1);
+        backwards.nonces.insert(account, account as u64);
+    }
+
+    assert_eq!(crate::hash(&forwards), crate::hash(&backwards));
+}
+
+#[test]
+fn sm_4_state_hash_through_different_transactions() {
+    let mint = |minter, amount| AccountingTransaction::Mint {
+        minter,
+        amount,
+        nonce: 0,
+    };
+
+    let mut first = BalancesB::default();
+    for t in [
+        mint(User::Alice, 10),
+        mint(User::Bob, 20),
+        mint(User::Charlie, 30),
+    ] {
+        first = AccountedCurrency::try_next_state(&first, &t).unwrap();
+    }
+    let mut second = BalancesB::default();
+    for t in [
+        mint(User::Charlie, 30),
+        mint(User::Alice, 10),
+        mint(User::Bob, 20),
+    ] {
+        second = AccountedCurrency::try_next_state(&second, &t).unwrap();
+    }
+
+    assert_eq!(crate::hash(&first), crate::hash(&second));
+}
+
+#[test]
+fn sm_4_state_round_trips_through_trie_entries() {
+    let mut state = BalancesB::default();
+    state.balances.insert(User::Alice, 10);
+    state.balances.insert(User::Bob, 20);
+    state.nonces.insert(User::Alice, 3);
+    state.fees = 2;
+
+    let entries = state.to_entries();
+    assert_eq!(entries.len(), 4);
+    assert!(entries.contains(&(BalancesKey::Balance(User::Bob), 20)));
+
+    let rebuilt = BalancesB::from_entries(entries);
+    assert_eq!(rebuilt.balances, state.balances);
+    assert_eq!(rebuilt.nonces, state.nonces);
+    assert_eq!(rebuilt.fees, 2);
+    assert_eq!(crate::state_root(&rebuilt), crate::state_root(&state));
+}
+
+#[test]
+fn sm_4_transactions_and_events_round_trip() {
+    let transactions = vec![
+        AccountingTransaction::Mint {
+            minter: User::Alice,
+            amount: 100,
+            nonce: 0,
+        },
+        AccountingTransaction::Burn {
+            burner: User::Bob,
+            amount: 5,
+            nonce: 0,
+        },
+        AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Charlie,
+            amount: 7,
+            nonce: 0,
+        },
+    ];
+    let bytes = transactions.encode();
+    assert_eq!(
+        Vec::<AccountingTransaction>::decode_all(&bytes),
+        Ok(transactions)
+    );
+
+    let events = vec![
+        AccountingEvent::AccountCreated {
+            account: User::Alice,
+        },
+        AccountingEvent::FeePaid {
+            payer: User::Bob,
+            amount: 1,
+        },
+    ];
+    assert_eq!(
+        Vec::<AccountingEvent>::decode_all(&events.encode()),
+        Ok(events)
+    );
+
+    let keys = vec![
+        BalancesKey::Balance(User::Bob),
+        BalancesKey::Nonce(User::Charlie),
+        BalancesKey::Fees,
+    ];
+    assert_eq!(Vec::<BalancesKey>::decode_all(&keys.encode()), Ok(keys));
+}
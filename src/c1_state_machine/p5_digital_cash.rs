@@ -2,20 +2,24 @@
 //! accounts, but rather, is modelled after a paper cash system. The system tracks individual
 //! cash bills. Each bill has an amount and an owner, and can be spent in its entirety.
 //! When a state transition spends bills, new bills are created in lesser or equal amount.
+//!
+//! Like the accounted currency, bills are generic over who owns them and default to our play users.
 
-use super::{StateMachine, User};
-use std::{cmp::Reverse, collections::HashSet};
+use super::{Account, StateMachine, User};
+use crate::{Decode, DecodeError, Encode, TrieState};
+use std::{collections::HashSet, hash::Hash, marker::PhantomData};
 
 /// This state machine models a multi-user currency system. It tracks a set of bills in
 /// circulation, and updates that set when money is transferred.
-pub struct DigitalCashSystem;
+#[derive(Clone, Hash)]
+pub struct DigitalCashSystem<A = User>(PhantomData<A>);
 
 /// A single bill in the digital cash system. Each bill has an owner who is allowed to spent
 /// it and an amount that it is worth. It also has serial number to ensure that each bill
 /// is unique.
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
-pub struct Bill {
-    owner: User,
+pub struct Bill<A = User> {
+    owner: A,
     amount: u64,
     serial: u64,
 }
@@ -23,17 +27,17 @@ pub struct Bill {
 /// The State of a digital cash system. Primarily just the set of currently circulating bills.,
 /// but also a counter for the next serial number.
 #[derive(Clone, Debug, Eq, PartialEq)]
-pub struct State {
+pub struct State<A: Account = User> {
     /// The set of currently circulating bills
-    bills: HashSet<Bill>,
+    bills: HashSet<Bill<A>>,
     /// The next serial number to use when a bill is created.
     next_serial: u64,
 }
 
-impl State {
+impl<A: Account> State<A> {
     pub fn new() -> Self {
         State {
-            bills: HashSet::<Bill>::new(),
+            bills: HashSet::<Bill<A>>::new(),
             next_serial: 0,
         }
     }
@@ -50,14 +54,36 @@ impl State {
         self.next_serial += 1
     }
 
-    fn add_bill(&mut self, elem: Bill) {
+    /// Put a new bill into circulation. It takes the next serial number.
+    pub fn issue_bill(&mut self, owner: A, amount: u64) {
+        let serial = self.next_serial;
+        self.add_bill(Bill {
+            owner,
+            amount,
+            serial,
+        });
+    }
+
+    fn add_bill(&mut self, elem: Bill<A>) {
         self.bills.insert(elem);
         self.increment_serial()
     }
 }
 
-impl FromIterator<Bill> for State {
-    fn from_iter<I: IntoIterator<Item = Bill>>(iter: I) -> Self {
+/// Bills are encoded in order of their serial numbers, which are unique. This way the
+/// encoding, and so the hash, does not depend on the order in which the `HashSet` happens
+/// to iterate.
+impl<A: Account> Encode for State<A> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        let mut bills: Vec<_> = self.bills.iter().collect();
+        bills.sort_by_key(|bill| bill.serial);
+        bills.encode_to(out);
+        self.next_serial.encode_to(out);
+    }
+}
+
+impl<A: Account> FromIterator<Bill<A>> for State<A> {
+    fn from_iter<I: IntoIterator<Item = Bill<A>>>(iter: I) -> Self {
         let mut state = State::new();
 
         for i in iter {
@@ -67,80 +93,320 @@ impl FromIterator<Bill> for State {
     }
 }
 
-impl<const N: usize> From<[Bill; N]> for State {
-    fn from(value: [Bill; N]) -> Self {
+impl<A: Account, const N: usize> From<[Bill<A>; N]> for State<A> {
+    fn from(value: [Bill<A>; N]) -> Self {
         State::from_iter(value)
     }
 }
 
+/// Where each piece of the cash state is stored when it is kept in a trie.
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
+pub enum CashKey {
+    /// The bill with the given serial number.
+    Bill(u64),
+    NextSerial,
+}
+
+/// What is stored under a `CashKey`.
+#[derive(Clone, Hash, PartialEq, Eq, Debug)]
+pub enum CashValue<A = User> {
+    Bill(Bill<A>),
+    NextSerial(u64),
+}
+
+/// Every bill is its own entry, so a single bill can be proven against the state root.
+impl<A: Account> TrieState for State<A> {
+    type Key = CashKey;
+    type Value = CashValue<A>;
+
+    fn to_entries(&self) -> Vec<(CashKey, CashValue<A>)> {
+        let bills = self
+            .bills
+            .iter()
+            .map(|bill| (CashKey::Bill(bill.serial), CashValue::Bill(bill.clone())));
+        let next_serial = (CashKey::NextSerial, CashValue::NextSerial(self.next_serial));
+        bills.chain(Some(next_serial)).collect()
+    }
+
+    fn from_entries(entries: Vec<(CashKey, CashValue<A>)>) -> Self {
+        let mut state = State::new();
+        for (_, value) in entries {
+            match value {
+                CashValue::Bill(bill) => {
+                    state.bills.insert(bill);
+                }
+                CashValue::NextSerial(serial) => state.next_serial = serial,
+            }
+        }
+        state
+    }
+}
+
 /// The state transitions that users can make in a digital cash system
-pub enum CashTransaction {
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum CashTransaction<A = User> {
     /// Mint a single new bill owned by the minter
-    Mint { minter: User, amount: u64 },
+    Mint { minter: A, amount: u64 },
     /// Send some money from some users to other users. The money does not all need
     /// to come from the same user, and it does not all need to go to the same user.
     /// The total amount received must be less than or equal to the amount spent.
     /// The discrepancy between the amount sent and received is destroyed. Therefore,
     /// no dedicated burn transaction is required.
     Transfer {
-        spends: Vec<Bill>,
-        receives: Vec<Bill>,
+        spends: Vec<Bill<A>>,
+        receives: Vec<Bill<A>>,
     },
 }
 
+/// The reasons a cash transaction may be rejected
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
+pub enum CashError {
+    /// The bills received are worth more than the bills spent
+    OutputExceedsInput,
+    /// A bill being spent or created is worth nothing
+    ZeroValueBill,
+    /// A bill being spent is not in circulation. It was either never created
+    /// or it has already been spent (possibly earlier in the same transaction).
+    BillNotFound,
+    /// A bill being created does not carry the next serial number
+    IncorrectSerial,
+}
+
+/// The events emitted while executing cash transactions
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum CashEvent<A = User> {
+    /// A new bill was minted
+    Minted { bill: Bill<A> },
+    /// A bill was spent and taken out of circulation
+    Spent { bill: Bill<A> },
+    /// A bill was created as the output of a transfer
+    Received { bill: Bill<A> },
+    /// The part of a transfer's input that was not sent to anybody was destroyed
+    Burned { amount: u64 },
+}
+
 /// We model this system as a state machine with two possible transitions
-impl StateMachine for DigitalCashSystem {
-    type State = State;
-    type Transition = CashTransaction;
+impl<A: Account> StateMachine for DigitalCashSystem<A> {
+    type State = State<A>;
+    type Transition = CashTransaction<A>;
+    type Error = CashError;
+    type Event = CashEvent<A>;
+    type AccountId = A;
 
+    /// Invalid transactions leave the set of bills untouched.
     fn next_state(starting_state: &Self::State, t: &Self::Transition) -> Self::State {
+        Self::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+    }
+
+    fn try_next_state(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+    ) -> Result<Self::State, Self::Error> {
+        Self::try_next_state_with_events(starting_state, t).map(|(state, _)| state)
+    }
+
+    fn try_next_state_with_events(
+        starting_state: &Self::State,
+        t: &Self::Transition,
+    ) -> Result<(Self::State, Vec<Self::Event>), Self::Error> {
+        let mut events = Vec::new();
         match t {
             CashTransaction::Mint { minter, amount } => {
                 let mut new_state = starting_state.clone();
 
-                new_state.add_bill(Bill {
-                    owner: minter.clone(),
+                let bill = Bill {
+                    owner: *minter,
                     amount: *amount,
                     serial: new_state.next_serial(),
-                });
+                };
+                new_state.add_bill(bill.clone());
+                events.push(CashEvent::Minted { bill });
 
-                new_state
+                Ok((new_state, events))
             }
             CashTransaction::Transfer { spends, receives } => {
-                // if amount received > amount spent -> do nothing
                 let total_spent: u128 = spends.iter().map(|bill| bill.amount as u128).sum();
                 let total_received: u128 = receives.iter().map(|bill| bill.amount as u128).sum();
 
-                let mut new_state = starting_state.clone();
                 if total_received > total_spent {
-                    return new_state;
+                    return Err(CashError::OutputExceedsInput);
                 }
 
                 // destroy the spends based on their serials
+                let mut new_state = starting_state.clone();
                 for bill in spends {
                     if bill.amount < 1 {
-                        return starting_state.clone();
+                        return Err(CashError::ZeroValueBill);
                     }
 
                     if !new_state.bills.remove(bill) {
-                        return starting_state.clone();
+                        return Err(CashError::BillNotFound);
                     }
+                    events.push(CashEvent::Spent { bill: bill.clone() });
                 }
 
                 // create new receives
                 for bill in receives {
                     if bill.serial != new_state.next_serial() {
-                        return starting_state.clone();
+                        return Err(CashError::IncorrectSerial);
                     }
                     if bill.amount < 1 {
-                        return starting_state.clone();
+                        return Err(CashError::ZeroValueBill);
                     }
 
                     new_state.add_bill(bill.clone());
+                    events.push(CashEvent::Received { bill: bill.clone() });
+                }
+
+                // the total received is at most the total spent, so this fits in a u64
+                let burned = (total_spent - total_received) as u64;
+                if burned > 0 {
+                    events.push(CashEvent::Burned { amount: burned });
                 }
 
-                new_state
+                Ok((new_state, events))
+            }
+        }
+    }
+}
+
+impl<A: Encode> Encode for Bill<A> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.owner.encode_to(out);
+        self.amount.encode_to(out);
+        self.serial.encode_to(out);
+    }
+}
+
+impl<A: Decode> Decode for Bill<A> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Bill {
+            owner: Decode::decode(input)?,
+            amount: Decode::decode(input)?,
+            serial: Decode::decode(input)?,
+        })
+    }
+}
+
+impl Encode for CashKey {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            CashKey::Bill(serial) => {
+                out.push(0);
+                serial.encode_to(out);
+            }
+            CashKey::NextSerial => out.push(1),
+        }
+    }
+}
+
+impl Decode for CashKey {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(CashKey::Bill(u64::decode(input)?)),
+            1 => Ok(CashKey::NextSerial),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl<A: Encode> Encode for CashValue<A> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            CashValue::Bill(bill) => {
+                out.push(0);
+                bill.encode_to(out);
+            }
+            CashValue::NextSerial(serial) => {
+                out.push(1);
+                serial.encode_to(out);
+            }
+        }
+    }
+}
+
+impl<A: Decode> Decode for CashValue<A> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(CashValue::Bill(Bill::decode(input)?)),
+            1 => Ok(CashValue::NextSerial(u64::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl<A: Encode> Encode for CashTransaction<A> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            CashTransaction::Mint { minter, amount } => {
+                out.push(0);
+                minter.encode_to(out);
+                amount.encode_to(out);
+            }
+            CashTransaction::Transfer { spends, receives } => {
+                out.push(1);
+                spends.encode_to(out);
+                receives.encode_to(out);
+            }
+        }
+    }
+}
+
+impl<A: Decode> Decode for CashTransaction<A> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(CashTransaction::Mint {
+                minter: Decode::decode(input)?,
+                amount: Decode::decode(input)?,
+            }),
+            1 => Ok(CashTransaction::Transfer {
+                spends: Decode::decode(input)?,
+                receives: Decode::decode(input)?,
+            }),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl<A: Encode> Encode for CashEvent<A> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            CashEvent::Minted { bill } => {
+                out.push(0);
+                bill.encode_to(out);
+            }
+            CashEvent::Spent { bill } => {
+                out.push(1);
+                bill.encode_to(out);
+            }
+            CashEvent::Received { bill } => {
+                out.push(2);
+                bill.encode_to(out);
             }
+            CashEvent::Burned { amount } => {
+                out.push(3);
+                amount.encode_to(out);
+            }
+        }
+    }
+}
+
+impl<A: Decode> Decode for CashEvent<A> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(CashEvent::Minted {
+                bill: Bill::decode(input)?,
+            }),
+            1 => Ok(CashEvent::Spent {
+                bill: Bill::decode(input)?,
+            }),
+            2 => Ok(CashEvent::Received {
+                bill: Bill::decode(input)?,
+            }),
+            3 => Ok(CashEvent::Burned {
+                amount: u64::decode(input)?,
+            }),
+            tag => Err(DecodeError::InvalidTag(tag)),
         }
     }
 }
@@ -726,3 +992,226 @@ fn sm_5_spending_from_charlie_to_all() {
     expected.set_serial(62);
     assert_eq!(end, expected);
 }
+
+#[test]
+fn sm_5_try_double_spend_errors() {
+    let bill = Bill {
+        owner: User::Alice,
+        amount: 40,
+        serial: 0,
+    };
+    let start = State::from([bill.clone()]);
+    let end = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![bill.clone(), bill],
+            receives: vec![Bill {
+                owner: User::Bob,
+                amount: 80,
+                serial: 1,
+            }],
+        },
+    );
+
+    assert_eq!(end, Err(CashError::BillNotFound));
+}
+
+#[test]
+fn sm_5_try_overspend_errors() {
+    let bill = Bill {
+        owner: User::Alice,
+        amount: 20,
+        serial: 0,
+    };
+    let start = State::from([bill.clone()]);
+    let end = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![bill],
+            receives: vec![Bill {
+                owner: User::Bob,
+                amount: 21,
+                serial: 1,
+            }],
+        },
+    );
+
+    assert_eq!(end, Err(CashError::OutputExceedsInput));
+}
+
+#[test]
+fn sm_5_try_incorrect_serial_errors() {
+    let bill = Bill {
+        owner: User::Alice,
+        amount: 20,
+        serial: 0,
+    };
+    let start = State::from([bill.clone()]);
+    let end = DigitalCashSystem::try_next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![bill],
+            receives: vec![Bill {
+                owner: User::Bob,
+                amount: 20,
+                serial: 7,
+            }],
+        },
+    );
+
+    assert_eq!(end, Err(CashError::IncorrectSerial));
+}
+
+#[test]
+fn sm_5_transfer_emits_events() {
+    let spent = Bill {
+        owner: User::Alice,
+        amount: 20,
+        serial: 0,
+    };
+    let received = Bill {
+        owner: User::Bob,
+        amount: 15,
+        serial: 1,
+    };
+    let start = State::from([spent.clone()]);
+    let (_, events) = DigitalCashSystem::try_next_state_with_events(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![spent.clone()],
+            receives: vec![received.clone()],
+        },
+    )
+    .unwrap();
+
+    assert_eq!(
+        events,
+        vec![
+            CashEvent::Spent { bill: spent },
+            CashEvent::Received { bill: received },
+            CashEvent::Burned { amount: 5 },
+        ]
+    );
+}
+
+#[test]
+fn sm_5_bills_with_numeric_owners() {
+    let start = State::from([Bill {
+        owner: 7u64,
+        amount: 20,
+        serial: 0,
+    }]);
+    let end = DigitalCashSystem::next_state(
+        &start,
+        &CashTransaction::Transfer {
+            spends: vec![Bill {
+                owner: 7,
+                amount: 20,
+                serial: 0,
+            }],
+            receives: vec![Bill {
+                owner: 1_000_000,
+                amount: 20,
+                serial: 1,
+            }],
+        },
+    );
+    let expected = State::from([Bill {
+        owner: 1_000_000,
+        amount: 20,
+        serial: 1,
+    }]);
+
+    assert_eq!(end.bills, expected.bills);
+}
+
+#[test]
+fn sm_5_state_hash_is_independent_of_insertion_order() {
+    let bills: Vec<_> = (0..100u64)
+        .map(|serial| Bill {
+            owner: serial % 7,
+            amount: serial + 1,
+            serial,
+        })
+        .collect();
+
+    let forwards: State<u64> = bills.iter().cloned().collect();
+    let backwards: State<u64> = bills.iter().rev().cloned().collect();
+
+    assert_eq!(forwards, backwards);
+    assert_eq!(crate::hash(&forwards), crate::hash(&backwards));
+}
+
+#[test]
+fn sm_5_cash_transactions_round_trip() {
+    let bill = |owner, amount, serial| Bill {
+        owner,
+        amount,
+        serial,
+    };
+    let transactions = [
+        CashTransaction::Mint {
+            minter: User::Alice,
+            amount: 20,
+        },
+        CashTransaction::Transfer {
+            spends: vec![bill(User::Alice, 20, 0)],
+            receives: vec![bill(User::Bob, 15, 1), bill(User::Charlie, 5, 2)],
+        },
+    ];
+
+    for transaction in transactions {
+        let bytes = transaction.encode();
+        let decoded = CashTransaction::<User>::decode_all(&bytes).unwrap();
+        assert_eq!(decoded.encode(), bytes);
+    }
+}
+
+#[test]
+fn sm_5_cash_events_and_entries_round_trip() {
+    let bill = Bill {
+        owner: User::Bob,
+        amount: 15,
+        serial: 1,
+    };
+    let events = vec![
+        CashEvent::Minted { bill: bill.clone() },
+        CashEvent::Spent { bill: bill.clone() },
+        CashEvent::Received { bill: bill.clone() },
+        CashEvent::Burned { amount: 5 },
+    ];
+    assert_eq!(Vec::<CashEvent>::decode_all(&events.encode()), Ok(events));
+
+    let entries = vec![
+        (CashKey::Bill(1), CashValue::Bill(bill)),
+        (CashKey::NextSerial, CashValue::NextSerial(2)),
+    ];
+    assert_eq!(
+        Vec::<(CashKey, CashValue)>::decode_all(&entries.encode()),
+        Ok(entries)
+    );
+}
+
+#[test]
+fn sm_5_state_round_trips_through_trie_entries() {
+    let mut state = State::<User>::new();
+    state.issue_bill(User::Alice, 20);
+    state.issue_bill(User::Bob, 5);
+    state.issue_bill(User::Alice, 7);
+    assert_eq!(state.next_serial(), 3);
+
+    let entries = state.to_entries();
+    assert_eq!(entries.len(), 4);
+    assert!(entries.contains(&(
+        CashKey::Bill(1),
+        CashValue::Bill(Bill {
+            owner: User::Bob,
+            amount: 5,
+            serial: 1
+        })
+    )));
+
+    let rebuilt = State::from_entries(entries);
+    assert_eq!(rebuilt, state);
+    assert_eq!(crate::state_root(&rebuilt), crate::state_root(&state));
+}
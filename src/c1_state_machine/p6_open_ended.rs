@@ -21,6 +21,7 @@
 //!   * Reputation System
 
 use super::StateMachine;
+use crate::{Decode, DecodeError, Encode};
 
 pub struct State {}
 
@@ -29,8 +30,24 @@ pub enum Transition {}
 impl StateMachine for State {
     type State = State;
     type Transition = Transition;
+    type Error = std::convert::Infallible;
+    type Event = ();
+    type AccountId = ();
 
     fn next_state(_starting: &Self::State, _t: &Self::Transition) -> Self::State {
         todo!()
     }
 }
+
+/// There are no transitions yet, so there is nothing to encode, and no bytes decode to one.
+impl Encode for Transition {
+    fn encode_to(&self, _: &mut Vec<u8>) {
+        match *self {}
+    }
+}
+
+impl Decode for Transition {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Err(DecodeError::InvalidTag(u8::decode(input)?))
+    }
+}
@@ -0,0 +1,513 @@
+//! The accounted currency from p4 trusts whatever it is told. A `Transfer` names its own sender,
+//! so anybody could submit one on behalf of anybody else. Real chains solve this by having the
+//! account owner sign every extrinsic they submit, and checking that signature before any state
+//! is touched.
+//!
+//! In this module we wrap the accounted currency in a signed extrinsic. We do not implement real
+//! public key cryptography here. Instead each play user has a secret seed and a signature is just a
+//! hash of that seed together with the payload. Because our users are a fixed, known set, anybody
+//! can recompute the expected signature and compare. This is enough to model the flow of signing and
+//! verifying, but of course it offers no security at all. Other account types can take part by
+//! implementing `Signer`.
+//!
+//! A valid signature alone does not stop the very same extrinsic from being included again and
+//! again. That is what the nonce in every accounting transaction is for, and the accounted
+//! currency already checks it. Since the nonce is part of the call, the signature covers it too.
+//!
+//! Finally, block space is not free. Each extrinsic has a weight and its signer pays a fee in
+//! proportion to it. The fees of a block are collected as it executes and paid to the block's
+//! author at the end.
+
+use super::{
+    Account, AccountedCurrency, AccountingError, AccountingEvent, AccountingTransaction, BalancesB,
+    StateMachine, User,
+};
+use crate::hash;
+use crate::{Decode, DecodeError, Encode};
+#[cfg(test)]
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+/// A signature over an extrinsic's payload.
+pub type Signature = crate::H256;
+
+/// How much a signer pays for each unit of weight their extrinsic uses.
+pub const FEE_PER_WEIGHT: u64 = 1;
+
+/// Accounts that can sign payloads, and whose signatures anybody can check.
+pub trait Signer: Account {
+    /// Sign the encoding of some payload as this account.
+    fn sign<T: Encode>(&self, payload: &T) -> Signature;
+
+    /// Check that the given signature was produced by this account over the given payload.
+    fn verify<T: Encode>(&self, payload: &T, signature: Signature) -> bool {
+        self.sign(payload) == signature
+    }
+}
+
+impl User {
+    /// The secret each play user signs with. In a real system this would be a private key that
+    /// never leaves the user's wallet.
+    fn secret_seed(&self) -> u64 {
+        match self {
+            User::Alice => 0xA11CE,
+            User::Bob => 0xB0B,
+            User::Charlie => 0xC4A7,
+        }
+    }
+}
+
+impl Signer for User {
+    fn sign<T: Encode>(&self, payload: &T) -> Signature {
+        hash(&(self.secret_seed(), payload))
+    }
+}
+
+/// An extrinsic along with who signed it and the signature itself.
+#[derive(Clone, Hash, PartialEq, Debug)]
+pub struct SignedExtrinsic<Call, A = User> {
+    pub signer: A,
+    pub call: Call,
+    pub signature: Signature,
+}
+
+impl<Call: Encode, A: Signer> SignedExtrinsic<Call, A> {
+    /// Create an extrinsic correctly signed by the given signer.
+    pub fn new(signer: A, call: Call) -> Self {
+        let signature = signer.sign(&call);
+        SignedExtrinsic {
+            signer,
+            call,
+            signature,
+        }
+    }
+
+    /// Whether the signature matches the signer and the call.
+    pub fn has_valid_signature(&self) -> bool {
+        self.signer.verify(&self.call, self.signature)
+    }
+}
+
+/// The reasons a signed extrinsic may be rejected
+#[derive(Clone, Copy, Hash, PartialEq, Eq, Debug)]
+pub enum ExtrinsicError {
+    /// The signature was not produced by the signer over this call
+    BadSignature,
+    /// The signature is fine, but the signer is not the account the call acts on behalf of
+    SignerMismatch,
+    /// After executing the call, the signer cannot afford the fee
+    CannotPayFee,
+    /// The call itself was rejected by the accounted currency, for example because its
+    /// nonce was already used
+    Call(AccountingError),
+}
+
+impl From<AccountingError> for ExtrinsicError {
+    fn from(e: AccountingError) -> Self {
+        ExtrinsicError::Call(e)
+    }
+}
+
+/// The accounted currency, but every transaction has to be signed by the account it acts on
+/// behalf of.
+#[derive(Clone, Hash)]
+pub struct SignedAccountedCurrency<A = User>(PhantomData<A>);
+
+impl<A> Default for SignedAccountedCurrency<A> {
+    fn default() -> Self {
+        SignedAccountedCurrency(PhantomData)
+    }
+}
+
+impl<A: Signer> StateMachine for SignedAccountedCurrency<A> {
+    type State = BalancesB<A>;
+    type Transition = SignedExtrinsic<AccountingTransaction<A>, A>;
+    type Error = ExtrinsicError;
+    type Event = AccountingEvent<A>;
+    type AccountId = A;
+
+    /// Invalid or badly signed transactions leave the balances untouched.
+    fn next_state(starting_state: &BalancesB<A>, t: &Self::Transition) -> BalancesB<A> {
+        Self::try_next_state(starting_state, t).unwrap_or_else(|_| starting_state.clone())
+    }
+
+    fn validate_transaction(t: &Self::Transition) -> Result<(), ExtrinsicError> {
+        if !t.has_valid_signature() {
+            return Err(ExtrinsicError::BadSignature);
+        }
+        if t.signer != t.call.origin() {
+            return Err(ExtrinsicError::SignerMismatch);
+        }
+        Ok(())
+    }
+
+    /// Transfers touch two accounts, everything else touches one.
+    fn weight(t: &Self::Transition) -> u64 {
+        match t.call {
+            AccountingTransaction::Transfer { .. } => 2,
+            _ => 1,
+        }
+    }
+
+    /// Fees are paid out to the author. Without a known author they are burned.
+    fn finalize_block(state: &mut BalancesB<A>, author: Option<&A>) {
+        let fees = std::mem::take(&mut state.fees);
+        if let (Some(author), true) = (author, fees > 0) {
+            // Receipts only cover extrinsics, so the author's events are dropped. If the
+            // author cannot hold any more money, the fees are burned.
+            let _ = state.deposit(*author, fees, &mut Vec::new());
+        }
+    }
+
+    fn sender_and_nonce(t: &Self::Transition) -> Option<(crate::H256, u64)> {
+        AccountedCurrency::<A>::sender_and_nonce(&t.call)
+    }
+
+    fn is_premature(starting_state: &BalancesB<A>, t: &Self::Transition) -> bool {
+        AccountedCurrency::<A>::is_premature(starting_state, &t.call)
+    }
+
+    fn is_stale(starting_state: &BalancesB<A>, t: &Self::Transition) -> bool {
+        AccountedCurrency::<A>::is_stale(starting_state, &t.call)
+    }
+
+    fn try_next_state(
+        starting_state: &BalancesB<A>,
+        t: &Self::Transition,
+    ) -> Result<BalancesB<A>, ExtrinsicError> {
+        Self::try_next_state_with_events(starting_state, t).map(|(state, _)| state)
+    }
+
+    fn try_next_state_with_events(
+        starting_state: &BalancesB<A>,
+        t: &Self::Transition,
+    ) -> Result<(BalancesB<A>, Vec<AccountingEvent<A>>), ExtrinsicError> {
+        Self::validate_transaction(t)?;
+
+        let (mut new_state, mut events) =
+            AccountedCurrency::<A>::try_next_state_with_events(starting_state, &t.call)?;
+
+        // The fee is charged after the call, so that a mint can pay for itself.
+        let fee = Self::weight(t) * FEE_PER_WEIGHT;
+        new_state
+            .withdraw(t.signer, fee, &mut events)
+            .map_err(|_| ExtrinsicError::CannotPayFee)?;
+        new_state.fees += fee;
+        events.push(AccountingEvent::FeePaid {
+            payer: t.signer,
+            amount: fee,
+        });
+
+        Ok((new_state, events))
+    }
+
+    fn human_name() -> String {
+        "Signed Accounted Currency".into()
+    }
+}
+
+impl<Call: Encode, A: Encode> Encode for SignedExtrinsic<Call, A> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.signer.encode_to(out);
+        self.call.encode_to(out);
+        self.signature.encode_to(out);
+    }
+}
+
+impl<Call: Decode, A: Decode> Decode for SignedExtrinsic<Call, A> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(SignedExtrinsic {
+            signer: Decode::decode(input)?,
+            call: Decode::decode(input)?,
+            signature: Decode::decode(input)?,
+        })
+    }
+}
+
+#[test]
+fn sm_7_signature_verifies() {
+    let call = AccountingTransaction::Mint {
+        minter: User::Alice,
+        amount: 100,
+        nonce: 0,
+    };
+    let signature = User::Alice.sign(&call);
+
+    assert!(User::Alice.verify(&call, signature));
+    assert!(!User::Bob.verify(&call, signature));
+}
+
+#[test]
+fn sm_7_signed_transfer_succeeds() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
+    };
+    let t = SignedExtrinsic::new(
+        User::Alice,
+        AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Bob,
+            amount: 10,
+            nonce: 0,
+        },
+    );
+    let end = SignedAccountedCurrency::try_next_state(&start, &t).unwrap();
+    // Alice also pays a fee of 2 for the transfer.
+    let expected = HashMap::from([(User::Alice, 88), (User::Bob, 10)]);
+
+    assert_eq!(end.balances, expected);
+    assert_eq!(end.fees, 2);
+}
+
+#[test]
+fn sm_7_tampered_call_is_rejected() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
+    };
+    let mut t = SignedExtrinsic::new(
+        User::Alice,
+        AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Bob,
+            amount: 10,
+            nonce: 0,
+        },
+    );
+    t.call = AccountingTransaction::Transfer {
+        sender: User::Alice,
+        receiver: User::Bob,
+        amount: 100,
+        nonce: 0,
+    };
+
+    assert_eq!(
+        SignedAccountedCurrency::try_next_state(&start, &t).err(),
+        Some(ExtrinsicError::BadSignature)
+    );
+    assert_eq!(
+        SignedAccountedCurrency::next_state(&start, &t).balances,
+        start.balances
+    );
+}
+
+#[test]
+fn sm_7_forged_signer_is_rejected() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
+    };
+    // Charlie claims to be Alice but can only produce their own signature.
+    let call = AccountingTransaction::Transfer {
+        sender: User::Alice,
+        receiver: User::Charlie,
+        amount: 100,
+        nonce: 0,
+    };
+    let t = SignedExtrinsic {
+        signer: User::Alice,
+        signature: User::Charlie.sign(&call),
+        call,
+    };
+
+    assert_eq!(
+        SignedAccountedCurrency::try_next_state(&start, &t).err(),
+        Some(ExtrinsicError::BadSignature)
+    );
+}
+
+#[test]
+fn sm_7_signing_on_behalf_of_someone_else_is_rejected() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
+    };
+    let t = SignedExtrinsic::new(
+        User::Charlie,
+        AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Charlie,
+            amount: 100,
+            nonce: 0,
+        },
+    );
+
+    assert_eq!(
+        SignedAccountedCurrency::try_next_state(&start, &t).err(),
+        Some(ExtrinsicError::SignerMismatch)
+    );
+}
+
+#[test]
+fn sm_7_signed_but_invalid_call_is_rejected() {
+    let start = BalancesB::default();
+    let t = SignedExtrinsic::new(
+        User::Alice,
+        AccountingTransaction::Burn {
+            burner: User::Alice,
+            amount: 10,
+            nonce: 0,
+        },
+    );
+
+    assert_eq!(
+        SignedAccountedCurrency::try_next_state(&start, &t).err(),
+        Some(ExtrinsicError::Call(AccountingError::AccountNotFound))
+    );
+}
+
+#[test]
+fn sm_7_transaction_increments_nonce() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
+    };
+    let t = SignedExtrinsic::new(
+        User::Alice,
+        AccountingTransaction::Burn {
+            burner: User::Alice,
+            amount: 10,
+            nonce: 0,
+        },
+    );
+    let end = SignedAccountedCurrency::try_next_state(&start, &t).unwrap();
+
+    assert_eq!(end.nonce(&User::Alice), 1);
+    assert_eq!(end.nonce(&User::Bob), 0);
+}
+
+#[test]
+fn sm_7_replayed_transaction_is_rejected() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
+    };
+    let t = SignedExtrinsic::new(
+        User::Alice,
+        AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Bob,
+            amount: 10,
+            nonce: 0,
+        },
+    );
+    let once = SignedAccountedCurrency::try_next_state(&start, &t).unwrap();
+
+    assert_eq!(
+        SignedAccountedCurrency::try_next_state(&once, &t).err(),
+        Some(ExtrinsicError::Call(AccountingError::StaleNonce))
+    );
+}
+
+#[test]
+fn sm_7_future_nonce_is_rejected_but_premature() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
+    };
+    let t = SignedExtrinsic::new(
+        User::Alice,
+        AccountingTransaction::Burn {
+            burner: User::Alice,
+            amount: 10,
+            nonce: 1,
+        },
+    );
+
+    assert_eq!(
+        SignedAccountedCurrency::try_next_state(&start, &t).err(),
+        Some(ExtrinsicError::Call(AccountingError::FutureNonce))
+    );
+    assert!(SignedAccountedCurrency::is_premature(&start, &t));
+}
+
+#[test]
+fn sm_7_nonce_survives_reaping() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 100)]),
+        ..Default::default()
+    };
+    let t = SignedExtrinsic::new(
+        User::Alice,
+        AccountingTransaction::Burn {
+            burner: User::Alice,
+            amount: 99,
+            nonce: 0,
+        },
+    );
+    let end = SignedAccountedCurrency::try_next_state(&start, &t).unwrap();
+
+    // The fee takes the last coin, so the account is reaped.
+    assert!(end.balances.is_empty());
+    assert_eq!(end.nonce(&User::Alice), 1);
+}
+
+#[test]
+fn sm_7_cannot_pay_fee_is_rejected() {
+    let start = BalancesB {
+        balances: HashMap::from([(User::Alice, 10)]),
+        ..Default::default()
+    };
+    let t = SignedExtrinsic::new(
+        User::Alice,
+        AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Bob,
+            amount: 10,
+            nonce: 0,
+        },
+    );
+
+    assert_eq!(
+        SignedAccountedCurrency::try_next_state(&start, &t).err(),
+        Some(ExtrinsicError::CannotPayFee)
+    );
+}
+
+#[test]
+fn sm_7_finalize_block_pays_the_author() {
+    let mut state = BalancesB {
+        balances: HashMap::from([(User::Alice, 10)]),
+        fees: 3,
+        ..Default::default()
+    };
+    SignedAccountedCurrency::finalize_block(&mut state, Some(&User::Charlie));
+
+    assert_eq!(
+        state.balances,
+        HashMap::from([(User::Alice, 10), (User::Charlie, 3)])
+    );
+    assert_eq!(state.fees, 0);
+}
+
+#[test]
+fn sm_7_finalize_block_without_author_burns_fees() {
+    let mut state = BalancesB {
+        balances: HashMap::from([(User::Alice, 10)]),
+        fees: 3,
+        ..Default::default()
+    };
+    SignedAccountedCurrency::finalize_block(&mut state, None);
+
+    assert_eq!(state.balances, HashMap::from([(User::Alice, 10)]));
+    assert_eq!(state.fees, 0);
+}
+
+#[test]
+fn sm_7_signed_extrinsics_round_trip() {
+    let extrinsic = SignedExtrinsic::new(
+        User::Alice,
+        AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Bob,
+            amount: 10,
+            nonce: 3,
+        },
+    );
+    let bytes = extrinsic.encode();
+    let decoded = SignedExtrinsic::decode_all(&bytes).unwrap();
+
+    assert_eq!(decoded, extrinsic);
+    assert!(decoded.has_valid_signature());
+}
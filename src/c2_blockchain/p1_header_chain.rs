@@ -5,11 +5,11 @@
 
 use std::hash::Hash;
 
-use crate::hash;
+use crate::hashing::{DefaultHashing, Hasher};
 
-// We will use Rust's built-in hashing where the output type is u64. I'll make an alias
-// so the code is slightly more readable.
-type HashT = u64;
+// We will use the crate's SHA-256 hashing where the output type is a 32-byte digest. I'll make
+// an alias so the code is slightly more readable.
+type HashT = crate::H256;
 
 /// The most basic blockchain header possible. We learned its basic structure from lecture.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -28,7 +28,7 @@ impl Header {
     /// Returns a new valid genesis header.
     fn genesis() -> Self {
         return Header {
-            parent: 0,
+            parent: 0.into(),
             height: 0,
             extrinsics_root: (),
             state_root: (),
@@ -39,7 +39,7 @@ impl Header {
     /// Create and return a valid child header.
     fn child(&self) -> Self {
         return Header {
-            parent: hash(&self),
+            parent: DefaultHashing::hash_of(&self),
             height: self.height + 1,
             extrinsics_root: (),
             state_root: (),
@@ -56,7 +56,7 @@ impl Header {
 
         for i in 0..chain.len() {
             let header_from_chain = chain.get(i).unwrap();
-            if header_from_chain.parent != hash(&curr_block) {
+            if header_from_chain.parent != DefaultHashing::hash_of(&curr_block) {
                 return false;
             }
             if header_from_chain.height != curr_block.height + 1 {
@@ -112,7 +112,7 @@ fn bc_1_genesis_block_height() {
 #[test]
 fn bc_1_genesis_block_parent() {
     let g = Header::genesis();
-    assert!(g.parent == 0);
+    assert!(g.parent == 0.into());
 }
 
 #[test]
@@ -126,7 +126,7 @@ fn bc_1_child_block_height() {
 fn bc_1_child_block_parent() {
     let g = Header::genesis();
     let b1 = g.child();
-    assert!(b1.parent == hash(&g));
+    assert!(b1.parent == DefaultHashing::hash_of(&g));
 }
 
 #[test]
@@ -162,7 +162,7 @@ fn bc_1_cant_verify_invalid_parent() {
     // not to give away the solution to writing that function.
     let g = Header::genesis();
     let mut b1 = g.child();
-    b1.parent = 10;
+    b1.parent = 10.into();
 
     assert!(!g.verify_sub_chain(&[b1]))
 }
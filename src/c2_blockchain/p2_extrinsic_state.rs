@@ -7,11 +7,11 @@
 //! In the coming parts of this tutorial, we will expand this to be more real-world like and
 //! use some real batching.
 
-use crate::hash;
+use crate::hashing::{DefaultHashing, Hasher};
 
-// We will use Rust's built-in hashing where the output type is u64. I'll make an alias
-// so the code is slightly more readable.
-type Hash = u64;
+// We will use the crate's SHA-256 hashing where the output type is a 32-byte digest. I'll make
+// an alias so the code is slightly more readable.
+type Hash = crate::H256;
 
 /// The header is now expanded to contain an extrinsic and a state. Note that we are not
 /// using roots yet, but rather directly embedding some minimal extrinsic and state info
@@ -32,7 +32,7 @@ impl Header {
     /// Returns a new valid genesis header.
     fn genesis() -> Self {
         return Header {
-            parent: 0,
+            parent: 0.into(),
             height: 0,
             extrinsic: 0,
             state: 0,
@@ -43,7 +43,7 @@ impl Header {
     /// Create and return a valid child header.
     fn child(&self, extrinsic: u64) -> Self {
         return Header {
-            parent: hash(&self),
+            parent: DefaultHashing::hash_of(&self),
             height: self.height + 1,
             extrinsic: extrinsic,
             state: self.state + extrinsic,
@@ -64,7 +64,7 @@ impl Header {
 
         for i in 0..chain.len() {
             let header_from_chain = chain.get(i).unwrap();
-            if header_from_chain.parent != hash(&curr_block) {
+            if header_from_chain.parent != DefaultHashing::hash_of(&curr_block) {
                 return false;
             }
             if header_from_chain.height != curr_block.height + 1 {
@@ -168,7 +168,7 @@ fn bc_2_genesis_block_height() {
 #[test]
 fn bc_2_genesis_block_parent() {
     let g = Header::genesis();
-    assert!(g.parent == 0);
+    assert!(g.parent == 0.into());
 }
 
 #[test]
@@ -196,7 +196,7 @@ fn bc_2_child_block_height() {
 fn bc_2_child_block_parent() {
     let g = Header::genesis();
     let b1 = g.child(0);
-    assert!(b1.parent == hash(&g));
+    assert!(b1.parent == DefaultHashing::hash_of(&g));
 }
 
 #[test]
@@ -234,7 +234,7 @@ fn bc_2_verify_three_blocks() {
 fn bc_2_cant_verify_invalid_parent() {
     let g = Header::genesis();
     let mut b1 = g.child(5);
-    b1.parent = 10;
+    b1.parent = 10.into();
 
     assert!(!g.verify_sub_chain(&[b1]));
 }
@@ -4,12 +4,12 @@
 //! 1. Rules to throttle authoring. In this case we will use a simple PoW.
 //! 2. Arbitrary / Political rules. Here we will implement two alternate validity rules
 
-use crate::hash;
+use crate::hashing::{DefaultHashing, Hasher};
 use rand::random;
 
-// We will use Rust's built-in hashing where the output type is u64. I'll make an alias
-// so the code is slightly more readable.
-type Hash = u64;
+// We will use the crate's SHA-256 hashing where the output type is a 32-byte digest. I'll make
+// an alias so the code is slightly more readable.
+type Hash = crate::H256;
 
 /// In this lesson we are introducing proof of work onto our blocks. We need a hash threshold.
 /// You may change this as you see fit, and I encourage you to experiment. Probably best to start
@@ -39,7 +39,7 @@ impl Header {
     /// Returns a new valid genesis header.
     fn genesis() -> Self {
         return Header {
-            parent: 0,
+            parent: 0.into(),
             height: 0,
             extrinsic: 0,
             state: 0,
@@ -50,14 +50,14 @@ impl Header {
     /// Create and return a valid child header.
     fn child(&self, extrinsic: u64) -> Self {
         let mut child = Header {
-            parent: hash(&self),
+            parent: DefaultHashing::hash_of(&self),
             height: self.height + 1,
             extrinsic: extrinsic,
             state: self.state + extrinsic,
             consensus_digest: rand::random::<u32>() as u64,
         };
 
-        while hash(&child) >= THRESHOLD {
+        while DefaultHashing::hash_of(&child).leading_u64() >= THRESHOLD {
             child.consensus_digest = rand::random::<u32>() as u64;
         }
 
@@ -73,7 +73,7 @@ impl Header {
 
         for i in 0..chain.len() {
             let header_from_chain = chain.get(i).unwrap();
-            if header_from_chain.parent != hash(&curr_block) {
+            if header_from_chain.parent != DefaultHashing::hash_of(&curr_block) {
                 return false;
             }
             if header_from_chain.height != curr_block.height + 1 {
@@ -82,7 +82,7 @@ impl Header {
             if header_from_chain.state != curr_block.state + header_from_chain.extrinsic {
                 return false;
             }
-            if hash(&header_from_chain) >= THRESHOLD {
+            if DefaultHashing::hash_of(&header_from_chain).leading_u64() >= THRESHOLD {
                 return false;
             }
 
@@ -105,13 +105,13 @@ impl Header {
 
         for i in 0..chain.len() {
             let header_from_chain = chain.get(i).unwrap();
-            if header_from_chain.parent != hash(&curr_block) {
+            if header_from_chain.parent != DefaultHashing::hash_of(&curr_block) {
                 return false;
             }
             if header_from_chain.height != curr_block.height + 1 {
                 return false;
             }
-            if hash(&header_from_chain) >= THRESHOLD {
+            if DefaultHashing::hash_of(&header_from_chain).leading_u64() >= THRESHOLD {
                 return false;
             }
             if header_from_chain.state != curr_block.state + header_from_chain.extrinsic {
@@ -135,13 +135,13 @@ impl Header {
 
         for i in 0..chain.len() {
             let header_from_chain = chain.get(i).unwrap();
-            if header_from_chain.parent != hash(&curr_block) {
+            if header_from_chain.parent != DefaultHashing::hash_of(&curr_block) {
                 return false;
             }
             if header_from_chain.height != curr_block.height + 1 {
                 return false;
             }
-            if hash(&header_from_chain) >= THRESHOLD {
+            if DefaultHashing::hash_of(&header_from_chain).leading_u64() >= THRESHOLD {
                 return false;
             }
             if header_from_chain.state != curr_block.state + header_from_chain.extrinsic {
@@ -232,7 +232,7 @@ fn bc_3_genesis_block_height() {
 #[test]
 fn bc_3_genesis_block_parent() {
     let g = Header::genesis();
-    assert!(g.parent == 0);
+    assert!(g.parent == 0.into());
 }
 
 #[test]
@@ -268,7 +268,7 @@ fn bc_3_child_block_height() {
 fn bc_3_child_block_parent() {
     let g = Header::genesis();
     let b1 = g.child(0);
-    assert!(b1.parent == hash(&g));
+    assert!(b1.parent == DefaultHashing::hash_of(&g));
 }
 
 #[test]
@@ -289,7 +289,7 @@ fn bc_3_child_block_state() {
 fn bc_3_child_block_consensus_digest() {
     let g = Header::genesis();
     let b1 = g.child(7);
-    assert!(hash(&b1) < THRESHOLD);
+    assert!(DefaultHashing::hash_of(&b1).leading_u64() < THRESHOLD);
 }
 
 #[test]
@@ -313,7 +313,7 @@ fn bc_3_verify_three_blocks() {
 fn bc_3_cant_verify_invalid_parent() {
     let g = Header::genesis();
     let mut b1 = g.child(5);
-    b1.parent = 10;
+    b1.parent = 10.into();
 
     assert!(!g.verify_sub_chain(&[b1]));
 }
@@ -1,8 +1,8 @@
 //! Until now, each block has contained just a single extrinsic. Really we would prefer to batch them.
 //! Now, we stop relying solely on headers, and instead, create complete blocks.
 
-use crate::hash;
-type Hash = u64;
+use crate::hashing::{DefaultHashing, Hasher};
+type Hash = crate::H256;
 use super::p3_consensus::THRESHOLD;
 use rand::{self, Rng};
 
@@ -34,9 +34,9 @@ impl Header {
     /// Returns a new valid genesis header.
     pub fn genesis() -> Self {
         return Header {
-            parent: 0,
+            parent: 0.into(),
             height: 0,
-            extrinsics_root: hash(&Vec::<u64>::new()),
+            extrinsics_root: DefaultHashing::hash_of(&Vec::<u64>::new()),
             state: 0,
             consensus_digest: 0,
         };
@@ -47,14 +47,14 @@ impl Header {
     /// so that information is passed in.
     pub fn child(&self, extrinsics_root: Hash, state: u64) -> Self {
         let mut child = Header {
-            parent: hash(&self),
+            parent: DefaultHashing::hash_of(&self),
             height: self.height + 1,
             extrinsics_root,
             state,
             consensus_digest: rand::random::<u32>() as u64,
         };
 
-        while hash(&child) >= THRESHOLD {
+        while DefaultHashing::hash_of(&child).leading_u64() >= THRESHOLD {
             child.consensus_digest = rand::random::<u32>() as u64;
         }
 
@@ -69,13 +69,13 @@ impl Header {
     /// subtask of checking an entire block. So it doesn't make sense to check
     /// the entire header chain at once if the chain may be invalid at the second block.
     fn verify_child(&self, child: &Header) -> bool {
-        if child.parent != hash(&self) {
+        if child.parent != DefaultHashing::hash_of(&self) {
             return false;
         }
         if child.height != self.height + 1 {
             return false;
         }
-        if hash(&child) >= THRESHOLD {
+        if DefaultHashing::hash_of(&child).leading_u64() >= THRESHOLD {
             return false;
         }
         return true;
@@ -130,7 +130,9 @@ impl Block {
             state += extr;
         }
         return Block {
-            header: self.header.child(hash(&extrinsics), state),
+            header: self
+                .header
+                .child(DefaultHashing::hash_of(&extrinsics), state),
             body: extrinsics,
         };
     }
@@ -143,7 +145,7 @@ impl Block {
 
         for i in 0..chain.len() {
             let next_block = chain.get(i).unwrap();
-            if hash(&next_block.body) != next_block.header.extrinsics_root {
+            if DefaultHashing::hash_of(&next_block.body) != next_block.header.extrinsics_root {
                 return false;
             }
             headers.push(next_block.header.clone());
@@ -173,7 +175,7 @@ fn build_invalid_child_block_with_valid_header(parent: &Header) -> Block {
 
     let invalid_extrinsics: Vec<u64> = (0..10).map(|_| rng.gen()).collect();
     return Block {
-        header: parent.child(hash(&extrinsics), state),
+        header: parent.child(DefaultHashing::hash_of(&extrinsics), state),
         body: invalid_extrinsics,
     };
 }
@@ -182,8 +184,11 @@ fn build_invalid_child_block_with_valid_header(parent: &Header) -> Block {
 fn bc_4_genesis_header() {
     let g = Header::genesis();
     assert_eq!(g.height, 0);
-    assert_eq!(g.parent, 0);
-    assert_eq!(g.extrinsics_root, hash(&Vec::<u64>::new()));
+    assert_eq!(g.parent, 0.into());
+    assert_eq!(
+        g.extrinsics_root,
+        DefaultHashing::hash_of(&Vec::<u64>::new())
+    );
     assert_eq!(g.state, 0);
     assert_eq!(g.consensus_digest, 0);
 }
@@ -203,7 +208,7 @@ fn bc_4_child_block_empty() {
     let b1 = b0.child(vec![]);
 
     assert_eq!(b1.header.height, 1);
-    assert_eq!(b1.header.parent, hash(&b0.header));
+    assert_eq!(b1.header.parent, DefaultHashing::hash_of(&b0.header));
     assert_eq!(
         b1,
         Block {
@@ -219,7 +224,7 @@ fn bc_4_child_block() {
     let b1 = b0.child(vec![1, 2, 3, 4, 5]);
 
     assert_eq!(b1.header.height, 1);
-    assert_eq!(b1.header.parent, hash(&b0.header));
+    assert_eq!(b1.header.parent, DefaultHashing::hash_of(&b0.header));
     assert_eq!(
         b1,
         Block {
@@ -232,21 +237,21 @@ fn bc_4_child_block() {
 #[test]
 fn bc_4_child_header() {
     let g = Header::genesis();
-    let h1 = g.child(hash(&[1, 2, 3]), 6);
+    let h1 = g.child(DefaultHashing::hash_of(&[1, 2, 3]), 6);
 
     assert_eq!(h1.height, 1);
-    assert_eq!(h1.parent, hash(&g));
-    assert_eq!(h1.extrinsics_root, hash(&[1, 2, 3]));
+    assert_eq!(h1.parent, DefaultHashing::hash_of(&g));
+    assert_eq!(h1.extrinsics_root, DefaultHashing::hash_of(&[1, 2, 3]));
     assert_eq!(h1.state, 6);
-    assert!(hash(&h1) < THRESHOLD);
+    assert!(DefaultHashing::hash_of(&h1).leading_u64() < THRESHOLD);
 
-    let h2 = h1.child(hash(&[10, 20]), 36);
+    let h2 = h1.child(DefaultHashing::hash_of(&[10, 20]), 36);
 
     assert_eq!(h2.height, 2);
-    assert_eq!(h2.parent, hash(&h1));
-    assert_eq!(h2.extrinsics_root, hash(&[10, 20]));
+    assert_eq!(h2.parent, DefaultHashing::hash_of(&h1));
+    assert_eq!(h2.extrinsics_root, DefaultHashing::hash_of(&[10, 20]));
     assert_eq!(h2.state, 36);
-    assert!(hash(&h2) < THRESHOLD);
+    assert!(DefaultHashing::hash_of(&h2).leading_u64() < THRESHOLD);
 }
 
 #[test]
@@ -262,9 +267,9 @@ fn bc_4_verify_three_blocks() {
 fn bc_4_invalid_header_does_not_check() {
     let g = Header::genesis();
     let h1 = Header {
-        parent: 0,
+        parent: 0.into(),
         height: 100,
-        extrinsics_root: 0,
+        extrinsics_root: 0.into(),
         state: 100,
         consensus_digest: 0,
     };
@@ -9,7 +9,7 @@
 
 use super::p3_consensus::THRESHOLD;
 use super::p4_batched_extrinsics::{Block, Header};
-use crate::hash;
+use crate::hashing::{DefaultHashing, Hasher};
 
 /// Judge which blockchain is "best" when there are multiple candidates. There are several
 /// meaningful notions of "best" which is why this is a trait instead of just a
@@ -73,7 +73,7 @@ pub struct HeaviestChainRule;
 /// usage is that you create a block using the normal `Block.child()` method
 /// and then pass the block to this helper for additional mining.
 fn mine_extra_hard(block: &mut Block, threshold: u64) {
-    while hash(&block.header) >= threshold {
+    while DefaultHashing::hash_of(&block.header).leading_u64() >= threshold {
         block.header.consensus_digest = rand::random::<u32>() as u64;
     }
 }
@@ -85,7 +85,7 @@ impl ForkChoice for HeaviestChainRule {
             if header.consensus_digest == 0 {
                 continue;
             }
-            work_in_chain_1 += THRESHOLD - hash(&header);
+            work_in_chain_1 += THRESHOLD - DefaultHashing::hash_of(&header).leading_u64();
         }
 
         let mut work_in_chain_2: u64 = 0;
@@ -93,7 +93,7 @@ impl ForkChoice for HeaviestChainRule {
             if header.consensus_digest == 0 {
                 continue;
             }
-            work_in_chain_2 += THRESHOLD - hash(&header);
+            work_in_chain_2 += THRESHOLD - DefaultHashing::hash_of(&header).leading_u64();
         }
 
         return work_in_chain_1 >= work_in_chain_2;
@@ -124,14 +124,14 @@ impl ForkChoice for MostBlocksWithEvenHash {
     fn first_chain_is_better(chain_1: &[Header], chain_2: &[Header]) -> bool {
         let mut count_even_chain_1: u64 = 0;
         for header in chain_1 {
-            if hash(&header) % 2 == 0 {
+            if DefaultHashing::hash_of(&header).low_u64() % 2 == 0 {
                 count_even_chain_1 += 1;
             }
         }
 
         let mut count_even_chain_2: u64 = 0;
         for header in chain_2 {
-            if hash(&header) % 2 == 0 {
+            if DefaultHashing::hash_of(&header).low_u64() % 2 == 0 {
                 count_even_chain_2 += 1;
             }
         }
@@ -164,7 +164,7 @@ fn build_valid_chain(n: u64) -> Vec<Header> {
     v.push(g.clone());
     let mut parent = g;
     for i in 0..n {
-        let child = parent.child(hash(&i), i);
+        let child = parent.child(DefaultHashing::hash_of(&i), i);
         v.push(child.clone());
         parent = child;
     }
@@ -188,19 +188,21 @@ fn create_fork_one_side_longer_other_side_heavier() -> (Vec<Header>, Vec<Header>
 
     let mut parent = chain.last().unwrap().clone();
     for i in 0..12 {
-        let child = parent.child(hash(&i), i);
+        let child = parent.child(DefaultHashing::hash_of(&i), i);
         longer.push(child.clone());
         parent = child;
     }
 
     parent = chain.last().unwrap().clone();
     for i in 0..10 {
-        let child = parent.child(hash(&i), i);
+        let child = parent.child(DefaultHashing::hash_of(&i), i);
         let mut block = Block {
             header: child.clone(),
             body: vec![],
         };
-        mine_extra_hard(&mut block, THRESHOLD / 10000);
+        // Ten blocks this far below the threshold already outweigh twelve ordinary ones, and
+        // every extra factor of ten costs ten times the hashing.
+        mine_extra_hard(&mut block, THRESHOLD / 100);
         heavier.push(block.header);
         parent = child;
     }
@@ -211,11 +213,11 @@ fn create_fork_one_side_longer_other_side_heavier() -> (Vec<Header>, Vec<Header>
 fn bc_5_longest_chain() {
     let g = Header::genesis();
 
-    let h_a1 = g.child(hash(&[1]), 1);
-    let h_a2 = h_a1.child(hash(&[2]), 2);
+    let h_a1 = g.child(DefaultHashing::hash_of(&[1]), 1);
+    let h_a2 = h_a1.child(DefaultHashing::hash_of(&[2]), 2);
     let chain_1 = &[g.clone(), h_a1, h_a2];
 
-    let h_b1 = g.child(hash(&[3]), 3);
+    let h_b1 = g.child(DefaultHashing::hash_of(&[3]), 3);
     let chain_2 = &[g, h_b1];
 
     assert!(LongestChainRule::first_chain_is_better(chain_1, chain_2));
@@ -234,20 +236,20 @@ fn bc_5_mine_to_custom_difficulty() {
     let custom_threshold = u64::max_value() / 1000;
     mine_extra_hard(&mut b1, custom_threshold);
 
-    assert!(hash(&b1.header) < custom_threshold);
+    assert!(DefaultHashing::hash_of(&b1.header).leading_u64() < custom_threshold);
 }
 
 #[test]
 fn bc_5_heaviest_chain() {
     let g = Header::genesis();
 
-    let h_a1 = g.child(hash(&[1]), 1);
+    let h_a1 = g.child(DefaultHashing::hash_of(&[1]), 1);
     let chain_1 = &[g.clone(), h_a1];
 
     let h_b1 = loop {
-        let header = g.child(hash(&[1]), 1);
+        let header = g.child(DefaultHashing::hash_of(&[1]), 1);
         // more work done - harder to find such a hash
-        if hash(&header) < THRESHOLD / 1000 {
+        if DefaultHashing::hash_of(&header).leading_u64() < THRESHOLD / 1000 {
             break header;
         }
     };
@@ -262,33 +264,33 @@ fn bc_5_heaviest_chain() {
 fn bc_5_most_even_blocks() {
     let g = Header::genesis();
 
-    let mut h_a1 = g.child(2, 0);
+    let mut h_a1 = g.child(2.into(), 0);
     for i in 0..u64::max_value() {
-        h_a1 = g.child(2, i);
-        if hash(&h_a1) % 2 == 0 {
+        h_a1 = g.child(2.into(), i);
+        if DefaultHashing::hash_of(&h_a1).low_u64().is_multiple_of(2) {
             break;
         }
     }
-    let mut h_a2 = g.child(2, 0);
+    let mut h_a2 = g.child(2.into(), 0);
     for i in 0..u64::max_value() {
-        h_a2 = h_a1.child(2, i);
-        if hash(&h_a2) % 2 == 0 {
+        h_a2 = h_a1.child(2.into(), i);
+        if DefaultHashing::hash_of(&h_a2).low_u64().is_multiple_of(2) {
             break;
         }
     }
     let chain_1 = &[g.clone(), h_a1, h_a2];
 
-    let mut h_b1 = g.child(2, 0);
+    let mut h_b1 = g.child(2.into(), 0);
     for i in 0..u64::max_value() {
-        h_b1 = g.child(2, i);
-        if hash(&h_b1) % 2 != 0 {
+        h_b1 = g.child(2.into(), i);
+        if !DefaultHashing::hash_of(&h_b1).low_u64().is_multiple_of(2) {
             break;
         }
     }
-    let mut h_b2 = g.child(2, 0);
+    let mut h_b2 = g.child(2.into(), 0);
     for i in 0..u64::max_value() {
-        h_b2 = h_b1.child(2, i);
-        if hash(&h_b2) % 2 != 0 {
+        h_b2 = h_b1.child(2.into(), i);
+        if !DefaultHashing::hash_of(&h_b2).low_u64().is_multiple_of(2) {
             break;
         }
     }
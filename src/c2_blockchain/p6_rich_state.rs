@@ -11,9 +11,10 @@
 //! This notion of state may sound familiar from our previous work on state machines. Indeed this
 //! naming coincidence foreshadows a key abstraction that we will make in a coming chapter.
 
-type Hash = u64;
+type Hash = crate::H256;
 use super::p3_consensus::THRESHOLD;
-use crate::hash;
+use crate::hashing::{DefaultHashing, Hasher};
+use crate::{merkle_proof, merkle_root, MerkleProof};
 
 /// In this section we will use sum and product together to be our state. While this is only a doubling of state size
 /// remember that in real world blockchains, the state is often really really large.
@@ -48,9 +49,9 @@ impl Header {
     /// Returns a new valid genesis header.
     pub fn genesis(genesis_state_root: Hash) -> Self {
         return Header {
-            parent: 0,
+            parent: 0.into(),
             height: 0,
-            extrinsics_root: hash(&Vec::<u64>::new()),
+            extrinsics_root: merkle_root(&Vec::<u64>::new()),
             state_root: genesis_state_root,
             consensus_digest: 0,
         };
@@ -62,14 +63,14 @@ impl Header {
     /// was in the previous section.
     pub fn child(&self, extrinsics_root: Hash, state_root: Hash) -> Self {
         let mut child = Header {
-            parent: hash(&self),
+            parent: DefaultHashing::hash_of(&self),
             height: self.height + 1,
             extrinsics_root,
             state_root,
             consensus_digest: rand::random::<u32>() as u64,
         };
 
-        while hash(&child) >= THRESHOLD {
+        while DefaultHashing::hash_of(&child).leading_u64() >= THRESHOLD {
             child.consensus_digest = rand::random::<u32>() as u64;
         }
 
@@ -78,18 +79,24 @@ impl Header {
 
     /// Verify a single child header.
     fn verify_child(&self, child: &Header) -> bool {
-        if child.parent != hash(&self) {
+        if child.parent != DefaultHashing::hash_of(&self) {
             return false;
         }
         if child.height != self.height + 1 {
             return false;
         }
-        if hash(&child) >= THRESHOLD {
+        if DefaultHashing::hash_of(&child).leading_u64() >= THRESHOLD {
             return false;
         }
         return true;
     }
 
+    /// Verify that the given extrinsic is part of the block with this header, using a Merkle
+    /// proof instead of the complete block body.
+    pub fn verify_extrinsic(&self, extrinsic: &u64, proof: &MerkleProof) -> bool {
+        proof.verify(&self.extrinsics_root, extrinsic)
+    }
+
     /// Verify that all the given headers form a valid chain from this header to the tip.
     fn verify_sub_chain(&self, chain: &[Header]) -> bool {
         let mut parent = self.clone();
@@ -124,7 +131,7 @@ pub struct Block {
 impl Block {
     /// Returns a new valid genesis block. By convention this block has no extrinsics.
     pub fn genesis(genesis_state: &State) -> Self {
-        let state_root = hash(&genesis_state);
+        let state_root = DefaultHashing::hash_of(&genesis_state);
 
         return Block {
             header: Header::genesis(state_root),
@@ -140,11 +147,19 @@ impl Block {
             state.product *= extr;
         }
         return Block {
-            header: self.header.child(hash(&extrinsics), hash(&state)),
+            header: self
+                .header
+                .child(merkle_root(&extrinsics), DefaultHashing::hash_of(&state)),
             body: extrinsics,
         };
     }
 
+    /// Build a proof that the extrinsic at the given index is part of this block. It can be
+    /// checked against the header alone with `Header::verify_extrinsic`.
+    pub fn extrinsic_proof(&self, index: usize) -> Option<MerkleProof> {
+        merkle_proof(&self.body, index)
+    }
+
     /// Verify that all the given blocks form a valid chain from this block to the tip.
     ///
     /// This time we need to validate the initial block itself by confirming that we
@@ -161,11 +176,11 @@ impl Block {
                 curr_state.sum += extr;
                 curr_state.product *= extr;
             }
-            if hash(&curr_state) != next_block.header.state_root {
+            if DefaultHashing::hash_of(&curr_state) != next_block.header.state_root {
                 return false;
             }
 
-            if hash(&next_block.body) != next_block.header.extrinsics_root {
+            if merkle_root(&next_block.body) != next_block.header.extrinsics_root {
                 return false;
             }
             headers.push(next_block.header.clone());
@@ -200,17 +215,17 @@ fn build_invalid_child_block_with_valid_header(parent: &Header, pre_state: &Stat
 #[test]
 fn bc_6_genesis_header() {
     let state = State { sum: 6, product: 9 };
-    let g = Header::genesis(hash(&state));
+    let g = Header::genesis(DefaultHashing::hash_of(&state));
     assert_eq!(g.height, 0);
-    assert_eq!(g.parent, 0);
-    assert_eq!(g.extrinsics_root, hash(&Vec::<u64>::new()));
-    assert_eq!(g.state_root, hash(&state));
+    assert_eq!(g.parent, 0.into());
+    assert_eq!(g.extrinsics_root, merkle_root(&Vec::<u64>::new()));
+    assert_eq!(g.state_root, DefaultHashing::hash_of(&state));
 }
 
 #[test]
 fn bc_6_genesis_block() {
     let state = State { sum: 6, product: 9 };
-    let gh = Header::genesis(hash(&state));
+    let gh = Header::genesis(DefaultHashing::hash_of(&state));
     let gb = Block::genesis(&state);
 
     assert_eq!(gb.header, gh);
@@ -224,7 +239,7 @@ fn bc_6_child_block_empty() {
     let b1 = b0.child(&state, vec![]);
 
     assert_eq!(b1.header.height, 1);
-    assert_eq!(b1.header.parent, hash(&b0.header));
+    assert_eq!(b1.header.parent, DefaultHashing::hash_of(&b0.header));
     assert_eq!(
         b1,
         Block {
@@ -241,7 +256,7 @@ fn bc_6_child_block() {
     let b1 = b0.child(&state, vec![1, 2, 3, 4, 5]);
 
     assert_eq!(b1.header.height, 1);
-    assert_eq!(b1.header.parent, hash(&b0.header));
+    assert_eq!(b1.header.parent, DefaultHashing::hash_of(&b0.header));
     assert_eq!(
         b1,
         Block {
@@ -254,19 +269,19 @@ fn bc_6_child_block() {
 #[test]
 fn bc_6_child_header() {
     let state_0 = State { sum: 6, product: 9 };
-    let g = Header::genesis(hash(&state_0));
+    let g = Header::genesis(DefaultHashing::hash_of(&state_0));
     let mut extrinsics = vec![1, 2, 3];
     let mut state_1 = state_0;
     for extrinsic in extrinsics.iter() {
         state_1.sum += extrinsic;
         state_1.product *= extrinsic;
     }
-    let h1 = g.child(hash(&extrinsics), hash(&state_1));
+    let h1 = g.child(merkle_root(&extrinsics), DefaultHashing::hash_of(&state_1));
 
     assert_eq!(h1.height, 1);
-    assert_eq!(h1.parent, hash(&g));
-    assert_eq!(h1.extrinsics_root, hash(&extrinsics));
-    assert_eq!(h1.state_root, hash(&state_1));
+    assert_eq!(h1.parent, DefaultHashing::hash_of(&g));
+    assert_eq!(h1.extrinsics_root, merkle_root(&extrinsics));
+    assert_eq!(h1.state_root, DefaultHashing::hash_of(&state_1));
 
     extrinsics = vec![10, 20];
     let mut state_2 = state_1;
@@ -275,12 +290,12 @@ fn bc_6_child_header() {
         state_2.product *= extrinsic;
     }
 
-    let h2 = h1.child(hash(&extrinsics), hash(&state_2));
+    let h2 = h1.child(merkle_root(&extrinsics), DefaultHashing::hash_of(&state_2));
 
     assert_eq!(h2.height, 2);
-    assert_eq!(h2.parent, hash(&h1));
-    assert_eq!(h2.extrinsics_root, hash(&extrinsics));
-    assert_eq!(h2.state_root, hash(&state_2));
+    assert_eq!(h2.parent, DefaultHashing::hash_of(&h1));
+    assert_eq!(h2.extrinsics_root, merkle_root(&extrinsics));
+    assert_eq!(h2.state_root, DefaultHashing::hash_of(&state_2));
 }
 
 #[test]
@@ -297,12 +312,12 @@ fn bc_6_verify_three_blocks() {
 #[test]
 fn bc_6_invalid_header_doesnt_check() {
     let state = State { sum: 6, product: 9 };
-    let g = Header::genesis(hash(&state));
+    let g = Header::genesis(DefaultHashing::hash_of(&state));
     let h1 = Header {
-        parent: 0,
+        parent: 0.into(),
         height: 100,
-        extrinsics_root: 0,
-        state_root: hash(&(State { sum: 0, product: 0 })),
+        extrinsics_root: 0.into(),
+        state_root: DefaultHashing::hash_of(&(State { sum: 0, product: 0 })),
         consensus_digest: 0,
     };
 
@@ -324,7 +339,7 @@ fn bc_6_block_with_invalid_header_doesnt_check() {
     let state = State { sum: 6, product: 9 };
     let b0 = Block::genesis(&state);
     let mut b1 = b0.child(&state, vec![1, 2, 3]);
-    b1.header = Header::genesis(hash(&state));
+    b1.header = Header::genesis(DefaultHashing::hash_of(&state));
 
     assert!(!b0.verify_sub_chain(&state, &[b1]));
 }
@@ -344,3 +359,16 @@ fn bc_6_student_invalid_block_really_is_invalid() {
     // Make sure that the block is not valid when executed.
     assert!(!gb.verify_sub_chain(&state, &[b1]));
 }
+
+#[test]
+fn bc_6_extrinsic_inclusion_proof() {
+    let state = State { sum: 6, product: 9 };
+    let b0 = Block::genesis(&state);
+    let b1 = b0.child(&state, vec![1, 2, 3, 4, 5]);
+
+    let proof = b1.extrinsic_proof(3).unwrap();
+    assert!(b1.header.verify_extrinsic(&4, &proof));
+    assert!(!b1.header.verify_extrinsic(&5, &proof));
+    assert!(!b0.header.verify_extrinsic(&4, &proof));
+    assert_eq!(b1.extrinsic_proof(5), None);
+}
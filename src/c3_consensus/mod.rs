@@ -16,8 +16,12 @@ pub use p3_poa::SimplePoa;
 pub use p4_even_only::EvenOnly;
 pub use p5_interleave::PowOrPoaDigest;
 pub use p6_forking::change_difficulty;
+pub use p6_forking::pow_to_poa;
 pub use p6_forking::Forked;
-type Hash = u64;
+type Hash = crate::H256;
+
+use crate::{Compact, Decode, DecodeError, Encode};
+
 ///
 /// Different consensus engines, require different information in the consensus digest.
 /// Therefore, the header is now generic over the digest type.
@@ -31,14 +35,50 @@ pub struct Header<Digest> {
     pub height: u64,
     pub state_root: Hash,
     pub extrinsics_root: Hash,
+    /// A commitment to the receipts produced by executing the block's extrinsics.
+    pub receipts_root: Hash,
     pub consensus_digest: Digest,
 }
+
+impl<Digest: Encode> Encode for Header<Digest> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.parent.encode_to(out);
+        Compact(self.height).encode_to(out);
+        self.state_root.encode_to(out);
+        self.extrinsics_root.encode_to(out);
+        self.receipts_root.encode_to(out);
+        self.consensus_digest.encode_to(out);
+    }
+}
+
+impl<Digest: Decode> Decode for Header<Digest> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Header {
+            parent: Decode::decode(input)?,
+            height: Compact::decode(input)?.0,
+            state_root: Decode::decode(input)?,
+            extrinsics_root: Decode::decode(input)?,
+            receipts_root: Decode::decode(input)?,
+            consensus_digest: Decode::decode(input)?,
+        })
+    }
+}
+
 /// A Consensus Engine. Responsible for Sealing blocks and verifying their seals
 ///
 /// Consensus exists independently of execution logic, and therefore operates
 /// only on the block headers.
 pub trait Consensus {
-    type Digest: Clone + core::fmt::Debug + Eq + PartialEq + std::hash::Hash + Default;
+    /// The digest is part of the header, and header hashes are taken over its encoding, so
+    /// every digest must be encodable.
+    type Digest: Clone
+        + core::fmt::Debug
+        + Eq
+        + PartialEq
+        + std::hash::Hash
+        + Default
+        + Encode
+        + Decode;
 
     /// Validates that a header is valid according to consensus rules. This
     /// function checks ONLY consensus-related aspects such as the signature
@@ -70,18 +110,21 @@ pub trait Consensus {
 
     /// Verify that all the given headers are valid according to the consensus rules.
     ///
-    /// This method assumes that the parent_digest is valid, and verifies all the
-    /// following headers relative to the given parent digest. This is a provided method
-    /// on the trait, so it must be general enough to work for any specific consensus engine.
+    /// This method assumes that the parent_digest is valid, and verifies the first header
+    /// relative to it, and every following header relative to the one before it. This is a
+    /// provided method on the trait, so it must be general enough to work for any specific
+    /// consensus engine.
     fn verify_sub_chain(
         &self,
         parent_digest: &Self::Digest,
         chain: &[Header<Self::Digest>],
     ) -> bool {
+        let mut parent_digest = parent_digest;
         for header in chain {
             if !self.validate(parent_digest, header) {
                 return false;
             }
+            parent_digest = &header.consensus_digest;
         }
         return true;
     }
@@ -122,3 +165,20 @@ pub enum ConsensusAuthority {
     Bob,
     Charlie,
 }
+
+impl Encode for ConsensusAuthority {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl Decode for ConsensusAuthority {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(ConsensusAuthority::Alice),
+            1 => Ok(ConsensusAuthority::Bob),
+            2 => Ok(ConsensusAuthority::Charlie),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
@@ -5,12 +5,11 @@
 //! generic consensus framework that we will use throughout the rest of the chapter.
 
 use super::{Consensus, Header};
-use crate::hash;
 
 /// A Proof of Work consensus engine. This is the same consensus logic that we
 /// implemented in the previous chapter. Here we simply re-implement it in the
 /// consensus framework that will be used throughout this chapter.
-#[derive(Clone)]
+#[derive(Clone, Hash)]
 pub struct Pow {
     pub threshold: u64,
 }
@@ -35,10 +34,11 @@ impl Consensus for Pow {
             consensus_digest: rand::random::<u32>() as u64,
             state_root: partial_header.state_root,
             extrinsics_root: partial_header.extrinsics_root,
+            receipts_root: partial_header.receipts_root,
             parent: partial_header.parent,
             height: partial_header.height,
         };
-        while hash(&header) >= self.threshold {
+        while header.hash().leading_u64() >= self.threshold {
             header.consensus_digest = rand::random::<u32>() as u64;
         }
         return Some(header);
@@ -34,6 +34,7 @@ impl Consensus for DictatorConsensus {
             consensus_digest: self.dictator,
             state_root: partial_header.state_root,
             extrinsics_root: partial_header.extrinsics_root,
+            receipts_root: partial_header.receipts_root,
             parent: partial_header.parent,
             height: partial_header.height,
         };
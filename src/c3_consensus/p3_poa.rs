@@ -10,6 +10,7 @@
 //! the proof of authority we are writing here.
 
 use super::{Consensus, ConsensusAuthority, Header};
+use crate::{Decode, DecodeError, Encode};
 
 /// A Proof of Authority consensus engine. If any of the authorities have signed the block, it is valid.
 #[derive(Hash, Clone)]
@@ -23,6 +24,7 @@ impl Default for Header<ConsensusAuthority> {
             consensus_digest: ConsensusAuthority::Alice,
             state_root: Default::default(),
             extrinsics_root: Default::default(),
+            receipts_root: Default::default(),
             parent: Default::default(),
             height: Default::default(),
         }
@@ -38,6 +40,7 @@ impl Default for Header<SlotDigest> {
             },
             state_root: Default::default(),
             extrinsics_root: Default::default(),
+            receipts_root: Default::default(),
             parent: Default::default(),
             height: Default::default(),
         }
@@ -49,6 +52,7 @@ impl Default for Header<()> {
         Header {
             state_root: Default::default(),
             extrinsics_root: Default::default(),
+            receipts_root: Default::default(),
             parent: Default::default(),
             height: Default::default(),
             consensus_digest: (),
@@ -72,6 +76,7 @@ impl Consensus for SimplePoa {
             consensus_digest: self.authorities.get(0).unwrap().clone(),
             state_root: partial_header.state_root,
             extrinsics_root: partial_header.extrinsics_root,
+            receipts_root: partial_header.receipts_root,
             parent: partial_header.parent,
             height: partial_header.height,
         };
@@ -82,6 +87,7 @@ impl Consensus for SimplePoa {
 /// A Proof of Authority consensus engine. Only one authority is valid at each block height.
 /// As ever, the genesis block does not require a seal. After that the authorities take turns
 /// in order.
+#[derive(Clone, Hash)]
 pub struct PoaRoundRobinByHeight {
     pub authorities: Vec<ConsensusAuthority>,
 }
@@ -122,6 +128,7 @@ impl Consensus for PoaRoundRobinByHeight {
                 .clone(),
             state_root: partial_header.state_root,
             extrinsics_root: partial_header.extrinsics_root,
+            receipts_root: partial_header.receipts_root,
             parent: partial_header.parent,
             height: partial_header.height,
         };
@@ -150,6 +157,22 @@ struct SlotDigest {
     signature: ConsensusAuthority,
 }
 
+impl Encode for SlotDigest {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.slot.encode_to(out);
+        self.signature.encode_to(out);
+    }
+}
+
+impl Decode for SlotDigest {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(SlotDigest {
+            slot: Decode::decode(input)?,
+            signature: Decode::decode(input)?,
+        })
+    }
+}
+
 impl Consensus for PoaRoundRobinBySlot {
     type Digest = SlotDigest;
 
@@ -197,6 +220,7 @@ impl Consensus for PoaRoundRobinBySlot {
             },
             state_root: partial_header.state_root,
             extrinsics_root: partial_header.extrinsics_root,
+            receipts_root: partial_header.receipts_root,
             parent: partial_header.parent,
             height: partial_header.height,
         };
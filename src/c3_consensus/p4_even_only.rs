@@ -4,7 +4,7 @@
 
 use std::marker::PhantomData;
 
-use crate::hash;
+use crate::hashing::{DefaultHashing, Hasher};
 
 use super::{p1_pow::moderate_difficulty_pow, Consensus, Header, Pow};
 
@@ -21,7 +21,7 @@ impl<Inner: Consensus> Consensus for EvenOnly<Inner> {
     type Digest = Inner::Digest;
 
     fn validate(&self, parent_digest: &Self::Digest, header: &Header<Self::Digest>) -> bool {
-        return header.state_root % 2 == 0 && self.inner.validate(parent_digest, header);
+        header.state_root.low_u64().is_multiple_of(2) && self.inner.validate(parent_digest, header)
     }
 
     fn seal(
@@ -29,7 +29,7 @@ impl<Inner: Consensus> Consensus for EvenOnly<Inner> {
         parent_digest: &Self::Digest,
         partial_header: Header<Self::Digest>,
     ) -> Option<Header<Self::Digest>> {
-        if partial_header.state_root % 2 == 0 {
+        if partial_header.state_root.low_u64() % 2 == 0 {
             return self.inner.seal(parent_digest, partial_header);
         } else {
             return None;
@@ -42,10 +42,13 @@ impl<Inner: Consensus> Consensus for EvenOnly<Inner> {
 /// this engine because the state roots are not all even.
 fn almost_valid_but_not_all_even() -> Vec<Header<u64>> {
     let mut chain = Vec::<HeaderPow>::new();
-    let g: HeaderPow = HeaderPow::genesis(2);
+    let g: HeaderPow = HeaderPow::genesis(2.into());
     chain.push(g.clone());
     for i in 0..10 {
-        chain.push(g.child(hash(&vec![i]), hash(&vec![i])));
+        chain.push(g.child(
+            DefaultHashing::hash_of(&vec![i]),
+            DefaultHashing::hash_of(&vec![i]),
+        ));
     }
 
     let mut result_chain = Vec::<Header<u64>>::new();
@@ -55,6 +58,7 @@ fn almost_valid_but_not_all_even() -> Vec<Header<u64>> {
             height: e.height,
             state_root: e.state_root,
             extrinsics_root: e.extrinsics_root,
+            receipts_root: 0.into(),
             consensus_digest: e.consensus_digest,
         });
     }
@@ -70,10 +74,11 @@ fn cs4_even_only_valid() {
 
     let parent_digest = 0;
     let header = Header {
-        parent: 0,
+        parent: 0.into(),
         height: 1,
-        state_root: 2,
-        extrinsics_root: 0,
+        state_root: 2.into(),
+        extrinsics_root: 0.into(),
+        receipts_root: 0.into(),
         consensus_digest: 0,
     };
 
@@ -87,10 +92,11 @@ fn cs4_even_only_invalid() {
 
     let parent_digest = 0;
     let header = Header {
-        parent: 0,
+        parent: 0.into(),
         height: 1,
-        state_root: 3,
-        extrinsics_root: 0,
+        state_root: 3.into(),
+        extrinsics_root: 0.into(),
+        receipts_root: 0.into(),
         consensus_digest: 0,
     };
 
@@ -104,10 +110,11 @@ fn cs4_even_only_seal_valid() {
 
     let parent_digest = 0;
     let partial_header = Header::<u64> {
-        parent: 0,
+        parent: 0.into(),
         height: 1,
-        state_root: 2,
-        extrinsics_root: 0,
+        state_root: 2.into(),
+        extrinsics_root: 0.into(),
+        receipts_root: 0.into(),
         consensus_digest: 0,
     };
 
@@ -121,10 +128,11 @@ fn cs4_even_only_seal_invalid() {
 
     let parent_digest = 0;
     let partial_header = Header::<u64> {
-        parent: 0,
+        parent: 0.into(),
         height: 1,
-        state_root: 3,
-        extrinsics_root: 0,
+        state_root: 3.into(),
+        extrinsics_root: 0.into(),
+        receipts_root: 0.into(),
         consensus_digest: 0,
     };
 
@@ -144,7 +152,7 @@ fn cs4_almost_valid_but_not_all_even() {
             chain[i - 1].consensus_digest
         };
         let header = &chain[i];
-        if header.state_root % 2 == 0 {
+        if header.state_root.low_u64().is_multiple_of(2) {
             assert!(even_only.validate(&parent_digest, header));
         } else {
             assert!(!even_only.validate(&parent_digest, header));
@@ -6,6 +6,9 @@
 use std::u64;
 
 use super::{Consensus, ConsensusAuthority, Header, Pow, SimplePoa};
+use crate::{Decode, DecodeError, Encode};
+#[cfg(test)]
+use crate::{Hasher, Sha256};
 
 /// A Consensus engine that alternates back and forth between PoW and PoA sealed blocks.
 ///
@@ -24,6 +27,31 @@ pub enum PowOrPoaDigest {
     Poa(ConsensusAuthority),
 }
 
+impl Encode for PowOrPoaDigest {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            PowOrPoaDigest::Pow(nonce) => {
+                out.push(0);
+                nonce.encode_to(out);
+            }
+            PowOrPoaDigest::Poa(authority) => {
+                out.push(1);
+                authority.encode_to(out);
+            }
+        }
+    }
+}
+
+impl Decode for PowOrPoaDigest {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(PowOrPoaDigest::Pow(Decode::decode(input)?)),
+            1 => Ok(PowOrPoaDigest::Poa(Decode::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
 impl Default for PowOrPoaDigest {
     fn default() -> Self {
         PowOrPoaDigest::Pow(0)
@@ -75,6 +103,7 @@ impl From<Header<PowOrPoaDigest>> for Header<ConsensusAuthority> {
             height: h.height,
             state_root: h.state_root,
             extrinsics_root: h.extrinsics_root,
+            receipts_root: h.receipts_root,
             consensus_digest: ConsensusAuthority::try_from(h.consensus_digest).unwrap(),
         }
     }
@@ -87,6 +116,7 @@ impl From<Header<PowOrPoaDigest>> for Header<u64> {
             height: h.height,
             state_root: h.state_root,
             extrinsics_root: h.extrinsics_root,
+            receipts_root: h.receipts_root,
             consensus_digest: u64::try_from(h.consensus_digest).unwrap(),
         }
     }
@@ -131,6 +161,7 @@ impl Consensus for AlternatingPowPoa {
                                 height: partial_header.height,
                                 state_root: partial_header.state_root,
                                 extrinsics_root: partial_header.extrinsics_root,
+                                receipts_root: partial_header.receipts_root,
                                 consensus_digest: ConsensusAuthority::default(),
                             },
                         )
@@ -149,6 +180,7 @@ impl Consensus for AlternatingPowPoa {
                             height: partial_header.height,
                             state_root: partial_header.state_root,
                             extrinsics_root: partial_header.extrinsics_root,
+                            receipts_root: partial_header.receipts_root,
                             consensus_digest: 0,
                         },
                     )
@@ -164,6 +196,7 @@ impl Consensus for AlternatingPowPoa {
             height: partial_header.height,
             state_root: partial_header.state_root,
             extrinsics_root: partial_header.extrinsics_root,
+            receipts_root: partial_header.receipts_root,
         });
     }
 }
@@ -202,10 +235,11 @@ fn cs5_alternating_pow_poa_validate_pow() {
 
     let parent_digest = PowOrPoaDigest::Pow(42);
     let header = Header {
-        parent: 0,
+        parent: 0.into(),
         height: 1,
-        state_root: 1,
-        extrinsics_root: 1,
+        state_root: 1.into(),
+        extrinsics_root: 1.into(),
+        receipts_root: 1.into(),
         consensus_digest: PowOrPoaDigest::Poa(ConsensusAuthority::Alice),
     };
 
@@ -222,10 +256,11 @@ fn cs5_alternating_pow_poa_validate_poa() {
 
     let parent_digest = PowOrPoaDigest::Poa(ConsensusAuthority::Alice);
     let header = Header {
-        parent: 0,
+        parent: 0.into(),
         height: 1,
-        state_root: 1,
-        extrinsics_root: 1,
+        state_root: 1.into(),
+        extrinsics_root: 1.into(),
+        receipts_root: 1.into(),
         consensus_digest: PowOrPoaDigest::Pow(12),
     };
 
@@ -244,10 +279,11 @@ fn cs5_alternating_pow_poa_seal_pow() {
 
     let parent_digest = PowOrPoaDigest::Poa(ConsensusAuthority::Alice);
     let partial_header = Header::<PowOrPoaDigest> {
-        parent: 0,
+        parent: 0.into(),
         height: 1,
-        state_root: 1,
-        extrinsics_root: 1,
+        state_root: 1.into(),
+        extrinsics_root: 1.into(),
+        receipts_root: 1.into(),
         consensus_digest: PowOrPoaDigest::Pow(0),
     };
 
@@ -268,10 +304,11 @@ fn cs5_alternating_pow_poa_seal_poa() {
 
     let parent_digest = PowOrPoaDigest::Pow(42);
     let partial_header = Header::<PowOrPoaDigest> {
-        parent: 0,
+        parent: 0.into(),
         height: 1,
-        state_root: 1,
-        extrinsics_root: 1,
+        state_root: 1.into(),
+        extrinsics_root: 1.into(),
+        receipts_root: 1.into(),
         consensus_digest: PowOrPoaDigest::Pow(0),
     };
 
@@ -281,3 +318,48 @@ fn cs5_alternating_pow_poa_seal_poa() {
         PowOrPoaDigest::Poa(_)
     ));
 }
+
+#[test]
+fn cs5_pow_or_poa_digest_encoding() {
+    assert_eq!(
+        PowOrPoaDigest::Pow(1).encode(),
+        vec![0, 1, 0, 0, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(
+        PowOrPoaDigest::Poa(ConsensusAuthority::Bob).encode(),
+        vec![1, 1]
+    );
+    for digest in [
+        PowOrPoaDigest::Pow(42),
+        PowOrPoaDigest::Poa(ConsensusAuthority::Charlie),
+    ] {
+        assert_eq!(PowOrPoaDigest::decode_all(&digest.encode()), Ok(digest));
+    }
+    assert_eq!(
+        PowOrPoaDigest::decode_all(&[2]),
+        Err(DecodeError::InvalidTag(2))
+    );
+}
+
+#[test]
+fn cs5_header_hash_is_over_its_encoding() {
+    let header = Header {
+        parent: 0.into(),
+        height: 1,
+        state_root: 1.into(),
+        extrinsics_root: 1.into(),
+        receipts_root: 1.into(),
+        consensus_digest: PowOrPoaDigest::Poa(ConsensusAuthority::Alice),
+    };
+    let bytes = header.encode();
+
+    assert_eq!(Header::decode_all(&bytes), Ok(header.clone()));
+    assert_eq!(header.hash(), Sha256::hash_bytes(&bytes));
+
+    // Every field is committed to, the digest included.
+    let resealed = Header {
+        consensus_digest: PowOrPoaDigest::Poa(ConsensusAuthority::Bob),
+        ..header.clone()
+    };
+    assert_ne!(resealed.hash(), header.hash());
+}
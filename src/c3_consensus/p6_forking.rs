@@ -11,9 +11,11 @@ use std::marker::PhantomData;
 use super::{
     Consensus, ConsensusAuthority, EvenOnly, Header, PoaRoundRobinByHeight, Pow, PowOrPoaDigest,
 };
+use crate::{Decode, Encode};
 
 /// A Higher-order consensus engine that represents a change from one set of consensus rules (Before) to
 /// another set (After) at a specific block height
+#[derive(Clone, Hash)]
 pub struct Forked<D, Before, After> {
     /// The first block height at which the new consensus rules apply
     pub fork_height: u64,
@@ -24,7 +26,7 @@ pub struct Forked<D, Before, After> {
 
 impl<D, B, A> Consensus for Forked<D, B, A>
 where
-    D: Clone + core::fmt::Debug + Eq + PartialEq + std::hash::Hash,
+    D: Clone + core::fmt::Debug + Eq + PartialEq + std::hash::Hash + Encode + Decode,
     B::Digest: TryFrom<D>, // Use TryFrom here for PoW digest (u64)
     A::Digest: TryFrom<D>, // Use TryFrom here for PoA digest (ConsensusAuthority)
     D: From<B::Digest> + From<A::Digest> + Default, // Handle From in the other direction
@@ -46,8 +48,9 @@ where
                         &parent_pow_digest,
                         &Header {
                             height: header.height,
-                            state_root: header.state_root.clone(),
-                            extrinsics_root: header.extrinsics_root.clone(),
+                            state_root: header.state_root,
+                            extrinsics_root: header.extrinsics_root,
+                            receipts_root: header.receipts_root,
                             parent: header.parent,
                             consensus_digest: header_pow_digest,
                         },
@@ -56,17 +59,19 @@ where
             }
             false
         } else {
-            if let Ok(parent_poa_digest) = A::Digest::try_from(parent_digest.clone()) {
+            if let Some(parent_poa_digest) = self.after_parent_digest(header.height, parent_digest)
+            {
                 if let Ok(header_pow_digest) =
                     A::Digest::try_from((header.consensus_digest).clone())
                 {
-                    A::validate(
+                    return A::validate(
                         &self.after,
                         &parent_poa_digest,
                         &Header {
                             height: header.height,
-                            state_root: header.state_root.clone(),
-                            extrinsics_root: header.extrinsics_root.clone(),
+                            state_root: header.state_root,
+                            extrinsics_root: header.extrinsics_root,
+                            receipts_root: header.receipts_root,
                             parent: header.parent,
                             consensus_digest: header_pow_digest,
                         },
@@ -94,6 +99,7 @@ where
                             height: partial_header.height,
                             state_root: partial_header.state_root,
                             extrinsics_root: partial_header.extrinsics_root,
+                            receipts_root: partial_header.receipts_root,
                             consensus_digest: <B as Consensus>::Digest::default(),
                         },
                     )
@@ -101,6 +107,7 @@ where
                         height: header.height,
                         state_root: header.state_root,
                         extrinsics_root: header.extrinsics_root,
+                        receipts_root: header.receipts_root,
                         parent: header.parent,
                         consensus_digest: header.consensus_digest.into(),
                     });
@@ -108,7 +115,8 @@ where
             None
         } else {
             // Convert parent digest to PoA digest
-            if let Ok(poa_digest) = A::Digest::try_from((*parent_digest).clone()) {
+            if let Some(poa_digest) = self.after_parent_digest(partial_header.height, parent_digest)
+            {
                 return self
                     .after
                     .seal(
@@ -118,6 +126,7 @@ where
                             height: partial_header.height,
                             state_root: partial_header.state_root,
                             extrinsics_root: partial_header.extrinsics_root,
+                            receipts_root: partial_header.receipts_root,
                             consensus_digest: <A as Consensus>::Digest::default(),
                         },
                     )
@@ -125,6 +134,7 @@ where
                         height: header.height,
                         state_root: header.state_root,
                         extrinsics_root: header.extrinsics_root,
+                        receipts_root: header.receipts_root,
                         parent: header.parent,
                         consensus_digest: header.consensus_digest.into(),
                     });
@@ -134,6 +144,23 @@ where
     }
 }
 
+impl<D, B, A> Forked<D, B, A>
+where
+    D: Clone,
+    A: Consensus,
+    A::Digest: TryFrom<D>,
+{
+    /// The parent digest to hand to the new consensus rules for a block at the given height.
+    /// The first block after the fork builds on a parent sealed under the old rules, whose
+    /// digest the new engine cannot read, so it starts from the new engine's default instead.
+    fn after_parent_digest(&self, height: u64, parent_digest: &D) -> Option<A::Digest> {
+        if height == self.fork_height {
+            return Some(A::Digest::default());
+        }
+        A::Digest::try_from(parent_digest.clone()).ok()
+    }
+}
+
 /// Create a PoA consensus engine that changes authorities part way through the chain's history.
 /// Given the initial authorities, the authorities after the fork, and the height at which the fork occurs.
 fn change_authorities(
@@ -196,11 +223,11 @@ fn even_after_given_height<Original: Consensus + Clone>(
 /// the fundamentals are the same.
 ///
 /// For this task, you may use the PowOrPoaDigest type from the previous module if you like.
-fn pow_to_poa(
+pub fn pow_to_poa(
     fork_height: u64,
     difficulty: u64,
     authorities: Vec<ConsensusAuthority>,
-) -> impl Consensus<Digest = PowOrPoaDigest> {
+) -> Forked<PowOrPoaDigest, Pow, PoaRoundRobinByHeight> {
     return Forked::<PowOrPoaDigest, Pow, PoaRoundRobinByHeight> {
         fork_height,
         before: Pow {
@@ -210,3 +237,80 @@ fn pow_to_poa(
         phdata: PhantomData,
     };
 }
+
+// --- TESTS ---
+
+fn genesis() -> Header<PowOrPoaDigest> {
+    Header {
+        parent: 0.into(),
+        height: 0,
+        state_root: 0.into(),
+        extrinsics_root: 0.into(),
+        receipts_root: 0.into(),
+        consensus_digest: PowOrPoaDigest::default(),
+    }
+}
+
+/// Seal a child of the given header under the given engine.
+fn seal_child<C: Consensus>(engine: &C, parent: &Header<C::Digest>) -> Header<C::Digest> {
+    let partial = Header {
+        parent: parent.hash(),
+        height: parent.height + 1,
+        state_root: 0.into(),
+        extrinsics_root: 0.into(),
+        receipts_root: 0.into(),
+        consensus_digest: C::Digest::default(),
+    };
+    engine.seal(&parent.consensus_digest, partial).unwrap()
+}
+
+#[test]
+fn cs6_pow_to_poa_seals_and_validates_across_the_fork() {
+    let engine = pow_to_poa(
+        3,
+        u64::MAX / 2,
+        vec![ConsensusAuthority::Alice, ConsensusAuthority::Bob],
+    );
+    let mut parent = genesis();
+    for height in 1..=5 {
+        let header = seal_child(&engine, &parent);
+        assert!(engine.validate(&parent.consensus_digest, &header));
+        match header.consensus_digest {
+            PowOrPoaDigest::Pow(_) => assert!(height < 3),
+            PowOrPoaDigest::Poa(_) => assert!(height >= 3),
+        }
+        parent = header;
+    }
+}
+
+#[test]
+fn cs6_pow_to_poa_rejects_the_wrong_seal_after_the_fork() {
+    let engine = pow_to_poa(2, u64::MAX / 2, vec![ConsensusAuthority::Alice]);
+    let genesis = genesis();
+    let first = seal_child(&engine, &genesis);
+
+    let mut second = seal_child(&engine, &first);
+    assert_eq!(
+        second.consensus_digest,
+        PowOrPoaDigest::Poa(ConsensusAuthority::Alice)
+    );
+    second.consensus_digest = PowOrPoaDigest::Poa(ConsensusAuthority::Bob);
+    assert!(!engine.validate(&first.consensus_digest, &second));
+    second.consensus_digest = PowOrPoaDigest::Pow(0);
+    assert!(!engine.validate(&first.consensus_digest, &second));
+}
+
+#[test]
+fn cs6_pow_to_poa_verifies_a_sub_chain_across_the_fork() {
+    let engine = pow_to_poa(2, u64::MAX / 2, vec![ConsensusAuthority::Alice]);
+    let genesis = genesis();
+    let mut chain = vec![seal_child(&engine, &genesis)];
+    for _ in 0..3 {
+        chain.push(seal_child(&engine, chain.last().unwrap()));
+    }
+    // Headers after the fork only check out against the digest of their own parent.
+    assert!(engine.verify_sub_chain(&genesis.consensus_digest, &chain));
+
+    chain[3].consensus_digest = PowOrPoaDigest::Pow(0);
+    assert!(!engine.verify_sub_chain(&genesis.consensus_digest, &chain));
+}
@@ -12,24 +12,44 @@
 
 // TODO Exercise for later: Client does a hard fork at a particular block height. The fork logic is to change runtimes.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::Sender;
 
 use crate::{
     c1_state_machine::{AccountedCurrency, BalancesB, StateMachine},
     c3_consensus::{Consensus, Header},
-    hash,
+    Encode, StateProof, TrieDb, TrieState, EMPTY_ROOT,
 };
-use p1_data_structure::Block;
-use p3_fork_choice::ForkChoice;
+use p1_data_structure::Receipt;
+use p7_reorg::TreeRoute;
 
+// Re-export what a node needs to assemble a client out of these parts.
+pub(crate) use p10_rpc::RpcServer;
+pub(crate) use p11_network::Network;
+pub(crate) use p1_data_structure::Block;
+pub(crate) use p2_importing_blocks::ImportBlock;
+pub(crate) use p3_fork_choice::{ForkChoice, HeaviestChain, LongestChain};
+pub(crate) use p4_transaction_pool::{PriorityPool, SimplePool, TransactionPool};
+pub(crate) use p9_file_storage::FileStorage;
+
+mod p10_rpc;
+mod p11_network;
+mod p12_sync;
+mod p13_light_client;
+mod p14_warp_sync;
 mod p1_data_structure;
 mod p2_importing_blocks;
 mod p3_fork_choice;
 mod p4_transaction_pool;
 mod p5_authoring_blocks;
 mod p6_finality;
+mod p7_reorg;
+mod p8_canonical_chain;
+mod p9_file_storage;
+#[cfg(test)]
+pub(crate) mod test_helpers;
 
-type Hash = u64;
+type Hash = crate::H256;
 
 /// A client represents one view of an evolving blockchain network. It knows of blocks,
 /// forks, state, and it also pools transactions waiting to be included in upcoming blocks.
@@ -51,8 +71,8 @@ type Hash = u64;
 /// P: TransactionPool<SM>
 ///
 /// But we leave them unconstrained here to avoid repeating many where clauses throughout the section.
-/// Instead we bind them on impl blocks.
-pub struct FullClient<C, SM, FC, P, S> {
+/// Instead we bind them on impl blocks. The one exception is SM, whose account type the author field needs.
+pub struct FullClient<C, SM: StateMachine, FC, P, S> {
     /// The consensus engine used by this client.
     consensus_engine: C,
     /// The state machine used by this client.
@@ -64,33 +84,132 @@ pub struct FullClient<C, SM, FC, P, S> {
     // TODO: You are free to add more fields here, and you will probably need to.
     // Please document them as you add them.
     storage: S,
+    /// The account credited with the fees of the blocks this client authors, if any.
+    author: Option<SM::AccountId>,
+    /// The most weight this client puts into a single block it authors.
+    block_weight_limit: u64,
+    /// The components that asked to hear about every change of the best chain.
+    chain_subscribers: Vec<Sender<TreeRoute>>,
+    /// The components that asked to hear about every block that enters the block tree.
+    import_subscribers: Vec<Sender<Hash>>,
 }
 
+/// The block weight limit used unless configured otherwise. With the default weight of 1
+/// per transition, this allows 10 transitions per block.
+pub const DEFAULT_BLOCK_WEIGHT_LIMIT: u64 = 10;
+
+/// The key and value types of the state machine's state entries, as kept in the state trie.
+type StateKey<SM> = <<SM as StateMachine>::State as TrieState>::Key;
+type StateValue<SM> = <<SM as StateMachine>::State as TrieState>::Value;
+
 // Key-value blocks storage where keys are hashes of blocks and values are the corresponding blocks.
 pub trait Storage<C: Consensus, SM: StateMachine>
 where
     Block<C, SM>: std::hash::Hash,
+    SM::State: TrieState,
 {
-    fn new() -> Self;
+    /// Create a storage holding nothing but the genesis block for the given genesis state.
+    fn with_genesis(genesis_state: SM::State) -> Self;
+
+    /// Create a storage whose block tree starts at the given block instead of genesis, such as
+    /// the block of a state snapshot. The block and its post-state are taken as given. The block
+    /// is the finalized block and the last block, and nothing below it is known. Its receipts,
+    /// if they are known, are set like those of any other block.
+    fn from_snapshot(block: Block<C, SM>, state: SM::State) -> Self;
+
+    /// Create a storage starting from the machine's default state.
+    fn new() -> Self
+    where
+        Self: Sized,
+        SM::State: Default,
+    {
+        Self::with_genesis(SM::State::default())
+    }
 
+    /// Add a block to the block tree. It becomes a new leaf, and its parent stops being one.
+    /// Adding a block that is already known does nothing.
     fn add_block(&mut self, block: Block<C, SM>);
     fn get_block(&self, block_hash: Hash) -> Option<Block<C, SM>>;
 
+    /// The hashes of the known children of a block, in the order they were added.
+    fn children(&self, block_hash: Hash) -> Vec<Hash>;
+
+    /// The hashes of all blocks that have no known children, in no particular order.
+    fn leaves(&self) -> Vec<Hash>;
+
+    /// Remove blocks from the block tree along with their receipts, and drop the states that
+    /// no remaining block has. Parents left without children become leaves again.
+    fn remove_blocks(&mut self, block_hashes: &[Hash]);
+
+    /// The most recently finalized block. Initially this is the genesis block.
+    fn finalized_block(&self) -> Hash;
+    fn set_finalized_block(&mut self, block_hash: Hash);
+
     fn get_last_block(&self) -> Block<C, SM>;
+    /// Make the given block the last one, and the chain leading to it the canonical chain.
     fn set_last_block(&mut self, block: Block<C, SM>);
 
+    /// The hash of the block at the given height on the canonical chain, which is the chain
+    /// from genesis to the last block. Returns None above the last block, and below the oldest
+    /// block of a storage that started from a snapshot.
+    fn canonical_hash(&self, height: u64) -> Option<Hash>;
+
+    /// The height of the oldest block. It is zero unless the storage started from a snapshot.
+    fn base_height(&self) -> u64;
+
     fn current_state(&self) -> SM::State;
     fn set_current_state(&mut self, state: SM::State);
 
     fn get_state(&self, state_root: Hash) -> Option<SM::State>;
     fn set_state(&mut self, state: SM::State);
+
+    /// Prove the value of a single state entry in the state with the given root. The proof
+    /// can be checked against a header's state root without having the state.
+    fn state_proof(
+        &self,
+        state_root: Hash,
+        key: &StateKey<SM>,
+    ) -> Option<StateProof<StateKey<SM>, StateValue<SM>>>;
+
+    /// Receipts are kept per block, keyed by the block hash.
+    fn get_receipts(&self, block_hash: Hash) -> Option<Vec<Receipt<SM::Event>>>;
+    fn set_receipts(&mut self, block_hash: Hash, receipts: Vec<Receipt<SM::Event>>);
 }
 
-pub struct BasicStorage<C: Consensus, SM: StateMachine> {
+pub struct BasicStorage<C: Consensus, SM: StateMachine>
+where
+    SM::State: TrieState,
+{
     last_block: Block<C, SM>,
+    /// The hashes of the blocks on the chain ending in the last block, indexed by height
+    /// above the oldest block.
+    canonical: Vec<Hash>,
+    /// The height of the oldest block. It is zero unless the storage started from a snapshot.
+    base_height: u64,
     current_state: SM::State,
     blocks_map: HashMap<Hash, Block<C, SM>>,
-    states_map: HashMap<Hash, SM::State>,
+    /// The children of every block that has any, keyed by the parent's hash.
+    children: HashMap<Hash, Vec<Hash>>,
+    /// The tips of all the forks in the block tree.
+    leaves: HashSet<Hash>,
+    /// The block that will never be reverted. Only its descendants may still be imported.
+    finalized: Hash,
+    /// The states of all blocks, stored in a single trie database keyed by state root.
+    /// States share the nodes of all entries that did not change between them.
+    state_trie: TrieDb<StateKey<SM>, StateValue<SM>>,
+    /// The roots of the complete states in the trie. Other nodes are only parts of a state.
+    state_roots: HashSet<Hash>,
+    receipts_map: HashMap<Hash, Vec<Receipt<SM::Event>>>,
+}
+
+impl<C: Consensus, SM: StateMachine> BasicStorage<C, SM>
+where
+    SM::State: TrieState,
+{
+    /// Whether a complete state with the given root is stored.
+    fn has_state(&self, state_root: Hash) -> bool {
+        self.state_roots.contains(&state_root)
+    }
 }
 
 impl<C, SM> Storage<C, SM> for BasicStorage<C, SM>
@@ -98,42 +217,140 @@ where
     C: Consensus,
     SM: StateMachine,
     Block<C, SM>: std::hash::Hash + Clone,
-    SM::State: Default + Clone + std::hash::Hash,
-    SM::Transition: std::hash::Hash + Clone,
+    SM::State: Clone + TrieState,
+    SM::Transition: Encode + Clone,
+    SM::Event: Encode + Clone,
     C::Digest: Default,
 {
-    fn new() -> Self {
-        let genesis_state = SM::State::default();
-        let genesis_block = Block::genesis(&genesis_state.clone());
+    fn with_genesis(genesis_state: SM::State) -> Self {
+        let genesis_block = Block::genesis(&genesis_state);
+        let genesis_hash = genesis_block.hash();
+        let mut storage = Self::from_snapshot(genesis_block, genesis_state);
+        // Genesis has no extrinsics, so its receipts are known without executing anything.
+        storage.receipts_map.insert(genesis_hash, Vec::new());
+        storage
+    }
 
+    fn from_snapshot(block: Block<C, SM>, state: SM::State) -> Self {
         let mut blocks_map = HashMap::new();
-        blocks_map.insert(hash(&genesis_block), genesis_block.clone());
+        blocks_map.insert(block.hash(), block.clone());
 
-        let mut states_map = HashMap::new();
-        states_map.insert(genesis_block.header.state_root, genesis_state.clone());
+        let mut state_trie = TrieDb::new();
+        let state_root = state_trie.insert_all(EMPTY_ROOT, state.to_entries());
 
         return BasicStorage {
-            last_block: genesis_block,
-            current_state: SM::State::default(),
+            children: HashMap::new(),
+            leaves: HashSet::from([block.hash()]),
+            finalized: block.hash(),
+            canonical: vec![block.hash()],
+            base_height: block.header.height,
+            last_block: block,
+            current_state: state,
             blocks_map: blocks_map,
-            states_map: states_map,
+            state_trie,
+            state_roots: HashSet::from([state_root]),
+            receipts_map: HashMap::new(),
         };
     }
 
     fn add_block(&mut self, block: Block<C, SM>) {
-        self.blocks_map.insert(hash(&block), block);
+        let block_hash = block.hash();
+        if self.blocks_map.contains_key(&block_hash) {
+            return;
+        }
+        self.children
+            .entry(block.header.parent)
+            .or_default()
+            .push(block_hash);
+        self.leaves.remove(&block.header.parent);
+        self.leaves.insert(block_hash);
+        self.blocks_map.insert(block_hash, block);
     }
 
     fn get_block(&self, block_hash: Hash) -> Option<Block<C, SM>> {
         self.blocks_map.get(&block_hash).cloned()
     }
 
+    fn children(&self, block_hash: Hash) -> Vec<Hash> {
+        self.children.get(&block_hash).cloned().unwrap_or_default()
+    }
+
+    fn leaves(&self) -> Vec<Hash> {
+        self.leaves.iter().copied().collect()
+    }
+
+    fn remove_blocks(&mut self, block_hashes: &[Hash]) {
+        for block_hash in block_hashes {
+            let Some(block) = self.blocks_map.remove(block_hash) else {
+                continue;
+            };
+            self.receipts_map.remove(block_hash);
+            self.leaves.remove(block_hash);
+            self.children.remove(block_hash);
+
+            let parent = block.header.parent;
+            if let Some(siblings) = self.children.get_mut(&parent) {
+                siblings.retain(|child| child != block_hash);
+                if siblings.is_empty() {
+                    self.children.remove(&parent);
+                    if self.blocks_map.contains_key(&parent) {
+                        self.leaves.insert(parent);
+                    }
+                }
+            }
+        }
+
+        let live_roots: HashSet<Hash> = self
+            .blocks_map
+            .values()
+            .map(|b| b.header.state_root)
+            .collect();
+        self.state_roots.retain(|root| live_roots.contains(root));
+        self.state_trie.retain_roots(&self.state_roots);
+    }
+
+    fn finalized_block(&self) -> Hash {
+        self.finalized
+    }
+
+    fn set_finalized_block(&mut self, block_hash: Hash) {
+        self.finalized = block_hash;
+    }
+
     fn get_last_block(&self) -> Block<C, SM> {
         self.last_block.clone()
     }
 
     fn set_last_block(&mut self, block: Block<C, SM>) {
-        self.last_block = block.clone();
+        // Rewrite the index from the new last block down until it meets the old chain.
+        let mut index = (block.header.height - self.base_height) as usize;
+        self.canonical.resize(index + 1, Hash::default());
+        let mut next = Some((block.hash(), block.header.parent));
+        while let Some((current, parent)) = next {
+            if self.canonical[index] == current {
+                break;
+            }
+            self.canonical[index] = current;
+            if index == 0 {
+                break;
+            }
+            index -= 1;
+            next = self
+                .blocks_map
+                .get(&parent)
+                .map(|b| (parent, b.header.parent));
+        }
+
+        self.last_block = block;
+    }
+
+    fn canonical_hash(&self, height: u64) -> Option<Hash> {
+        let index = height.checked_sub(self.base_height)?;
+        self.canonical.get(index as usize).copied()
+    }
+
+    fn base_height(&self) -> u64 {
+        self.base_height
     }
 
     fn current_state(&self) -> <SM as StateMachine>::State {
@@ -145,12 +362,34 @@ where
     }
 
     fn get_state(&self, state_root: Hash) -> Option<<SM as StateMachine>::State> {
-        self.states_map.get(&state_root).cloned()
+        if !self.state_roots.contains(&state_root) {
+            return None;
+        }
+        let entries = self.state_trie.entries(&state_root)?;
+        Some(SM::State::from_entries(entries))
     }
 
     fn set_state(&mut self, state: <SM as StateMachine>::State) {
-        self.states_map.insert(hash(&state), state.clone());
+        let state_root = self.state_trie.insert_all(EMPTY_ROOT, state.to_entries());
+        self.state_roots.insert(state_root);
     }
-}
 
-//TODO Consider exploring LightClient as well. It may import headers but not blocks for example.
+    fn state_proof(
+        &self,
+        state_root: Hash,
+        key: &StateKey<SM>,
+    ) -> Option<StateProof<StateKey<SM>, StateValue<SM>>> {
+        if !self.state_roots.contains(&state_root) {
+            return None;
+        }
+        self.state_trie.prove(&state_root, key)
+    }
+
+    fn get_receipts(&self, block_hash: Hash) -> Option<Vec<Receipt<SM::Event>>> {
+        self.receipts_map.get(&block_hash).cloned()
+    }
+
+    fn set_receipts(&mut self, block_hash: Hash, receipts: Vec<Receipt<SM::Event>>) {
+        self.receipts_map.insert(block_hash, receipts);
+    }
+}
@@ -0,0 +1,623 @@
+//! So far the only way to use a client is to link it into the same program. Wallets, block
+//! explorers and other apps usually live in other processes, so nodes expose their client over
+//! a remote procedure call (RPC) interface. Here we add a JSON-RPC 2.0 server.
+//!
+//! The server listens on a TCP socket, normally on localhost. Each request is a single line of
+//! JSON, and each response is written back as a single line on the same connection. A
+//! connection may send as many requests as it likes, and many connections may be open at once.
+//! They all share one client behind a mutex. Batches are not supported.
+//!
+//! Parameters are given by position. Hashes are `0x` prefixed hex strings, and transactions,
+//! digests, accounts and state entries travel as the hex of their encoding from the codec, so
+//! the server does not need to know anything about the state machine behind it. The methods are:
+//! * `submit_transaction(transaction)`: whether the transaction made it into the pool.
+//! * `pool_size()`: the number of transactions waiting in the pool.
+//! * `best_block()`: the header of the best block, along with its hash.
+//! * `get_block(hash)`: the header, author and body of a block, or null if it is not known.
+//! * `get_state(hash)`: the post-state of a block as a list of key and value pairs, or null.
+//! * `finalize(hash)`: whether the block was finalized.
+//! * `author_block()`: author a block from the pool on top of the best block, and return its
+//!   hash. Returns null if the pool had nothing to put in a block.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use super::p1_data_structure::Block;
+use super::p2_importing_blocks::ImportBlock;
+use super::p3_fork_choice::ForkChoice;
+use super::p4_transaction_pool::TransactionPool;
+#[cfg(test)]
+use super::test_helpers::{init_client_for_test, mint, TestClient};
+use super::{Consensus, FullClient, Hash, Header, StateMachine, Storage};
+use super::{StateKey, StateValue};
+#[cfg(test)]
+use crate::c1_state_machine::{AccountedCurrency, User};
+use crate::json::Json;
+use crate::{Decode, Encode, TrieState, H256};
+
+/// An error returned to the caller in place of a result.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    /// The request was not valid JSON.
+    pub const PARSE_ERROR: i64 = -32700;
+    /// The request was JSON, but not a JSON-RPC request.
+    pub const INVALID_REQUEST: i64 = -32600;
+    /// There is no method with the requested name.
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    /// The method exists, but the parameters did not fit it.
+    pub const INVALID_PARAMS: i64 = -32602;
+    /// The method was called correctly, but could not do what was asked.
+    pub const SERVER_ERROR: i64 = -32000;
+
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Something that can answer RPC calls.
+pub trait RpcHandler {
+    /// Call the method with the given name and positional parameters.
+    fn handle(&mut self, method: &str, params: &[Json]) -> Result<Json, RpcError>;
+}
+
+impl<C, SM, FC, P, S> RpcHandler for FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    FC: ForkChoice<C, SM> + Default,
+    P: TransactionPool<SM>,
+    S: Storage<C, SM>,
+    Block<C, SM>: std::hash::Hash + Clone,
+    SM::State: TrieState + Clone,
+    SM::Transition: Clone + Encode + Decode,
+    SM::Event: Encode,
+    SM::AccountId: Clone + Encode,
+    StateKey<SM>: Encode,
+    StateValue<SM>: Encode,
+{
+    fn handle(&mut self, method: &str, params: &[Json]) -> Result<Json, RpcError> {
+        match method {
+            "submit_transaction" => {
+                let transaction = decode_param(params, 0)?;
+                Ok(self.submit_transaction(transaction).into())
+            }
+            "pool_size" => Ok((self.pool_size() as u64).into()),
+            "best_block" => Ok(header_json(&self.get_last_block().header)),
+            "get_block" => {
+                let block = self.get_block(hash_param(params, 0)?);
+                Ok(block.map(|b| block_json(&b)).into())
+            }
+            "get_state" => {
+                let state = self.get_state(hash_param(params, 0)?);
+                Ok(state.map(|s| state_json::<SM>(&s)).into())
+            }
+            "finalize" => Ok(self.manually_finalize_block(hash_param(params, 0)?).into()),
+            "author_block" => {
+                let previous = self.get_last_block().hash();
+                self.author_and_import_automatic_block().map_err(|()| {
+                    RpcError::new(RpcError::SERVER_ERROR, "could not author a block")
+                })?;
+                let best = self.get_last_block().hash();
+                Ok((best != previous).then(|| best.to_string()).into())
+            }
+            _ => Err(RpcError::new(
+                RpcError::METHOD_NOT_FOUND,
+                format!("no method named {}", method),
+            )),
+        }
+    }
+}
+
+/// Write bytes as a `0x` prefixed hex string.
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 + bytes.len() * 2);
+    hex.push_str("0x");
+    for byte in bytes {
+        hex.push_str(&format!("{:02x}", byte));
+    }
+    hex
+}
+
+/// Read a `0x` prefixed hex string back into bytes.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    let digits = hex.strip_prefix("0x")?;
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn param_bytes(params: &[Json], index: usize) -> Result<Vec<u8>, RpcError> {
+    params
+        .get(index)
+        .and_then(Json::as_str)
+        .and_then(from_hex)
+        .ok_or_else(|| {
+            RpcError::new(
+                RpcError::INVALID_PARAMS,
+                format!("parameter {} must be a hex string", index),
+            )
+        })
+}
+
+fn decode_param<T: Decode>(params: &[Json], index: usize) -> Result<T, RpcError> {
+    T::decode_all(&param_bytes(params, index)?).map_err(|e| {
+        RpcError::new(
+            RpcError::INVALID_PARAMS,
+            format!("parameter {} could not be decoded: {:?}", index, e),
+        )
+    })
+}
+
+fn hash_param(params: &[Json], index: usize) -> Result<Hash, RpcError> {
+    let bytes = param_bytes(params, index)?;
+    let bytes = bytes.try_into().map_err(|_| {
+        RpcError::new(
+            RpcError::INVALID_PARAMS,
+            format!("parameter {} must be a 32 byte hash", index),
+        )
+    })?;
+    Ok(H256(bytes))
+}
+
+fn header_json<Digest: Encode>(header: &Header<Digest>) -> Json {
+    Json::Object(vec![
+        ("hash".into(), header.hash().to_string().into()),
+        ("parent".into(), header.parent.to_string().into()),
+        ("height".into(), header.height.into()),
+        ("state_root".into(), header.state_root.to_string().into()),
+        (
+            "extrinsics_root".into(),
+            header.extrinsics_root.to_string().into(),
+        ),
+        (
+            "receipts_root".into(),
+            header.receipts_root.to_string().into(),
+        ),
+        (
+            "consensus_digest".into(),
+            to_hex(&header.consensus_digest.encode()).into(),
+        ),
+    ])
+}
+
+fn block_json<C, SM>(block: &Block<C, SM>) -> Json
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::AccountId: Encode,
+    SM::Transition: Encode,
+{
+    let body = block
+        .body
+        .iter()
+        .map(|t| to_hex(&t.encode()).into())
+        .collect();
+    Json::Object(vec![
+        ("header".into(), header_json(&block.header)),
+        (
+            "author".into(),
+            block.author.as_ref().map(|a| to_hex(&a.encode())).into(),
+        ),
+        ("body".into(), Json::Array(body)),
+    ])
+}
+
+fn state_json<SM>(state: &SM::State) -> Json
+where
+    SM: StateMachine,
+    SM::State: TrieState,
+    StateKey<SM>: Encode,
+    StateValue<SM>: Encode,
+{
+    let entries = state
+        .to_entries()
+        .iter()
+        .map(|(key, value)| {
+            Json::Array(vec![
+                to_hex(&key.encode()).into(),
+                to_hex(&value.encode()).into(),
+            ])
+        })
+        .collect();
+    Json::Array(entries)
+}
+
+/// Answer a single line of input. Returns None for notifications, which are requests without
+/// an id that the caller does not want an answer to.
+fn respond<H: RpcHandler>(handler: &Mutex<H>, line: &str) -> Option<Json> {
+    let request = match Json::parse(line) {
+        Ok(request) => request,
+        Err(e) => {
+            let error = RpcError::new(RpcError::PARSE_ERROR, e.reason);
+            return Some(response(Json::Null, Err(error)));
+        }
+    };
+    let id = request.get("id").cloned();
+
+    let result = call(handler, &request);
+    id.map(|id| response(id, result))
+}
+
+fn call<H: RpcHandler>(handler: &Mutex<H>, request: &Json) -> Result<Json, RpcError> {
+    let invalid = |message| Err(RpcError::new(RpcError::INVALID_REQUEST, message));
+    if request.get("jsonrpc").and_then(Json::as_str) != Some("2.0") {
+        return invalid("jsonrpc must be \"2.0\"");
+    }
+    let Some(method) = request.get("method").and_then(Json::as_str) else {
+        return invalid("method must be a string");
+    };
+    let params = match request.get("params") {
+        None => &[][..],
+        Some(Json::Array(params)) => params,
+        Some(_) => return invalid("params must be an array"),
+    };
+
+    // A handler that panicked part way through leaves the mutex poisoned. The client itself
+    // is still there, so keep serving it rather than failing every request from then on.
+    let mut handler = handler.lock().unwrap_or_else(|e| e.into_inner());
+    handler.handle(method, params)
+}
+
+fn response(id: Json, result: Result<Json, RpcError>) -> Json {
+    let outcome = match result {
+        Ok(result) => ("result".into(), result),
+        Err(error) => (
+            "error".into(),
+            Json::Object(vec![
+                ("code".into(), Json::Number(error.code as i128)),
+                ("message".into(), error.message.into()),
+            ]),
+        ),
+    };
+    Json::Object(vec![
+        ("jsonrpc".into(), "2.0".into()),
+        outcome,
+        ("id".into(), id),
+    ])
+}
+
+/// A running RPC server. It stops when dropped.
+pub struct RpcServer {
+    address: SocketAddr,
+    stopping: Arc<AtomicBool>,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+impl RpcServer {
+    /// Start serving the given handler on the given address. Use port 0 to let the operating
+    /// system pick a free port, and `local_addr` to find out which one it picked.
+    pub fn start<H>(handler: Arc<Mutex<H>>, address: impl ToSocketAddrs) -> io::Result<Self>
+    where
+        H: RpcHandler + Send + 'static,
+    {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let stopping = Arc::new(AtomicBool::new(false));
+
+        let accept_stopping = stopping.clone();
+        let accept_thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accept_stopping.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let handler = handler.clone();
+                thread::spawn(move || serve_connection(stream, &handler));
+            }
+        });
+
+        Ok(RpcServer {
+            address,
+            stopping,
+            accept_thread: Some(accept_thread),
+        })
+    }
+
+    /// The address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        // The accept thread is blocked waiting for a connection, so give it one to wake up to.
+        let _ = TcpStream::connect(self.address);
+        if let Some(thread) = self.accept_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Answer requests on a connection until the other side hangs up.
+fn serve_connection<H: RpcHandler>(stream: TcpStream, handler: &Mutex<H>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = respond(handler, &line) {
+            if writeln!(writer, "{}", response).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// --- TESTS ---
+
+#[cfg(test)]
+fn start_server_for_test() -> (RpcServer, Arc<Mutex<TestClient>>) {
+    let client = Arc::new(Mutex::new(init_client_for_test()));
+    let server = RpcServer::start(client.clone(), "127.0.0.1:0").unwrap();
+    (server, client)
+}
+
+#[cfg(test)]
+fn connect(server: &RpcServer) -> BufReader<TcpStream> {
+    BufReader::new(TcpStream::connect(server.local_addr()).unwrap())
+}
+
+/// Send a raw line and read the line that comes back.
+#[cfg(test)]
+fn send_line(connection: &mut BufReader<TcpStream>, line: &str) -> Json {
+    writeln!(connection.get_mut(), "{}", line).unwrap();
+    let mut response = String::new();
+    connection.read_line(&mut response).unwrap();
+    Json::parse(&response).unwrap()
+}
+
+/// Call a method and return its result, failing the test if it returned an error.
+#[cfg(test)]
+fn call_ok(connection: &mut BufReader<TcpStream>, method: &str, params: Vec<Json>) -> Json {
+    let request = Json::Object(vec![
+        ("jsonrpc".into(), "2.0".into()),
+        ("id".into(), 1.into()),
+        ("method".into(), method.into()),
+        ("params".into(), Json::Array(params)),
+    ]);
+    let response = send_line(connection, &request.to_string());
+    assert_eq!(response.get("error"), None, "{}", response);
+    response.get("result").unwrap().clone()
+}
+
+#[cfg(test)]
+fn error_code(response: &Json) -> Option<u64> {
+    let code = response.get("error")?.get("code")?;
+    match code {
+        Json::Number(n) => Some(n.unsigned_abs() as u64),
+        _ => None,
+    }
+}
+
+#[test]
+fn hex_round_trips() {
+    assert_eq!(to_hex(&[0, 1, 0xab]), "0x0001ab");
+    assert_eq!(from_hex("0x0001ab"), Some(vec![0, 1, 0xab]));
+    assert_eq!(from_hex("0x"), Some(vec![]));
+    assert_eq!(from_hex("0001ab"), None);
+    assert_eq!(from_hex("0x1ab"), None);
+    assert_eq!(from_hex("0xzz"), None);
+}
+
+#[test]
+fn cl10_submit_and_author_a_block() {
+    let (server, client) = start_server_for_test();
+    let mut connection = connect(&server);
+    let transaction = to_hex(&mint(User::Alice, 0, 10).encode());
+
+    assert_eq!(
+        call_ok(
+            &mut connection,
+            "submit_transaction",
+            vec![transaction.clone().into()]
+        ),
+        Json::Bool(true)
+    );
+    assert_eq!(call_ok(&mut connection, "pool_size", vec![]), 1.into());
+
+    let authored = call_ok(&mut connection, "author_block", vec![]);
+    assert_eq!(call_ok(&mut connection, "pool_size", vec![]), 0.into());
+    // With nothing left in the pool there is nothing to author.
+    assert_eq!(call_ok(&mut connection, "author_block", vec![]), Json::Null);
+
+    let best = call_ok(&mut connection, "best_block", vec![]);
+    assert_eq!(best.get("hash"), Some(&authored));
+    assert_eq!(best.get("height"), Some(&1.into()));
+
+    let block = call_ok(&mut connection, "get_block", vec![authored.clone()]);
+    assert_eq!(block.get("header"), Some(&best));
+    assert_eq!(block.get("author"), Some(&Json::Null));
+    assert_eq!(
+        block.get("body"),
+        Some(&Json::Array(vec![transaction.into()]))
+    );
+
+    // The server works on the very client it was given.
+    let client = client.lock().unwrap();
+    assert_eq!(
+        Some(client.get_last_block().hash().to_string().as_str()),
+        authored.as_str()
+    );
+}
+
+#[test]
+fn cl10_get_state_of_a_block() {
+    let (server, client) = start_server_for_test();
+    let mut connection = connect(&server);
+    let genesis = call_ok(&mut connection, "best_block", vec![]);
+    let genesis_hash = genesis.get("hash").unwrap().clone();
+
+    let authored = author_with_mint(&mut connection, User::Bob, 0, 7);
+
+    let state = call_ok(&mut connection, "get_state", vec![authored]);
+    let expected = state_json::<AccountedCurrency>(&client.lock().unwrap().current_state());
+    assert_eq!(state, expected);
+    assert!(!state.as_array().unwrap().is_empty());
+
+    // Genesis has a state of its own, and it is a different one.
+    let genesis_state = call_ok(&mut connection, "get_state", vec![genesis_hash]);
+    assert_ne!(genesis_state, state);
+}
+
+#[test]
+fn cl10_unknown_blocks_are_null() {
+    let (server, _) = start_server_for_test();
+    let mut connection = connect(&server);
+    let unknown = Json::from(H256::from(42).to_string());
+
+    assert_eq!(
+        call_ok(&mut connection, "get_block", vec![unknown.clone()]),
+        Json::Null
+    );
+    assert_eq!(
+        call_ok(&mut connection, "get_state", vec![unknown.clone()]),
+        Json::Null
+    );
+    assert_eq!(
+        call_ok(&mut connection, "finalize", vec![unknown]),
+        Json::Bool(false)
+    );
+}
+
+/// Submit a transaction and author a block with it, returning the block's hash.
+#[cfg(test)]
+fn author_with_mint(
+    connection: &mut BufReader<TcpStream>,
+    minter: User,
+    nonce: u64,
+    amount: u64,
+) -> Json {
+    let transaction = to_hex(&mint(minter, nonce, amount).encode());
+    call_ok(connection, "submit_transaction", vec![transaction.into()]);
+    call_ok(connection, "author_block", vec![])
+}
+
+#[test]
+fn cl10_finalize() {
+    let (server, client) = start_server_for_test();
+    let mut connection = connect(&server);
+    let first = author_with_mint(&mut connection, User::Alice, 0, 10);
+    author_with_mint(&mut connection, User::Alice, 1, 10);
+
+    assert_eq!(
+        call_ok(&mut connection, "finalize", vec![first.clone()]),
+        Json::Bool(true)
+    );
+    assert_eq!(
+        Some(
+            client
+                .lock()
+                .unwrap()
+                .finalized_block()
+                .to_string()
+                .as_str()
+        ),
+        first.as_str()
+    );
+}
+
+#[test]
+fn cl10_errors() {
+    let (server, _) = start_server_for_test();
+    let mut connection = connect(&server);
+
+    let response = send_line(&mut connection, "{not json");
+    assert_eq!(error_code(&response), Some(32700));
+    assert_eq!(response.get("id"), Some(&Json::Null));
+
+    let response = send_line(
+        &mut connection,
+        r#"{"jsonrpc":"1.0","id":3,"method":"pool_size"}"#,
+    );
+    assert_eq!(error_code(&response), Some(32600));
+    assert_eq!(response.get("id"), Some(&3.into()));
+
+    let response = send_line(
+        &mut connection,
+        r#"{"jsonrpc":"2.0","id":4,"method":"mine"}"#,
+    );
+    assert_eq!(error_code(&response), Some(32601));
+
+    for params in [r#"[]"#, r#"["nothex"]"#, r#"["0x0102"]"#, r#"[1]"#] {
+        let request = format!(
+            r#"{{"jsonrpc":"2.0","id":"a","method":"get_block","params":{}}}"#,
+            params
+        );
+        let response = send_line(&mut connection, &request);
+        assert_eq!(error_code(&response), Some(32602), "{}", params);
+    }
+
+    // A transaction that does not decode.
+    let response = send_line(
+        &mut connection,
+        r#"{"jsonrpc":"2.0","id":5,"method":"submit_transaction","params":["0xff"]}"#,
+    );
+    assert_eq!(error_code(&response), Some(32602));
+
+    // The connection is still usable after all that.
+    assert_eq!(call_ok(&mut connection, "pool_size", vec![]), 0.into());
+}
+
+#[test]
+fn cl10_notifications_get_no_response() {
+    let (server, _) = start_server_for_test();
+    let mut connection = connect(&server);
+    let transaction = to_hex(&mint(User::Alice, 0, 10).encode());
+
+    writeln!(
+        connection.get_mut(),
+        r#"{{"jsonrpc":"2.0","method":"submit_transaction","params":["{}"]}}"#,
+        transaction
+    )
+    .unwrap();
+
+    // The next line back answers the next request, and sees the notification's effect.
+    assert_eq!(call_ok(&mut connection, "pool_size", vec![]), 1.into());
+}
+
+#[test]
+fn cl10_connections_share_the_client() {
+    let (server, _) = start_server_for_test();
+    let mut first = connect(&server);
+    let mut second = connect(&server);
+
+    call_ok(
+        &mut first,
+        "submit_transaction",
+        vec![to_hex(&mint(User::Alice, 0, 10).encode()).into()],
+    );
+    assert_eq!(call_ok(&mut second, "pool_size", vec![]), 1.into());
+    assert_eq!(call_ok(&mut first, "pool_size", vec![]), 1.into());
+}
+
+#[test]
+fn cl10_stops_listening_when_dropped() {
+    let (server, _) = start_server_for_test();
+    let address = server.local_addr();
+    drop(server);
+    assert!(TcpStream::connect(address).is_err());
+}
@@ -0,0 +1,890 @@
+//! A client on its own only knows the blocks it authored and the transactions it was handed.
+//! A blockchain network is many clients that keep each other up to date, so here we connect
+//! clients to each other over TCP and let them gossip.
+//!
+//! Every connection starts with a handshake in each direction: the hash of the sender's genesis
+//! block, and the hash and height of its best block. Nodes only keep talking to peers that
+//! started from the same genesis. After that, either side may send:
+//! * `Block`: a block the sender has imported. The receiver imports it too, and passes it on to
+//!   its other peers if it was new. A block whose parent is unknown is held back, and the parent
+//!   is asked for, until the chain down to a known block has arrived. The parent may also come
+//!   from elsewhere, such as the node authoring it or an RPC call, so the node watches every
+//!   block the client imports for blocks waiting on it. Blocks held back for too long, or sent
+//!   by a peer that has since gone away, are given up on.
+//! * `Transaction`: a transaction the sender has accepted into its pool. The receiver submits it
+//!   to its own pool, and passes it on if it was new there. A transaction that arrives after
+//!   the best chain has included it is stale, so the pool refuses it and the gossip ends there.
+//! * `GetBlock(hash)`: ask for a block, which comes back as a `Block` message if it is known.
+//!   A node that learns about a best block it does not have during the handshake asks for it.
+//!
+//! Each message travels as its versioned encoding from the codec, preceded by the length of that
+//! encoding as a four byte little-endian integer.
+//!
+//! Fetching blocks one parent at a time is fine for a node that is a few blocks behind. It is
+//! no way to catch up with a long chain. That is what sync is for.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::p1_data_structure::Block;
+use super::p2_importing_blocks::ImportBlock;
+use super::p3_fork_choice::ForkChoice;
+#[cfg(test)]
+use super::p3_fork_choice::LongestChain;
+#[cfg(test)]
+use super::p4_transaction_pool::SimplePool;
+use super::p4_transaction_pool::TransactionPool;
+#[cfg(test)]
+use super::test_helpers::{mint, TestClient};
+#[cfg(test)]
+use super::BasicStorage;
+use super::{Consensus, FullClient, Hash, StateMachine, Storage};
+#[cfg(test)]
+use crate::c1_state_machine::{AccountedCurrency, BalancesB, User};
+use crate::{Decode, DecodeError, Encode, TrieState};
+
+/// The most bytes a single message may take up. A peer announcing anything longer is treated
+/// as broken rather than trusted with that much memory.
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// The most blocks held back while waiting for their parents.
+const MAX_ORPHANS: usize = 1024;
+
+/// How long a block is held back while waiting for its parent before it is given up on.
+const ORPHAN_LIFETIME: Duration = Duration::from_secs(60);
+
+/// How long a new peer gets to send its handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the thread watching the client's imports checks whether the network is stopping.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a node tells a new peer about itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Handshake {
+    /// The hash of the genesis block of the sender's chain.
+    pub genesis: Hash,
+    /// The hash of the sender's best block.
+    pub best_hash: Hash,
+    /// The height of the sender's best block.
+    pub best_height: u64,
+}
+
+/// A message between two connected nodes.
+pub enum Message<C: Consensus, SM: StateMachine> {
+    /// The first message in each direction, and only the first.
+    Handshake(Handshake),
+    /// A block the sender has imported.
+    Block(Block<C, SM>),
+    /// A transaction the sender has accepted into its pool.
+    Transaction(SM::Transition),
+    /// A request for the block with the given hash.
+    GetBlock(Hash),
+}
+
+impl Encode for Handshake {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.genesis.encode_to(out);
+        self.best_hash.encode_to(out);
+        self.best_height.encode_to(out);
+    }
+}
+
+impl Decode for Handshake {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Handshake {
+            genesis: Decode::decode(input)?,
+            best_hash: Decode::decode(input)?,
+            best_height: Decode::decode(input)?,
+        })
+    }
+}
+
+impl<C, SM> Encode for Message<C, SM>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::AccountId: Encode,
+    SM::Transition: Encode,
+{
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            Message::Handshake(handshake) => {
+                out.push(0);
+                handshake.encode_to(out);
+            }
+            Message::Block(block) => {
+                out.push(1);
+                block.encode_to(out);
+            }
+            Message::Transaction(transaction) => {
+                out.push(2);
+                transaction.encode_to(out);
+            }
+            Message::GetBlock(hash) => {
+                out.push(3);
+                hash.encode_to(out);
+            }
+        }
+    }
+}
+
+impl<C, SM> Decode for Message<C, SM>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::AccountId: Decode,
+    SM::Transition: Decode,
+{
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(Message::Handshake(Decode::decode(input)?)),
+            1 => Ok(Message::Block(Decode::decode(input)?)),
+            2 => Ok(Message::Transaction(Decode::decode(input)?)),
+            3 => Ok(Message::GetBlock(Decode::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+/// Send a single message.
+fn write_message<M: Encode>(mut stream: &TcpStream, message: &M) -> io::Result<()> {
+    let bytes = message.encode_versioned();
+    stream.write_all(&(bytes.len() as u32).to_le_bytes())?;
+    stream.write_all(&bytes)
+}
+
+/// Wait for the next message.
+fn read_message<M: Decode>(mut stream: &TcpStream) -> io::Result<M> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_MESSAGE_LEN {
+        return Err(invalid_data(format!(
+            "a message of {} bytes is too long",
+            len
+        )));
+    }
+    let mut bytes = vec![0; len];
+    stream.read_exact(&mut bytes)?;
+    M::decode_versioned(&bytes)
+        .map_err(|e| invalid_data(format!("a message could not be decoded: {:?}", e)))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A connected peer that has completed its handshake.
+struct Peer {
+    id: usize,
+    /// Messages to the peer are written through the lock, so that those sent from different
+    /// threads do not interleave.
+    writer: Arc<Mutex<TcpStream>>,
+    /// Another handle on the connection, to hang up even while a write is stuck.
+    stream: TcpStream,
+}
+
+/// A block held back until its parent arrives.
+struct Orphan<C: Consensus, SM: StateMachine> {
+    block: Block<C, SM>,
+    /// The peer that sent the block.
+    from: usize,
+    received: Instant,
+}
+
+/// A client shared between the network threads and whoever else drives it.
+type SharedClient<C, SM, FC, P, S> = Arc<Mutex<FullClient<C, SM, FC, P, S>>>;
+
+/// Blocks waiting for their parent to arrive, by the hash of that parent.
+type Orphans<C, SM> = HashMap<Hash, Vec<Orphan<C, SM>>>;
+
+/// The parts of the network that every connection works with.
+struct Shared<C: Consensus, SM: StateMachine, FC, P, S> {
+    client: SharedClient<C, SM, FC, P, S>,
+    peers: Mutex<Vec<Peer>>,
+    orphans: Mutex<Orphans<C, SM>>,
+    next_peer_id: AtomicUsize,
+}
+
+/// A running network endpoint for a client. It listens for peers, can connect to more, and
+/// stops when dropped.
+pub struct Network<C: Consensus, SM: StateMachine, FC, P, S> {
+    address: SocketAddr,
+    stopping: Arc<AtomicBool>,
+    shared: Arc<Shared<C, SM, FC, P, S>>,
+    accept_thread: Option<JoinHandle<()>>,
+    import_thread: Option<JoinHandle<()>>,
+}
+
+impl<C, SM, FC, P, S> Network<C, SM, FC, P, S>
+where
+    C: Consensus + Send + 'static,
+    C::Digest: Send,
+    SM: StateMachine + Send + 'static,
+    FC: ForkChoice<C, SM> + Send + 'static,
+    P: TransactionPool<SM> + Send + 'static,
+    S: Storage<C, SM> + Send + 'static,
+    Block<C, SM>: std::hash::Hash + Clone,
+    SM::State: TrieState + Clone,
+    SM::Transition: Clone + Send + Encode + Decode,
+    SM::Event: Encode,
+    SM::AccountId: Send + Encode + Decode,
+{
+    /// Start listening for peers of the given client on the given address. Use port 0 to let
+    /// the operating system pick a free port, and `local_addr` to find out which one it picked.
+    pub fn start(
+        client: SharedClient<C, SM, FC, P, S>,
+        address: impl ToSocketAddrs,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let stopping = Arc::new(AtomicBool::new(false));
+        let imports = client.lock().unwrap().subscribe_imports();
+        let shared = Arc::new(Shared {
+            client,
+            peers: Mutex::new(Vec::new()),
+            orphans: Mutex::new(HashMap::new()),
+            next_peer_id: AtomicUsize::new(0),
+        });
+
+        let accept_stopping = stopping.clone();
+        let accept_shared = shared.clone();
+        let accept_thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accept_stopping.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let shared = accept_shared.clone();
+                thread::spawn(move || {
+                    if let Ok(id) = shared.open(&stream) {
+                        shared.serve(id, stream);
+                    }
+                });
+            }
+        });
+
+        // Blocks held back may be waiting for a block that did not arrive over the network.
+        let import_stopping = stopping.clone();
+        let import_shared = shared.clone();
+        let import_thread = thread::spawn(move || loop {
+            match imports.recv_timeout(STOP_CHECK_INTERVAL) {
+                Ok(hash) => import_shared.adopt_orphans(hash),
+                Err(RecvTimeoutError::Timeout) if !import_stopping.load(Ordering::SeqCst) => {}
+                Err(_) => break,
+            }
+        });
+
+        Ok(Network {
+            address,
+            stopping,
+            shared,
+            accept_thread: Some(accept_thread),
+            import_thread: Some(import_thread),
+        })
+    }
+
+    /// The address the network is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Connect to a peer. Returns once the handshakes have been exchanged, or with an error if
+    /// the peer could not be reached or follows another chain.
+    pub fn connect(&self, address: impl ToSocketAddrs) -> io::Result<()> {
+        let stream = TcpStream::connect(address)?;
+        let id = self.shared.open(&stream)?;
+        let shared = self.shared.clone();
+        thread::spawn(move || shared.serve(id, stream));
+        Ok(())
+    }
+
+    /// The number of peers currently connected.
+    pub fn peer_count(&self) -> usize {
+        self.shared.peers.lock().unwrap().len()
+    }
+
+    /// Submit a transaction to the client's pool, and gossip it to all peers if it was accepted.
+    pub fn submit_transaction(&self, transaction: SM::Transition) -> bool {
+        let accepted = self.shared.accept_transaction(transaction.clone());
+        if accepted {
+            self.shared
+                .broadcast(None, &Message::Transaction(transaction));
+        }
+        accepted
+    }
+
+    /// Tell all peers about the client's best block, such as one it just authored.
+    pub fn announce_best_block(&self) {
+        let best = self.shared.client.lock().unwrap().get_last_block();
+        self.shared.broadcast(None, &Message::Block(best));
+    }
+}
+
+impl<C, SM, FC, P, S> Shared<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    FC: ForkChoice<C, SM>,
+    P: TransactionPool<SM>,
+    S: Storage<C, SM>,
+    Block<C, SM>: std::hash::Hash + Clone,
+    SM::State: TrieState + Clone,
+    SM::Transition: Clone + Encode + Decode,
+    SM::Event: Encode,
+    SM::AccountId: Encode + Decode,
+{
+    fn handshake(&self) -> Handshake {
+        let client = self.client.lock().unwrap();
+        let best = client.get_last_block();
+        Handshake {
+            genesis: client
+                .canonical_block_hash(0)
+                .expect("every chain has a genesis block"),
+            best_hash: best.hash(),
+            best_height: best.header.height,
+        }
+    }
+
+    /// Exchange handshakes on a new connection, and add the other side as a peer if it
+    /// follows the same chain. Returns the id of the new peer.
+    fn open(&self, stream: &TcpStream) -> io::Result<usize> {
+        let ours = self.handshake();
+        write_message(stream, &Message::<C, SM>::Handshake(ours))?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        let Message::<C, SM>::Handshake(theirs) = read_message(stream)? else {
+            return Err(invalid_data(
+                "the peer did not start with a handshake".into(),
+            ));
+        };
+        stream.set_read_timeout(None)?;
+        if theirs.genesis != ours.genesis {
+            let _ = stream.shutdown(Shutdown::Both);
+            return Err(invalid_data(format!(
+                "the peer follows the chain with genesis {}, not {}",
+                theirs.genesis, ours.genesis
+            )));
+        }
+
+        let id = self.next_peer_id.fetch_add(1, Ordering::SeqCst);
+        self.peers.lock().unwrap().push(Peer {
+            id,
+            writer: Arc::new(Mutex::new(stream.try_clone()?)),
+            stream: stream.try_clone()?,
+        });
+        if !self.knows_block(theirs.best_hash) {
+            self.send(id, &Message::GetBlock(theirs.best_hash));
+        }
+        Ok(id)
+    }
+
+    /// Handle the messages of a peer until it hangs up or misbehaves.
+    fn serve(&self, id: usize, stream: TcpStream) {
+        while let Ok(message) = read_message(&stream) {
+            match message {
+                Message::Handshake(_) => break,
+                Message::Block(block) => self.receive_block(id, block),
+                Message::Transaction(transaction) => {
+                    if self.accept_transaction(transaction.clone()) {
+                        self.broadcast(Some(id), &Message::Transaction(transaction));
+                    }
+                }
+                Message::GetBlock(hash) => {
+                    let block = self.client.lock().unwrap().get_block(hash);
+                    if let Some(block) = block {
+                        self.send(id, &Message::Block(block));
+                    }
+                }
+            }
+        }
+        let _ = stream.shutdown(Shutdown::Both);
+        self.peers.lock().unwrap().retain(|peer| peer.id != id);
+        // Nobody is left to ask for the parents of the blocks this peer sent.
+        self.orphans.lock().unwrap().retain(|_, waiting| {
+            waiting.retain(|orphan| orphan.from != id);
+            !waiting.is_empty()
+        });
+    }
+
+    fn knows_block(&self, hash: Hash) -> bool {
+        self.client.lock().unwrap().get_block(hash).is_some()
+    }
+
+    /// Put a transaction in the client's pool, unless it is already there. The client refuses
+    /// stale transactions too, such as one gossiped back after the best chain included it.
+    fn accept_transaction(&self, transaction: SM::Transition) -> bool {
+        let mut client = self.client.lock().unwrap();
+        !client.pool_contains(transaction.clone()) && client.submit_transaction(transaction)
+    }
+
+    /// Import a block from the given peer, or hold it back and ask that peer for its parent.
+    /// Blocks that are new to the client are passed on to the other peers.
+    fn receive_block(&self, from: usize, block: Block<C, SM>) {
+        let mut client = self.client.lock().unwrap();
+        if client.get_block(block.hash()).is_some() {
+            return;
+        }
+        if client.get_block(block.header.parent).is_none() {
+            // The client stays locked until the block is held back, so that the parent cannot
+            // be imported in between and leave the block waiting for nothing.
+            let parent = block.header.parent;
+            let mut orphans = self.orphans.lock().unwrap();
+            orphans.retain(|_, waiting| {
+                waiting.retain(|orphan| orphan.received.elapsed() < ORPHAN_LIFETIME);
+                !waiting.is_empty()
+            });
+            let held = orphans.values().map(Vec::len).sum::<usize>();
+            let waiting = orphans.entry(parent).or_default();
+            if held < MAX_ORPHANS && !waiting.iter().any(|o| o.block.hash() == block.hash()) {
+                waiting.push(Orphan {
+                    block,
+                    from,
+                    received: Instant::now(),
+                });
+            }
+            drop(orphans);
+            drop(client);
+            self.send(from, &Message::GetBlock(parent));
+            return;
+        }
+
+        let hash = block.hash();
+        if !client.import_block(block.clone()) {
+            return;
+        }
+        let adopted = self.import_orphans(&mut client, hash);
+        drop(client);
+
+        self.broadcast(Some(from), &Message::Block(block));
+        for (from, block) in adopted {
+            self.broadcast(Some(from), &Message::Block(block));
+        }
+    }
+
+    /// Import the blocks that were waiting for the given block, which the client imported
+    /// without the network's help, and pass them on.
+    fn adopt_orphans(&self, hash: Hash) {
+        if !self.orphans.lock().unwrap().contains_key(&hash) {
+            return;
+        }
+        let mut client = self.client.lock().unwrap();
+        let adopted = self.import_orphans(&mut client, hash);
+        drop(client);
+
+        for (from, block) in adopted {
+            self.broadcast(Some(from), &Message::Block(block));
+        }
+    }
+
+    /// Import the blocks that were waiting for the given block, and those waiting for them
+    /// in turn, parents first. Returns the blocks that were imported, each with the peer that
+    /// sent it.
+    fn import_orphans(
+        &self,
+        client: &mut FullClient<C, SM, FC, P, S>,
+        hash: Hash,
+    ) -> Vec<(usize, Block<C, SM>)> {
+        let mut ready = vec![hash];
+        let mut imported = Vec::new();
+        while let Some(hash) = ready.pop() {
+            let children = self.orphans.lock().unwrap().remove(&hash);
+            for orphan in children.into_iter().flatten() {
+                if client.import_block(orphan.block.clone()) {
+                    ready.push(orphan.block.hash());
+                    imported.push((orphan.from, orphan.block));
+                }
+            }
+        }
+        imported
+    }
+}
+
+impl<C: Consensus, SM: StateMachine, FC, P, S> Shared<C, SM, FC, P, S> {
+    /// Send a message to a single peer. A peer that cannot be written to is left for its own
+    /// connection to notice and drop.
+    fn send(&self, id: usize, message: &Message<C, SM>)
+    where
+        SM::AccountId: Encode,
+        SM::Transition: Encode,
+    {
+        let writer = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|peer| peer.id == id)
+            .map(|peer| peer.writer.clone());
+        if let Some(writer) = writer {
+            let _ = write_message(&writer.lock().unwrap(), message);
+        }
+    }
+
+    /// Send a message to every peer, except possibly the one it came from.
+    fn broadcast(&self, except: Option<usize>, message: &Message<C, SM>)
+    where
+        SM::AccountId: Encode,
+        SM::Transition: Encode,
+    {
+        // The list of peers is not locked while writing, so that a peer that is slow to read
+        // holds up nobody but those writing to it.
+        let writers: Vec<_> = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|peer| Some(peer.id) != except)
+            .map(|peer| peer.writer.clone())
+            .collect();
+        for writer in writers {
+            let _ = write_message(&writer.lock().unwrap(), message);
+        }
+    }
+}
+
+impl<C: Consensus, SM: StateMachine, FC, P, S> Drop for Network<C, SM, FC, P, S> {
+    fn drop(&mut self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        // The accept thread is blocked waiting for a connection, so give it one to wake up to.
+        let _ = TcpStream::connect(self.address);
+        if let Some(thread) = self.accept_thread.take() {
+            let _ = thread.join();
+        }
+        if let Some(thread) = self.import_thread.take() {
+            let _ = thread.join();
+        }
+        // Hanging up on every peer ends the threads serving them.
+        for peer in self.shared.peers.lock().unwrap().drain(..) {
+            let _ = peer.stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+// --- TESTS ---
+
+#[cfg(test)]
+type TestNetwork = Network<
+    (),
+    AccountedCurrency,
+    LongestChain,
+    SimplePool<AccountedCurrency>,
+    BasicStorage<(), AccountedCurrency>,
+>;
+
+#[cfg(test)]
+fn start_node_for_test(genesis_state: BalancesB) -> (TestNetwork, Arc<Mutex<TestClient>>) {
+    let client = Arc::new(Mutex::new(TestClient::new(
+        genesis_state,
+        AccountedCurrency::default(),
+        (),
+        LongestChain::default(),
+        SimplePool::default(),
+    )));
+    let network = Network::start(client.clone(), "127.0.0.1:0").unwrap();
+    (network, client)
+}
+
+/// Author a block with a single mint on the client, and return its hash.
+#[cfg(test)]
+fn author_with_mint(client: &Mutex<TestClient>, amount: u64) -> Hash {
+    let mut client = client.lock().unwrap();
+    let nonce = client.current_state().nonce(&User::Alice);
+    assert!(client.submit_transaction(mint(User::Alice, nonce, amount)));
+    client.author_and_import_automatic_block().unwrap();
+    client.get_last_block().hash()
+}
+
+#[cfg(test)]
+fn best_hash(client: &Mutex<TestClient>) -> Hash {
+    client.lock().unwrap().get_last_block().hash()
+}
+
+/// Wait a few seconds at most for a condition that depends on messages in flight.
+#[cfg(test)]
+fn eventually(condition: impl Fn() -> bool) -> bool {
+    for _ in 0..500 {
+        if condition() {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+/// Connect the first node to the second, and wait until both sides count each other as peers.
+#[cfg(test)]
+fn connect_for_test(from: &TestNetwork, to: &TestNetwork) {
+    from.connect(to.local_addr()).unwrap();
+    assert!(eventually(|| from.peer_count() == 1 && to.peer_count() == 1));
+}
+
+/// Connect to a node without being one. The node's own handshake is sent back, so the node
+/// takes the connection for a peer and the test can send it whatever it likes.
+#[cfg(test)]
+fn connect_raw_for_test(network: &TestNetwork) -> TcpStream {
+    let stream = TcpStream::connect(network.local_addr()).unwrap();
+    let handshake: Message<(), AccountedCurrency> = read_message(&stream).unwrap();
+    write_message(&stream, &handshake).unwrap();
+    stream
+}
+
+#[cfg(test)]
+fn orphan_count(network: &TestNetwork) -> usize {
+    network
+        .shared
+        .orphans
+        .lock()
+        .unwrap()
+        .values()
+        .map(Vec::len)
+        .sum()
+}
+
+#[test]
+fn cl11_messages_round_trip() {
+    let handshake = Handshake {
+        genesis: Hash::from(1),
+        best_hash: Hash::from(2),
+        best_height: 3,
+    };
+    let message = Message::<(), AccountedCurrency>::Handshake(handshake);
+    let decoded = Message::<(), AccountedCurrency>::decode_all(&message.encode());
+    assert!(matches!(decoded, Ok(Message::Handshake(h)) if h == handshake));
+
+    let message = Message::<(), AccountedCurrency>::Transaction(mint(User::Bob, 0, 4));
+    let decoded = Message::<(), AccountedCurrency>::decode_all(&message.encode());
+    assert!(matches!(decoded, Ok(Message::Transaction(t)) if t == mint(User::Bob, 0, 4)));
+
+    assert_eq!(
+        Message::<(), AccountedCurrency>::decode_all(&[9]).err(),
+        Some(DecodeError::InvalidTag(9))
+    );
+}
+
+#[test]
+fn cl11_two_nodes_converge_on_the_same_best_block() {
+    let (first, first_client) = start_node_for_test(BalancesB::default());
+    let (second, second_client) = start_node_for_test(BalancesB::default());
+
+    // The first node is a couple of blocks ahead before the two ever meet. The second
+    // learns of its best block in the handshake, and fetches the chain leading to it.
+    author_with_mint(&first_client, 1);
+    let best = author_with_mint(&first_client, 2);
+    connect_for_test(&second, &first);
+    assert!(eventually(|| best_hash(&second_client) == best));
+
+    // A transaction submitted on one node ends up in the pool of the other.
+    assert!(second.submit_transaction(mint(User::Bob, 0, 3)));
+    assert!(eventually(|| first_client
+        .lock()
+        .unwrap()
+        .pool_contains(mint(User::Bob, 0, 3))));
+
+    // The node that authors a block with it announces the block, and both agree on it.
+    let best = {
+        let mut client = first_client.lock().unwrap();
+        client.author_and_import_automatic_block().unwrap();
+        client.get_last_block().hash()
+    };
+    first.announce_best_block();
+    assert!(eventually(|| best_hash(&second_client) == best));
+    assert_eq!(second_client.lock().unwrap().pool_size(), 0);
+    assert_eq!(
+        second_client.lock().unwrap().current_state().balances,
+        first_client.lock().unwrap().current_state().balances
+    );
+}
+
+#[test]
+fn cl11_forks_resolve_by_the_fork_choice() {
+    let (first, first_client) = start_node_for_test(BalancesB::default());
+    let (second, second_client) = start_node_for_test(BalancesB::default());
+
+    // Both nodes build on genesis alone, and the first gets further.
+    author_with_mint(&first_client, 1);
+    let longest = author_with_mint(&first_client, 2);
+    let shorter = author_with_mint(&second_client, 3);
+    connect_for_test(&first, &second);
+
+    assert!(eventually(|| best_hash(&second_client) == longest));
+    // The first node still has the shorter fork, but does not follow it.
+    assert!(eventually(|| first_client
+        .lock()
+        .unwrap()
+        .get_block(shorter)
+        .is_some()));
+    assert_eq!(best_hash(&first_client), longest);
+}
+
+#[test]
+fn cl11_blocks_are_relayed_between_peers() {
+    let (first, first_client) = start_node_for_test(BalancesB::default());
+    let (second, _) = start_node_for_test(BalancesB::default());
+    let (third, third_client) = start_node_for_test(BalancesB::default());
+
+    // The first and third nodes only know each other through the second.
+    connect_for_test(&first, &second);
+    third.connect(second.local_addr()).unwrap();
+    assert!(eventually(
+        || second.peer_count() == 2 && third.peer_count() == 1
+    ));
+
+    let best = author_with_mint(&first_client, 1);
+    first.announce_best_block();
+    assert!(eventually(|| best_hash(&third_client) == best));
+
+    assert!(third.submit_transaction(mint(User::Charlie, 0, 2)));
+    assert!(eventually(|| first_client
+        .lock()
+        .unwrap()
+        .pool_contains(mint(User::Charlie, 0, 2))));
+}
+
+#[test]
+fn cl11_late_gossip_of_an_included_transaction_goes_nowhere() {
+    let (first, first_client) = start_node_for_test(BalancesB::default());
+    let (second, second_client) = start_node_for_test(BalancesB::default());
+    connect_for_test(&second, &first);
+
+    let transaction = mint(User::Bob, 0, 3);
+    assert!(second.submit_transaction(transaction.clone()));
+    assert!(eventually(|| first_client
+        .lock()
+        .unwrap()
+        .pool_contains(transaction.clone())));
+    let best = {
+        let mut client = first_client.lock().unwrap();
+        client.author_and_import_automatic_block().unwrap();
+        client.get_last_block().hash()
+    };
+    first.announce_best_block();
+    assert!(eventually(|| best_hash(&second_client) == best));
+    assert_eq!(second_client.lock().unwrap().pool_size(), 0);
+
+    // Both nodes refuse the transaction now that it is included, however late it comes.
+    assert!(!second.submit_transaction(transaction.clone()));
+    let late = connect_raw_for_test(&first);
+    write_message(
+        &late,
+        &Message::<(), AccountedCurrency>::Transaction(transaction),
+    )
+    .unwrap();
+    // A peer's messages are handled in order, so once the block comes back the transaction
+    // has been dealt with.
+    write_message(&late, &Message::<(), AccountedCurrency>::GetBlock(best)).unwrap();
+    let reply: Message<(), AccountedCurrency> = read_message(&late).unwrap();
+    assert!(matches!(reply, Message::Block(block) if block.hash() == best));
+    assert_eq!(first_client.lock().unwrap().pool_size(), 0);
+    assert_eq!(second_client.lock().unwrap().pool_size(), 0);
+}
+
+#[test]
+fn cl11_orphans_are_given_up_on() {
+    let (network, _) = start_node_for_test(BalancesB::default());
+    // A chain on the same genesis that the node has never heard of.
+    let (_other, other_client) = start_node_for_test(BalancesB::default());
+    let hashes: Vec<_> = (1..=3)
+        .map(|amount| author_with_mint(&other_client, amount))
+        .collect();
+    let block = |i: usize| other_client.lock().unwrap().get_block(hashes[i]).unwrap();
+
+    // A block with an unknown parent is held back, and the parent asked for.
+    let peer = connect_raw_for_test(&network);
+    write_message(&peer, &Message::Block(block(1))).unwrap();
+    let reply: Message<(), AccountedCurrency> = read_message(&peer).unwrap();
+    assert!(matches!(reply, Message::GetBlock(hash) if hash == hashes[0]));
+    assert_eq!(orphan_count(&network), 1);
+
+    // Once it has waited too long, it is dropped the next time a block is held back.
+    for orphan in network
+        .shared
+        .orphans
+        .lock()
+        .unwrap()
+        .values_mut()
+        .flatten()
+    {
+        orphan.received -= ORPHAN_LIFETIME;
+    }
+    write_message(&peer, &Message::Block(block(2))).unwrap();
+    let reply: Message<(), AccountedCurrency> = read_message(&peer).unwrap();
+    assert!(matches!(reply, Message::GetBlock(hash) if hash == hashes[1]));
+    assert_eq!(orphan_count(&network), 1);
+    assert!(network
+        .shared
+        .orphans
+        .lock()
+        .unwrap()
+        .contains_key(&hashes[1]));
+
+    // And when the peer that sent them goes away, nobody is left to ask for their parents.
+    drop(peer);
+    assert!(eventually(|| orphan_count(&network) == 0));
+}
+
+#[test]
+fn cl11_orphans_are_imported_when_their_parent_arrives_another_way() {
+    let (network, client) = start_node_for_test(BalancesB::default());
+    let (_other, other_client) = start_node_for_test(BalancesB::default());
+    let hashes: Vec<_> = (1..=2)
+        .map(|amount| author_with_mint(&other_client, amount))
+        .collect();
+    let block = |i: usize| other_client.lock().unwrap().get_block(hashes[i]).unwrap();
+
+    let peer = connect_raw_for_test(&network);
+    write_message(&peer, &Message::Block(block(1))).unwrap();
+    let reply: Message<(), AccountedCurrency> = read_message(&peer).unwrap();
+    assert!(matches!(reply, Message::GetBlock(hash) if hash == hashes[0]));
+
+    // The parent comes in through the client itself, as it would over RPC.
+    assert!(client.lock().unwrap().import_block(block(0)));
+    assert!(eventually(|| best_hash(&client) == hashes[1]));
+    assert_eq!(orphan_count(&network), 0);
+}
+
+#[test]
+fn cl11_refuses_peers_on_another_chain() {
+    let (first, _) = start_node_for_test(BalancesB::default());
+    let mut other_genesis = BalancesB::default();
+    other_genesis.balances.insert(User::Alice, 10);
+    let (second, _) = start_node_for_test(other_genesis);
+
+    let error = second.connect(first.local_addr()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(second.peer_count(), 0);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(first.peer_count(), 0);
+}
+
+#[test]
+fn cl11_drops_peers_that_send_garbage() {
+    let (network, _) = start_node_for_test(BalancesB::default());
+    let stream = TcpStream::connect(network.local_addr()).unwrap();
+    let _: Message<(), AccountedCurrency> = read_message(&stream).unwrap();
+    (&stream).write_all(&[3, 0, 0, 0, 1, 9, 9]).unwrap();
+
+    // The connection never counts as a peer, and the node hangs up on it.
+    let mut rest = Vec::new();
+    (&stream).read_to_end(&mut rest).unwrap();
+    assert_eq!(network.peer_count(), 0);
+}
+
+#[test]
+fn cl11_stops_when_dropped() {
+    let (first, _) = start_node_for_test(BalancesB::default());
+    let (second, _) = start_node_for_test(BalancesB::default());
+    connect_for_test(&first, &second);
+
+    let address = second.local_addr();
+    drop(second);
+    assert!(TcpStream::connect(address).is_err());
+    assert!(eventually(|| first.peer_count() == 0));
+}
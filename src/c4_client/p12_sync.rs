@@ -0,0 +1,457 @@
+//! Gossip only carries what happens while a node is listening. A node that starts late, or was
+//! offline for a while, needs to fetch the part of the chain it missed from its peers in bulk.
+//! This is called sync, and it goes in three steps:
+//! 1. Ask a peer how high its best block is. If it is no higher than ours, there is nothing to
+//!    fetch from it.
+//! 2. Ask for headers by height, a batch at a time, starting just above our best block. Check
+//!    that they link up with each other and with a block we already have, and that their seals
+//!    pass `Consensus::verify_sub_chain`. Headers are small and quick to check, so a peer
+//!    serving a bogus chain is caught before any bodies are downloaded.
+//! 3. Download the blocks of those headers, and import them with `import_block`, which executes
+//!    them and checks them against their headers.
+//!
+//! If the first headers a peer serves do not attach to any block we know, the peer follows
+//! another fork. Sync then steps back a batch at a time, no further than the finalized block,
+//! until it finds where the two chains meet.
+//!
+//! Peers can go away in the middle of a sync, or serve data that does not hold up. Sync gives
+//! up on such a peer and carries on with the next. Blocks already imported from it stay.
+//!
+//! Sync does not care how requests reach a peer. Anything implementing `SyncPeer` will do,
+//! including a client in the same process.
+
+use super::p1_data_structure::Block;
+use super::p2_importing_blocks::ImportBlock;
+use super::p3_fork_choice::ForkChoice;
+#[cfg(test)]
+use super::p3_fork_choice::LongestChain;
+#[cfg(test)]
+use super::p4_transaction_pool::SimplePool;
+use super::p4_transaction_pool::TransactionPool;
+#[cfg(test)]
+use super::BasicStorage;
+use super::{Consensus, FullClient, Hash, Header, StateMachine, Storage};
+#[cfg(test)]
+use crate::c1_state_machine::{AccountedCurrency, AccountingTransaction, BalancesB, User};
+#[cfg(test)]
+use crate::c3_consensus::{ConsensusAuthority, PoaRoundRobinByHeight};
+use crate::{Encode, TrieState};
+
+/// The reasons sync gives up on a peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncError {
+    /// The peer went away, or stopped answering.
+    PeerUnavailable,
+    /// The peer answered with something other than what was asked for, such as headers of
+    /// the wrong heights or the wrong number of blocks.
+    BadResponse,
+    /// The peer's headers do not link up with each other, or with any block we know.
+    BadLinkage,
+    /// The peer's headers do not pass the consensus rules.
+    BadSeal,
+    /// A block did not match its header, or failed to import.
+    BadBlock,
+    /// A state snapshot did not match the header of its block.
+    BadSnapshot,
+}
+
+/// The requests sync makes of a peer. An implementation may send them to a node over the
+/// network, or answer them from a client in the same process.
+pub trait SyncPeer<C: Consensus, SM: StateMachine> {
+    /// The height of the peer's best block.
+    fn best_height(&mut self) -> Result<u64, SyncError>;
+
+    /// Up to `count` headers of the peer's canonical chain, starting at the given height.
+    /// Fewer come back if the chain ends sooner.
+    fn headers(&mut self, from: u64, count: u64) -> Result<Vec<Header<C::Digest>>, SyncError>;
+
+    /// The blocks with the given hashes, in the same order. Blocks the peer does not know are
+    /// left out.
+    fn blocks(&mut self, hashes: &[Hash]) -> Result<Vec<Block<C, SM>>, SyncError>;
+}
+
+/// A client serves sync requests from its own chain.
+impl<C, SM, FC, P, S> SyncPeer<C, SM> for FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    FC: ForkChoice<C, SM>,
+    P: TransactionPool<SM>,
+    S: Storage<C, SM>,
+    Block<C, SM>: std::hash::Hash + Clone,
+    SM::State: TrieState + Clone,
+    SM::Transition: Encode,
+    SM::Event: Encode,
+{
+    fn best_height(&mut self) -> Result<u64, SyncError> {
+        Ok(self.get_last_block().header.height)
+    }
+
+    fn headers(&mut self, from: u64, count: u64) -> Result<Vec<Header<C::Digest>>, SyncError> {
+        Ok(self
+            .canonical_chain_from(from)
+            .take(count as usize)
+            .map(|block| block.header)
+            .collect())
+    }
+
+    fn blocks(&mut self, hashes: &[Hash]) -> Result<Vec<Block<C, SM>>, SyncError> {
+        Ok(hashes.iter().filter_map(|h| self.get_block(*h)).collect())
+    }
+}
+
+/// What came of syncing from a set of peers.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// The number of blocks imported.
+    pub imported: usize,
+    /// The peers that were given up on, by their position in the list, and why.
+    pub failed: Vec<(usize, SyncError)>,
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    FC: ForkChoice<C, SM>,
+    P: TransactionPool<SM>,
+    S: Storage<C, SM>,
+    Block<C, SM>: std::hash::Hash + Clone,
+    SM::State: TrieState + Clone,
+    SM::Transition: Encode,
+    SM::Event: Encode,
+{
+    /// Catch up with each of the given peers in turn, asking for `batch` headers at a time.
+    pub fn sync(&mut self, peers: &mut [&mut dyn SyncPeer<C, SM>], batch: u64) -> SyncReport {
+        let mut report = SyncReport::default();
+        for (index, peer) in peers.iter_mut().enumerate() {
+            if let Err(e) = self.sync_from(&mut **peer, batch.max(1), &mut report.imported) {
+                report.failed.push((index, e));
+            }
+        }
+        report
+    }
+
+    /// Import the blocks of a single peer's chain up to its current best block.
+    fn sync_from(
+        &mut self,
+        peer: &mut dyn SyncPeer<C, SM>,
+        batch: u64,
+        imported: &mut usize,
+    ) -> Result<(), SyncError> {
+        let target = peer.best_height()?;
+        let lowest = match self.storage.get_block(self.storage.finalized_block()) {
+            Some(finalized) => finalized.header.height + 1,
+            None => 1,
+        };
+        let mut from = self.get_last_block().header.height + 1;
+        // Whether the peer's headers have been seen to attach to our chain yet.
+        let mut attached = false;
+
+        while from <= target {
+            let headers = peer.headers(from, batch)?;
+            let heights_match = headers
+                .iter()
+                .zip(from..)
+                .all(|(header, height)| header.height == height);
+            if headers.is_empty() || headers.len() as u64 > batch || !heights_match {
+                return Err(SyncError::BadResponse);
+            }
+
+            let Some(parent) = self.storage.get_block(headers[0].parent) else {
+                // Once the peer's chain has attached to ours, it has no business leaving it.
+                if attached || from <= lowest {
+                    return Err(SyncError::BadLinkage);
+                }
+                from = from.saturating_sub(batch).max(lowest);
+                continue;
+            };
+            attached = true;
+
+            if !self
+                .consensus_engine
+                .verify_sub_chain(&parent.header.consensus_digest, &headers)
+            {
+                return Err(SyncError::BadSeal);
+            }
+            if headers.windows(2).any(|w| w[1].parent != w[0].hash()) {
+                return Err(SyncError::BadLinkage);
+            }
+
+            // Blocks we already have, on a fork of our own, need not be downloaded again.
+            let wanted: Vec<Hash> = headers
+                .iter()
+                .map(Header::hash)
+                .filter(|hash| self.storage.get_block(*hash).is_none())
+                .collect();
+            let blocks = peer.blocks(&wanted)?;
+            if blocks.len() != wanted.len() {
+                return Err(SyncError::BadResponse);
+            }
+            for (block, hash) in blocks.into_iter().zip(wanted) {
+                if block.hash() != hash || !self.import_block(block) {
+                    return Err(SyncError::BadBlock);
+                }
+                *imported += 1;
+            }
+
+            from += headers.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+// --- TESTS ---
+
+#[cfg(test)]
+type TestClient = FullClient<
+    PoaRoundRobinByHeight,
+    AccountedCurrency,
+    LongestChain,
+    SimplePool<AccountedCurrency>,
+    BasicStorage<PoaRoundRobinByHeight, AccountedCurrency>,
+>;
+
+#[cfg(test)]
+fn client_for_test(genesis_state: BalancesB) -> TestClient {
+    TestClient::new(
+        genesis_state,
+        AccountedCurrency::default(),
+        PoaRoundRobinByHeight {
+            authorities: vec![ConsensusAuthority::Alice, ConsensusAuthority::Bob],
+        },
+        LongestChain::default(),
+        SimplePool::default(),
+    )
+}
+
+/// Author the given number of blocks on top of the best block, each minting a different
+/// amount starting from `first_amount`, so that no two clients author the same blocks.
+#[cfg(test)]
+fn author_blocks(client: &mut TestClient, count: u64, first_amount: u64) {
+    for amount in first_amount..first_amount + count {
+        assert!(client.submit_transaction(AccountingTransaction::Mint {
+            minter: User::Alice,
+            amount,
+            nonce: client.current_state().nonce(&User::Alice),
+        }));
+        client.author_and_import_automatic_block().unwrap();
+    }
+}
+
+/// The ways a mock peer can misbehave.
+#[cfg(test)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Fault {
+    None,
+    /// Stops answering after this many requests.
+    GoesAwayAfter(usize),
+    /// Serves the header at this height sealed by the wrong authority.
+    BadSealAt(u64),
+    /// Serves the header at this height with a parent that is not the header before it.
+    BadLinkAt(u64),
+    /// Serves the block at this height with a transaction its header does not commit to.
+    BadBodyAt(u64),
+}
+
+/// A peer that serves the chain of its own client, the way a remote node would, unless it
+/// was told to misbehave.
+#[cfg(test)]
+struct MockPeer {
+    client: TestClient,
+    fault: Fault,
+    requests: usize,
+}
+
+#[cfg(test)]
+impl MockPeer {
+    fn new(client: TestClient, fault: Fault) -> Self {
+        MockPeer {
+            client,
+            fault,
+            requests: 0,
+        }
+    }
+
+    fn request(&mut self) -> Result<(), SyncError> {
+        self.requests += 1;
+        match self.fault {
+            Fault::GoesAwayAfter(n) if self.requests > n => Err(SyncError::PeerUnavailable),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl SyncPeer<PoaRoundRobinByHeight, AccountedCurrency> for MockPeer {
+    fn best_height(&mut self) -> Result<u64, SyncError> {
+        self.request()?;
+        self.client.best_height()
+    }
+
+    fn headers(
+        &mut self,
+        from: u64,
+        count: u64,
+    ) -> Result<Vec<Header<ConsensusAuthority>>, SyncError> {
+        self.request()?;
+        let mut headers = self.client.headers(from, count)?;
+        for header in &mut headers {
+            match self.fault {
+                Fault::BadSealAt(height) if header.height == height => {
+                    header.consensus_digest = ConsensusAuthority::Charlie
+                }
+                Fault::BadLinkAt(height) if header.height == height => {
+                    header.parent = Hash::from(7)
+                }
+                _ => {}
+            }
+        }
+        Ok(headers)
+    }
+
+    fn blocks(
+        &mut self,
+        hashes: &[Hash],
+    ) -> Result<Vec<Block<PoaRoundRobinByHeight, AccountedCurrency>>, SyncError> {
+        self.request()?;
+        let mut blocks = self.client.blocks(hashes)?;
+        for block in &mut blocks {
+            if self.fault == Fault::BadBodyAt(block.header.height) {
+                block.body.push(AccountingTransaction::Mint {
+                    minter: User::Bob,
+                    amount: 1000,
+                    nonce: 0,
+                });
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+fn best_height(client: &TestClient) -> u64 {
+    client.get_last_block().header.height
+}
+
+#[test]
+fn cl12_catches_up_with_a_peer() {
+    let mut peer_client = client_for_test(BalancesB::default());
+    author_blocks(&mut peer_client, 10, 1);
+    let best = peer_client.get_last_block().hash();
+    let mut peer = MockPeer::new(peer_client, Fault::None);
+
+    let mut client = client_for_test(BalancesB::default());
+    let report = client.sync(&mut [&mut peer], 4);
+    assert_eq!(
+        report,
+        SyncReport {
+            imported: 10,
+            failed: vec![]
+        }
+    );
+    assert_eq!(client.get_last_block().hash(), best);
+    assert_eq!(
+        client.current_state().balances,
+        peer.client.current_state().balances
+    );
+
+    // Once caught up, there is nothing left to fetch.
+    let requests = peer.requests;
+    assert_eq!(client.sync(&mut [&mut peer], 4), SyncReport::default());
+    assert_eq!(peer.requests, requests + 1);
+}
+
+#[test]
+fn cl12_finds_where_a_fork_meets_our_chain() {
+    let mut peer_client = client_for_test(BalancesB::default());
+    let mut client = client_for_test(BalancesB::default());
+    author_blocks(&mut peer_client, 3, 1);
+    client.sync(&mut [&mut peer_client], 4);
+
+    // Both go their own way from height 3, and the peer gets further.
+    author_blocks(&mut client, 2, 100);
+    author_blocks(&mut peer_client, 5, 200);
+    let best = peer_client.get_last_block().hash();
+
+    let report = client.sync(&mut [&mut peer_client], 2);
+    assert_eq!(report.imported, 5);
+    assert!(report.failed.is_empty());
+    assert_eq!(client.get_last_block().hash(), best);
+    assert_eq!(best_height(&client), 8);
+}
+
+#[test]
+fn cl12_moves_on_from_a_peer_that_goes_away() {
+    let mut peer_client = client_for_test(BalancesB::default());
+    author_blocks(&mut peer_client, 10, 1);
+    let best = peer_client.get_last_block().hash();
+    // The first peer answers the height and the first batch of headers, and then vanishes.
+    let mut flaky = MockPeer::new(peer_client, Fault::GoesAwayAfter(2));
+    let mut peer_client = client_for_test(BalancesB::default());
+    author_blocks(&mut peer_client, 10, 1);
+    let mut good = MockPeer::new(peer_client, Fault::None);
+
+    let mut client = client_for_test(BalancesB::default());
+    let report = client.sync(&mut [&mut flaky, &mut good], 4);
+    assert_eq!(
+        report,
+        SyncReport {
+            imported: 10,
+            failed: vec![(0, SyncError::PeerUnavailable)]
+        }
+    );
+    assert_eq!(client.get_last_block().hash(), best);
+}
+
+#[test]
+fn cl12_rejects_peers_serving_invalid_data() {
+    for (fault, error) in [
+        (Fault::BadSealAt(6), SyncError::BadSeal),
+        (Fault::BadLinkAt(6), SyncError::BadLinkage),
+        (Fault::BadBodyAt(6), SyncError::BadBlock),
+    ] {
+        let mut peer_client = client_for_test(BalancesB::default());
+        author_blocks(&mut peer_client, 10, 1);
+        let mut peer = MockPeer::new(peer_client, fault);
+
+        let mut client = client_for_test(BalancesB::default());
+        let report = client.sync(&mut [&mut peer], 4);
+        assert_eq!(report.failed, vec![(0, error)], "{:?}", fault);
+        // The first batch was fine and stays. Nothing of the bad one gets in, except for
+        // the blocks before the bad one when only its body is wrong.
+        let expected = if error == SyncError::BadBlock { 5 } else { 4 };
+        assert_eq!(best_height(&client), expected, "{:?}", fault);
+        assert_eq!(report.imported as u64, expected, "{:?}", fault);
+    }
+}
+
+#[test]
+fn cl12_rejects_a_peer_on_another_chain() {
+    let mut other_genesis = BalancesB::default();
+    other_genesis.balances.insert(User::Alice, 10);
+    let mut peer_client = client_for_test(other_genesis);
+    author_blocks(&mut peer_client, 5, 1);
+
+    let mut client = client_for_test(BalancesB::default());
+    author_blocks(&mut client, 2, 1);
+    let report = client.sync(&mut [&mut peer_client], 2);
+    assert_eq!(report.failed, vec![(0, SyncError::BadLinkage)]);
+    assert_eq!(report.imported, 0);
+    assert_eq!(best_height(&client), 2);
+}
+
+#[test]
+fn cl12_does_not_step_back_past_finality() {
+    let mut peer_client = client_for_test(BalancesB::default());
+    let mut client = client_for_test(BalancesB::default());
+    author_blocks(&mut peer_client, 6, 1);
+    author_blocks(&mut client, 3, 100);
+    let finalized = client.canonical_block_hash(2).unwrap();
+    assert!(client.manually_finalize_block(finalized));
+
+    // The peer's chain leaves ours at genesis, below the finalized block.
+    let report = client.sync(&mut [&mut peer_client], 2);
+    assert_eq!(report.failed, vec![(0, SyncError::BadLinkage)]);
+    assert_eq!(best_height(&client), 3);
+}
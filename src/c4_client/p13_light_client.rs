@@ -0,0 +1,375 @@
+//! A full client executes every block, so it needs every body and the whole state. Many users
+//! only want to follow the chain and check a few facts about it, such as whether their
+//! transaction made it in or what their balance is, on a device that could never hold all that.
+//!
+//! A light client imports headers only. It checks that each header links up with a header it
+//! already has and that its seal passes the consensus rules, and it follows the best chain with
+//! a fork choice rule that only looks at headers. It cannot tell whether the blocks behind
+//! those headers executed correctly, so it trusts the consensus engine on that.
+//!
+//! Headers commit to everything else through their roots, so a light client can still check
+//! facts a full client hands it, without holding any state:
+//! * that an extrinsic is part of a block, with a Merkle proof against the extrinsics root.
+//! * that a state entry holds a value after a block, with a state proof against the state root.
+
+use std::collections::{HashMap, HashSet};
+
+#[cfg(test)]
+use super::p2_importing_blocks::ImportBlock;
+use super::p3_fork_choice::HeaderForkChoice;
+#[cfg(test)]
+use super::p3_fork_choice::LongestChain;
+#[cfg(test)]
+use super::p4_transaction_pool::SimplePool;
+#[cfg(test)]
+use super::test_helpers::mint;
+#[cfg(test)]
+use super::{BasicStorage, FullClient};
+use super::{Consensus, Hash, Header};
+#[cfg(test)]
+use crate::c1_state_machine::{AccountedCurrency, AccountingTransaction, BalancesKey, User};
+#[cfg(test)]
+use crate::c3_consensus::{ConsensusAuthority, PoaRoundRobinByHeight};
+use crate::{Encode, MerkleProof, StateProof};
+
+/// A client that follows the chain by its headers alone.
+pub struct LightClient<C: Consensus, FC> {
+    /// The consensus engine used to check the seals of imported headers.
+    consensus_engine: C,
+    /// The header-level fork choice used to pick the best header.
+    fork_choice: FC,
+    /// Every known header, by hash.
+    headers: HashMap<Hash, Header<C::Digest>>,
+    /// The hash of the most recently finalized header. Initially the genesis header.
+    finalized: Hash,
+}
+
+impl<C, FC> LightClient<C, FC>
+where
+    C: Consensus,
+    FC: HeaderForkChoice<C> + Default,
+{
+    /// Create a light client that starts from the given genesis header. The genesis header is
+    /// trusted as given, so it should come from somewhere trustworthy, such as a chain spec.
+    pub fn new(genesis: Header<C::Digest>, consensus_engine: C, fork_choice: FC) -> Self {
+        let mut client = LightClient {
+            consensus_engine,
+            fork_choice,
+            headers: HashMap::new(),
+            finalized: genesis.hash(),
+        };
+        client.fork_choice.import_header(&genesis);
+        client.headers.insert(genesis.hash(), genesis);
+        client
+    }
+
+    /// Attempt to import a header.
+    /// Returns whether the import was successful or not.
+    pub fn import_header(&mut self, header: Header<C::Digest>) -> bool {
+        let hash = header.hash();
+        if self.headers.contains_key(&hash) {
+            return true;
+        }
+        let Some(parent) = self.headers.get(&header.parent) else {
+            return false;
+        };
+        if header.height != parent.height + 1 {
+            return false;
+        }
+        // Every header left below the finalized one is on the finalized chain, so a fork off
+        // it would be a fork below finality.
+        if parent.height < self.finalized_header().height {
+            return false;
+        }
+        if !self
+            .consensus_engine
+            .validate(&parent.consensus_digest, &header)
+        {
+            return false;
+        }
+
+        self.fork_choice.import_header(&header);
+        self.headers.insert(hash, header);
+        true
+    }
+
+    /// Retrieve an imported header.
+    /// Returns None if the header is not known.
+    pub fn get_header(&self, hash: Hash) -> Option<Header<C::Digest>> {
+        self.headers.get(&hash).cloned()
+    }
+
+    /// The best header according to the fork choice rule.
+    pub fn best_header(&self) -> Header<C::Digest> {
+        self.fork_choice
+            .best_header()
+            .and_then(|hash| self.get_header(hash))
+            .expect("the fork choice knows at least the genesis header")
+    }
+
+    /// The most recently finalized header.
+    pub fn finalized_header(&self) -> Header<C::Digest> {
+        self.headers[&self.finalized].clone()
+    }
+
+    /// Mark the given header as final so that it will never be reverted.
+    /// Returns whether or not the header was known and marked successfully.
+    ///
+    /// Only descendants of the previously finalized header can be finalized. Headers that do
+    /// not descend from the newly finalized one, other than its own ancestors, are forgotten.
+    pub fn finalize(&mut self, hash: Hash) -> bool {
+        let Some(header) = self.get_header(hash) else {
+            return false;
+        };
+        if self.ancestor_at(&header, self.finalized_header().height) != Some(self.finalized) {
+            return false;
+        }
+
+        let mut kept = HashSet::new();
+        let mut descendants = Vec::new();
+        for (h, candidate) in &self.headers {
+            if candidate.height <= header.height {
+                if self.ancestor_at(&header, candidate.height) == Some(*h) {
+                    kept.insert(*h);
+                }
+            } else if self.ancestor_at(candidate, header.height) == Some(hash) {
+                kept.insert(*h);
+                descendants.push(candidate.clone());
+            }
+        }
+        descendants.sort_by_key(|h| h.height);
+
+        self.headers.retain(|h, _| kept.contains(h));
+        self.finalized = hash;
+        self.fork_choice.finalize_header(&header, &descendants);
+        true
+    }
+
+    /// The hash of the ancestor of the given header at the given height, which may be the
+    /// header itself. Returns None if the ancestry is not known that far down.
+    fn ancestor_at(&self, header: &Header<C::Digest>, height: u64) -> Option<Hash> {
+        let mut current = header;
+        while current.height > height {
+            current = self.headers.get(&current.parent)?;
+        }
+        (current.height == height).then(|| current.hash())
+    }
+
+    /// Check that an extrinsic is part of the block with the given hash.
+    /// Returns false if the header is not known.
+    pub fn verify_extrinsic<T: Encode>(
+        &self,
+        block_hash: Hash,
+        extrinsic: &T,
+        proof: &MerkleProof,
+    ) -> bool {
+        match self.headers.get(&block_hash) {
+            Some(header) => header.verify_extrinsic(extrinsic, proof),
+            None => false,
+        }
+    }
+
+    /// Check that after the block with the given hash a state entry holds the expected value,
+    /// where None means that the entry is absent.
+    /// Returns false if the header is not known.
+    pub fn verify_state<K, V>(
+        &self,
+        block_hash: Hash,
+        key: &K,
+        expected: Option<&V>,
+        proof: &StateProof<K, V>,
+    ) -> bool
+    where
+        K: Eq + Encode,
+        V: Eq + Encode,
+    {
+        match self.headers.get(&block_hash) {
+            Some(header) => proof.verify(&header.state_root, key, expected),
+            None => false,
+        }
+    }
+}
+
+// --- TESTS ---
+
+#[cfg(test)]
+type TestFullClient = FullClient<
+    PoaRoundRobinByHeight,
+    AccountedCurrency,
+    LongestChain,
+    SimplePool<AccountedCurrency>,
+    BasicStorage<PoaRoundRobinByHeight, AccountedCurrency>,
+>;
+
+#[cfg(test)]
+type TestLightClient = LightClient<PoaRoundRobinByHeight, LongestChain>;
+
+#[cfg(test)]
+fn engine_for_test() -> PoaRoundRobinByHeight {
+    PoaRoundRobinByHeight {
+        authorities: vec![ConsensusAuthority::Alice, ConsensusAuthority::Bob],
+    }
+}
+
+#[cfg(test)]
+fn full_client_for_test() -> TestFullClient {
+    TestFullClient::new(
+        Default::default(),
+        AccountedCurrency::default(),
+        engine_for_test(),
+        LongestChain::default(),
+        SimplePool::default(),
+    )
+}
+
+/// A light client following the same chain as the given full client.
+#[cfg(test)]
+fn light_client_for_test(full: &TestFullClient) -> TestLightClient {
+    let genesis = full
+        .get_block(full.canonical_block_hash(0).unwrap())
+        .unwrap();
+    LightClient::new(genesis.header, engine_for_test(), LongestChain::default())
+}
+
+/// Author a block on the full client with the given transactions, and return its header.
+#[cfg(test)]
+fn author_for_test(
+    full: &mut TestFullClient,
+    transactions: Vec<AccountingTransaction>,
+) -> Header<ConsensusAuthority> {
+    for transaction in transactions {
+        assert!(full.submit_transaction(transaction));
+    }
+    full.author_and_import_automatic_block().unwrap();
+    full.get_last_block().header
+}
+
+/// A child of the given header, sealed by the authority whose turn it is. The light client
+/// cannot tell that no such block exists.
+#[cfg(test)]
+fn child_for_test(
+    parent: &Header<ConsensusAuthority>,
+    state_root: u64,
+) -> Header<ConsensusAuthority> {
+    let partial = Header {
+        parent: parent.hash(),
+        height: parent.height + 1,
+        state_root: state_root.into(),
+        extrinsics_root: parent.extrinsics_root,
+        receipts_root: parent.receipts_root,
+        consensus_digest: ConsensusAuthority::Alice,
+    };
+    engine_for_test()
+        .seal(&parent.consensus_digest, partial)
+        .unwrap()
+}
+
+#[test]
+fn cl13_follows_the_headers_of_a_full_client() {
+    let mut full = full_client_for_test();
+    let mut light = light_client_for_test(&full);
+    let genesis = light.best_header();
+    assert_eq!(light.finalized_header(), genesis);
+
+    let headers: Vec<_> = (1..=3)
+        .map(|i| author_for_test(&mut full, vec![mint(User::Alice, i - 1, i)]))
+        .collect();
+    for header in &headers {
+        assert!(light.import_header(header.clone()));
+    }
+    assert_eq!(light.best_header(), full.get_last_block().header);
+    assert_eq!(
+        light.get_header(headers[0].hash()),
+        Some(headers[0].clone())
+    );
+    // Importing a known header again changes nothing.
+    assert!(light.import_header(headers[1].clone()));
+    assert_eq!(light.best_header(), headers[2]);
+}
+
+#[test]
+fn cl13_rejects_headers_that_do_not_check_out() {
+    let full = full_client_for_test();
+    let mut light = light_client_for_test(&full);
+    let genesis = light.best_header();
+
+    // The parent is not known.
+    let orphan = child_for_test(&child_for_test(&genesis, 1), 2);
+    assert!(!light.import_header(orphan));
+
+    // The height does not follow the parent's.
+    let mut skipping = child_for_test(&genesis, 1);
+    skipping.height = 2;
+    assert!(!light.import_header(skipping));
+
+    // It is not the turn of the authority that sealed it.
+    let mut wrong_seal = child_for_test(&genesis, 1);
+    wrong_seal.consensus_digest = ConsensusAuthority::Bob;
+    assert!(!light.import_header(wrong_seal));
+
+    assert_eq!(light.best_header(), genesis);
+    assert!(light.import_header(child_for_test(&genesis, 1)));
+}
+
+#[test]
+fn cl13_follows_the_best_fork_and_prunes_on_finality() {
+    let full = full_client_for_test();
+    let mut light = light_client_for_test(&full);
+    let genesis = light.best_header();
+
+    let a1 = child_for_test(&genesis, 1);
+    let a2 = child_for_test(&a1, 2);
+    let b1 = child_for_test(&genesis, 3);
+    let b2 = child_for_test(&b1, 4);
+    let b3 = child_for_test(&b2, 5);
+    for header in [&a1, &a2, &b1, &b2] {
+        assert!(light.import_header(header.clone()));
+    }
+    assert_eq!(light.best_header(), a2);
+    assert!(light.import_header(b3.clone()));
+    assert_eq!(light.best_header(), b3);
+
+    // Finalizing a1 abandons the longer fork, and the best header moves back.
+    assert!(light.finalize(a1.hash()));
+    assert_eq!(light.finalized_header(), a1);
+    assert_eq!(light.best_header(), a2);
+    assert_eq!(light.get_header(b3.hash()), None);
+    assert_eq!(light.get_header(genesis.hash()), Some(genesis.clone()));
+
+    // Nothing can fork off below it, and blocks off its chain cannot be finalized.
+    assert!(!light.import_header(b1.clone()));
+    assert!(!light.import_header(child_for_test(&genesis, 6)));
+    assert!(!light.finalize(genesis.hash()));
+    assert!(!light.finalize(b2.hash()));
+    assert!(light.import_header(child_for_test(&a1, 7)));
+}
+
+#[test]
+fn cl13_verifies_proofs_without_holding_state() {
+    let mut full = full_client_for_test();
+    let mut light = light_client_for_test(&full);
+    let transactions = vec![mint(User::Alice, 0, 10), mint(User::Bob, 0, 20)];
+    let header = author_for_test(&mut full, transactions.clone());
+    let hash = header.hash();
+    assert!(light.import_header(header));
+
+    // The full client hands out the proofs, and the light client checks them.
+    let block = full.get_block(hash).unwrap();
+    let proof = block.extrinsic_proof(1).unwrap();
+    assert!(light.verify_extrinsic(hash, &transactions[1], &proof));
+    assert!(!light.verify_extrinsic(hash, &transactions[0], &proof));
+
+    let bob = BalancesKey::Balance(User::Bob);
+    let proof = full.state_proof(hash, &bob).unwrap();
+    assert!(light.verify_state(hash, &bob, Some(&20), &proof));
+    assert!(!light.verify_state(hash, &bob, Some(&21), &proof));
+    let charlie = BalancesKey::Balance(User::Charlie);
+    let proof = full.state_proof(hash, &charlie).unwrap();
+    assert!(light.verify_state(hash, &charlie, None, &proof));
+
+    // Nothing verifies against a block the light client has not seen.
+    let unknown = Hash::from(12);
+    assert!(!light.verify_state(unknown, &charlie, None, &proof));
+    let proof = block.extrinsic_proof(0).unwrap();
+    assert!(!light.verify_extrinsic(unknown, &transactions[0], &proof));
+}
@@ -0,0 +1,512 @@
+//! Sync imports every block from where a client left off, and import executes every block it
+//! imports. A new client therefore has to execute the whole history of the chain before it
+//! reaches the tip, which takes longer and longer as the chain grows.
+//!
+//! Warp sync skips that. Instead of executing the history, the client downloads the state
+//! after a finalized block, called a state snapshot, and starts from there:
+//! 1. Ask a peer for a snapshot: the finalized block, its receipts and the entries of the state
+//!    after it.
+//! 2. Ask for the headers from genesis up to that block, a batch at a time. Check that they
+//!    link up with the genesis header we trust, and with each other, and that their seals pass
+//!    `Consensus::verify_sub_chain`. Headers are small, so this is quick even for a long chain.
+//! 3. Check the snapshot against the last of those headers. The block must be the one the
+//!    header belongs to, the receipts must have the header's receipts root, and the state
+//!    entries must have the header's state root. A peer cannot make up a state that matches the
+//!    root, so the state is exactly the one every node executing the chain arrived at.
+//! 4. Install the block, its receipts and the state into a fresh `Storage`, as the oldest and
+//!    finalized block.
+//! 5. Import the blocks after it with ordinary sync.
+//!
+//! The client never holds the blocks below the snapshot, nor their states or receipts. It
+//! cannot serve them to other peers, and it cannot reorg below the snapshot, which is fine
+//! because that block is final.
+//!
+//! The seals prove the headers were produced by the consensus rules, but not that the snapshot
+//! block was finalized. There are no finality proofs in this client, so that much is taken on
+//! the peer's word.
+
+use super::p12_sync::{SyncError, SyncPeer, SyncReport};
+use super::p1_data_structure::{Block, Receipt};
+#[cfg(test)]
+use super::p2_importing_blocks::ImportBlock;
+use super::p3_fork_choice::ForkChoice;
+#[cfg(test)]
+use super::p3_fork_choice::LongestChain;
+#[cfg(test)]
+use super::p4_transaction_pool::SimplePool;
+use super::p4_transaction_pool::TransactionPool;
+#[cfg(test)]
+use super::{BasicStorage, Hash};
+use super::{Consensus, FullClient, Header, StateMachine, Storage};
+use super::{StateKey, StateValue};
+#[cfg(test)]
+use crate::c1_state_machine::{AccountedCurrency, AccountingTransaction, BalancesB, User};
+#[cfg(test)]
+use crate::c3_consensus::{ConsensusAuthority, PoaRoundRobinByHeight};
+use crate::{hash, merkle_root, state_root, Decode, DecodeError, Encode, TrieState};
+
+/// The state after a block, together with that block, so a client can start from there.
+pub struct Snapshot<C: Consensus, SM: StateMachine>
+where
+    SM::State: TrieState,
+{
+    /// The block the state comes after.
+    pub block: Block<C, SM>,
+    /// The receipts of executing the block, which its header commits to like the state.
+    pub receipts: Vec<Receipt<SM::Event>>,
+    /// All the entries of the state after the block.
+    pub entries: Vec<(StateKey<SM>, StateValue<SM>)>,
+}
+
+impl<C, SM> Encode for Snapshot<C, SM>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: TrieState,
+    Block<C, SM>: Encode,
+    SM::Event: Encode,
+    StateKey<SM>: Encode,
+    StateValue<SM>: Encode,
+{
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.block.encode_to(out);
+        self.receipts.encode_to(out);
+        self.entries.encode_to(out);
+    }
+}
+
+impl<C, SM> Decode for Snapshot<C, SM>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::State: TrieState,
+    Block<C, SM>: Decode,
+    SM::Event: Decode,
+    StateKey<SM>: Decode,
+    StateValue<SM>: Decode,
+{
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Snapshot {
+            block: Decode::decode(input)?,
+            receipts: Decode::decode(input)?,
+            entries: Decode::decode(input)?,
+        })
+    }
+}
+
+/// A peer that can serve a state snapshot on top of the requests of sync.
+pub trait WarpPeer<C: Consensus, SM: StateMachine>: SyncPeer<C, SM>
+where
+    SM::State: TrieState,
+{
+    /// A snapshot of the state after the peer's finalized block.
+    fn snapshot(&mut self) -> Result<Snapshot<C, SM>, SyncError>;
+}
+
+/// A client serves the snapshot of its own finalized block.
+impl<C, SM, FC, P, S> WarpPeer<C, SM> for FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    FC: ForkChoice<C, SM>,
+    P: TransactionPool<SM>,
+    S: Storage<C, SM>,
+    Block<C, SM>: std::hash::Hash + Clone,
+    SM::State: TrieState + Clone,
+    SM::Transition: Encode,
+    SM::Event: Encode,
+{
+    fn snapshot(&mut self) -> Result<Snapshot<C, SM>, SyncError> {
+        self.export_snapshot().ok_or(SyncError::BadResponse)
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    FC: ForkChoice<C, SM>,
+    P: TransactionPool<SM>,
+    S: Storage<C, SM>,
+    Block<C, SM>: std::hash::Hash + Clone,
+    SM::State: TrieState + Clone,
+    SM::Transition: Encode,
+    SM::Event: Encode,
+{
+    /// A snapshot of the state after the finalized block.
+    /// Returns None if the client does not have the receipts of that block, which is the case
+    /// when its storage started from a snapshot that came without them.
+    pub fn export_snapshot(&self) -> Option<Snapshot<C, SM>> {
+        let block = self
+            .storage
+            .get_block(self.storage.finalized_block())
+            .expect("the finalized block is never pruned");
+        let state = self
+            .storage
+            .get_state(block.header.state_root)
+            .expect("the state after the finalized block is never pruned");
+        let receipts = self.storage.get_receipts(block.hash())?;
+        Some(Snapshot {
+            block,
+            receipts,
+            entries: state.to_entries(),
+        })
+    }
+
+    /// Create a client by warp syncing from the given peer, asking for `batch` headers at a
+    /// time. The genesis header is trusted as given, so it should come from somewhere
+    /// trustworthy, such as a chain spec.
+    ///
+    /// Fails if the snapshot cannot be verified. Once it is installed, the blocks after it are
+    /// synced as usual, and the report tells how that went.
+    pub fn warp_sync(
+        genesis: Header<C::Digest>,
+        state_machine: SM,
+        consensus_engine: C,
+        fork_choice: FC,
+        transaction_pool: P,
+        peer: &mut dyn WarpPeer<C, SM>,
+        batch: u64,
+    ) -> Result<(Self, SyncReport), SyncError> {
+        let batch = batch.max(1);
+        let snapshot = peer.snapshot()?;
+        let target = snapshot.block.header.height;
+
+        // Only the last header is kept, to check the next batch against.
+        let mut last = genesis;
+        let mut from = 1;
+        while from <= target {
+            let count = batch.min(target - from + 1);
+            let headers = peer.headers(from, count)?;
+            let heights_match = headers
+                .iter()
+                .zip(from..)
+                .all(|(header, height)| header.height == height);
+            if headers.is_empty() || headers.len() as u64 > count || !heights_match {
+                return Err(SyncError::BadResponse);
+            }
+
+            if headers[0].parent != last.hash() {
+                return Err(SyncError::BadLinkage);
+            }
+            if !consensus_engine.verify_sub_chain(&last.consensus_digest, &headers) {
+                return Err(SyncError::BadSeal);
+            }
+            if headers.windows(2).any(|w| w[1].parent != w[0].hash()) {
+                return Err(SyncError::BadLinkage);
+            }
+
+            from += headers.len() as u64;
+            last = headers.into_iter().last().expect("checked not to be empty");
+        }
+
+        let state = SM::State::from_entries(snapshot.entries);
+        if snapshot.block.hash() != last.hash()
+            || merkle_root(&snapshot.block.body) != last.extrinsics_root
+            || hash(&snapshot.receipts) != last.receipts_root
+            || state_root(&state) != last.state_root
+        {
+            return Err(SyncError::BadSnapshot);
+        }
+
+        let block_hash = snapshot.block.hash();
+        let mut storage = S::from_snapshot(snapshot.block, state);
+        storage.set_receipts(block_hash, snapshot.receipts);
+        let mut client = Self::resume(
+            state_machine,
+            consensus_engine,
+            fork_choice,
+            transaction_pool,
+            storage,
+        );
+        let report = client.sync(&mut [peer], batch);
+        Ok((client, report))
+    }
+}
+
+// --- TESTS ---
+
+#[cfg(test)]
+type TestClient = FullClient<
+    PoaRoundRobinByHeight,
+    AccountedCurrency,
+    LongestChain,
+    SimplePool<AccountedCurrency>,
+    BasicStorage<PoaRoundRobinByHeight, AccountedCurrency>,
+>;
+
+#[cfg(test)]
+fn engine_for_test() -> PoaRoundRobinByHeight {
+    PoaRoundRobinByHeight {
+        authorities: vec![ConsensusAuthority::Alice, ConsensusAuthority::Bob],
+    }
+}
+
+#[cfg(test)]
+fn client_for_test() -> TestClient {
+    TestClient::new(
+        BalancesB::default(),
+        AccountedCurrency::default(),
+        engine_for_test(),
+        LongestChain::default(),
+        SimplePool::default(),
+    )
+}
+
+/// Author the given number of blocks on top of the best block, each minting a different
+/// amount starting from `first_amount`.
+#[cfg(test)]
+fn author_blocks(client: &mut TestClient, count: u64, first_amount: u64) {
+    for amount in first_amount..first_amount + count {
+        assert!(client.submit_transaction(AccountingTransaction::Mint {
+            minter: User::Alice,
+            amount,
+            nonce: client.current_state().nonce(&User::Alice),
+        }));
+        client.author_and_import_automatic_block().unwrap();
+    }
+}
+
+/// A peer with 10 blocks, of which the first 6 are finalized.
+#[cfg(test)]
+fn peer_client_for_test() -> TestClient {
+    let mut client = client_for_test();
+    author_blocks(&mut client, 10, 1);
+    let finalized = client.canonical_block_hash(6).unwrap();
+    assert!(client.manually_finalize_block(finalized));
+    client
+}
+
+#[cfg(test)]
+fn warp_sync_for_test(
+    peer: &mut dyn WarpPeer<PoaRoundRobinByHeight, AccountedCurrency>,
+    batch: u64,
+) -> Result<(TestClient, SyncReport), SyncError> {
+    TestClient::warp_sync(
+        Block::<PoaRoundRobinByHeight, AccountedCurrency>::genesis(&BalancesB::default()).header,
+        AccountedCurrency::default(),
+        engine_for_test(),
+        LongestChain::default(),
+        SimplePool::default(),
+        peer,
+        batch,
+    )
+}
+
+/// The ways a tampering peer can change what it serves.
+#[cfg(test)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Tamper {
+    /// Serves a snapshot with an extra balance in its state.
+    Entries,
+    /// Serves a snapshot whose block has a transaction its header does not commit to.
+    Body,
+    /// Serves a snapshot without the receipts its header commits to.
+    Receipts,
+    /// Serves a snapshot of a block below the finalized one.
+    OlderBlock,
+    /// Serves the header at this height sealed by the wrong authority.
+    SealAt(u64),
+    /// Serves the header at this height with a parent that is not the header before it.
+    LinkAt(u64),
+}
+
+/// A peer that serves the chain of its own client, but tampers with part of it.
+#[cfg(test)]
+struct TamperingPeer {
+    client: TestClient,
+    tamper: Tamper,
+}
+
+#[cfg(test)]
+impl SyncPeer<PoaRoundRobinByHeight, AccountedCurrency> for TamperingPeer {
+    fn best_height(&mut self) -> Result<u64, SyncError> {
+        self.client.best_height()
+    }
+
+    fn headers(
+        &mut self,
+        from: u64,
+        count: u64,
+    ) -> Result<Vec<Header<ConsensusAuthority>>, SyncError> {
+        let mut headers = self.client.headers(from, count)?;
+        for header in &mut headers {
+            match self.tamper {
+                Tamper::SealAt(height) if header.height == height => {
+                    header.consensus_digest = ConsensusAuthority::Charlie
+                }
+                Tamper::LinkAt(height) if header.height == height => header.parent = Hash::from(7),
+                _ => {}
+            }
+        }
+        Ok(headers)
+    }
+
+    fn blocks(
+        &mut self,
+        hashes: &[Hash],
+    ) -> Result<Vec<Block<PoaRoundRobinByHeight, AccountedCurrency>>, SyncError> {
+        self.client.blocks(hashes)
+    }
+}
+
+#[cfg(test)]
+impl WarpPeer<PoaRoundRobinByHeight, AccountedCurrency> for TamperingPeer {
+    fn snapshot(
+        &mut self,
+    ) -> Result<Snapshot<PoaRoundRobinByHeight, AccountedCurrency>, SyncError> {
+        let mut snapshot = self.client.snapshot()?;
+        match self.tamper {
+            Tamper::Entries => {
+                let mut state = BalancesB::from_entries(snapshot.entries);
+                state.balances.insert(User::Charlie, 1000);
+                snapshot.entries = state.to_entries();
+            }
+            Tamper::Body => snapshot.block.body.push(AccountingTransaction::Mint {
+                minter: User::Charlie,
+                amount: 1000,
+                nonce: 0,
+            }),
+            Tamper::Receipts => snapshot.receipts.clear(),
+            Tamper::OlderBlock => {
+                let older = self.client.canonical_block_hash(4).unwrap();
+                snapshot.block = self.client.get_block(older).unwrap();
+            }
+            Tamper::SealAt(_) | Tamper::LinkAt(_) => {}
+        }
+        Ok(snapshot)
+    }
+}
+
+#[test]
+fn cl14_warp_syncs_to_the_tip_from_the_finalized_state() {
+    let mut peer = peer_client_for_test();
+    let finalized = peer.finalized_block();
+
+    let (mut client, report) = warp_sync_for_test(&mut peer, 4).unwrap();
+    // Only the blocks after the snapshot are imported, and none of the history below it.
+    assert_eq!(
+        report,
+        SyncReport {
+            imported: 4,
+            failed: vec![]
+        }
+    );
+    assert_eq!(client.get_last_block().hash(), peer.get_last_block().hash());
+    assert_eq!(
+        client.current_state().balances,
+        peer.current_state().balances
+    );
+    assert_eq!(client.finalized_block(), finalized);
+    assert_eq!(client.canonical_block_hash(6), Some(finalized));
+    assert_eq!(client.canonical_block_hash(5), None);
+    assert_eq!(client.get_receipts(finalized), peer.get_receipts(finalized));
+    assert!(client
+        .get_receipts(peer.canonical_block_hash(5).unwrap())
+        .is_none());
+    assert!(client
+        .get_block(peer.canonical_block_hash(5).unwrap())
+        .is_none());
+
+    // From here on, the client follows the chain like any other.
+    author_blocks(&mut peer, 2, 100);
+    assert_eq!(client.sync(&mut [&mut peer], 4).imported, 2);
+    author_blocks(&mut client, 1, 200);
+    assert_eq!(client.get_last_block().header.height, 13);
+}
+
+#[test]
+fn cl14_canonical_chain_starts_at_the_snapshot() {
+    let mut peer = peer_client_for_test();
+    let (client, _) = warp_sync_for_test(&mut peer, 4).unwrap();
+
+    let chain: Vec<Hash> = client.canonical_chain().map(|b| b.hash()).collect();
+    let expected: Vec<Hash> = peer.canonical_chain_from(6).map(|b| b.hash()).collect();
+    assert_eq!(chain.len(), 5);
+    assert_eq!(chain, expected);
+}
+
+#[test]
+fn cl14_warp_syncs_from_genesis_when_nothing_is_finalized() {
+    let mut peer = client_for_test();
+    author_blocks(&mut peer, 3, 1);
+
+    let (client, report) = warp_sync_for_test(&mut peer, 4).unwrap();
+    assert_eq!(report.imported, 3);
+    assert_eq!(client.get_last_block().hash(), peer.get_last_block().hash());
+    assert_eq!(client.canonical_block_hash(0), peer.canonical_block_hash(0));
+}
+
+#[test]
+fn cl14_rejects_a_snapshot_that_does_not_match_its_header() {
+    for tamper in [
+        Tamper::Entries,
+        Tamper::Body,
+        Tamper::Receipts,
+        Tamper::OlderBlock,
+    ] {
+        let mut peer = TamperingPeer {
+            client: peer_client_for_test(),
+            tamper,
+        };
+        assert_eq!(
+            warp_sync_for_test(&mut peer, 4).err(),
+            Some(SyncError::BadSnapshot),
+            "{:?}",
+            tamper
+        );
+    }
+}
+
+#[test]
+fn cl14_rejects_headers_that_do_not_check_out() {
+    for (tamper, error) in [
+        (Tamper::SealAt(5), SyncError::BadSeal),
+        (Tamper::LinkAt(5), SyncError::BadLinkage),
+        (Tamper::LinkAt(1), SyncError::BadLinkage),
+    ] {
+        let mut peer = TamperingPeer {
+            client: peer_client_for_test(),
+            tamper,
+        };
+        assert_eq!(
+            warp_sync_for_test(&mut peer, 4).err(),
+            Some(error),
+            "{:?}",
+            tamper
+        );
+    }
+}
+
+#[test]
+fn cl14_rejects_a_peer_on_another_chain() {
+    let mut other_genesis = BalancesB::default();
+    other_genesis.balances.insert(User::Alice, 10);
+    let mut peer = TestClient::new(
+        other_genesis,
+        AccountedCurrency::default(),
+        engine_for_test(),
+        LongestChain::default(),
+        SimplePool::default(),
+    );
+    author_blocks(&mut peer, 3, 1);
+    let finalized = peer.canonical_block_hash(2).unwrap();
+    assert!(peer.manually_finalize_block(finalized));
+
+    assert_eq!(
+        warp_sync_for_test(&mut peer, 4).err(),
+        Some(SyncError::BadLinkage)
+    );
+}
+
+#[test]
+fn cl14_snapshot_round_trips_through_the_codec() {
+    let snapshot = peer_client_for_test().export_snapshot().unwrap();
+    let decoded =
+        Snapshot::<PoaRoundRobinByHeight, AccountedCurrency>::decode_all(&snapshot.encode())
+            .unwrap();
+    assert_eq!(decoded.block.hash(), snapshot.block.hash());
+    assert_eq!(decoded.block.body, snapshot.block.body);
+    assert_eq!(decoded.receipts, snapshot.receipts);
+    assert_eq!(decoded.entries, snapshot.entries);
+}
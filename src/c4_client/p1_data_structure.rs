@@ -19,42 +19,54 @@
 use crate::c1_state_machine::AccountedCurrency;
 use crate::c3_consensus::Pow;
 use crate::hash;
+use crate::{merkle_proof, merkle_root, state_root, MerkleProof, TrieState};
+use crate::{Decode, DecodeError, Encode};
 
 use super::p4_transaction_pool::TransactionPool;
 use super::{Consensus, ForkChoice, Header, StateMachine, Storage};
 
-use super::FullClient;
-type Hash = u64;
+use super::{FullClient, DEFAULT_BLOCK_WEIGHT_LIMIT};
+type Hash = crate::H256;
+
+impl<Digest: Encode> Header<Digest> {
+    /// The hash of this header, which is also the hash that identifies its block. It is taken
+    /// over the header's encoding, so any node can compute it from the bytes it received.
+    pub fn hash(&self) -> Hash {
+        self.encoded_hash()
+    }
+}
 
 impl<Digest> Header<Digest>
 where
-    Digest: Default + std::hash::Hash,
+    Digest: Default + std::hash::Hash + Encode,
 {
     /// Returns a new valid genesis header.
     fn genesis(genesis_state_root: Hash) -> Self {
         return Header {
-            parent: 0,
+            parent: 0.into(),
             height: 0,
             state_root: genesis_state_root,
-            extrinsics_root: hash(&Vec::<u8>::new()),
+            extrinsics_root: merkle_root(&Vec::<u8>::new()),
+            receipts_root: hash(&Vec::<u8>::new()),
             consensus_digest: Digest::default(),
         };
     }
 
     /// Create and return a valid child header.
-    fn child(&self, state_root: Hash, extrinsics_root: Hash) -> Self {
+    fn child(&self, state_root: Hash, extrinsics_root: Hash, receipts_root: Hash) -> Self {
         return Header {
-            parent: hash(&self),
+            parent: self.hash(),
             height: self.height + 1,
             state_root,
             extrinsics_root,
+            receipts_root,
             consensus_digest: Digest::default(),
         };
     }
 
     /// Verify a single child header.
     fn verify_child(&self, child: &Self) -> bool {
-        if child.parent != hash(&self) {
+        if child.parent != self.hash() {
             return false;
         }
         if child.height != self.height + 1 {
@@ -63,6 +75,12 @@ where
         return true;
     }
 
+    /// Verify that the given extrinsic is part of the block with this header, using a Merkle
+    /// proof instead of the complete block body.
+    pub fn verify_extrinsic<T: Encode>(&self, extrinsic: &T, proof: &MerkleProof) -> bool {
+        proof.verify(&self.extrinsics_root, extrinsic)
+    }
+
     /// Verify that all the given headers form a valid chain from this header to the tip.
     fn verify_sub_chain(&self, chain: &[Self]) -> bool {
         let mut parent = self;
@@ -79,34 +97,174 @@ where
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Block<C: Consensus, SM: StateMachine> {
     pub header: Header<C::Digest>,
+    /// The account that authored this block and collects its fees, if any. The author is
+    /// not part of the header, but it is still committed to. Paying a different author
+    /// leads to a different post-state and therefore a different state root.
+    pub author: Option<SM::AccountId>,
     pub body: Vec<SM::Transition>,
 }
 
+impl<C: Consensus, SM: StateMachine> Block<C, SM> {
+    /// The hash identifying this block. Blocks are identified by their header's hash, which is
+    /// what children refer to as their parent. The header commits to the rest of the block.
+    pub fn hash(&self) -> Hash {
+        self.header.hash()
+    }
+}
+
+/// A record of what happened when a single extrinsic was executed.
+///
+/// Receipts are not stored in the block itself. Like the state, they are the result of
+/// executing the block and the header only commits to them through its receipts root.
+///
+/// Blocks only ever contain extrinsics that executed successfully, so every receipt
+/// describes a success. Extrinsics that fail are rejected with the state machine's
+/// error before they get anywhere near a block.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Receipt<Event> {
+    /// The position of the extrinsic in the block body
+    pub extrinsic_index: usize,
+    /// The events emitted by the extrinsic, in the order they happened
+    pub events: Vec<Event>,
+}
+
+/// The outcome of executing a block body: the post-state and one receipt per extrinsic,
+/// or the error of the first extrinsic that failed.
+pub type ExecutionOutcome<SM> = Result<
+    (
+        <SM as StateMachine>::State,
+        Vec<Receipt<<SM as StateMachine>::Event>>,
+    ),
+    <SM as StateMachine>::Error,
+>;
+
+impl<C, SM> Encode for Block<C, SM>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::AccountId: Encode,
+    SM::Transition: Encode,
+{
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.header.encode_to(out);
+        self.author.encode_to(out);
+        self.body.encode_to(out);
+    }
+}
+
+impl<C, SM> Decode for Block<C, SM>
+where
+    C: Consensus,
+    SM: StateMachine,
+    SM::AccountId: Decode,
+    SM::Transition: Decode,
+{
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Block {
+            header: Decode::decode(input)?,
+            author: Decode::decode(input)?,
+            body: Decode::decode(input)?,
+        })
+    }
+}
+
+impl<Event: Encode> Encode for Receipt<Event> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.extrinsic_index.encode_to(out);
+        self.events.encode_to(out);
+    }
+}
+
+impl<Event: Decode> Decode for Receipt<Event> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Receipt {
+            extrinsic_index: Decode::decode(input)?,
+            events: Decode::decode(input)?,
+        })
+    }
+}
+
+/// Execute the given extrinsics in order on top of the pre-state, then finalize the
+/// block on behalf of its author.
+///
+/// Returns the post-state along with one receipt per extrinsic, or the error of the
+/// first extrinsic that is not a valid transition.
+pub fn execute_extrinsics<SM: StateMachine>(
+    pre_state: &SM::State,
+    author: Option<&SM::AccountId>,
+    extrinsics: &[SM::Transition],
+) -> ExecutionOutcome<SM>
+where
+    SM::State: Clone,
+{
+    let mut state = pre_state.clone();
+    let mut receipts = Vec::new();
+    for (extrinsic_index, extrinsic) in extrinsics.iter().enumerate() {
+        let (new_state, events) = SM::try_next_state_with_events(&state, extrinsic)?;
+        state = new_state;
+        receipts.push(Receipt {
+            extrinsic_index,
+            events,
+        });
+    }
+    SM::finalize_block(&mut state, author);
+    Ok((state, receipts))
+}
+
 impl<C: Consensus, SM: StateMachine> Block<C, SM>
 where
     C::Digest: Default + std::hash::Hash,
-    SM::State: std::hash::Hash + Clone,
-    SM::Transition: std::hash::Hash + Clone,
+    SM::State: TrieState + Clone,
+    SM::Transition: Encode + Clone,
+    SM::Event: Encode,
 {
     /// Returns a new valid genesis block. By convention this block has no extrinsics.
     pub fn genesis(genesis_state: &SM::State) -> Self {
         return Block {
-            header: Header::genesis(hash(&genesis_state)),
+            header: Header::genesis(state_root(genesis_state)),
+            author: None,
             body: Vec::<SM::Transition>::new(),
         };
     }
 
     /// Create and return a valid child block.
-    pub fn child(&self, pre_state: &SM::State, extrinsics: Vec<SM::Transition>) -> Self {
-        let mut new_state = pre_state.clone();
-        for e in extrinsics.clone() {
-            new_state = SM::next_state(&new_state.clone(), &e)
-        }
+    ///
+    /// Fails if any of the extrinsics is not a valid transition, because a block
+    /// containing it would not be valid either.
+    pub fn child(
+        &self,
+        pre_state: &SM::State,
+        extrinsics: Vec<SM::Transition>,
+    ) -> Result<Self, SM::Error> {
+        self.child_with_author(pre_state, extrinsics, None)
+    }
 
-        return Block {
-            header: Header::child(&self.header, hash(&new_state), hash(&extrinsics)),
+    /// Like `child`, but the block's fees are paid to the given author.
+    pub fn child_with_author(
+        &self,
+        pre_state: &SM::State,
+        extrinsics: Vec<SM::Transition>,
+        author: Option<SM::AccountId>,
+    ) -> Result<Self, SM::Error> {
+        let (new_state, receipts) =
+            execute_extrinsics::<SM>(pre_state, author.as_ref(), &extrinsics)?;
+
+        Ok(Block {
+            header: Header::child(
+                &self.header,
+                state_root(&new_state),
+                merkle_root(&extrinsics),
+                hash(&receipts),
+            ),
+            author,
             body: extrinsics,
-        };
+        })
+    }
+
+    /// Build a proof that the extrinsic at the given index is part of this block. It can be
+    /// checked against the header alone with `Header::verify_extrinsic`.
+    pub fn extrinsic_proof(&self, index: usize) -> Option<MerkleProof> {
+        merkle_proof(&self.body, index)
     }
 
     /// Verify that all the given blocks form a valid chain from this block to the tip.
@@ -117,15 +275,27 @@ where
         for i in 0..chain.len() {
             let next_block = chain.get(i).unwrap();
 
-            for extr in &next_block.body.clone() {
-                curr_state = SM::next_state(&curr_state.clone(), &extr)
+            let receipts = match execute_extrinsics::<SM>(
+                &curr_state,
+                next_block.author.as_ref(),
+                &next_block.body,
+            ) {
+                Ok((state, receipts)) => {
+                    curr_state = state;
+                    receipts
+                }
+                Err(_) => return false,
+            };
+
+            if state_root(&curr_state) != next_block.header.state_root {
+                return false;
             }
 
-            if hash(&curr_state) != next_block.header.state_root {
+            if hash(&receipts) != next_block.header.receipts_root {
                 return false;
             }
 
-            if hash(&next_block.body) != next_block.header.extrinsics_root {
+            if merkle_root(&next_block.body) != next_block.header.extrinsics_root {
                 return false;
             }
 
@@ -143,8 +313,9 @@ where
     C: Consensus,
     SM: StateMachine,
     C::Digest: Default + std::hash::Hash,
-    SM::State: std::hash::Hash + Clone,
-    SM::Transition: std::hash::Hash + Clone,
+    SM::State: TrieState + Clone,
+    SM::Transition: Encode + Clone,
+    SM::Event: Encode,
     Block<C, SM>: Clone,
 {
     let mut chain = Vec::<Block<C, SM>>::new();
@@ -153,7 +324,9 @@ where
     chain.push(g.clone());
 
     for _ in 1..n {
-        let new_block = Block::child(&g, &genesis_state.clone(), Vec::new());
+        let new_block = Block::child(&g, &genesis_state.clone(), Vec::new())
+            .ok()
+            .expect("a block without extrinsics is always valid");
         chain.push(new_block);
     }
 
@@ -170,25 +343,58 @@ where
     FC: ForkChoice<C, SM>,
     P: TransactionPool<SM>,
     S: Storage<C, SM>,
+    SM::State: TrieState,
     Block<C, SM>: std::hash::Hash,
 {
-    fn new(
+    /// Create a client whose chain starts at the genesis block for the given state.
+    pub fn new(
         genesis_state: SM::State,
         state_machine: SM,
         consensus_engine: C,
         fork_choice: FC,
         transaction_pool: P,
-        mut storage: S,
     ) -> Self {
-        storage.set_state(genesis_state);
-        let mut client = FullClient {
+        let storage = S::with_genesis(genesis_state);
+        Self::resume(
+            state_machine,
+            consensus_engine,
+            fork_choice,
+            transaction_pool,
+            storage,
+        )
+    }
+
+    /// Create a client that carries on with the chain already kept in the given storage,
+    /// such as one reopened from disk. The fork choice only lives in memory, so it is told
+    /// about the canonical chain from the finalized block onwards again before the client
+    /// builds on it.
+    pub(crate) fn resume(
+        state_machine: SM,
+        consensus_engine: C,
+        mut fork_choice: FC,
+        transaction_pool: P,
+        storage: S,
+    ) -> Self {
+        let mut height = storage
+            .get_block(storage.finalized_block())
+            .map_or(0, |finalized| finalized.header.height);
+        while let Some(block_hash) = storage.canonical_hash(height) {
+            if let Some(block) = storage.get_block(block_hash) {
+                fork_choice.import_hook(block);
+            }
+            height += 1;
+        }
+        FullClient {
             consensus_engine,
             state_machine,
             fork_choice,
             transaction_pool,
             storage,
-        };
-        return client;
+            author: None,
+            block_weight_limit: DEFAULT_BLOCK_WEIGHT_LIMIT,
+            chain_subscribers: Vec::new(),
+            import_subscribers: Vec::new(),
+        }
     }
 }
 
@@ -202,6 +408,7 @@ where
     SM: StateMachine,
     FC: ForkChoice<C, SM>,
     S: Storage<C, SM>,
+    SM::State: TrieState,
     Block<C, SM>: std::hash::Hash,
 {
     fn default() -> Self {
@@ -213,6 +420,7 @@ impl Default for Block<Pow, AccountedCurrency> {
     fn default() -> Self {
         Block {
             header: Header::<<Pow as Consensus>::Digest>::default(),
+            author: None,
             body: Vec::new(),
         }
     }
@@ -225,6 +433,7 @@ impl Default for Header<<Pow as Consensus>::Digest> {
             height: Default::default(),
             state_root: Default::default(),
             extrinsics_root: Default::default(),
+            receipts_root: Default::default(),
             consensus_digest: Default::default(),
         }
     }
@@ -2,88 +2,158 @@
 //! blocks and headers. Full clients import entire blocks while light clients only import headers.
 
 use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver};
 use std::u64;
 
+#[cfg(test)]
+use super::p3_fork_choice::LongestChain;
+use super::p4_transaction_pool::TransactionPool;
+#[cfg(test)]
+use super::test_helpers::mint;
+#[cfg(test)]
+use crate::c1_state_machine::SignedAccountedCurrency;
+#[cfg(test)]
+use crate::c1_state_machine::User;
+#[cfg(test)]
 use crate::c1_state_machine::{AccountedCurrency, AccountingTransaction};
-use crate::c1_state_machine::{Balances, User};
-use crate::c3_consensus::change_difficulty;
+#[cfg(test)]
+use crate::c1_state_machine::{AccountingError, AccountingEvent, BalancesKey, SignedExtrinsic};
 use crate::c3_consensus::Forked;
 use crate::c3_consensus::Pow;
 use crate::hash;
+use crate::{merkle_root, state_root, Encode, StateProof, TrieState};
 
+use super::p1_data_structure::{execute_extrinsics, Receipt};
 use super::BasicStorage;
-use super::{Block, Consensus, FullClient, StateMachine, Storage};
+#[cfg(test)]
+use super::DEFAULT_BLOCK_WEIGHT_LIMIT;
+use super::{Block, Consensus, ForkChoice, FullClient, Hash, StateMachine, Storage};
+use super::{StateKey, StateValue};
 
 /// A trait that represents the ability to import complete blocks of the chain.
 ///
 /// The main method here is `import_block` but several other methods are provided
 /// to access data about imported blocks.
-pub trait ImportBlock<C: Consensus, SM: StateMachine> {
+pub trait ImportBlock<C: Consensus, SM: StateMachine>
+where
+    SM::State: TrieState,
+{
     /// Attempt to import a block.
-    /// Returns whether the import was successful or not.
+    /// Returns whether the import was successful or not. A block that is already known is
+    /// not imported again.
     fn import_block(&mut self, _: Block<C, SM>) -> bool;
 
+    /// The best block according to the fork choice rule. Importing a block may move it,
+    /// possibly onto another fork.
     fn get_last_block(&self) -> Block<C, SM>;
 
+    /// The state after the best block.
     fn current_state(&self) -> SM::State;
 
     /// Retrieve the full body of an imported block.
     /// Returns None if the block is not known.
-    fn get_block(&self, block_hash: u64) -> Option<Block<C, SM>>;
+    fn get_block(&self, block_hash: Hash) -> Option<Block<C, SM>>;
+
+    /// Retrieve the receipts produced by executing an imported block, one per extrinsic.
+    /// Returns None if the block is not known.
+    fn get_receipts(&self, block_hash: Hash) -> Option<Vec<Receipt<SM::Event>>>;
 
-    // Retrieve the state associated with a given block.
-    // Returns None if the block is not known.
-    // fn get_state(&self, block_hash: u64) -> Option<SM::State>;
+    /// Prove the value of a single state entry after an imported block. The proof can be
+    /// checked against the block's header alone.
+    /// Returns None if the block is not known.
+    fn state_proof(
+        &self,
+        block_hash: Hash,
+        key: &StateKey<SM>,
+    ) -> Option<StateProof<StateKey<SM>, StateValue<SM>>>;
 
-    // Check whether a given block is a leaf (aka tip) of the chain.
-    // A leaf block has no known children.
-    // Returns None if the block is not known.
-    // fn is_leaf(&self, block_hash: u64) -> Option<bool>;
+    /// Retrieve the state associated with a given block.
+    /// Returns None if the block is not known.
+    fn get_state(&self, block_hash: Hash) -> Option<SM::State>;
 
-    // Get a list of all the leaf nodes in the chain.
-    // fn all_leaves(&self) -> Vec<u64>;
+    /// Check whether a given block is a leaf (aka tip) of the chain.
+    /// A leaf block has no known children.
+    /// Returns None if the block is not known.
+    fn is_leaf(&self, block_hash: Hash) -> Option<bool>;
+
+    /// Get a list of all the leaf nodes in the chain.
+    fn all_leaves(&self) -> Vec<Hash>;
 }
 
 impl<C, SM, FC, P, S> ImportBlock<C, SM> for FullClient<C, SM, FC, P, S>
 where
     C: Consensus,
     SM: StateMachine,
+    FC: ForkChoice<C, SM>,
+    P: TransactionPool<SM>,
     S: Storage<C, SM>,
     Block<C, SM>: std::hash::Hash + Clone,
-    SM::State: std::hash::Hash,
-    SM::Transition: std::hash::Hash,
+    SM::State: TrieState + Clone,
+    SM::Transition: Encode,
+    SM::Event: Encode,
 {
     fn import_block(&mut self, block: Block<C, SM>) -> bool {
-        let last_block: Block<C, SM> = self.storage.get_last_block();
+        if self.storage.get_block(block.hash()).is_some() {
+            return false;
+        }
 
-        if block.header.height != last_block.header.height + 1 {
+        // The parent may be any known block, not just the last one. This is how forks get in.
+        let Some(parent) = self.storage.get_block(block.header.parent) else {
+            return false;
+        };
+
+        if block.header.height != parent.header.height + 1 {
             return false;
         }
-        if block.header.parent != hash(&last_block.header) {
+
+        if self.forks_below_finality(&parent) {
             return false;
         }
 
         if !self
             .consensus_engine
-            .validate(&last_block.header.consensus_digest, &block.header)
+            .validate(&parent.header.consensus_digest, &block.header)
         {
             return false;
         }
 
-        let mut current_state = self.storage.current_state();
-        for tr in &block.body {
-            current_state = SM::next_state(&current_state, &tr);
+        // A block containing an invalid transition is invalid as a whole.
+        // The stateless checks are cheap, so they go first.
+        if block
+            .body
+            .iter()
+            .any(|t| SM::validate_transaction(t).is_err())
+        {
+            return false;
         }
 
-        if hash(&current_state) != block.header.state_root {
+        // Execute on top of the parent's post-state, which is not necessarily the current one.
+        let Some(parent_state) = self.storage.get_state(parent.header.state_root) else {
+            return false;
+        };
+        let (post_state, receipts) =
+            match execute_extrinsics::<SM>(&parent_state, block.author.as_ref(), &block.body) {
+                Ok(executed) => executed,
+                Err(_) => return false,
+            };
+
+        if state_root(&post_state) != block.header.state_root {
             return false;
         }
-        if hash(&block.body) != block.header.extrinsics_root {
+        if merkle_root(&block.body) != block.header.extrinsics_root {
+            return false;
+        }
+        if hash(&receipts) != block.header.receipts_root {
             return false;
         }
 
-        self.storage.set_state(current_state);
-        self.storage.add_block(block);
+        self.storage.set_state(post_state);
+        self.storage.set_receipts(block.hash(), receipts);
+        self.storage.add_block(block.clone());
+        let block_hash = block.hash();
+        self.fork_choice.import_hook(block);
+        self.follow_fork_choice();
+        self.announce_import(block_hash);
 
         return true;
     }
@@ -96,42 +166,63 @@ where
         self.storage.current_state()
     }
 
-    fn get_block(&self, block_hash: u64) -> Option<Block<C, SM>> {
+    fn get_block(&self, block_hash: Hash) -> Option<Block<C, SM>> {
         self.storage.get_block(block_hash)
     }
 
-    // fn get_state(&self, block_hash: u64) -> Option<<SM as StateMachine>::State> {
-    //     todo!("Exercise 3")
-    // }
+    fn get_receipts(&self, block_hash: Hash) -> Option<Vec<Receipt<SM::Event>>> {
+        self.storage.get_receipts(block_hash)
+    }
 
-    // fn is_leaf(&self, block_hash: u64) -> Option<bool> {
-    //     todo!("Exercise 4")
-    // }
+    fn state_proof(
+        &self,
+        block_hash: Hash,
+        key: &StateKey<SM>,
+    ) -> Option<StateProof<StateKey<SM>, StateValue<SM>>> {
+        let block = self.storage.get_block(block_hash)?;
+        self.storage.state_proof(block.header.state_root, key)
+    }
 
-    // fn all_leaves(&self) -> Vec<u64> {
-    //     todo!("Exercise 5")
-    // }
+    fn get_state(&self, block_hash: Hash) -> Option<<SM as StateMachine>::State> {
+        let block = self.storage.get_block(block_hash)?;
+        self.storage.get_state(block.header.state_root)
+    }
+
+    fn is_leaf(&self, block_hash: Hash) -> Option<bool> {
+        self.storage.get_block(block_hash)?;
+        Some(self.storage.children(block_hash).is_empty())
+    }
+
+    fn all_leaves(&self) -> Vec<Hash> {
+        self.storage.leaves()
+    }
 }
 
-// --- TESTS ---
+impl<C, SM: StateMachine, FC, P, S> FullClient<C, SM, FC, P, S> {
+    /// Subscribe to the hashes of the blocks imported from now on, whether they came from
+    /// elsewhere or were authored here. Drop the receiver to unsubscribe.
+    pub fn subscribe_imports(&mut self) -> Receiver<Hash> {
+        let (sender, receiver) = mpsc::channel();
+        self.import_subscribers.push(sender);
+        receiver
+    }
 
-impl std::hash::Hash for Block<Pow, AccountedCurrency> {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.header.hash(state);
-        self.body.hash(state);
+    /// Tell every subscriber about a block that just entered the block tree.
+    pub(super) fn announce_import(&mut self, block_hash: Hash) {
+        self.import_subscribers
+            .retain(|subscriber| subscriber.send(block_hash).is_ok());
     }
 }
 
+// --- TESTS ---
+
+#[cfg(test)]
 fn init_client_for_test() -> impl ImportBlock<Pow, AccountedCurrency> {
     let consensus_engine = Pow {
         threshold: u64::MAX / 10,
     };
-    let state_machine = AccountedCurrency {};
-    let fork_choice = change_difficulty(
-        3,
-        consensus_engine.threshold,
-        consensus_engine.threshold / 10,
-    );
+    let state_machine = AccountedCurrency::default();
+    let fork_choice = LongestChain::default();
     let transaction_pool = ();
 
     let storage: BasicStorage<Pow, AccountedCurrency> =
@@ -143,6 +234,10 @@ fn init_client_for_test() -> impl ImportBlock<Pow, AccountedCurrency> {
         fork_choice,
         transaction_pool,
         storage,
+        author: None,
+        block_weight_limit: DEFAULT_BLOCK_WEIGHT_LIMIT,
+        chain_subscribers: Vec::new(),
+        import_subscribers: Vec::new(),
     }
 }
 
@@ -153,12 +248,14 @@ fn cl2_import_valid_block() {
     let current_state = client.current_state();
     let extrinsic = AccountingTransaction::Mint {
         minter: User::Alice,
-        amount: 0,
+        amount: 10,
+        nonce: 0,
     };
 
     let valid_next_block = client
         .get_last_block()
-        .child(&current_state, vec![extrinsic]);
+        .child(&current_state, vec![extrinsic])
+        .unwrap();
 
     let imported = client.import_block(valid_next_block);
     assert!(imported);
@@ -171,7 +268,7 @@ fn cl2_import_block_with_invalid_parent() {
     let mut block = Block::<Pow, AccountedCurrency>::genesis(
         &<AccountedCurrency as StateMachine>::State::default(),
     );
-    block.header.parent = 0;
+    block.header.parent = 0.into();
 
     let imported = client.import_block(block);
     assert!(!imported);
@@ -184,12 +281,14 @@ fn cl2_import_block_with_invalid_height() {
     let current_state = client.current_state();
     let extrinsic = AccountingTransaction::Mint {
         minter: User::Alice,
-        amount: 0,
+        amount: 10,
+        nonce: 0,
     };
 
     let mut next_block = client
         .get_last_block()
-        .child(&current_state, vec![extrinsic]);
+        .child(&current_state, vec![extrinsic])
+        .unwrap();
 
     next_block.header.height = 17;
 
@@ -204,14 +303,16 @@ fn cl2_import_block_with_invalid_state_root() {
     let current_state = client.current_state();
     let extrinsic = AccountingTransaction::Mint {
         minter: User::Alice,
-        amount: 0,
+        amount: 10,
+        nonce: 0,
     };
 
     let mut next_block = client
         .get_last_block()
-        .child(&current_state, vec![extrinsic]);
+        .child(&current_state, vec![extrinsic])
+        .unwrap();
 
-    next_block.header.state_root = 12;
+    next_block.header.state_root = 12.into();
 
     let imported = client.import_block(next_block);
     assert!(!imported);
@@ -224,14 +325,16 @@ fn cl2_import_block_with_invalid_transaction_root() {
     let current_state = client.current_state();
     let extrinsic = AccountingTransaction::Mint {
         minter: User::Alice,
-        amount: 0,
+        amount: 10,
+        nonce: 0,
     };
 
     let mut next_block = client
         .get_last_block()
-        .child(&current_state, vec![extrinsic]);
+        .child(&current_state, vec![extrinsic])
+        .unwrap();
 
-    next_block.header.extrinsics_root = 12;
+    next_block.header.extrinsics_root = 12.into();
 
     let imported = client.import_block(next_block);
     assert!(!imported);
@@ -244,12 +347,14 @@ fn cl2_import_block_with_invalid_seal() {
     let current_state = client.current_state();
     let extrinsic = AccountingTransaction::Mint {
         minter: User::Alice,
-        amount: 0,
+        amount: 10,
+        nonce: 0,
     };
 
     let mut next_block = client
         .get_last_block()
-        .child(&current_state, vec![extrinsic]);
+        .child(&current_state, vec![extrinsic])
+        .unwrap();
 
     next_block.header.consensus_digest = u64::MAX;
 
@@ -264,7 +369,7 @@ fn cl2_get_block_existing() {
     let last_block = client.get_last_block();
     assert_eq!(last_block.header.height, 0);
 
-    let block = client.get_block(hash(&last_block));
+    let block = client.get_block(last_block.hash());
     assert!(block.is_some());
     assert_eq!(block.unwrap().header.height, last_block.header.height);
 }
@@ -273,7 +378,7 @@ fn cl2_get_block_existing() {
 fn cl2_get_block_not_existing() {
     let client = init_client_for_test();
 
-    let block = client.get_block(12);
+    let block = client.get_block(12.into());
     assert!(!block.is_some());
 }
 
@@ -284,17 +389,599 @@ fn cl2_import_valid_block_and_get_it() {
     let current_state = client.current_state();
     let extrinsic = AccountingTransaction::Mint {
         minter: User::Alice,
-        amount: 0,
+        amount: 10,
+        nonce: 0,
     };
 
     let valid_next_block = client
         .get_last_block()
-        .child(&current_state, vec![extrinsic]);
+        .child(&current_state, vec![extrinsic])
+        .unwrap();
 
     let imported = client.import_block(valid_next_block.clone());
     assert!(imported);
 
-    let block = client.get_block(hash(&valid_next_block));
+    let block = client.get_block(valid_next_block.hash());
     assert!(block.is_some());
     assert_eq!(block.unwrap().header.height, valid_next_block.header.height);
 }
+
+#[test]
+fn cl2_import_block_with_invalid_transaction() {
+    let mut client = init_client_for_test();
+
+    let current_state = client.current_state();
+    let valid_next_block = client
+        .get_last_block()
+        .child(
+            &current_state,
+            vec![AccountingTransaction::Mint {
+                minter: User::Alice,
+                amount: 10,
+                nonce: 0,
+            }],
+        )
+        .unwrap();
+
+    // Bob has no funds, so this transfer would have been a no-op before.
+    let mut next_block = valid_next_block.clone();
+    next_block.body = vec![AccountingTransaction::Transfer {
+        sender: User::Bob,
+        receiver: User::Alice,
+        amount: 10,
+        nonce: 0,
+    }];
+    next_block.header.extrinsics_root = merkle_root(&next_block.body);
+    next_block.header.state_root = state_root(&current_state);
+
+    let imported = client.import_block(next_block);
+    assert!(!imported);
+}
+
+#[test]
+fn cl2_import_block_replaying_a_transfer() {
+    let mut client = init_client_for_test();
+
+    let transfer = AccountingTransaction::Transfer {
+        sender: User::Alice,
+        receiver: User::Bob,
+        amount: 5,
+        nonce: 1,
+    };
+    let first_block = client
+        .get_last_block()
+        .child(
+            &client.current_state(),
+            vec![
+                AccountingTransaction::Mint {
+                    minter: User::Alice,
+                    amount: 20,
+                    nonce: 0,
+                },
+                transfer.clone(),
+            ],
+        )
+        .unwrap();
+    assert!(client.import_block(first_block.clone()));
+    // Alice could well afford to send the same amount again.
+    assert_eq!(client.current_state().balances.get(&User::Alice), Some(&15));
+
+    // But the very same transfer in the next block has a used up nonce.
+    let mut replay = first_block.clone();
+    replay.header.parent = first_block.hash();
+    replay.header.height = first_block.header.height + 1;
+    replay.body = vec![transfer];
+    replay.header.extrinsics_root = merkle_root(&replay.body);
+
+    assert!(!client.import_block(replay));
+    assert_eq!(client.get_last_block().hash(), first_block.hash());
+}
+
+#[test]
+fn cl2_child_with_invalid_transaction_fails() {
+    let client = init_client_for_test();
+
+    let current_state = client.current_state();
+    let child = client.get_last_block().child(
+        &current_state,
+        vec![AccountingTransaction::Burn {
+            burner: User::Charlie,
+            amount: 10,
+            nonce: 0,
+        }],
+    );
+
+    assert_eq!(child.err(), Some(AccountingError::AccountNotFound));
+}
+
+#[test]
+fn cl2_import_block_with_invalid_receipts_root() {
+    let mut client = init_client_for_test();
+
+    let current_state = client.current_state();
+    let extrinsic = AccountingTransaction::Mint {
+        minter: User::Alice,
+        amount: 10,
+        nonce: 0,
+    };
+
+    let mut next_block = client
+        .get_last_block()
+        .child(&current_state, vec![extrinsic])
+        .unwrap();
+
+    next_block.header.receipts_root = 12.into();
+
+    let imported = client.import_block(next_block);
+    assert!(!imported);
+}
+
+#[test]
+fn cl2_import_valid_block_and_get_its_receipts() {
+    let mut client = init_client_for_test();
+
+    let current_state = client.current_state();
+    let extrinsics = vec![
+        AccountingTransaction::Mint {
+            minter: User::Alice,
+            amount: 10,
+            nonce: 0,
+        },
+        AccountingTransaction::Transfer {
+            sender: User::Alice,
+            receiver: User::Bob,
+            amount: 10,
+            nonce: 1,
+        },
+    ];
+
+    let valid_next_block = client
+        .get_last_block()
+        .child(&current_state, extrinsics)
+        .unwrap();
+
+    assert!(client.import_block(valid_next_block.clone()));
+
+    let receipts = client.get_receipts(valid_next_block.hash()).unwrap();
+    assert_eq!(
+        receipts,
+        vec![
+            Receipt {
+                extrinsic_index: 0,
+                events: vec![
+                    AccountingEvent::AccountCreated {
+                        account: User::Alice
+                    },
+                    AccountingEvent::Minted {
+                        minter: User::Alice,
+                        amount: 10
+                    },
+                ],
+            },
+            Receipt {
+                extrinsic_index: 1,
+                events: vec![
+                    AccountingEvent::AccountReaped {
+                        account: User::Alice
+                    },
+                    AccountingEvent::AccountCreated { account: User::Bob },
+                    AccountingEvent::Transferred {
+                        sender: User::Alice,
+                        receiver: User::Bob,
+                        amount: 10
+                    },
+                ],
+            },
+        ]
+    );
+}
+
+#[test]
+fn cl2_get_receipts_of_unknown_block() {
+    let client = init_client_for_test();
+
+    assert!(client.get_receipts(12.into()).is_none());
+}
+
+#[cfg(test)]
+fn init_signed_client_for_test() -> impl ImportBlock<(), SignedAccountedCurrency> {
+    FullClient {
+        consensus_engine: (),
+        state_machine: SignedAccountedCurrency::default(),
+        fork_choice: (),
+        transaction_pool: (),
+        storage: BasicStorage::<(), SignedAccountedCurrency>::new(),
+        author: None,
+        block_weight_limit: DEFAULT_BLOCK_WEIGHT_LIMIT,
+        chain_subscribers: Vec::new(),
+        import_subscribers: Vec::new(),
+    }
+}
+
+#[test]
+fn cl2_import_block_with_signed_extrinsics() {
+    let mut client = init_signed_client_for_test();
+
+    let current_state = client.current_state();
+    let extrinsic = SignedExtrinsic::new(
+        User::Alice,
+        AccountingTransaction::Mint {
+            minter: User::Alice,
+            amount: 10,
+            nonce: 0,
+        },
+    );
+
+    let next_block = client
+        .get_last_block()
+        .child(&current_state, vec![extrinsic])
+        .unwrap();
+
+    assert!(client.import_block(next_block));
+}
+
+#[test]
+fn cl2_import_block_with_forged_signature() {
+    let mut client = init_signed_client_for_test();
+
+    let current_state = client.current_state();
+    let extrinsic = SignedExtrinsic::new(
+        User::Alice,
+        AccountingTransaction::Mint {
+            minter: User::Alice,
+            amount: 10,
+            nonce: 0,
+        },
+    );
+    let mut next_block = client
+        .get_last_block()
+        .child(&current_state, vec![extrinsic])
+        .unwrap();
+
+    // Swap in a transfer to Bob, which nobody but Alice can sign.
+    // The extrinsics root is recomputed so that it does not give the forgery away.
+    next_block.body[0].call = AccountingTransaction::Transfer {
+        sender: User::Alice,
+        receiver: User::Bob,
+        amount: 10,
+        nonce: 0,
+    };
+    next_block.header.extrinsics_root = merkle_root(&next_block.body);
+
+    assert!(!client.import_block(next_block));
+}
+
+#[test]
+fn cl2_import_block_replaying_an_extrinsic() {
+    let mut client = init_signed_client_for_test();
+
+    let extrinsic = SignedExtrinsic::new(
+        User::Alice,
+        AccountingTransaction::Mint {
+            minter: User::Alice,
+            amount: 10,
+            nonce: 0,
+        },
+    );
+    let genesis_state = client.current_state();
+    let next_block = client
+        .get_last_block()
+        .child(&genesis_state, vec![extrinsic.clone()])
+        .unwrap();
+    assert!(client.import_block(next_block.clone()));
+
+    // The same extrinsic, validly signed, cannot be executed a second time.
+    let post_state = SignedAccountedCurrency::next_state(&genesis_state, &extrinsic);
+    assert!(next_block.child(&post_state, vec![extrinsic]).is_err());
+}
+
+#[test]
+fn cl2_import_block_that_pays_its_author() {
+    let mut client = init_signed_client_for_test();
+
+    let extrinsic = SignedExtrinsic::new(
+        User::Alice,
+        AccountingTransaction::Mint {
+            minter: User::Alice,
+            amount: 10,
+            nonce: 0,
+        },
+    );
+    let next_block = client
+        .get_last_block()
+        .child_with_author(
+            &client.current_state(),
+            vec![extrinsic],
+            Some(User::Charlie),
+        )
+        .unwrap();
+    assert!(client.import_block(next_block.clone()));
+
+    // Claiming the fees for someone else changes the post-state, so the state root no longer matches.
+    let mut stolen = next_block;
+    stolen.author = Some(User::Bob);
+    assert!(!client.import_block(stolen));
+}
+
+#[test]
+fn cl2_imported_block_proves_inclusion_of_extrinsics() {
+    let mut client = init_signed_client_for_test();
+
+    let mint = |minter: User, nonce: u64| {
+        SignedExtrinsic::new(
+            minter,
+            AccountingTransaction::Mint {
+                minter,
+                amount: 10,
+                nonce,
+            },
+        )
+    };
+    let extrinsics = vec![
+        mint(User::Alice, 0),
+        mint(User::Bob, 0),
+        mint(User::Alice, 1),
+    ];
+    let next_block = client
+        .get_last_block()
+        .child(&client.current_state(), extrinsics.clone())
+        .unwrap();
+    assert!(client.import_block(next_block.clone()));
+
+    // A light client holding only the header can check a single extrinsic was included.
+    let header = next_block.header.clone();
+    let proof = next_block.extrinsic_proof(1).unwrap();
+    assert!(header.verify_extrinsic(&extrinsics[1], &proof));
+    assert!(!header.verify_extrinsic(&extrinsics[0], &proof));
+    assert!(!header.verify_extrinsic(&mint(User::Charlie, 0), &proof));
+    assert_eq!(next_block.extrinsic_proof(3), None);
+}
+
+#[test]
+fn cl2_prove_a_balance_at_a_given_block() {
+    let mut client = init_signed_client_for_test();
+
+    let mint = |minter: User, nonce: u64, amount: u64| {
+        SignedExtrinsic::new(
+            minter,
+            AccountingTransaction::Mint {
+                minter,
+                amount,
+                nonce,
+            },
+        )
+    };
+    let genesis = client.get_last_block();
+    let b1 = genesis
+        .child(&client.current_state(), vec![mint(User::Alice, 0, 10)])
+        .unwrap();
+    assert!(client.import_block(b1.clone()));
+
+    let alice = BalancesKey::Balance(User::Alice);
+    let bob = BalancesKey::Balance(User::Bob);
+
+    // Minting 10 costs Alice a fee of 1.
+    let proof = client.state_proof(b1.hash(), &alice).unwrap();
+    assert!(proof.verify(&b1.header.state_root, &alice, Some(&9)));
+    assert!(!proof.verify(&b1.header.state_root, &alice, Some(&10)));
+
+    // Bob has no balance at all, which can be proven too.
+    let proof = client.state_proof(b1.hash(), &bob).unwrap();
+    assert!(proof.verify(&b1.header.state_root, &bob, None));
+
+    // A proof from genesis does not verify against the later header.
+    let proof = client.state_proof(genesis.hash(), &alice).unwrap();
+    assert!(proof.verify(&genesis.header.state_root, &alice, None));
+    assert!(!proof.verify(&b1.header.state_root, &alice, None));
+
+    assert!(client.state_proof(12.into(), &alice).is_none());
+}
+
+#[cfg(test)]
+fn init_forking_client_for_test(
+) -> FullClient<(), AccountedCurrency, LongestChain, (), BasicStorage<(), AccountedCurrency>> {
+    FullClient {
+        consensus_engine: (),
+        state_machine: AccountedCurrency::default(),
+        fork_choice: LongestChain::default(),
+        transaction_pool: (),
+        storage: BasicStorage::new(),
+        author: None,
+        block_weight_limit: DEFAULT_BLOCK_WEIGHT_LIMIT,
+        chain_subscribers: Vec::new(),
+        import_subscribers: Vec::new(),
+    }
+}
+
+#[test]
+fn cl2_import_competing_forks() {
+    let mut client = init_forking_client_for_test();
+    let genesis = client.get_last_block();
+    let genesis_state = client.current_state();
+
+    // Two competing children of genesis
+    let a1 = genesis
+        .child(&genesis_state, vec![mint(User::Alice, 0, 10)])
+        .unwrap();
+    let b1 = genesis
+        .child(&genesis_state, vec![mint(User::Bob, 0, 5)])
+        .unwrap();
+    assert!(client.import_block(a1.clone()));
+    assert!(client.import_block(b1.clone()));
+
+    // And each fork grows on its own post-state
+    let a1_state = AccountedCurrency::next_state(&genesis_state, &a1.body[0]);
+    let b1_state = AccountedCurrency::next_state(&genesis_state, &b1.body[0]);
+    let a2 = a1.child(&a1_state, vec![mint(User::Alice, 1, 1)]).unwrap();
+    let b2 = b1.child(&b1_state, vec![mint(User::Bob, 1, 1)]).unwrap();
+    assert!(client.import_block(a2.clone()));
+    assert!(client.import_block(b2.clone()));
+
+    let mut leaves = client.storage.leaves();
+    leaves.sort();
+    let mut expected = vec![a2.hash(), b2.hash()];
+    expected.sort();
+    assert_eq!(leaves, expected);
+
+    // The fork choice saw the blocks too
+    assert_eq!(
+        <LongestChain as ForkChoice<(), AccountedCurrency>>::best_block(&client.fork_choice),
+        Some(a2.hash())
+    );
+}
+
+#[test]
+fn cl2_fork_block_executes_on_its_parents_state() {
+    let mut client = init_forking_client_for_test();
+    let genesis = client.get_last_block();
+    let genesis_state = client.current_state();
+
+    let a1 = genesis
+        .child(&genesis_state, vec![mint(User::Alice, 0, 10)])
+        .unwrap();
+    assert!(client.import_block(a1.clone()));
+
+    // A sibling of a1 built as if a1 had already executed has a wrong state root.
+    let a1_state = AccountedCurrency::next_state(&genesis_state, &a1.body[0]);
+    let bad_sibling = genesis
+        .child(&a1_state, vec![mint(User::Bob, 0, 5)])
+        .unwrap();
+    assert!(!client.import_block(bad_sibling));
+
+    // While the correct sibling is accepted even though a1 came first.
+    let good_sibling = genesis
+        .child(&genesis_state, vec![mint(User::Bob, 0, 5)])
+        .unwrap();
+    assert!(client.import_block(good_sibling));
+}
+
+#[test]
+fn cl2_import_block_with_unknown_parent() {
+    let mut client = init_forking_client_for_test();
+    let genesis = client.get_last_block();
+    let genesis_state = client.current_state();
+
+    let a1 = genesis
+        .child(&genesis_state, vec![mint(User::Alice, 0, 10)])
+        .unwrap();
+    let a1_state = AccountedCurrency::next_state(&genesis_state, &a1.body[0]);
+    let a2 = a1.child(&a1_state, vec![mint(User::Alice, 1, 1)]).unwrap();
+
+    // a1 was never imported
+    assert!(!client.import_block(a2.clone()));
+    assert!(client.import_block(a1));
+    assert!(client.import_block(a2));
+}
+
+#[test]
+fn cl2_get_state_of_any_known_block() {
+    let mut client = init_forking_client_for_test();
+    let genesis = client.get_last_block();
+    let genesis_state = client.current_state();
+
+    let a1 = genesis
+        .child(&genesis_state, vec![mint(User::Alice, 0, 10)])
+        .unwrap();
+    let b1 = genesis
+        .child(&genesis_state, vec![mint(User::Bob, 0, 5)])
+        .unwrap();
+    assert!(client.import_block(a1.clone()));
+    assert!(client.import_block(b1.clone()));
+
+    // Both forks' states are available, not just the best one.
+    let a1_state = client.get_state(a1.hash()).unwrap();
+    let b1_state = client.get_state(b1.hash()).unwrap();
+    assert_eq!(a1_state.balances.get(&User::Alice), Some(&10));
+    assert_eq!(b1_state.balances.get(&User::Bob), Some(&5));
+    assert_eq!(b1_state.balances.get(&User::Alice), None);
+    assert_eq!(
+        state_root(&client.get_state(genesis.hash()).unwrap()),
+        genesis.header.state_root
+    );
+
+    assert!(client.get_state(42.into()).is_none());
+}
+
+#[test]
+fn cl2_leaves_of_the_block_tree() {
+    let mut client = init_forking_client_for_test();
+    let genesis = client.get_last_block();
+    let genesis_state = client.current_state();
+    assert_eq!(client.all_leaves(), vec![genesis.hash()]);
+    assert_eq!(client.is_leaf(genesis.hash()), Some(true));
+
+    let a1 = genesis
+        .child(&genesis_state, vec![mint(User::Alice, 0, 10)])
+        .unwrap();
+    let b1 = genesis
+        .child(&genesis_state, vec![mint(User::Bob, 0, 5)])
+        .unwrap();
+    let a1_state = AccountedCurrency::next_state(&genesis_state, &a1.body[0]);
+    let a2 = a1.child(&a1_state, vec![mint(User::Alice, 1, 1)]).unwrap();
+    for block in [&a1, &b1, &a2] {
+        assert!(client.import_block(block.clone()));
+    }
+
+    assert_eq!(client.is_leaf(genesis.hash()), Some(false));
+    assert_eq!(client.is_leaf(a1.hash()), Some(false));
+    assert_eq!(client.is_leaf(a2.hash()), Some(true));
+    assert_eq!(client.is_leaf(b1.hash()), Some(true));
+    assert_eq!(client.is_leaf(42.into()), None);
+
+    let mut leaves = client.all_leaves();
+    leaves.sort();
+    let mut expected = vec![a2.hash(), b1.hash()];
+    expected.sort();
+    assert_eq!(leaves, expected);
+
+    assert_eq!(
+        client.storage.children(genesis.hash()),
+        vec![a1.hash(), b1.hash()]
+    );
+    assert_eq!(client.storage.children(a1.hash()), vec![a2.hash()]);
+    assert!(client.storage.children(a2.hash()).is_empty());
+}
+
+#[test]
+fn cl2_reimporting_a_block_keeps_the_tree_intact() {
+    let mut client = init_forking_client_for_test();
+    let genesis = client.get_last_block();
+    let genesis_state = client.current_state();
+
+    let a1 = genesis
+        .child(&genesis_state, vec![mint(User::Alice, 0, 10)])
+        .unwrap();
+    let a1_state = AccountedCurrency::next_state(&genesis_state, &a1.body[0]);
+    let a2 = a1.child(&a1_state, vec![mint(User::Alice, 1, 1)]).unwrap();
+    assert!(client.import_block(a1.clone()));
+    assert!(client.import_block(a2.clone()));
+    assert!(!client.import_block(a1.clone()));
+
+    assert_eq!(client.is_leaf(a1.hash()), Some(false));
+    assert_eq!(client.all_leaves(), vec![a2.hash()]);
+    assert_eq!(client.storage.children(genesis.hash()), vec![a1.hash()]);
+}
+
+#[test]
+fn cl2_subscribers_hear_of_every_imported_block() {
+    let mut client = init_forking_client_for_test();
+    let genesis = client.get_last_block();
+    let genesis_state = client.current_state();
+    let imports = client.subscribe_imports();
+
+    let a1 = genesis
+        .child(&genesis_state, vec![mint(User::Alice, 0, 10)])
+        .unwrap();
+    let b1 = genesis
+        .child(&genesis_state, vec![mint(User::Bob, 0, 5)])
+        .unwrap();
+    assert!(client.import_block(a1.clone()));
+    // Blocks that do not become the best block count too, but those already known do not.
+    assert!(client.import_block(b1.clone()));
+    assert!(!client.import_block(a1.clone()));
+
+    assert_eq!(
+        imports.try_iter().collect::<Vec<_>>(),
+        vec![a1.hash(), b1.hash()]
+    );
+}
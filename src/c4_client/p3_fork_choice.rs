@@ -10,11 +10,12 @@ use std::collections::HashMap;
 use super::p1_data_structure::Block;
 use super::p2_importing_blocks::ImportBlock;
 use super::{BasicStorage, Storage};
-use super::{Consensus, FullClient};
+use super::{Consensus, FullClient, Hash, DEFAULT_BLOCK_WEIGHT_LIMIT};
 use crate::c1_state_machine::AccountedCurrency;
 use crate::c1_state_machine::StateMachine;
 use crate::c3_consensus::{ConsensusAuthority, Pow, SimplePoa};
 use crate::c4_client::Header;
+#[cfg(test)]
 use crate::hash;
 
 /// A means for a blockchain client to decide which chain is best among the many
@@ -30,32 +31,101 @@ where
     Block<C, SM>: std::hash::Hash,
 {
     /// Return the hash of the best block currently known according to this fork choice rule.
-    fn best_block(&self) -> Option<u64>;
+    fn best_block(&self) -> Option<Hash>;
 
     /// Perform some bookkeeping activities when importing a new block.
     fn import_hook(&mut self, block: Block<C, SM>);
+
+    /// Forget every block that does not descend from the newly finalized one, as the client
+    /// will never switch to them. `descendants` are all known descendants of the finalized
+    /// block, parents before children.
+    ///
+    /// By default the rule simply starts over from the finalized block and replays its
+    /// descendants, which works for any rule that is happy to treat it as a genesis.
+    fn finality_hook(&mut self, finalized: Block<C, SM>, descendants: Vec<Block<C, SM>>)
+    where
+        Self: Sized + Default,
+    {
+        *self = Self::default();
+        self.import_hook(finalized);
+        for block in descendants {
+            self.import_hook(block);
+        }
+    }
+}
+
+/// A fork choice rule that can decide between chains from their headers alone. Such a rule
+/// also works for a light client, which never sees block bodies. A rule that implements this
+/// can implement `ForkChoice` by handing over the header of each block.
+pub trait HeaderForkChoice<C: Consensus> {
+    /// Return the hash of the best header currently known according to this fork choice rule.
+    fn best_header(&self) -> Option<Hash>;
+
+    /// Perform some bookkeeping activities when importing a new header.
+    fn import_header(&mut self, header: &Header<C::Digest>);
+
+    /// Forget every header that does not descend from the newly finalized one. `descendants`
+    /// are all known descendants of the finalized header, parents before children.
+    ///
+    /// Like `ForkChoice::finality_hook`, this starts over from the finalized header by default.
+    fn finalize_header(&mut self, finalized: &Header<C::Digest>, descendants: &[Header<C::Digest>])
+    where
+        Self: Sized + Default,
+    {
+        *self = Self::default();
+        self.import_header(finalized);
+        for header in descendants {
+            self.import_header(header);
+        }
+    }
+}
+
+/// The trivial fork choice that never has an opinion. It suits clients that only import
+/// blocks and never need to build on the best one.
+impl<C: Consensus, SM: StateMachine> ForkChoice<C, SM> for ()
+where
+    Block<C, SM>: std::hash::Hash,
+{
+    fn best_block(&self) -> Option<Hash> {
+        None
+    }
+
+    fn import_hook(&mut self, _: Block<C, SM>) {}
 }
 
 /// The chain with the highest block height is the best
 /// TODO: take another look at the implementation
 pub struct LongestChain {
     best_header_height: u64,
-    best_header_hash: u64,
+    best_header_hash: Hash,
+}
+
+impl<C: Consensus> HeaderForkChoice<C> for LongestChain {
+    fn best_header(&self) -> Option<Hash> {
+        Some(self.best_header_hash)
+    }
+
+    fn import_header(&mut self, header: &Header<C::Digest>) {
+        // Until it has seen a header, the best hash is only a placeholder, so the first header
+        // wins even at height zero. That is how genesis gets in.
+        let first = self.best_header_hash == Hash::default();
+        if first || header.height > self.best_header_height {
+            self.best_header_height = header.height;
+            self.best_header_hash = header.hash();
+        }
+    }
 }
 
 impl<C: Consensus, SM: StateMachine> ForkChoice<C, SM> for LongestChain
 where
     Block<C, SM>: std::hash::Hash,
 {
-    fn best_block(&self) -> Option<u64> {
-        return Some(self.best_header_hash);
+    fn best_block(&self) -> Option<Hash> {
+        HeaderForkChoice::<C>::best_header(self)
     }
 
     fn import_hook(&mut self, block: Block<C, SM>) {
-        if block.header.height > self.best_header_height {
-            self.best_header_height = block.header.height;
-            self.best_header_hash = hash(&block);
-        }
+        HeaderForkChoice::<C>::import_header(self, &block.header)
     }
 }
 
@@ -63,7 +133,7 @@ impl Default for LongestChain {
     fn default() -> Self {
         Self {
             best_header_height: 0,
-            best_header_hash: 0,
+            best_header_hash: Hash::default(),
         }
     }
 }
@@ -72,45 +142,49 @@ impl Default for LongestChain {
 /// This fork choice rule only makes sense with the PoW consensus engine
 /// and the generics reflect that.
 pub struct HeaviestChain {
-    chain_weight_to_last_block_hash: BTreeMap<u64, u64>,
+    chain_weight_to_last_block_hash: BTreeMap<u64, Hash>,
 }
 
-impl<SM: StateMachine> ForkChoice<Pow, SM> for HeaviestChain
-where
-    Block<Pow, SM>: std::hash::Hash,
-{
-    fn best_block(&self) -> Option<u64> {
+impl HeaderForkChoice<Pow> for HeaviestChain {
+    fn best_header(&self) -> Option<Hash> {
         self.chain_weight_to_last_block_hash
             .iter()
             .last()
             .map(|(_, &v)| v)
     }
 
-    fn import_hook(&mut self, block: Block<Pow, SM>) {
+    fn import_header(&mut self, header: &Header<u64>) {
         let chain_weight = self
             .chain_weight_to_last_block_hash
             .iter()
-            .find_map(|(k, v)| {
-                if *v == block.header.parent {
-                    Some(*k)
-                } else {
-                    None
-                }
-            });
+            .find_map(|(k, v)| if *v == header.parent { Some(*k) } else { None });
         match chain_weight {
             Some(chain_weight_v) => {
                 self.chain_weight_to_last_block_hash.remove(&chain_weight_v);
                 self.chain_weight_to_last_block_hash
-                    .insert(chain_weight_v + block.header.consensus_digest, hash(&block));
+                    .insert(chain_weight_v + header.consensus_digest, header.hash());
             }
             None => {
                 self.chain_weight_to_last_block_hash
-                    .insert(block.header.consensus_digest, hash(&block));
+                    .insert(header.consensus_digest, header.hash());
             }
         }
     }
 }
 
+impl<SM: StateMachine> ForkChoice<Pow, SM> for HeaviestChain
+where
+    Block<Pow, SM>: std::hash::Hash,
+{
+    fn best_block(&self) -> Option<Hash> {
+        self.best_header()
+    }
+
+    fn import_hook(&mut self, block: Block<Pow, SM>) {
+        self.import_header(&block.header)
+    }
+}
+
 impl Default for HeaviestChain {
     fn default() -> Self {
         Self {
@@ -123,14 +197,14 @@ impl Default for HeaviestChain {
 /// This fork choice rule only makes sense with the PoA consensus engine
 /// and the generics reflect that.
 pub struct MostAliceSigs {
-    chains_alice_sigs_to_last_block_hash: BTreeMap<u64, u64>,
+    chains_alice_sigs_to_last_block_hash: BTreeMap<u64, Hash>,
 }
 
 impl<SM: StateMachine> ForkChoice<SimplePoa, SM> for MostAliceSigs
 where
     Block<SimplePoa, SM>: std::hash::Hash,
 {
-    fn best_block(&self) -> Option<u64> {
+    fn best_block(&self) -> Option<Hash> {
         self.chains_alice_sigs_to_last_block_hash
             .iter()
             .last()
@@ -160,7 +234,7 @@ where
                 self.chains_alice_sigs_to_last_block_hash
                     .remove(&chain_alice_sigs_v);
                 self.chains_alice_sigs_to_last_block_hash
-                    .insert(chain_alice_sigs_v + 1, hash(&block));
+                    .insert(chain_alice_sigs_v + 1, block.hash());
             }
             None => {
                 let mut to_insert = 0;
@@ -168,7 +242,7 @@ where
                     to_insert = 1;
                 }
                 self.chains_alice_sigs_to_last_block_hash
-                    .insert(to_insert, hash(&block));
+                    .insert(to_insert, block.hash());
             }
         }
     }
@@ -186,14 +260,14 @@ impl Default for MostAliceSigs {
 /// You start from the genesis block, and at each fork, you choose the side of the fork
 /// that has the most accumulated proof of work on _all_ of its descendants.
 pub struct Ghost {
-    cum_chain_weight_to_blocks_in_chain: BTreeMap<u64, Vec<u64>>,
+    cum_chain_weight_to_blocks_in_chain: BTreeMap<u64, Vec<Hash>>,
 }
 
 impl<SM: StateMachine> ForkChoice<Pow, SM> for Ghost
 where
     Block<Pow, SM>: std::hash::Hash,
 {
-    fn best_block(&self) -> Option<u64> {
+    fn best_block(&self) -> Option<Hash> {
         self.cum_chain_weight_to_blocks_in_chain
             .iter()
             .last()
@@ -214,7 +288,7 @@ where
 
         match maybe_found_chain {
             Some((weight, mut chain)) => {
-                chain.push(hash(&block));
+                chain.push(block.hash());
 
                 let new_weight = weight + block.header.consensus_digest;
                 let new_chain = chain.clone();
@@ -225,7 +299,7 @@ where
             }
             None => {
                 self.cum_chain_weight_to_blocks_in_chain
-                    .insert(block.header.consensus_digest, vec![hash(&block)]);
+                    .insert(block.header.consensus_digest, vec![block.hash()]);
             }
         }
     }
@@ -250,11 +324,11 @@ where
     Block<C, SM>: std::hash::Hash,
 {
     /// Return the hash of the best block currently known to the client
-    fn best_block(&self) -> u64 {
+    fn best_block(&self) -> Hash {
         if let Some(v) = FC::best_block(&self.fork_choice) {
             v
         } else {
-            0
+            Hash::default()
         }
     }
 }
@@ -264,6 +338,7 @@ where
 fn block_from_header<C: Consensus, SM: StateMachine>(header: Header<C::Digest>) -> Block<C, SM> {
     return Block::<C, SM> {
         header,
+        author: None,
         body: Vec::new(),
     };
 }
@@ -272,7 +347,7 @@ fn init_client_for_test() -> impl ImportBlock<Pow, AccountedCurrency> {
     let consensus_engine = Pow {
         threshold: u64::MAX / 10,
     };
-    let state_machine = AccountedCurrency {};
+    let state_machine = AccountedCurrency::default();
     let fork_choice = LongestChain::default();
     let transaction_pool = ();
 
@@ -285,6 +360,10 @@ fn init_client_for_test() -> impl ImportBlock<Pow, AccountedCurrency> {
         fork_choice,
         transaction_pool,
         storage,
+        author: None,
+        block_weight_limit: DEFAULT_BLOCK_WEIGHT_LIMIT,
+        chain_subscribers: Vec::new(),
+        import_subscribers: Vec::new(),
     }
 }
 
@@ -300,6 +379,7 @@ mod cl3_longest {
                 height: 1,
                 ..Default::default()
             },
+            author: None,
             body: Vec::new(),
         };
         <LongestChain as ForkChoice<Pow, AccountedCurrency>>::import_hook(
@@ -311,7 +391,30 @@ mod cl3_longest {
             <LongestChain as ForkChoice<Pow, AccountedCurrency>>::best_block(&mut fork_choice);
 
         assert!(best_block.is_some());
-        assert_eq!(hash(&block), best_block.unwrap());
+        assert_eq!(block.hash(), best_block.unwrap());
+    }
+
+    #[test]
+    fn cl3_longest_chain_imports_genesis() {
+        let mut fork_choice = LongestChain::default();
+
+        let genesis = Block::<Pow, AccountedCurrency> {
+            header: Header {
+                height: 0,
+                ..Default::default()
+            },
+            author: None,
+            body: Vec::new(),
+        };
+        <LongestChain as ForkChoice<Pow, AccountedCurrency>>::import_hook(
+            &mut fork_choice,
+            genesis.clone(),
+        );
+
+        let best_block =
+            <LongestChain as ForkChoice<Pow, AccountedCurrency>>::best_block(&fork_choice);
+
+        assert_eq!(best_block, Some(genesis.hash()));
     }
 
     #[test]
@@ -323,6 +426,7 @@ mod cl3_longest {
                 height: 5,
                 ..Default::default()
             },
+            author: None,
             body: Vec::new(),
         };
         <LongestChain as ForkChoice<Pow, AccountedCurrency>>::import_hook(
@@ -335,6 +439,7 @@ mod cl3_longest {
                 height: 2,
                 ..Default::default()
             },
+            author: None,
             body: Vec::new(),
         };
         <LongestChain as ForkChoice<Pow, AccountedCurrency>>::import_hook(
@@ -346,7 +451,7 @@ mod cl3_longest {
             <LongestChain as ForkChoice<Pow, AccountedCurrency>>::best_block(&mut fork_choice);
 
         assert!(best_block.is_some());
-        assert_eq!(hash(&block), best_block.unwrap());
+        assert_eq!(block.hash(), best_block.unwrap());
     }
 }
 
@@ -367,7 +472,7 @@ mod cl3_heaviest {
             <HeaviestChain as ForkChoice<Pow, AccountedCurrency>>::best_block(&mut fork_choice);
 
         assert!(best_block.is_some());
-        assert_eq!(hash(&block), best_block.unwrap());
+        assert_eq!(block.hash(), best_block.unwrap());
     }
 
     #[test]
@@ -377,11 +482,12 @@ mod cl3_heaviest {
         // 1st chain - heavier
         let block_heavier = Block::<Pow, AccountedCurrency> {
             header: Header {
-                parent: 111,
+                parent: 111.into(),
                 height: 1,
                 consensus_digest: 12,
                 ..Default::default()
             },
+            author: None,
             body: Vec::new(),
         };
         <HeaviestChain as ForkChoice<Pow, AccountedCurrency>>::import_hook(
@@ -392,11 +498,12 @@ mod cl3_heaviest {
         // 2nd chain - lighter
         let block_lighter = Block::<Pow, AccountedCurrency> {
             header: Header {
-                parent: 120,
+                parent: 120.into(),
                 height: 1,
                 consensus_digest: 10,
                 ..Default::default()
             },
+            author: None,
             body: Vec::new(),
         };
         <HeaviestChain as ForkChoice<Pow, AccountedCurrency>>::import_hook(
@@ -408,7 +515,7 @@ mod cl3_heaviest {
             <HeaviestChain as ForkChoice<Pow, AccountedCurrency>>::best_block(&mut fork_choice);
 
         assert!(best_block.is_some());
-        assert_eq!(hash(&block_heavier), best_block.unwrap());
+        assert_eq!(block_heavier.hash(), best_block.unwrap());
 
         let block2 = Block::<Pow, AccountedCurrency> {
             header: Header {
@@ -417,6 +524,7 @@ mod cl3_heaviest {
                 consensus_digest: 15,
                 ..Default::default()
             },
+            author: None,
             body: Vec::new(),
         };
         <HeaviestChain as ForkChoice<Pow, AccountedCurrency>>::import_hook(
@@ -428,7 +536,43 @@ mod cl3_heaviest {
             <HeaviestChain as ForkChoice<Pow, AccountedCurrency>>::best_block(&mut fork_choice);
 
         assert!(best_block.is_some());
-        assert_eq!(hash(&block2), best_block.unwrap());
+        assert_eq!(block2.hash(), best_block.unwrap());
+    }
+
+    #[test]
+    fn cl3_heaviest_chain_forgets_forks_of_finalized() {
+        let mut fork_choice = HeaviestChain::default();
+        let block = |parent: Hash, height, consensus_digest| Block::<Pow, AccountedCurrency> {
+            header: Header {
+                parent,
+                height,
+                consensus_digest,
+                ..Default::default()
+            },
+            author: None,
+            body: Vec::new(),
+        };
+
+        let finalized = block(111.into(), 1, 5);
+        let descendant = block(finalized.hash(), 2, 5);
+        let heavy_fork = block(111.into(), 1, 50);
+        for b in [finalized.clone(), descendant.clone(), heavy_fork.clone()] {
+            <HeaviestChain as ForkChoice<Pow, AccountedCurrency>>::import_hook(&mut fork_choice, b);
+        }
+        assert_eq!(
+            <HeaviestChain as ForkChoice<Pow, AccountedCurrency>>::best_block(&fork_choice),
+            Some(heavy_fork.hash())
+        );
+
+        <HeaviestChain as ForkChoice<Pow, AccountedCurrency>>::finality_hook(
+            &mut fork_choice,
+            finalized,
+            vec![descendant.clone()],
+        );
+        assert_eq!(
+            <HeaviestChain as ForkChoice<Pow, AccountedCurrency>>::best_block(&fork_choice),
+            Some(descendant.hash())
+        );
     }
 }
 
@@ -466,7 +610,7 @@ mod cl3_most_alice_sigs {
         let header_alice_2: Header<<SimplePoa as Consensus>::Digest> = Header {
             height: 2,
             consensus_digest: ConsensusAuthority::Alice,
-            parent: hash(&block_alice_1),
+            parent: block_alice_1.hash(),
             ..Default::default()
         };
         let block_alice_2 = block_from_header(header_alice_2);
@@ -482,7 +626,7 @@ mod cl3_most_alice_sigs {
 
         // 2nd chain - 1 Alice sig
         let header_alice_3: Header<<SimplePoa as Consensus>::Digest> = Header {
-            parent: 12,
+            parent: 12.into(),
             height: 1,
             consensus_digest: ConsensusAuthority::Alice,
             ..Default::default()
@@ -495,7 +639,7 @@ mod cl3_most_alice_sigs {
 
         // 3rd chain - 3 Bob sigs
         let header_bob: Header<<SimplePoa as Consensus>::Digest> = Header {
-            parent: 11,
+            parent: 11.into(),
             height: 1,
             consensus_digest: ConsensusAuthority::Bob,
             ..Default::default()
@@ -507,7 +651,7 @@ mod cl3_most_alice_sigs {
         );
         for _ in 0..2 {
             let header: Header<<SimplePoa as Consensus>::Digest> = Header {
-                parent: hash(&block_bob),
+                parent: block_bob.hash(),
                 height: header_bob.height + 1,
                 consensus_digest: ConsensusAuthority::Bob,
                 ..Default::default()
@@ -525,7 +669,7 @@ mod cl3_most_alice_sigs {
         );
 
         assert!(best_block.is_some());
-        assert_eq!(hash(&block_alice_2), best_block.unwrap());
+        assert_eq!(block_alice_2.hash(), best_block.unwrap());
     }
 }
 
@@ -538,19 +682,21 @@ mod cl3_ghost {
 
         // 1st chain - 2 blocks with 10 work
         let header_1: Header<<Pow as Consensus>::Digest> = Header {
-            parent: 111,
+            parent: 111.into(),
             height: 1,
-            state_root: 0,
-            extrinsics_root: 0,
+            state_root: 0.into(),
+            extrinsics_root: 0.into(),
+            receipts_root: 0.into(),
             consensus_digest: 2,
         };
         let block_1 = block_from_header(header_1.clone());
 
         let mut header_2: Header<<Pow as Consensus>::Digest> = Header {
-            parent: hash(&block_1),
+            parent: block_1.hash(),
             height: 2,
-            state_root: 0,
-            extrinsics_root: 0,
+            state_root: 0.into(),
+            extrinsics_root: 0.into(),
+            receipts_root: 0.into(),
             consensus_digest: 8,
         };
         let block_2 = block_from_header(header_2.clone());
@@ -567,10 +713,11 @@ mod cl3_ghost {
 
         // 2nd chain - 1 block with 12 work
         let header_3: Header<<Pow as Consensus>::Digest> = Header {
-            parent: 12,
+            parent: 12.into(),
             height: 1,
-            state_root: 0,
-            extrinsics_root: 0,
+            state_root: 0.into(),
+            extrinsics_root: 0.into(),
+            receipts_root: 0.into(),
             consensus_digest: 12,
         };
         let block_3 = block_from_header(header_3.clone());
@@ -582,10 +729,11 @@ mod cl3_ghost {
 
         // 3rd chain - 3 blocks with 6 work
         let mut header_4: Header<<Pow as Consensus>::Digest> = Header {
-            parent: 11,
+            parent: 11.into(),
             height: 1,
-            state_root: 0,
-            extrinsics_root: 0,
+            state_root: 0.into(),
+            extrinsics_root: 0.into(),
+            receipts_root: 0.into(),
             consensus_digest: 2,
         };
         let block_4 = block_from_header(header_4.clone());
@@ -597,10 +745,11 @@ mod cl3_ghost {
 
         for _ in 0..2 {
             let header: Header<<Pow as Consensus>::Digest> = Header {
-                parent: hash(&block_4),
+                parent: block_4.hash(),
                 height: 1,
-                state_root: 0,
-                extrinsics_root: 0,
+                state_root: 0.into(),
+                extrinsics_root: 0.into(),
+                receipts_root: 0.into(),
                 consensus_digest: 2,
             };
             let block = block_from_header(header.clone());
@@ -615,6 +764,6 @@ mod cl3_ghost {
             <Ghost as ForkChoice<Pow, AccountedCurrency>>::best_block(&mut fork_choice);
 
         assert!(best_block.is_some());
-        assert_eq!(hash(&block_3), best_block.unwrap());
+        assert_eq!(block_3.hash(), best_block.unwrap());
     }
 }
@@ -7,12 +7,17 @@
 //! * Accepting transactions from users
 //! * Removing transactions that are included in blocks as they are imported
 //! * Making the current transactions available for a block authoring process
-//! * Re-queueing transactions from orphaned blocks when re-orgs happen (This one happens IRL; might not cover it in BFS; TBD)
+//! * Re-queueing transactions from orphaned blocks when re-orgs happen
 
 use crate::c1_state_machine::{AccountedCurrency, AccountingTransaction};
-use std::collections::VecDeque;
+use crate::{hash, Encode, TrieState};
+use std::collections::{HashSet, VecDeque};
 
-use super::{FullClient, StateMachine};
+use super::p1_data_structure::Block;
+use super::p7_reorg::TreeRoute;
+#[cfg(test)]
+use super::DEFAULT_BLOCK_WEIGHT_LIMIT;
+use super::{Consensus, FullClient, StateMachine, Storage};
 
 /// An abstraction over the notion of transaction pool.
 pub trait TransactionPool<SM: StateMachine> {
@@ -48,12 +53,6 @@ where
     SM: StateMachine,
     P: TransactionPool<SM>,
 {
-    /// Submit a transaction to the client's transaction pool to hopefully
-    /// be included in a future block.
-    pub fn submit_transaction(&mut self, t: SM::Transition) {
-        P::try_insert(&mut self.transaction_pool, t);
-    }
-
     /// Get the total number of transactions in the node's
     /// transaction pool.
     pub fn pool_size(&self) -> usize {
@@ -66,6 +65,104 @@ where
     }
 }
 
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    P: TransactionPool<SM>,
+    S: Storage<C, SM>,
+    Block<C, SM>: std::hash::Hash,
+    SM::State: TrieState,
+    SM::Transition: Encode,
+{
+    /// Submit a transaction to the client's transaction pool to hopefully
+    /// be included in a future block. Transactions that fail the state machine's
+    /// validation, for example because of a bad signature, never reach the pool.
+    /// Neither do stale ones, such as a replay of a transaction the best chain already
+    /// includes. Returns whether the transaction was accepted.
+    pub fn submit_transaction(&mut self, t: SM::Transition) -> bool {
+        if SM::validate_transaction(&t).is_err() {
+            return false;
+        }
+        if SM::is_stale(&S::current_state(&self.storage), &t) {
+            return false;
+        }
+        P::try_insert(&mut self.transaction_pool, t)
+    }
+
+    /// Bring the pool in line with a change of the best chain.
+    ///
+    /// Transactions in the enacted blocks are now included, so they leave the pool. Those in the
+    /// retracted blocks are no longer included, unless the new branch has them too, so they go
+    /// back into the pool to be picked up by a future block, unless the new best state has made
+    /// them stale. The pool may still refuse them, just like it may refuse any other transaction.
+    pub(super) fn update_pool(&mut self, route: &TreeRoute) {
+        let mut included = HashSet::new();
+        for block_hash in &route.enacted {
+            let Some(block) = self.storage.get_block(*block_hash) else {
+                continue;
+            };
+            for t in block.body {
+                included.insert(hash(&t));
+                P::remove(&mut self.transaction_pool, t);
+            }
+        }
+
+        // Oldest block first, so that each sender's transactions go back in their original order.
+        let best_state = self.storage.current_state();
+        for block_hash in route.retracted.iter().rev() {
+            let Some(block) = self.storage.get_block(*block_hash) else {
+                continue;
+            };
+            for t in block.body {
+                if !included.contains(&hash(&t)) && !SM::is_stale(&best_state, &t) {
+                    P::try_insert(&mut self.transaction_pool, t);
+                }
+            }
+        }
+    }
+}
+
+/// Find where a new transaction has to go in a queue, given the position the pool would
+/// like to put it at.
+///
+/// For state machines that number each sender's transitions, a transaction is moved so
+/// that it comes after the sender's lower nonces and before their higher ones. That way a
+/// transaction that arrives out of order is held back until the ones before it are taken.
+/// Returns None if the sender already has a transaction with the same nonce queued, which
+/// is how a replay looks from inside the pool.
+fn nonce_ordered_position<SM: StateMachine>(
+    queue: &VecDeque<SM::Transition>,
+    t: &SM::Transition,
+    preferred: usize,
+) -> Option<usize> {
+    let Some((sender, nonce)) = SM::sender_and_nonce(t) else {
+        return Some(preferred);
+    };
+
+    let mut last_lower = None;
+    let mut first_higher = None;
+    for (i, queued) in queue.iter().enumerate() {
+        match SM::sender_and_nonce(queued) {
+            Some((s, n)) if s == sender && n == nonce => return None,
+            Some((s, n)) if s == sender && n < nonce => last_lower = Some(i),
+            Some((s, n)) if s == sender && first_higher.is_none() && n > nonce => {
+                first_higher = Some(i)
+            }
+            _ => {}
+        }
+    }
+
+    let mut position = preferred;
+    if let Some(i) = last_lower {
+        position = position.max(i + 1);
+    }
+    if let Some(i) = first_higher {
+        position = position.min(i);
+    }
+    Some(position)
+}
+
 /// A simple state machine that is just a first-in-first-out queue.
 pub struct SimplePool<SM: StateMachine>(VecDeque<SM::Transition>);
 
@@ -78,8 +175,13 @@ where
         SM: 'a;
 
     fn try_insert(&mut self, t: <SM as StateMachine>::Transition) -> bool {
-        self.0.push_back(t);
-        true
+        match nonce_ordered_position::<SM>(&self.0, &t, self.0.len()) {
+            Some(pos) => {
+                self.0.insert(pos, t);
+                true
+            }
+            None => false,
+        }
     }
 
     fn remove(&mut self, t: <SM as StateMachine>::Transition) {
@@ -103,6 +205,12 @@ where
     }
 }
 
+impl<SM: StateMachine> Default for SimplePool<SM> {
+    fn default() -> Self {
+        SimplePool(VecDeque::new())
+    }
+}
+
 /// A transaction pool that assigns a priority to each transaction and then provides
 /// them (to the authoring process, presumably) highest priority first.
 ///
@@ -138,14 +246,16 @@ where
 
         // find first elem with lower prio
         // insert it just after that elem
-        if let Some(pos) = self
+        let prio_pos = self
             .queue
             .iter()
             .position(|e| (self.prioritizer)(e.clone()) < prio)
-        {
-            self.queue.insert(pos, t); // Insert at the correct position
-        } else {
-            self.queue.push_back(t); // Append if no lower-priority element is found
+            .unwrap_or(self.queue.len());
+
+        // a sender's nonce order trumps priority
+        match nonce_ordered_position::<SM>(&self.queue, &t, prio_pos) {
+            Some(pos) => self.queue.insert(pos, t),
+            None => return false,
         }
 
         return true;
@@ -232,7 +342,10 @@ where
         if (self.might_be_terrorist)(t.clone()) {
             return false;
         }
-        self.queue.push_back(t.clone());
+        match nonce_ordered_position::<SM>(&self.queue, &t, self.queue.len()) {
+            Some(pos) => self.queue.insert(pos, t),
+            None => return false,
+        }
         return true;
     }
 
@@ -257,6 +370,51 @@ where
     }
 }
 
+impl<SM, P> CensoringPool<SM, P>
+where
+    SM: StateMachine,
+    P: Fn(SM::Transition) -> bool,
+{
+    pub fn new(might_be_terrorist: P) -> Self {
+        Self {
+            might_be_terrorist,
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+/// Clients that do not collect transactions, for example because they never author blocks,
+/// can use the unit type as a pool that refuses everything.
+/// Its iterator borrows nothing to tie the state machine's lifetime to, hence the `'static`.
+impl<SM: StateMachine + 'static> TransactionPool<SM> for () {
+    type Iterator<'a>
+        = std::iter::Empty<&'a SM::Transition>
+    where
+        SM: 'a;
+
+    fn try_insert(&mut self, _: SM::Transition) -> bool {
+        false
+    }
+
+    fn remove(&mut self, _: SM::Transition) {}
+
+    fn size(&self) -> usize {
+        0
+    }
+
+    fn contains(&self, _: SM::Transition) -> bool {
+        false
+    }
+
+    fn next_from_pool(&mut self) -> Option<SM::Transition> {
+        None
+    }
+
+    fn iter<'a>(&'a self) -> Self::Iterator<'a> {
+        std::iter::empty()
+    }
+}
+
 // --- TESTS ---
 
 mod cl4_prio_pool {
@@ -271,6 +429,7 @@ mod cl4_prio_pool {
         let elem = AccountingTransaction::Mint {
             minter: User::Alice,
             amount: 12,
+            nonce: 0,
         };
 
         // removal of an element from an empty pool, shouldn't panic
@@ -287,6 +446,7 @@ mod cl4_prio_pool {
         let elem = AccountingTransaction::Mint {
             minter: User::Alice,
             amount: 12,
+            nonce: 0,
         };
         assert!(PriorityPool::try_insert(&mut pool, elem.clone()));
         assert_eq!(PriorityPool::size(&pool), 1);
@@ -305,6 +465,7 @@ mod cl4_prio_pool {
         let elem = AccountingTransaction::Mint {
             minter: User::Alice,
             amount: 12,
+            nonce: 0,
         };
         assert!(PriorityPool::try_insert(&mut pool, elem.clone()));
         assert_eq!(PriorityPool::size(&pool), 1);
@@ -313,6 +474,7 @@ mod cl4_prio_pool {
         let elem2 = AccountingTransaction::Mint {
             minter: User::Bob,
             amount: 11,
+            nonce: 0,
         };
         assert!(PriorityPool::try_insert(&mut pool, elem2.clone()));
         assert_eq!(PriorityPool::size(&pool), 2);
@@ -321,12 +483,13 @@ mod cl4_prio_pool {
 
     fn prioritizer(t: AccountingTransaction) -> u64 {
         match t {
-            AccountingTransaction::Mint { minter, amount } => return 5,
-            AccountingTransaction::Burn { burner, amount } => return 4,
+            AccountingTransaction::Mint { minter, amount, .. } => return 5,
+            AccountingTransaction::Burn { burner, amount, .. } => return 4,
             AccountingTransaction::Transfer {
                 sender,
                 receiver,
                 amount,
+                ..
             } => return 6,
         }
     }
@@ -339,6 +502,7 @@ mod cl4_prio_pool {
         let elem = AccountingTransaction::Mint {
             minter: User::Alice,
             amount: 12,
+            nonce: 0,
         };
         assert!(PriorityPool::try_insert(&mut pool, elem.clone()));
         assert_eq!(PriorityPool::size(&pool), 1);
@@ -347,6 +511,7 @@ mod cl4_prio_pool {
         let elem2 = AccountingTransaction::Mint {
             minter: User::Bob,
             amount: 11,
+            nonce: 0,
         };
         assert!(PriorityPool::try_insert(&mut pool, elem2.clone()));
         assert_eq!(PriorityPool::size(&pool), 2);
@@ -358,6 +523,7 @@ mod cl4_prio_pool {
             sender: User::Alice,
             receiver: User::Bob,
             amount: 12,
+            nonce: 0,
         };
         assert!(PriorityPool::try_insert(&mut pool, elem_transfer.clone()));
         assert_eq!(PriorityPool::size(&pool), 2);
@@ -371,6 +537,7 @@ mod cl4_prio_pool {
         let elem_burn = AccountingTransaction::Burn {
             burner: User::Alice,
             amount: 12,
+            nonce: 0,
         };
         // does not insert - prio too low
         assert_eq!(
@@ -382,3 +549,351 @@ mod cl4_prio_pool {
         assert_eq!(PriorityPool::contains(&pool, elem_burn.clone()), false);
     }
 }
+
+#[cfg(test)]
+mod cl4_nonce_ordering {
+    use crate::c1_state_machine::{SignedAccountedCurrency, SignedExtrinsic, User};
+
+    use super::*;
+
+    fn burn(signer: User, nonce: u64) -> SignedExtrinsic<AccountingTransaction> {
+        SignedExtrinsic::new(
+            signer,
+            AccountingTransaction::Burn {
+                burner: signer,
+                amount: 1,
+                nonce,
+            },
+        )
+    }
+
+    #[test]
+    fn simple_pool_holds_back_out_of_order_nonces() {
+        let mut pool: SimplePool<SignedAccountedCurrency> = SimplePool(VecDeque::new());
+
+        assert!(pool.try_insert(burn(User::Alice, 1)));
+        assert!(pool.try_insert(burn(User::Bob, 0)));
+        assert!(pool.try_insert(burn(User::Alice, 0)));
+
+        assert_eq!(pool.next_from_pool(), Some(burn(User::Alice, 0)));
+        assert_eq!(pool.next_from_pool(), Some(burn(User::Alice, 1)));
+        assert_eq!(pool.next_from_pool(), Some(burn(User::Bob, 0)));
+    }
+
+    #[test]
+    fn simple_pool_rejects_replayed_nonce() {
+        let mut pool: SimplePool<SignedAccountedCurrency> = SimplePool(VecDeque::new());
+
+        assert!(pool.try_insert(burn(User::Alice, 0)));
+        assert!(!pool.try_insert(burn(User::Alice, 0)));
+        assert_eq!(pool.size(), 1);
+    }
+
+    #[test]
+    fn prio_pool_keeps_nonce_order_over_priority() {
+        fn prioritizer(t: SignedExtrinsic<AccountingTransaction>) -> u64 {
+            // Later nonces bid more, but they still cannot jump the queue.
+            t.call.nonce()
+        }
+        let mut pool: PriorityPool<
+            SignedAccountedCurrency,
+            fn(SignedExtrinsic<AccountingTransaction>) -> u64,
+        > = PriorityPool::new(prioritizer, 0);
+
+        assert!(pool.try_insert(burn(User::Alice, 0)));
+        assert!(pool.try_insert(burn(User::Bob, 1)));
+        assert!(pool.try_insert(burn(User::Alice, 2)));
+        assert!(pool.try_insert(burn(User::Alice, 1)));
+        assert!(pool.try_insert(burn(User::Bob, 0)));
+
+        let order: Vec<_> = pool.iter().cloned().collect();
+        assert_eq!(
+            order,
+            vec![
+                burn(User::Bob, 0),
+                burn(User::Bob, 1),
+                burn(User::Alice, 0),
+                burn(User::Alice, 1),
+                burn(User::Alice, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn prio_pool_rejects_replayed_nonce() {
+        let mut pool: PriorityPool<SignedAccountedCurrency, fn(_) -> u64> = PriorityPool::default();
+
+        assert!(pool.try_insert(burn(User::Alice, 0)));
+        assert!(!pool.try_insert(burn(User::Alice, 0)));
+        assert_eq!(pool.size(), 1);
+    }
+}
+
+#[cfg(test)]
+mod cl4_submit_transaction {
+    use crate::c1_state_machine::{SignedAccountedCurrency, SignedExtrinsic, User};
+    use crate::c4_client::p2_importing_blocks::ImportBlock;
+    use crate::c4_client::p3_fork_choice::LongestChain;
+    use crate::c4_client::BasicStorage;
+
+    use super::*;
+
+    type TestClient = FullClient<
+        (),
+        SignedAccountedCurrency,
+        LongestChain,
+        SimplePool<SignedAccountedCurrency>,
+        BasicStorage<(), SignedAccountedCurrency>,
+    >;
+
+    fn init_client_for_test() -> TestClient {
+        FullClient {
+            consensus_engine: (),
+            state_machine: SignedAccountedCurrency::default(),
+            fork_choice: LongestChain::default(),
+            transaction_pool: SimplePool(VecDeque::new()),
+            storage: BasicStorage::new(),
+            author: None,
+            block_weight_limit: DEFAULT_BLOCK_WEIGHT_LIMIT,
+            chain_subscribers: Vec::new(),
+            import_subscribers: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn accepts_correctly_signed_transaction() {
+        let mut client = init_client_for_test();
+
+        let t = SignedExtrinsic::new(
+            User::Alice,
+            AccountingTransaction::Mint {
+                minter: User::Alice,
+                amount: 12,
+                nonce: 0,
+            },
+        );
+
+        assert!(client.submit_transaction(t.clone()));
+        assert!(client.pool_contains(t));
+    }
+
+    #[test]
+    fn rejects_transaction_with_bad_signature() {
+        let mut client = init_client_for_test();
+
+        let mut t = SignedExtrinsic::new(
+            User::Alice,
+            AccountingTransaction::Mint {
+                minter: User::Alice,
+                amount: 12,
+                nonce: 0,
+            },
+        );
+        t.signature.0[0] ^= 1;
+
+        assert!(!client.submit_transaction(t.clone()));
+        assert_eq!(client.pool_size(), 0);
+    }
+
+    #[test]
+    fn rejects_transaction_signed_by_someone_other_than_the_sender() {
+        let mut client = init_client_for_test();
+
+        let t = SignedExtrinsic::new(
+            User::Bob,
+            AccountingTransaction::Transfer {
+                sender: User::Alice,
+                receiver: User::Bob,
+                amount: 12,
+                nonce: 0,
+            },
+        );
+
+        assert!(!client.submit_transaction(t.clone()));
+        assert_eq!(client.pool_size(), 0);
+    }
+
+    #[test]
+    fn rejects_transaction_whose_nonce_is_used_up() {
+        let mut client = init_client_for_test();
+
+        let mint = |nonce: u64| {
+            SignedExtrinsic::new(
+                User::Alice,
+                AccountingTransaction::Mint {
+                    minter: User::Alice,
+                    amount: 12,
+                    nonce,
+                },
+            )
+        };
+        let block = client
+            .get_last_block()
+            .child(&client.current_state(), vec![mint(0)])
+            .unwrap();
+        assert!(client.import_block(block));
+
+        // Replaying the included transaction can never succeed, so it is refused outright.
+        assert!(!client.submit_transaction(mint(0)));
+        assert_eq!(client.pool_size(), 0);
+
+        // While the next one, and even one after it, may wait in the pool.
+        assert!(client.submit_transaction(mint(2)));
+        assert!(client.submit_transaction(mint(1)));
+        assert_eq!(client.pool_size(), 2);
+    }
+}
+
+#[cfg(test)]
+mod cl4_reorg {
+    use crate::c1_state_machine::User;
+    use crate::c4_client::p2_importing_blocks::ImportBlock;
+    use crate::c4_client::p3_fork_choice::LongestChain;
+    use crate::c4_client::BasicStorage;
+
+    use super::*;
+
+    type TestClient<P> =
+        FullClient<(), AccountedCurrency, LongestChain, P, BasicStorage<(), AccountedCurrency>>;
+
+    fn init_client_for_test<P: TransactionPool<AccountedCurrency>>(
+        transaction_pool: P,
+    ) -> TestClient<P> {
+        FullClient {
+            consensus_engine: (),
+            state_machine: AccountedCurrency::default(),
+            fork_choice: LongestChain::default(),
+            transaction_pool,
+            storage: BasicStorage::new(),
+            author: None,
+            block_weight_limit: DEFAULT_BLOCK_WEIGHT_LIMIT,
+            chain_subscribers: Vec::new(),
+            import_subscribers: Vec::new(),
+        }
+    }
+
+    fn mint(minter: User, amount: u64) -> AccountingTransaction {
+        AccountingTransaction::Mint {
+            minter,
+            amount,
+            nonce: 0,
+        }
+    }
+
+    fn by_amount(t: AccountingTransaction) -> u64 {
+        match t {
+            AccountingTransaction::Mint { amount, .. } => amount,
+            _ => 0,
+        }
+    }
+
+    /// Import a fork `a1` and then overtake it with `b1 <- b2`, both on top of genesis.
+    /// `a1` mints for Alice and Charlie, `b1` for Charlie too and `b2` for Bob.
+    /// While `a1` is best, Bob's mint waits in the pool.
+    fn reorg_away_from_a1<P: TransactionPool<AccountedCurrency>>(client: &mut TestClient<P>) {
+        let genesis = client.get_last_block();
+        let genesis_state = client.current_state();
+
+        let a1 = genesis
+            .child(
+                &genesis_state,
+                vec![mint(User::Alice, 10), mint(User::Charlie, 3)],
+            )
+            .unwrap();
+        let b1 = genesis
+            .child(&genesis_state, vec![mint(User::Charlie, 3)])
+            .unwrap();
+        let b1_state = AccountedCurrency::next_state(&genesis_state, &mint(User::Charlie, 3));
+        let b2 = b1.child(&b1_state, vec![mint(User::Bob, 5)]).unwrap();
+
+        assert!(client.import_block(a1.clone()));
+        client.submit_transaction(mint(User::Bob, 5));
+        assert!(client.import_block(b1));
+        assert!(client.import_block(b2.clone()));
+        assert_eq!(client.get_last_block().hash(), b2.hash());
+    }
+
+    #[test]
+    fn simple_pool_requeues_retracted_and_prunes_enacted() {
+        let mut client = init_client_for_test(SimplePool(VecDeque::new()));
+        reorg_away_from_a1(&mut client);
+
+        // Alice's mint is only in the retracted block, Charlie's is in both forks,
+        // and Bob's made it into the new best chain.
+        let pooled: Vec<_> = client.transaction_pool.iter().cloned().collect();
+        assert_eq!(pooled, vec![mint(User::Alice, 10)]);
+    }
+
+    #[test]
+    fn prio_pool_requeues_retracted_and_prunes_enacted() {
+        let pool: PriorityPool<AccountedCurrency, fn(AccountingTransaction) -> u64> =
+            PriorityPool::new(by_amount, 1);
+        let mut client = init_client_for_test(pool);
+        assert!(client.submit_transaction(mint(User::Bob, 50)));
+        reorg_away_from_a1(&mut client);
+
+        // The requeued transaction takes its place by priority like any other.
+        let pooled: Vec<_> = client.transaction_pool.iter().cloned().collect();
+        assert_eq!(pooled, vec![mint(User::Bob, 50), mint(User::Alice, 10)]);
+    }
+
+    #[test]
+    fn prio_pool_refuses_requeued_transactions_below_minimum_priority() {
+        let pool: PriorityPool<AccountedCurrency, fn(AccountingTransaction) -> u64> =
+            PriorityPool::new(by_amount, 20);
+        let mut client = init_client_for_test(pool);
+        reorg_away_from_a1(&mut client);
+
+        assert_eq!(client.pool_size(), 0);
+    }
+
+    #[test]
+    fn censoring_pool_requeues_retracted_and_prunes_enacted() {
+        fn might_be_terrorist(_: AccountingTransaction) -> bool {
+            false
+        }
+        let pool: CensoringPool<AccountedCurrency, fn(AccountingTransaction) -> bool> =
+            CensoringPool::new(might_be_terrorist);
+        let mut client = init_client_for_test(pool);
+        reorg_away_from_a1(&mut client);
+
+        let pooled: Vec<_> = client.transaction_pool.iter().cloned().collect();
+        assert_eq!(pooled, vec![mint(User::Alice, 10)]);
+    }
+
+    #[test]
+    fn censoring_pool_keeps_censoring_requeued_transactions() {
+        fn might_be_terrorist(t: AccountingTransaction) -> bool {
+            matches!(
+                t,
+                AccountingTransaction::Mint {
+                    minter: User::Alice,
+                    ..
+                }
+            )
+        }
+        let pool: CensoringPool<AccountedCurrency, fn(AccountingTransaction) -> bool> =
+            CensoringPool::new(might_be_terrorist);
+        let mut client = init_client_for_test(pool);
+        reorg_away_from_a1(&mut client);
+
+        assert_eq!(client.pool_size(), 0);
+    }
+
+    #[test]
+    fn importing_on_the_best_chain_prunes_included_transactions() {
+        let mut client = init_client_for_test(SimplePool(VecDeque::new()));
+        let genesis = client.get_last_block();
+        let genesis_state = client.current_state();
+        assert!(client.submit_transaction(mint(User::Alice, 10)));
+        assert!(client.submit_transaction(mint(User::Bob, 5)));
+
+        let block = genesis
+            .child(&genesis_state, vec![mint(User::Alice, 10)])
+            .unwrap();
+        assert!(client.import_block(block));
+
+        let pooled: Vec<_> = client.transaction_pool.iter().cloned().collect();
+        assert_eq!(pooled, vec![mint(User::Bob, 5)]);
+    }
+}
@@ -9,12 +9,16 @@ use crate::c3_consensus::Pow;
 use crate::c3_consensus::{Consensus, Header};
 use crate::c4_client::BasicStorage;
 use crate::c4_client::Block;
-use crate::hash;
+
+use super::p1_data_structure::{execute_extrinsics, Receipt};
+use crate::{hash, Encode};
+use crate::{merkle_root, state_root, TrieState};
 
 use super::p3_fork_choice::Ghost;
 use super::{
     p3_fork_choice::ForkChoice, p4_transaction_pool::PriorityPool,
-    p4_transaction_pool::TransactionPool, FullClient, StateMachine, Storage,
+    p4_transaction_pool::TransactionPool, FullClient, Hash, StateMachine, Storage,
+    DEFAULT_BLOCK_WEIGHT_LIMIT,
 };
 
 // You may need to add trait bounds to make this work.
@@ -26,15 +30,27 @@ where
     P: TransactionPool<SM>,
     S: Storage<C, SM>,
     Block<C, SM>: std::hash::Hash + Clone,
-    SM::State: std::hash::Hash + Clone,
-    SM::Transition: std::hash::Hash + Clone,
+    SM::State: TrieState + Clone,
+    SM::Transition: Encode + Clone,
+    SM::Event: Encode,
+    SM::AccountId: Clone,
 {
+    /// Set the account credited with the fees of the blocks this client authors from now on.
+    pub fn set_author(&mut self, author: Option<SM::AccountId>) {
+        self.author = author;
+    }
+
+    /// Set the most weight this client puts into a single block it authors from now on.
+    pub fn set_block_weight_limit(&mut self, block_weight_limit: u64) {
+        self.block_weight_limit = block_weight_limit;
+    }
+
     /// Author a new block with the given transactions on top of the given parent
     /// and import the new block into the local database.
     pub fn author_and_import_manual_block(
         &mut self,
         transactions: Vec<SM::Transition>,
-        parent_hash: u64,
+        parent_hash: Hash,
     ) -> Result<(), ()> {
         // ---- author part
 
@@ -43,25 +59,28 @@ where
             return Err(());
         }
         let parent_block = parent_block.unwrap();
+        if self.forks_below_finality(&parent_block) {
+            return Err(());
+        }
 
         let parent_state = S::get_state(&self.storage, parent_block.header.state_root);
         if parent_state.is_none() {
             return Err(());
         }
-        let mut new_state = parent_state.unwrap();
-
-        for t in &transactions {
-            new_state = SM::next_state(&new_state, &t);
-        }
+        let (new_state, receipts) =
+            execute_extrinsics::<SM>(&parent_state.unwrap(), self.author.as_ref(), &transactions)
+                .map_err(|_| ())?;
 
         let mut block = Block::<C, SM> {
             header: Header::<C::Digest> {
                 parent: parent_hash,
                 height: parent_block.header.height + 1,
-                state_root: hash(&new_state),
-                extrinsics_root: hash(&transactions),
+                state_root: state_root(&new_state),
+                extrinsics_root: merkle_root(&transactions),
+                receipts_root: hash(&receipts),
                 consensus_digest: <C as Consensus>::Digest::default(),
             },
+            author: self.author.clone(),
             body: transactions,
         };
 
@@ -77,16 +96,22 @@ where
 
         // ---- import part
 
+        S::set_receipts(&mut self.storage, block.hash(), receipts);
         S::add_block(&mut self.storage, block.clone());
         S::set_state(&mut self.storage, new_state.clone());
 
         FC::import_hook(&mut self.fork_choice, block.clone());
+        self.follow_fork_choice();
+        self.announce_import(block.hash());
 
         return Ok(());
     }
 
     /// Author a new block with the transactions from the pool on top of the "best" block
     /// and import the new block into the local database.
+    ///
+    /// Transactions are taken from the pool until the next one would push the block over
+    /// the client's weight limit.
     pub fn author_and_import_automatic_block(&mut self) -> Result<(), ()> {
         // ---- author part
 
@@ -97,28 +122,66 @@ where
 
         // transactions from the pool instead
         let mut used_transactions = Vec::new();
-        for _ in 0..10 {
+        let mut receipts = Vec::new();
+        let mut held_back = Vec::new();
+        let mut block_weight = 0;
+        loop {
             let transaction = P::next_from_pool(&mut self.transaction_pool);
             if transaction.is_none() {
                 break;
             }
             let transaction = transaction.unwrap();
 
-            new_state = SM::next_state(&new_state, &transaction);
+            // A transaction that does not fit waits for the next block, unless it could
+            // never fit into any block at all.
+            let weight = SM::weight(&transaction);
+            if weight > self.block_weight_limit {
+                continue;
+            }
+            if block_weight + weight > self.block_weight_limit {
+                held_back.push(transaction);
+                break;
+            }
+
+            // Transactions that are invalid on top of the state so far are dropped
+            // rather than sealed into the block as no-ops. Those that are merely
+            // premature go back to the pool to wait for their turn.
+            match SM::try_next_state_with_events(&new_state, &transaction) {
+                Ok((state, events)) => {
+                    new_state = state;
+                    block_weight += weight;
+                    receipts.push(Receipt {
+                        extrinsic_index: used_transactions.len(),
+                        events,
+                    });
+                }
+                Err(_) => {
+                    if SM::is_premature(&new_state, &transaction) {
+                        held_back.push(transaction);
+                    }
+                    continue;
+                }
+            }
             used_transactions.push(transaction);
         }
+        for tx in held_back {
+            P::try_insert(&mut self.transaction_pool, tx);
+        }
         if used_transactions.len() == 0 {
             return Ok(());
         }
+        SM::finalize_block(&mut new_state, self.author.as_ref());
 
         let mut block = Block::<C, SM> {
             header: Header::<C::Digest> {
                 parent: parent_block_hash,
                 height: parent_block.header.height + 1,
-                state_root: hash(&new_state),
-                extrinsics_root: hash(&used_transactions),
+                state_root: state_root(&new_state),
+                extrinsics_root: merkle_root(&used_transactions),
+                receipts_root: hash(&receipts),
                 consensus_digest: <C as Consensus>::Digest::default(),
             },
+            author: self.author.clone(),
             body: used_transactions.clone(),
         };
 
@@ -128,6 +191,10 @@ where
             block.header,
         );
         if sealed_header.is_none() {
+            // The transactions are still good for the next attempt.
+            for tx in used_transactions {
+                P::try_insert(&mut self.transaction_pool, tx);
+            }
             return Err(());
         }
         block.header = sealed_header.unwrap();
@@ -139,12 +206,13 @@ where
 
         // ---- import part
 
+        S::set_receipts(&mut self.storage, block.hash(), receipts);
         S::add_block(&mut self.storage, block.clone());
         S::set_state(&mut self.storage, new_state.clone());
-        S::set_current_state(&mut self.storage, new_state.clone());
-        S::set_last_block(&mut self.storage, block.clone());
 
         FC::import_hook(&mut self.fork_choice, block.clone());
+        self.follow_fork_choice();
+        self.announce_import(block.hash());
 
         return Ok(());
     }
@@ -154,24 +222,26 @@ where
 
 fn prioritizer(t: AccountingTransaction) -> u64 {
     match t {
-        AccountingTransaction::Mint { minter, amount } => return 5,
-        AccountingTransaction::Burn { burner, amount } => return 4,
+        AccountingTransaction::Mint { minter, amount, .. } => return 5,
+        AccountingTransaction::Burn { burner, amount, .. } => return 4,
         AccountingTransaction::Transfer {
             sender,
             receiver,
             amount,
+            ..
         } => return 6,
     }
 }
 
 fn prioritizer_same_prio(t: AccountingTransaction) -> u64 {
     match t {
-        AccountingTransaction::Mint { minter, amount } => return 5,
-        AccountingTransaction::Burn { burner, amount } => return 5,
+        AccountingTransaction::Mint { minter, amount, .. } => return 5,
+        AccountingTransaction::Burn { burner, amount, .. } => return 5,
         AccountingTransaction::Transfer {
             sender,
             receiver,
             amount,
+            ..
         } => return 5,
     }
 }
@@ -190,7 +260,7 @@ fn init_client_for_test(
     let consensus_engine = Pow {
         threshold: u64::MAX / 10,
     };
-    let state_machine = AccountedCurrency {};
+    let state_machine = AccountedCurrency::default();
     let fork_choice = Ghost::default();
     let transaction_pool: PriorityPool<AccountedCurrency, AccountingTransactionPrioritizer> =
         PriorityPool::new(prioritizer, 4);
@@ -203,9 +273,14 @@ fn init_client_for_test(
         fork_choice,
         transaction_pool,
         storage,
+        author: None,
+        block_weight_limit: DEFAULT_BLOCK_WEIGHT_LIMIT,
+        chain_subscribers: Vec::new(),
+        import_subscribers: Vec::new(),
     }
 }
 
+#[cfg(test)]
 mod cl5_manual_authoring {
     use super::*;
     use crate::c1_state_machine::p4_accounted_currency::BalancesB;
@@ -221,44 +296,51 @@ mod cl5_manual_authoring {
             AccountingTransaction::Mint {
                 minter: User::Alice,
                 amount: 10,
+                nonce: 0,
             },
             AccountingTransaction::Mint {
                 minter: User::Bob,
                 amount: 20,
+                nonce: 0,
             },
             AccountingTransaction::Burn {
                 burner: User::Bob,
                 amount: 2,
+                nonce: 1,
             },
             AccountingTransaction::Transfer {
                 sender: User::Bob,
                 receiver: User::Alice,
                 amount: 2,
+                nonce: 2,
             },
         ];
 
         let genesis_state = BalancesB {
             balances: HashMap::from([(User::Charlie, 1)]),
+            ..Default::default()
         };
-        let previous_block = Block::<Pow, AccountedCurrency>::genesis(&genesis_state);
-
-        client.storage.add_block(previous_block.clone());
-        client.storage.set_state(genesis_state);
+        client.storage = BasicStorage::with_genesis(genesis_state.clone());
+        let previous_block = client.storage.get_last_block();
+        assert_eq!(
+            previous_block.hash(),
+            Block::<Pow, AccountedCurrency>::genesis(&genesis_state).hash()
+        );
 
         // when
         assert!(client
-            .author_and_import_manual_block(transactions.clone(), hash(&previous_block))
+            .author_and_import_manual_block(transactions.clone(), previous_block.hash())
             .is_ok());
 
         // then
         assert_eq!(client.storage.blocks_map.len(), 2);
-        assert_eq!(client.storage.states_map.len(), 2);
+        assert_eq!(client.storage.state_roots.len(), 2);
 
-        let mut state_hash = 0;
+        let mut state_hash = Hash::default();
         for (block_hash, block) in &client.storage.blocks_map {
-            if *block_hash != hash(&previous_block) {
+            if *block_hash != previous_block.hash() {
                 assert_eq!(block.body, transactions);
-                assert_eq!(block.header.parent, hash(&previous_block));
+                assert_eq!(block.header.parent, previous_block.hash());
                 assert_eq!(block.header.height, 1);
 
                 state_hash = block.header.state_root;
@@ -291,24 +373,29 @@ mod cl5_manual_authoring {
             AccountingTransaction::Mint {
                 minter: User::Alice,
                 amount: 10,
+                nonce: 0,
             },
             AccountingTransaction::Mint {
                 minter: User::Bob,
                 amount: 20,
+                nonce: 0,
             },
             AccountingTransaction::Burn {
                 burner: User::Bob,
                 amount: 2,
+                nonce: 1,
             },
             AccountingTransaction::Transfer {
                 sender: User::Bob,
                 receiver: User::Alice,
                 amount: 2,
+                nonce: 2,
             },
         ];
 
         let genesis_state = BalancesB {
             balances: HashMap::from([(User::Charlie, 1)]),
+            ..Default::default()
         };
         let previous_block = Block::<Pow, AccountedCurrency>::genesis(&genesis_state);
 
@@ -316,7 +403,7 @@ mod cl5_manual_authoring {
 
         // when + then
         assert!(client
-            .author_and_import_manual_block(transactions.clone(), hash(&previous_block))
+            .author_and_import_manual_block(transactions.clone(), previous_block.hash())
             .is_err());
     }
 
@@ -329,24 +416,29 @@ mod cl5_manual_authoring {
             AccountingTransaction::Mint {
                 minter: User::Alice,
                 amount: 10,
+                nonce: 0,
             },
             AccountingTransaction::Mint {
                 minter: User::Bob,
                 amount: 20,
+                nonce: 0,
             },
             AccountingTransaction::Burn {
                 burner: User::Bob,
                 amount: 2,
+                nonce: 1,
             },
             AccountingTransaction::Transfer {
                 sender: User::Bob,
                 receiver: User::Alice,
                 amount: 2,
+                nonce: 2,
             },
         ];
 
         let genesis_state = BalancesB {
             balances: HashMap::from([(User::Charlie, 1)]),
+            ..Default::default()
         };
         let previous_block = Block::<Pow, AccountedCurrency>::genesis(&genesis_state);
 
@@ -354,18 +446,44 @@ mod cl5_manual_authoring {
 
         // when + then
         assert!(client
-            .author_and_import_manual_block(transactions.clone(), hash(&previous_block))
+            .author_and_import_manual_block(transactions.clone(), previous_block.hash())
             .is_err());
     }
 }
 
+#[cfg(test)]
 mod cl5_automatic_authoring {
     use super::*;
     use crate::c1_state_machine::p4_accounted_currency::BalancesB;
+    use crate::c1_state_machine::{SignedAccountedCurrency, SignedExtrinsic};
     use crate::c4_client::p2_importing_blocks::ImportBlock;
+    use crate::c4_client::p3_fork_choice::LongestChain;
+    use crate::c4_client::p4_transaction_pool::SimplePool;
     use crate::{c1_state_machine::User, c4_client::p1_data_structure::Block};
     use std::collections::HashMap;
 
+    type SignedClient = FullClient<
+        (),
+        SignedAccountedCurrency,
+        LongestChain,
+        PriorityPool<SignedAccountedCurrency, fn(SignedExtrinsic<AccountingTransaction>) -> u64>,
+        BasicStorage<(), SignedAccountedCurrency>,
+    >;
+
+    fn init_signed_client_for_test(author: Option<User>) -> SignedClient {
+        FullClient {
+            consensus_engine: (),
+            state_machine: SignedAccountedCurrency::default(),
+            fork_choice: LongestChain::default(),
+            transaction_pool: PriorityPool::default(),
+            storage: BasicStorage::new(),
+            author,
+            block_weight_limit: DEFAULT_BLOCK_WEIGHT_LIMIT,
+            chain_subscribers: Vec::new(),
+            import_subscribers: Vec::new(),
+        }
+    }
+
     #[test]
     fn uses_internal_transactions_and_best_block_then_sets_current_state_and_block() {
         // --- GIVEN
@@ -376,19 +494,23 @@ mod cl5_automatic_authoring {
             AccountingTransaction::Mint {
                 minter: User::Alice,
                 amount: 10,
+                nonce: 0,
             },
             AccountingTransaction::Mint {
                 minter: User::Bob,
                 amount: 20,
+                nonce: 0,
             },
             AccountingTransaction::Burn {
                 burner: User::Bob,
                 amount: 2,
+                nonce: 1,
             },
             AccountingTransaction::Transfer {
                 sender: User::Bob,
                 receiver: User::Alice,
                 amount: 2,
+                nonce: 2,
             },
         ];
         for tx in &transactions {
@@ -399,6 +521,7 @@ mod cl5_automatic_authoring {
         // adding block and state to storage
         let genesis_state = BalancesB {
             balances: HashMap::from([(User::Charlie, 1)]),
+            ..Default::default()
         };
         let previous_block = Block::<Pow, AccountedCurrency>::genesis(&genesis_state);
 
@@ -419,11 +542,11 @@ mod cl5_automatic_authoring {
         // storage was updated
         // storage is initialized with one genesis block, then one more was added in the "given" step, then one was added during block authoring
         assert_eq!(client.storage.blocks_map.len(), 3);
-        assert_eq!(client.storage.states_map.len(), 3);
+        assert_eq!(client.storage.state_roots.len(), 3);
 
         let last_block = client.storage.get_last_block();
         assert_eq!(last_block.body, transactions);
-        assert_eq!(last_block.header.parent, hash(&previous_block));
+        assert_eq!(last_block.header.parent, previous_block.hash());
         assert_eq!(last_block.header.height, 1);
 
         let state = client.current_state();
@@ -440,10 +563,10 @@ mod cl5_automatic_authoring {
             .is_some_and(|bobs_balance| *bobs_balance == 16));
 
         // fork choice best block is the new one
-        assert!(client
-            .fork_choice
-            .best_block()
-            .is_some_and(|b| b == hash(&last_block)));
+        assert!(
+            ForkChoice::<Pow, AccountedCurrency>::best_block(&client.fork_choice)
+                .is_some_and(|b| b == last_block.hash())
+        );
 
         // transactions were removed from the pool
         assert_eq!(client.transaction_pool.size(), 0);
@@ -459,19 +582,23 @@ mod cl5_automatic_authoring {
             AccountingTransaction::Mint {
                 minter: User::Alice,
                 amount: 10,
+                nonce: 0,
             },
             AccountingTransaction::Mint {
                 minter: User::Bob,
                 amount: 20,
+                nonce: 0,
             },
             AccountingTransaction::Burn {
                 burner: User::Bob,
                 amount: 2,
+                nonce: 1,
             },
             AccountingTransaction::Transfer {
                 sender: User::Bob,
                 receiver: User::Alice,
                 amount: 2,
+                nonce: 2,
             },
         ];
         for tx in &transactions {
@@ -482,6 +609,7 @@ mod cl5_automatic_authoring {
         // adding block and state to storage
         let genesis_state = BalancesB {
             balances: HashMap::from([(User::Charlie, 1)]),
+            ..Default::default()
         };
         let previous_block = Block::<Pow, AccountedCurrency>::genesis(&genesis_state);
 
@@ -500,7 +628,7 @@ mod cl5_automatic_authoring {
 
         // storage not updated
         assert_eq!(client.storage.blocks_map.len(), 2);
-        assert_eq!(client.storage.states_map.len(), 2);
+        assert_eq!(client.storage.state_roots.len(), 2);
 
         let state = client.current_state();
         assert_eq!(state.balances, genesis_state.balances)
@@ -516,19 +644,23 @@ mod cl5_automatic_authoring {
             AccountingTransaction::Mint {
                 minter: User::Alice,
                 amount: 10,
+                nonce: 0,
             },
             AccountingTransaction::Mint {
                 minter: User::Bob,
                 amount: 20,
+                nonce: 0,
             },
             AccountingTransaction::Burn {
                 burner: User::Bob,
                 amount: 2,
+                nonce: 1,
             },
             AccountingTransaction::Transfer {
                 sender: User::Bob,
                 receiver: User::Alice,
                 amount: 2,
+                nonce: 2,
             },
         ];
         for tx in &transactions {
@@ -539,6 +671,7 @@ mod cl5_automatic_authoring {
         // adding block and state to storage
         let genesis_state = BalancesB {
             balances: HashMap::from([(User::Charlie, 1)]),
+            ..Default::default()
         };
         let previous_block = Block::<Pow, AccountedCurrency>::genesis(&genesis_state);
 
@@ -556,7 +689,7 @@ mod cl5_automatic_authoring {
 
         // storage not updated
         assert_eq!(client.storage.blocks_map.len(), 1);
-        assert_eq!(client.storage.states_map.len(), 2);
+        assert_eq!(client.storage.state_roots.len(), 2);
 
         let state = client.current_state();
         assert_eq!(state.balances, genesis_state.balances)
@@ -572,19 +705,23 @@ mod cl5_automatic_authoring {
             AccountingTransaction::Mint {
                 minter: User::Alice,
                 amount: 10,
+                nonce: 0,
             },
             AccountingTransaction::Mint {
                 minter: User::Bob,
                 amount: 20,
+                nonce: 0,
             },
             AccountingTransaction::Burn {
                 burner: User::Bob,
                 amount: 2,
+                nonce: 1,
             },
             AccountingTransaction::Transfer {
                 sender: User::Bob,
                 receiver: User::Alice,
                 amount: 2,
+                nonce: 2,
             },
         ];
         for tx in &transactions {
@@ -595,6 +732,7 @@ mod cl5_automatic_authoring {
         // adding block and state to storage
         let genesis_state = BalancesB {
             balances: HashMap::from([(User::Charlie, 1)]),
+            ..Default::default()
         };
         let previous_block = Block::<Pow, AccountedCurrency>::genesis(&genesis_state);
 
@@ -612,7 +750,7 @@ mod cl5_automatic_authoring {
 
         // storage not updated
         assert_eq!(client.storage.blocks_map.len(), 2);
-        assert_eq!(client.storage.states_map.len(), 1);
+        assert_eq!(client.storage.state_roots.len(), 1);
     }
 
     #[test]
@@ -626,6 +764,7 @@ mod cl5_automatic_authoring {
         // adding block and state to storage
         let genesis_state = BalancesB {
             balances: HashMap::from([(User::Charlie, 1)]),
+            ..Default::default()
         };
         let previous_block = Block::<Pow, AccountedCurrency>::genesis(&genesis_state);
 
@@ -645,9 +784,228 @@ mod cl5_automatic_authoring {
 
         // storage not updated
         assert_eq!(client.storage.blocks_map.len(), 2);
-        assert_eq!(client.storage.states_map.len(), 2);
+        assert_eq!(client.storage.state_roots.len(), 2);
 
         let state = client.current_state();
         assert_eq!(state.balances, genesis_state.balances)
     }
+
+    #[test]
+    fn drops_invalid_transactions_from_the_block() {
+        // --- GIVEN
+        let mut client = init_client_for_test(prioritizer_same_prio);
+
+        let valid = AccountingTransaction::Mint {
+            minter: User::Alice,
+            amount: 10,
+            nonce: 0,
+        };
+        // Bob has no account, so this transfer is invalid.
+        let invalid = AccountingTransaction::Transfer {
+            sender: User::Bob,
+            receiver: User::Alice,
+            amount: 5,
+            nonce: 0,
+        };
+        assert!(client.transaction_pool.try_insert(invalid.clone()));
+        assert!(client.transaction_pool.try_insert(valid.clone()));
+
+        let genesis_block = client.storage.get_last_block();
+        client.fork_choice.import_hook(genesis_block.clone());
+
+        // --- WHEN
+        let res = client.author_and_import_automatic_block();
+
+        // --- THEN
+        assert!(res.is_ok());
+
+        let last_block = client.storage.get_last_block();
+        assert_eq!(last_block.body, vec![valid]);
+        assert_eq!(last_block.header.parent, genesis_block.hash());
+        assert_eq!(client.transaction_pool.size(), 0);
+    }
+
+    #[test]
+    fn holds_back_transactions_with_future_nonces() {
+        // --- GIVEN
+        let mut client = init_signed_client_for_test(None);
+        let mint = |minter: User, nonce: u64| {
+            SignedExtrinsic::new(
+                minter,
+                AccountingTransaction::Mint {
+                    minter,
+                    amount: 10,
+                    nonce,
+                },
+            )
+        };
+
+        // The longest chain rule only picks up blocks above genesis.
+        let genesis_block = client.storage.get_last_block();
+        client
+            .author_and_import_manual_block(vec![], genesis_block.hash())
+            .unwrap();
+
+        // Alice's nonce 0 has not arrived yet.
+        assert!(client.submit_transaction(mint(User::Alice, 1)));
+        assert!(client.submit_transaction(mint(User::Bob, 0)));
+
+        // --- WHEN
+        let res = client.author_and_import_automatic_block();
+
+        // --- THEN
+        assert!(res.is_ok());
+        assert_eq!(
+            client.storage.get_last_block().body,
+            vec![mint(User::Bob, 0)]
+        );
+        assert!(client.pool_contains(mint(User::Alice, 1)));
+
+        // --- WHEN
+        assert!(client.submit_transaction(mint(User::Alice, 0)));
+        let res = client.author_and_import_automatic_block();
+
+        // --- THEN
+        assert!(res.is_ok());
+        assert_eq!(
+            client.storage.get_last_block().body,
+            vec![mint(User::Alice, 0), mint(User::Alice, 1)]
+        );
+        assert_eq!(client.pool_size(), 0);
+    }
+
+    #[test]
+    fn fills_the_block_up_to_the_weight_limit() {
+        // --- GIVEN
+        let mut client = init_client_for_test(prioritizer_same_prio);
+        client.set_block_weight_limit(2);
+
+        let mints: Vec<_> = [User::Alice, User::Bob, User::Charlie]
+            .into_iter()
+            .map(|minter| AccountingTransaction::Mint {
+                minter,
+                amount: 10,
+                nonce: 0,
+            })
+            .collect();
+        for mint in &mints {
+            assert!(client.transaction_pool.try_insert(mint.clone()));
+        }
+
+        let genesis_block = client.storage.get_last_block();
+        client.fork_choice.import_hook(genesis_block.clone());
+
+        // --- WHEN
+        let res = client.author_and_import_automatic_block();
+
+        // --- THEN
+        assert!(res.is_ok());
+        assert_eq!(client.storage.get_last_block().body, mints[..2].to_vec());
+        assert_eq!(client.transaction_pool.size(), 1);
+        assert!(client.pool_contains(mints[2].clone()));
+    }
+
+    #[test]
+    fn credits_the_fees_to_the_author() {
+        // --- GIVEN
+        let mut client = init_signed_client_for_test(Some(User::Charlie));
+
+        // The longest chain rule only picks up blocks above genesis.
+        let genesis_block = client.storage.get_last_block();
+        client
+            .author_and_import_manual_block(vec![], genesis_block.hash())
+            .unwrap();
+
+        let mint = SignedExtrinsic::new(
+            User::Alice,
+            AccountingTransaction::Mint {
+                minter: User::Alice,
+                amount: 10,
+                nonce: 0,
+            },
+        );
+        let transfer = SignedExtrinsic::new(
+            User::Alice,
+            AccountingTransaction::Transfer {
+                sender: User::Alice,
+                receiver: User::Bob,
+                amount: 5,
+                nonce: 1,
+            },
+        );
+        assert!(client.submit_transaction(mint));
+        assert!(client.submit_transaction(transfer));
+
+        // --- WHEN
+        let res = client.author_and_import_automatic_block();
+
+        // --- THEN
+        assert!(res.is_ok());
+        let last_block = client.storage.get_last_block();
+        assert_eq!(last_block.author, Some(User::Charlie));
+
+        // The mint costs 1 and the transfer 2.
+        let state = client.storage.current_state();
+        assert_eq!(
+            state.balances,
+            HashMap::from([(User::Alice, 2), (User::Bob, 5), (User::Charlie, 3)])
+        );
+        assert_eq!(state.fees, 0);
+    }
+
+    /// A consensus engine whose authority is never around to seal a block.
+    #[derive(Clone, Hash)]
+    struct NoAuthority;
+
+    impl Consensus for NoAuthority {
+        type Digest = ();
+
+        fn validate(&self, _: &(), _: &Header<()>) -> bool {
+            false
+        }
+
+        fn seal(&self, _: &(), _: Header<()>) -> Option<Header<()>> {
+            None
+        }
+    }
+
+    #[test]
+    fn keeps_the_transactions_when_the_block_cannot_be_sealed() {
+        // --- GIVEN
+        let mut client = FullClient {
+            consensus_engine: NoAuthority,
+            state_machine: AccountedCurrency::default(),
+            fork_choice: LongestChain::default(),
+            transaction_pool: SimplePool::default(),
+            storage: BasicStorage::<NoAuthority, AccountedCurrency>::new(),
+            author: None,
+            block_weight_limit: DEFAULT_BLOCK_WEIGHT_LIMIT,
+            chain_subscribers: Vec::new(),
+            import_subscribers: Vec::new(),
+        };
+        let genesis_block = client.storage.get_last_block();
+        client.fork_choice.import_hook(genesis_block.clone());
+
+        let mints: Vec<_> = (0..2)
+            .map(|nonce| AccountingTransaction::Mint {
+                minter: User::Alice,
+                amount: 10,
+                nonce,
+            })
+            .collect();
+        for mint in &mints {
+            assert!(client.submit_transaction(mint.clone()));
+        }
+
+        // --- WHEN
+        let res = client.author_and_import_automatic_block();
+
+        // --- THEN
+        assert!(res.is_err());
+        assert_eq!(client.storage.get_last_block().hash(), genesis_block.hash());
+        assert_eq!(
+            client.transaction_pool.iter().cloned().collect::<Vec<_>>(),
+            mints
+        );
+    }
 }
@@ -1,18 +1,268 @@
-//! Sometimes you want a block to never be reverted.
-//! In practice this is usually implemented by some kind of BFT based consensus game.
-//! We will model a very simple alternative where node operators manually request finality.
+//! Fork choice rules only ever tell us which chain looks best right now. A longer or heavier
+//! fork may always come along and revert blocks we thought were settled. Finality is the
+//! promise that a block will never be reverted.
 //!
-//! Although we elide the details of the game itself, this model still allows us to explore
-//! the consequences of having some blocks that are never reverted.
+//! Real chains reach finality through a separate gadget, like GRANDPA, which has validators
+//! vote on blocks. Here the client is told directly which blocks are final. Once a block is
+//! final, nothing may fork off below it, so every branch that does not contain it is abandoned
+//! and can be pruned from storage along with its states.
 
-use super::FullClient;
+use std::collections::{HashMap, HashSet};
 
-impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S> {
+use super::p1_data_structure::Block;
+#[cfg(test)]
+use super::p2_importing_blocks::ImportBlock;
+use super::p3_fork_choice::ForkChoice;
+use super::p4_transaction_pool::TransactionPool;
+#[cfg(test)]
+use super::test_helpers::{import_chain, init_client_for_test, mint};
+use super::{Consensus, FullClient, Hash, StateMachine, Storage};
+#[cfg(test)]
+use crate::c1_state_machine::User;
+use crate::{Encode, TrieState};
+
+/// The descendants of a newly finalized block, parents before children, and the hashes of
+/// the blocks abandoned by finalizing it.
+type BlockTreeSplit<C, SM> = (Vec<Block<C, SM>>, Vec<Hash>);
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    S: Storage<C, SM>,
+    Block<C, SM>: std::hash::Hash,
+    SM::State: TrieState,
+{
+    /// The most recently finalized block.
+    pub fn finalized_block(&self) -> Hash {
+        self.storage.finalized_block()
+    }
+
+    /// Whether a child of the given block would fork off the chain below the finalized block.
+    /// Blocks on other branches above it are pruned when it is finalized, so the height is all
+    /// there is to check.
+    pub(super) fn forks_below_finality(&self, parent: &Block<C, SM>) -> bool {
+        match self.storage.get_block(self.storage.finalized_block()) {
+            Some(finalized) => parent.header.height < finalized.header.height,
+            None => false,
+        }
+    }
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    FC: ForkChoice<C, SM> + Default,
+    P: TransactionPool<SM>,
+    S: Storage<C, SM>,
+    Block<C, SM>: std::hash::Hash + Clone,
+    SM::State: TrieState,
+    SM::Transition: Encode,
+{
     /// Mark the given block as final so that it will never be reverted.
     /// Returns whether or not the block was known and marked successfully.
-    pub fn manually_finalize_block(&mut self, block_hash: u64) -> bool {
-        todo!("Exercise 1")
+    ///
+    /// Only descendants of the previously finalized block can be finalized. All branches that
+    /// do not contain the block are pruned, and if the best block was on one of them, the
+    /// client switches to the best descendant of the finalized block.
+    pub fn manually_finalize_block(&mut self, block_hash: Hash) -> bool {
+        let Some(block) = self.storage.get_block(block_hash) else {
+            return false;
+        };
+        let previous = self.storage.finalized_block();
+        if block_hash == previous {
+            return true;
+        }
+        let Some(finalized_chain) = self.ancestry_since(&block, previous) else {
+            return false;
+        };
+        let Some((descendants, abandoned)) = self.split_block_tree(&block, &finalized_chain) else {
+            return false;
+        };
+
+        self.storage.set_finalized_block(block_hash);
+        self.fork_choice.finality_hook(block, descendants);
+        // The best block may be abandoned, so move off it while it is still in storage.
+        self.follow_fork_choice();
+        self.storage.remove_blocks(&abandoned);
+
+        true
+    }
+
+    /// The hashes of the given block and its ancestors down to the previously finalized block,
+    /// keyed by height. Returns None if the previously finalized block is not an ancestor.
+    fn ancestry_since(&self, block: &Block<C, SM>, previous: Hash) -> Option<HashMap<u64, Hash>> {
+        let previous_height = self.storage.get_block(previous)?.header.height;
+        let mut ancestry = HashMap::new();
+        let mut current = block.clone();
+        while current.header.height > previous_height {
+            ancestry.insert(current.header.height, current.hash());
+            current = self.storage.get_block(current.header.parent)?;
+        }
+        if current.hash() != previous {
+            return None;
+        }
+        ancestry.insert(previous_height, previous);
+        Some(ancestry)
+    }
+
+    /// Split the blocks off the finalized chain into descendants of the newly finalized block,
+    /// parents before children, and the hashes of the abandoned ones.
+    fn split_block_tree(
+        &self,
+        finalized: &Block<C, SM>,
+        finalized_chain: &HashMap<u64, Hash>,
+    ) -> Option<BlockTreeSplit<C, SM>> {
+        let finalized_height = finalized.header.height;
+        let mut descendants = HashMap::new();
+        let mut abandoned = HashSet::new();
+
+        for leaf in self.storage.leaves() {
+            let mut branch = Vec::new();
+            let mut current = self.storage.get_block(leaf)?;
+            while current.header.height > finalized_height {
+                branch.push(current.clone());
+                current = self.storage.get_block(current.header.parent)?;
+            }
+            if current.hash() == finalized.hash() {
+                descendants.extend(branch.into_iter().map(|b| (b.hash(), b)));
+                continue;
+            }
+
+            // Any other branch is abandoned all the way down to where it forked off.
+            while finalized_chain.get(&current.header.height) != Some(&current.hash()) {
+                branch.push(current.clone());
+                current = self.storage.get_block(current.header.parent)?;
+            }
+            abandoned.extend(branch.iter().map(|b| b.hash()));
+        }
+
+        let mut descendants: Vec<_> = descendants.into_values().collect();
+        descendants.sort_by_key(|b| b.header.height);
+        Some((descendants, abandoned.into_iter().collect()))
+    }
+}
+
+// --- TESTS ---
+
+#[test]
+fn cl6_genesis_is_finalized_initially() {
+    let client = init_client_for_test();
+    assert_eq!(client.finalized_block(), client.get_last_block().hash());
+}
+
+#[test]
+fn cl6_cannot_finalize_unknown_block() {
+    let mut client = init_client_for_test();
+    assert!(!client.manually_finalize_block(42.into()));
+    assert_eq!(client.finalized_block(), client.get_last_block().hash());
+}
+
+#[test]
+fn cl6_finality_only_moves_forward() {
+    let mut client = init_client_for_test();
+    let genesis = client.get_last_block();
+    let a = import_chain(&mut client, &genesis, User::Alice, 10, 3);
+
+    assert!(client.manually_finalize_block(a[1].hash()));
+    assert_eq!(client.finalized_block(), a[1].hash());
+
+    // Finalizing it again is fine, going back to an ancestor is not.
+    assert!(client.manually_finalize_block(a[1].hash()));
+    assert!(!client.manually_finalize_block(a[0].hash()));
+    assert!(!client.manually_finalize_block(genesis.hash()));
+    assert_eq!(client.finalized_block(), a[1].hash());
+
+    assert!(client.manually_finalize_block(a[2].hash()));
+    assert_eq!(client.finalized_block(), a[2].hash());
+}
+
+#[test]
+fn cl6_import_rejects_blocks_forking_below_finalized() {
+    let mut client = init_client_for_test();
+    let genesis = client.get_last_block();
+    let genesis_state = client.current_state();
+    let a = import_chain(&mut client, &genesis, User::Alice, 10, 2);
+    assert!(client.manually_finalize_block(a[1].hash()));
+
+    let off_genesis = genesis
+        .child(&genesis_state, vec![mint(User::Bob, 0, 5)])
+        .unwrap();
+    assert!(!client.import_block(off_genesis));
+
+    let a0_state = client.storage.get_state(a[0].header.state_root).unwrap();
+    let off_a0 = a[0].child(&a0_state, vec![mint(User::Bob, 0, 5)]).unwrap();
+    assert!(!client.import_block(off_a0));
+
+    // Building on the finalized block itself is fine.
+    import_chain(&mut client, &a[1], User::Bob, 5, 1);
+}
+
+#[test]
+fn cl6_manual_authoring_cannot_fork_below_finalized() {
+    let mut client = init_client_for_test();
+    let genesis = client.get_last_block();
+    let a = import_chain(&mut client, &genesis, User::Alice, 10, 2);
+    assert!(client.manually_finalize_block(a[1].hash()));
+
+    assert_eq!(
+        client.author_and_import_manual_block(vec![mint(User::Bob, 0, 5)], a[0].hash()),
+        Err(())
+    );
+}
+
+#[test]
+fn cl6_abandoned_branches_are_pruned() {
+    let mut client = init_client_for_test();
+    let genesis = client.get_last_block();
+    let a = import_chain(&mut client, &genesis, User::Alice, 10, 2);
+    let b = import_chain(&mut client, &genesis, User::Bob, 5, 3);
+    assert_eq!(client.get_last_block().hash(), b[2].hash());
+
+    assert!(client.manually_finalize_block(a[0].hash()));
+
+    for block in &b {
+        assert!(client.get_block(block.hash()).is_none());
+        assert!(client.get_receipts(block.hash()).is_none());
+        assert!(client.storage.get_state(block.header.state_root).is_none());
+    }
+    assert_eq!(client.storage.leaves(), vec![a[1].hash()]);
+    assert_eq!(client.storage.children(genesis.hash()), vec![a[0].hash()]);
+
+    // The finalized chain and its states are all still there.
+    for block in [&genesis, &a[0], &a[1]] {
+        assert!(client.get_block(block.hash()).is_some());
+        assert!(client.storage.get_state(block.header.state_root).is_some());
     }
 }
 
-//TODO tests
+#[test]
+fn cl6_fork_choice_only_considers_descendants_of_finalized() {
+    let mut client = init_client_for_test();
+    let genesis = client.get_last_block();
+    let a = import_chain(&mut client, &genesis, User::Alice, 10, 2);
+    let b = import_chain(&mut client, &genesis, User::Bob, 5, 3);
+
+    // The longer fork is abandoned, so the best block moves back to the finalized branch,
+    // and the abandoned transactions go back to the pool.
+    assert!(client.manually_finalize_block(a[0].hash()));
+    assert_eq!(client.get_last_block().hash(), a[1].hash());
+    assert_eq!(
+        client.current_state().balances,
+        client
+            .storage
+            .get_state(a[1].header.state_root)
+            .unwrap()
+            .balances
+    );
+    assert!(client.pool_contains(mint(User::Bob, 0, 5)));
+
+    // The next block on the finalized branch is best even though the abandoned fork was
+    // just as long.
+    let a2 = import_chain(&mut client, &a[1], User::Alice, 1, 1);
+    assert_eq!(client.get_last_block().hash(), a2[0].hash());
+    assert!(b
+        .iter()
+        .all(|block| client.get_block(block.hash()).is_none()));
+}
@@ -0,0 +1,327 @@
+//! Once the client can import forks, the fork choice rule may change its mind about which
+//! block is best. When it does, the client has to move its view of the chain over to the new
+//! best block. That is called a chain reorganisation, or reorg for short.
+//!
+//! A reorg walks back from the old best block to the last block it has in common with the new
+//! one, undoing, or retracting, the blocks along the way. Then it walks forward to the new best
+//! block, applying, or enacting, the blocks of the new branch. Simply extending the best chain
+//! by a block is the special case where nothing is retracted.
+
+use std::sync::mpsc::{self, Receiver};
+
+use super::p1_data_structure::Block;
+#[cfg(test)]
+use super::p2_importing_blocks::ImportBlock;
+use super::p3_fork_choice::ForkChoice;
+use super::p4_transaction_pool::TransactionPool;
+#[cfg(test)]
+use super::test_helpers::{init_client_for_test, mint};
+use super::{Consensus, FullClient, Hash, StateMachine, Storage};
+#[cfg(test)]
+use crate::c1_state_machine::{AccountedCurrency, User};
+#[cfg(test)]
+use crate::state_root;
+use crate::{Encode, TrieState};
+
+/// The path through the block tree from one block to another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeRoute {
+    /// The last block the two branches have in common.
+    pub common_ancestor: Hash,
+    /// The blocks leaving the best chain, from the old best block down to, but excluding,
+    /// the common ancestor.
+    pub retracted: Vec<Hash>,
+    /// The blocks joining the best chain, from just after the common ancestor up to the new
+    /// best block.
+    pub enacted: Vec<Hash>,
+}
+
+impl TreeRoute {
+    /// Whether following this route takes blocks out of the best chain, rather than just
+    /// extending it.
+    pub fn is_reorg(&self) -> bool {
+        !self.retracted.is_empty()
+    }
+}
+
+/// Find the route from one block to another. Returns None if either block, or any of
+/// their ancestors down to the common one, is not in storage.
+pub fn tree_route<C, SM, S>(storage: &S, from: Hash, to: Hash) -> Option<TreeRoute>
+where
+    C: Consensus,
+    SM: StateMachine,
+    S: Storage<C, SM>,
+    Block<C, SM>: std::hash::Hash,
+    SM::State: TrieState,
+{
+    let mut from_block = storage.get_block(from)?;
+    let mut to_block = storage.get_block(to)?;
+    let mut retracted = Vec::new();
+    let mut enacted = Vec::new();
+
+    while from_block.header.height > to_block.header.height {
+        retracted.push(from_block.hash());
+        from_block = storage.get_block(from_block.header.parent)?;
+    }
+    while to_block.header.height > from_block.header.height {
+        enacted.push(to_block.hash());
+        to_block = storage.get_block(to_block.header.parent)?;
+    }
+    while from_block.hash() != to_block.hash() {
+        retracted.push(from_block.hash());
+        enacted.push(to_block.hash());
+        from_block = storage.get_block(from_block.header.parent)?;
+        to_block = storage.get_block(to_block.header.parent)?;
+    }
+
+    enacted.reverse();
+    Some(TreeRoute {
+        common_ancestor: from_block.hash(),
+        retracted,
+        enacted,
+    })
+}
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    FC: ForkChoice<C, SM>,
+    P: TransactionPool<SM>,
+    S: Storage<C, SM>,
+    Block<C, SM>: std::hash::Hash + Clone,
+    SM::State: TrieState,
+    SM::Transition: Encode,
+{
+    /// Move the client's last block and current state to the fork choice's best block, if it
+    /// has changed, and update the transaction pool to match. Called after every block that
+    /// enters the block tree.
+    ///
+    /// Returns the route that was followed, which is also sent to every subscriber.
+    pub(super) fn follow_fork_choice(&mut self) -> Option<TreeRoute> {
+        let best_hash = self.fork_choice.best_block()?;
+        let last_block = self.storage.get_last_block();
+        if best_hash == last_block.hash() {
+            return None;
+        }
+
+        // The fork choice may point at blocks we never stored, for example before it has
+        // seen any block at all.
+        let best_block = self.storage.get_block(best_hash)?;
+        let best_state = self.storage.get_state(best_block.header.state_root)?;
+        let route = tree_route(&self.storage, last_block.hash(), best_hash)?;
+
+        self.storage.set_current_state(best_state);
+        self.storage.set_last_block(best_block);
+        self.update_pool(&route);
+        // Subscribers that hung up are forgotten, so nothing piles up for nobody.
+        self.chain_subscribers
+            .retain(|subscriber| subscriber.send(route.clone()).is_ok());
+
+        Some(route)
+    }
+
+    /// Subscribe to the routes the best chain follows from now on, oldest first. This is how
+    /// other components learn which blocks were retracted from and enacted into the best chain.
+    /// Drop the receiver to unsubscribe.
+    pub fn subscribe_chain_updates(&mut self) -> Receiver<TreeRoute> {
+        let (sender, receiver) = mpsc::channel();
+        self.chain_subscribers.push(sender);
+        receiver
+    }
+}
+
+// --- TESTS ---
+
+/// Build `length` blocks on top of `parent`, whose post-state is `parent_state`, each minting
+/// `amount` for `minter`.
+#[cfg(test)]
+fn build_on(
+    parent: &Block<(), AccountedCurrency>,
+    parent_state: &<AccountedCurrency as StateMachine>::State,
+    minter: User,
+    amount: u64,
+    length: usize,
+) -> Vec<Block<(), AccountedCurrency>> {
+    let mut blocks = Vec::new();
+    let mut parent = parent.clone();
+    let mut state = parent_state.clone();
+    for _ in 0..length {
+        let transaction = mint(minter, state.nonce(&minter), amount);
+        let block = parent.child(&state, vec![transaction.clone()]).unwrap();
+        state = AccountedCurrency::next_state(&state, &transaction);
+        parent = block.clone();
+        blocks.push(block);
+    }
+    blocks
+}
+
+#[cfg(test)]
+fn hashes(blocks: &[Block<(), AccountedCurrency>]) -> Vec<Hash> {
+    blocks.iter().map(|b| b.hash()).collect()
+}
+
+#[test]
+fn cl7_extending_the_best_chain_is_not_a_reorg() {
+    let mut client = init_client_for_test();
+    let updates = client.subscribe_chain_updates();
+    let genesis = client.get_last_block();
+    let genesis_state = client.current_state();
+
+    let a = build_on(&genesis, &genesis_state, User::Alice, 10, 2);
+    assert!(client.import_block(a[0].clone()));
+    assert!(client.import_block(a[1].clone()));
+
+    assert_eq!(client.get_last_block().hash(), a[1].hash());
+    assert_eq!(
+        updates.try_iter().collect::<Vec<_>>(),
+        vec![
+            TreeRoute {
+                common_ancestor: genesis.hash(),
+                retracted: vec![],
+                enacted: vec![a[0].hash()],
+            },
+            TreeRoute {
+                common_ancestor: a[0].hash(),
+                retracted: vec![],
+                enacted: vec![a[1].hash()],
+            },
+        ]
+    );
+    assert!(updates.try_recv().is_err());
+}
+
+#[test]
+fn cl7_shorter_fork_does_not_move_the_best_block() {
+    let mut client = init_client_for_test();
+    let genesis = client.get_last_block();
+    let genesis_state = client.current_state();
+
+    let a = build_on(&genesis, &genesis_state, User::Alice, 10, 2);
+    let b = build_on(&genesis, &genesis_state, User::Bob, 5, 1);
+    assert!(client.import_block(a[0].clone()));
+    assert!(client.import_block(a[1].clone()));
+    let updates = client.subscribe_chain_updates();
+
+    assert!(client.import_block(b[0].clone()));
+    assert_eq!(client.get_last_block().hash(), a[1].hash());
+    assert!(updates.try_recv().is_err());
+}
+
+#[test]
+fn cl7_longer_fork_triggers_reorg() {
+    let mut client = init_client_for_test();
+    let genesis = client.get_last_block();
+    let genesis_state = client.current_state();
+
+    let a = build_on(&genesis, &genesis_state, User::Alice, 10, 1);
+    let b = build_on(&genesis, &genesis_state, User::Bob, 5, 2);
+    assert!(client.import_block(a[0].clone()));
+    let updates = client.subscribe_chain_updates();
+
+    // The first block of the other fork only ties, the second one overtakes.
+    assert!(client.import_block(b[0].clone()));
+    assert_eq!(client.get_last_block().hash(), a[0].hash());
+    assert!(client.import_block(b[1].clone()));
+
+    let route = TreeRoute {
+        common_ancestor: genesis.hash(),
+        retracted: vec![a[0].hash()],
+        enacted: hashes(&b),
+    };
+    assert!(route.is_reorg());
+    assert_eq!(updates.try_iter().collect::<Vec<_>>(), vec![route]);
+
+    // The client now sees the world as the new best block does.
+    assert_eq!(client.get_last_block().hash(), b[1].hash());
+    let mut expected_state = genesis_state.clone();
+    expected_state = AccountedCurrency::next_state(&expected_state, &mint(User::Bob, 0, 5));
+    expected_state = AccountedCurrency::next_state(&expected_state, &mint(User::Bob, 1, 5));
+    assert_eq!(
+        state_root(&client.current_state()),
+        state_root(&expected_state)
+    );
+}
+
+#[test]
+fn cl7_reorg_from_deep_in_a_fork() {
+    let mut client = init_client_for_test();
+    let genesis = client.get_last_block();
+    let genesis_state = client.current_state();
+
+    // A shared trunk of two blocks, then forks of three and four blocks.
+    let trunk = build_on(&genesis, &genesis_state, User::Charlie, 1, 2);
+    for block in &trunk {
+        assert!(client.import_block(block.clone()));
+    }
+    let trunk_state = client.current_state();
+    let updates = client.subscribe_chain_updates();
+    let a = build_on(&trunk[1], &trunk_state, User::Alice, 10, 3);
+    let b = build_on(&trunk[1], &trunk_state, User::Bob, 5, 4);
+    for block in a.iter().chain(b.iter()) {
+        assert!(client.import_block(block.clone()));
+    }
+
+    let mut retracted = hashes(&a);
+    retracted.reverse();
+    assert_eq!(
+        updates.try_iter().last(),
+        Some(TreeRoute {
+            common_ancestor: trunk[1].hash(),
+            retracted,
+            enacted: hashes(&b),
+        })
+    );
+    assert_eq!(client.get_last_block().hash(), b[3].hash());
+}
+
+#[test]
+fn cl7_every_subscriber_hears_of_updates_until_it_hangs_up() {
+    let mut client = init_client_for_test();
+    let genesis = client.get_last_block();
+    let genesis_state = client.current_state();
+    let a = build_on(&genesis, &genesis_state, User::Alice, 10, 2);
+
+    let first = client.subscribe_chain_updates();
+    let second = client.subscribe_chain_updates();
+    assert!(client.import_block(a[0].clone()));
+    assert_eq!(first.try_iter().count(), 1);
+    assert_eq!(second.try_iter().count(), 1);
+
+    drop(first);
+    assert!(client.import_block(a[1].clone()));
+    assert_eq!(second.try_iter().count(), 1);
+    assert_eq!(client.chain_subscribers.len(), 1);
+}
+
+#[test]
+fn cl7_tree_route_between_blocks_on_the_same_chain() {
+    let mut client = init_client_for_test();
+    let genesis = client.get_last_block();
+    let genesis_state = client.current_state();
+
+    let a = build_on(&genesis, &genesis_state, User::Alice, 10, 3);
+    for block in &a {
+        assert!(client.import_block(block.clone()));
+    }
+
+    // Walking backwards along a chain only retracts.
+    assert_eq!(
+        tree_route(&client.storage, a[2].hash(), genesis.hash()),
+        Some(TreeRoute {
+            common_ancestor: genesis.hash(),
+            retracted: vec![a[2].hash(), a[1].hash(), a[0].hash()],
+            enacted: vec![],
+        })
+    );
+    // And from a block to itself is empty.
+    assert_eq!(
+        tree_route(&client.storage, a[1].hash(), a[1].hash()),
+        Some(TreeRoute {
+            common_ancestor: a[1].hash(),
+            retracted: vec![],
+            enacted: vec![],
+        })
+    );
+    assert_eq!(tree_route(&client.storage, a[1].hash(), 42.into()), None);
+}
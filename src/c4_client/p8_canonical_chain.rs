@@ -0,0 +1,168 @@
+//! Of all the forks a client knows, only one is the canonical chain: the chain from genesis to
+//! the best block. Block explorers and wallets mostly care about this chain, and they usually
+//! refer to its blocks by height rather than by hash.
+//!
+//! The storage keeps a height to hash index of the canonical chain up to date whenever the last
+//! block changes, which happens on import, on reorgs and when finality prunes the best fork.
+//! Here we expose that index on the client.
+
+use super::p1_data_structure::Block;
+#[cfg(test)]
+use super::p2_importing_blocks::ImportBlock;
+#[cfg(test)]
+use super::test_helpers::{import_chain, init_client_for_test, TestClient};
+use super::{Consensus, FullClient, Hash, StateMachine, Storage};
+#[cfg(test)]
+use crate::c1_state_machine::User;
+use crate::TrieState;
+
+impl<C, SM, FC, P, S> FullClient<C, SM, FC, P, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    S: Storage<C, SM>,
+    Block<C, SM>: std::hash::Hash,
+    SM::State: TrieState,
+{
+    /// The hash of the canonical block at the given height.
+    /// Returns None if the best block is lower than that, or if the client started from a
+    /// snapshot above it.
+    pub fn canonical_block_hash(&self, height: u64) -> Option<Hash> {
+        self.storage.canonical_hash(height)
+    }
+
+    /// Iterate over the canonical chain from genesis to the best block. A client that started
+    /// from a snapshot has nothing below it, so its chain starts at the snapshot block instead.
+    pub fn canonical_chain(&self) -> CanonicalChain<'_, C, SM, S> {
+        self.canonical_chain_from(self.storage.base_height())
+    }
+
+    /// Iterate over the canonical chain from the given height to the best block. Handy for
+    /// paging through the chain without walking it from genesis every time.
+    pub fn canonical_chain_from(&self, height: u64) -> CanonicalChain<'_, C, SM, S> {
+        CanonicalChain {
+            storage: &self.storage,
+            height,
+            _marker: std::marker::PhantomData,
+        }
+    }
+}
+
+/// An iterator over the blocks of the canonical chain, in ascending height.
+pub struct CanonicalChain<'a, C, SM, S> {
+    storage: &'a S,
+    /// The height of the next block to return.
+    height: u64,
+    _marker: std::marker::PhantomData<(C, SM)>,
+}
+
+impl<'a, C, SM, S> Iterator for CanonicalChain<'a, C, SM, S>
+where
+    C: Consensus,
+    SM: StateMachine,
+    S: Storage<C, SM>,
+    Block<C, SM>: std::hash::Hash,
+    SM::State: TrieState,
+{
+    type Item = Block<C, SM>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let block_hash = self.storage.canonical_hash(self.height)?;
+        let block = self.storage.get_block(block_hash)?;
+        self.height += 1;
+        Some(block)
+    }
+}
+
+// --- TESTS ---
+
+#[cfg(test)]
+fn canonical_hashes(client: &TestClient) -> Vec<Hash> {
+    client.canonical_chain().map(|b| b.hash()).collect()
+}
+
+#[test]
+fn cl8_genesis_only() {
+    let client = init_client_for_test();
+    let genesis = client.get_last_block();
+
+    assert_eq!(client.canonical_block_hash(0), Some(genesis.hash()));
+    assert_eq!(client.canonical_block_hash(1), None);
+    assert_eq!(canonical_hashes(&client), vec![genesis.hash()]);
+}
+
+#[test]
+fn cl8_index_follows_imports() {
+    let mut client = init_client_for_test();
+    let genesis = client.get_last_block();
+    let a = import_chain(&mut client, &genesis, User::Alice, 10, 3);
+
+    for (i, block) in a.iter().enumerate() {
+        assert_eq!(
+            client.canonical_block_hash(i as u64 + 1),
+            Some(block.hash())
+        );
+    }
+    assert_eq!(client.canonical_block_hash(4), None);
+    assert_eq!(
+        canonical_hashes(&client),
+        vec![genesis.hash(), a[0].hash(), a[1].hash(), a[2].hash()]
+    );
+
+    // A shorter fork is not canonical.
+    let b = import_chain(&mut client, &genesis, User::Bob, 5, 2);
+    assert_eq!(client.canonical_block_hash(1), Some(a[0].hash()));
+    assert_eq!(client.canonical_block_hash(2), Some(a[1].hash()));
+    assert!(!canonical_hashes(&client).contains(&b[0].hash()));
+}
+
+#[test]
+fn cl8_index_follows_reorgs() {
+    let mut client = init_client_for_test();
+    let genesis = client.get_last_block();
+    let a = import_chain(&mut client, &genesis, User::Alice, 10, 3);
+    let b = import_chain(&mut client, &a[0], User::Bob, 5, 3);
+
+    assert_eq!(
+        canonical_hashes(&client),
+        vec![
+            genesis.hash(),
+            a[0].hash(),
+            b[0].hash(),
+            b[1].hash(),
+            b[2].hash()
+        ]
+    );
+}
+
+#[test]
+fn cl8_index_follows_finality() {
+    let mut client = init_client_for_test();
+    let genesis = client.get_last_block();
+    let a = import_chain(&mut client, &genesis, User::Alice, 10, 1);
+    let b = import_chain(&mut client, &genesis, User::Bob, 5, 3);
+    assert_eq!(client.canonical_block_hash(3), Some(b[2].hash()));
+
+    // Finalizing the shorter fork abandons the canonical chain, and the index shrinks with it.
+    assert!(client.manually_finalize_block(a[0].hash()));
+    assert_eq!(canonical_hashes(&client), vec![genesis.hash(), a[0].hash()]);
+    assert_eq!(client.canonical_block_hash(2), None);
+}
+
+#[test]
+fn cl8_paginate_the_chain() {
+    let mut client = init_client_for_test();
+    let genesis = client.get_last_block();
+    let a = import_chain(&mut client, &genesis, User::Alice, 10, 7);
+
+    let page: Vec<_> = client
+        .canonical_chain_from(3)
+        .take(3)
+        .map(|b| b.header.height)
+        .collect();
+    assert_eq!(page, vec![3, 4, 5]);
+
+    let last_page: Vec<_> = client.canonical_chain_from(6).map(|b| b.hash()).collect();
+    assert_eq!(last_page, vec![a[5].hash(), a[6].hash()]);
+    assert!(client.canonical_chain_from(8).next().is_none());
+}
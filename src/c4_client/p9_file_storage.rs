@@ -0,0 +1,742 @@
+//! `BasicStorage` keeps everything in memory, so a node forgets the whole chain when it stops.
+//! Here we add a storage that survives restarts by writing to disk.
+//!
+//! Every change to the storage is appended to a log file before it is applied in memory. The
+//! log is never rewritten, only added to, which keeps writes cheap and means a crash can only
+//! ever damage the last record. When the storage is opened again, the log is replayed from the
+//! start into a fresh in-memory storage, which rebuilds every index: blocks by hash, the block
+//! tree, the canonical chain, states and receipts. A record that was only partly written when
+//! the node went down is cut off, so the node restarts at the last change that made it to disk
+//! completely. Only the last record can have been cut short like that. A record further back
+//! that fails its checksum means the log was damaged some other way, and the storage refuses
+//! to open rather than throw away everything after it.
+//!
+//! The log is synced to disk once all the records of a change to the chain are written: after
+//! a block is added, after the best block moves and after a block is finalized.
+//!
+//! A block's post-state is written in full once, when it is stored. Making a state the current
+//! one only records its root, unless it was never stored, in which case it is stored first.
+//!
+//! The log starts with the version of the codec its records were written with. A log from
+//! another version is refused rather than misread. The first record holds the genesis state,
+//! so a chain that does not start from the default state is rebuilt from the right genesis.
+//! A storage that started from a state snapshot instead records the snapshot's block and state
+//! there, and is rebuilt on top of that block.
+//!
+//! Nothing is ever removed from the log, not even pruned blocks. Replaying their removal just
+//! removes them again. A real node would compact the log from time to time.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::p1_data_structure::{Block, Receipt};
+#[cfg(test)]
+use super::p2_importing_blocks::ImportBlock;
+#[cfg(test)]
+use super::p3_fork_choice::LongestChain;
+#[cfg(test)]
+use super::p4_transaction_pool::SimplePool;
+#[cfg(test)]
+use super::test_helpers::{self, client_with_storage_for_test, import_chain, TempDir};
+use super::{BasicStorage, Consensus, Hash, StateMachine, Storage};
+use super::{StateKey, StateValue};
+#[cfg(test)]
+use crate::c1_state_machine::{AccountedCurrency, BalancesB, User};
+use crate::{state_root, Decode, Encode, Hasher, Sha256, StateProof, TrieState, CODEC_VERSION};
+
+/// The name of the log file inside the storage directory.
+const LOG_FILE: &str = "chain.log";
+
+/// The kinds of records in the log, one for each way the storage can change.
+const ADD_BLOCK: u8 = 0;
+const REMOVE_BLOCKS: u8 = 1;
+const FINALIZED_BLOCK: u8 = 2;
+const LAST_BLOCK: u8 = 3;
+const CURRENT_STATE: u8 = 4;
+const STATE: u8 = 5;
+const RECEIPTS: u8 = 6;
+/// Only ever the first record, holding the entries of the genesis state.
+const GENESIS: u8 = 7;
+/// Only ever the first record, holding the block of a state snapshot and the state's entries.
+const SNAPSHOT: u8 = 8;
+
+/// What a new log starts from.
+enum Start<C: Consensus, SM: StateMachine> {
+    Genesis(SM::State),
+    Snapshot(Block<C, SM>, SM::State),
+}
+
+/// A storage that keeps an append-only log on disk, so it can be reopened after a restart.
+pub struct FileStorage<C: Consensus, SM: StateMachine>
+where
+    SM::State: TrieState,
+{
+    /// The directory the log lives in.
+    dir: PathBuf,
+    /// The log every change is appended to.
+    log: File,
+    /// Everything in the log so far, replayed into memory. Reads are served from here.
+    memory: BasicStorage<C, SM>,
+    /// Whether the directory was made up by the storage itself, and is removed with it.
+    temporary: bool,
+}
+
+/// A short checksum of a record, so that partly written records can be told apart from
+/// complete ones.
+fn checksum(bytes: &[u8]) -> [u8; 4] {
+    let digest = Sha256::hash_bytes(bytes);
+    [digest.0[0], digest.0[1], digest.0[2], digest.0[3]]
+}
+
+/// Split the next complete record off the front of the log, returning its kind and payload.
+/// Returns None if nothing is left, or if what is left is a single record that was cut short.
+/// Fails if a record that is followed by more of the log does not match its checksum.
+fn next_record<'a>(log: &mut &'a [u8]) -> io::Result<Option<(u8, &'a [u8])>> {
+    let mut input = *log;
+    let Ok(len) = u32::decode(&mut input) else {
+        return Ok(None);
+    };
+    let len = len as usize;
+    if input.len() < len + 4 {
+        return Ok(None);
+    }
+    let (body, rest) = input.split_at(len);
+    let (sum, rest) = rest.split_at(4);
+    if len == 0 || checksum(body) != sum {
+        if rest.is_empty() {
+            return Ok(None);
+        }
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "storage record does not match its checksum",
+        ));
+    }
+    *log = rest;
+    Ok(Some((body[0], &body[1..])))
+}
+
+impl<C, SM> FileStorage<C, SM>
+where
+    C: Consensus,
+    SM: StateMachine,
+    BasicStorage<C, SM>: Storage<C, SM>,
+    Block<C, SM>: std::hash::Hash + Encode + Decode,
+    SM::State: TrieState,
+    SM::Event: Encode + Decode,
+    StateKey<SM>: Encode + Decode,
+    StateValue<SM>: Encode + Decode,
+{
+    /// Open the storage kept in the given directory, creating it if it does not exist yet.
+    /// A new storage starts from the machine's default state. Use `create` to start from
+    /// another genesis.
+    ///
+    /// Fails if the directory cannot be used, if the log was written by another version of the
+    /// codec, or if a complete record in the log cannot be understood, which means it was
+    /// written by some other program.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self>
+    where
+        SM::State: Default,
+    {
+        Self::load(dir.as_ref(), Start::Genesis(SM::State::default()), false)
+    }
+
+    /// Create a new storage in the given directory, starting from the given genesis state.
+    /// Fails if the directory already holds a storage.
+    pub fn create(dir: impl AsRef<Path>, genesis_state: SM::State) -> io::Result<Self> {
+        Self::load(dir.as_ref(), Start::Genesis(genesis_state), true)
+    }
+
+    /// Create a new storage in the given directory, starting from the given block of a state
+    /// snapshot and its post-state. Fails if the directory already holds a storage.
+    pub fn create_from_snapshot(
+        dir: impl AsRef<Path>,
+        block: Block<C, SM>,
+        state: SM::State,
+    ) -> io::Result<Self> {
+        Self::load(dir.as_ref(), Start::Snapshot(block, state), true)
+    }
+
+    /// Create a storage in a new directory under the system's temporary directory, which is
+    /// removed again when the storage is dropped. Directories that already exist are skipped
+    /// rather than reused.
+    fn create_temporary(start: Start<C, SM>) -> io::Result<Self> {
+        static OPENED: AtomicUsize = AtomicUsize::new(0);
+        let dir = loop {
+            let dir = std::env::temp_dir().join(format!(
+                "diy-blockchain-{}-{}",
+                std::process::id(),
+                OPENED.fetch_add(1, Ordering::Relaxed)
+            ));
+            match std::fs::create_dir(&dir) {
+                Ok(()) => break dir,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        };
+        let mut storage = Self::load(&dir, start, true)?;
+        storage.temporary = true;
+        Ok(storage)
+    }
+
+    /// Replay the log in the given directory. A new log starts from the given start, while an
+    /// existing one starts from the genesis or snapshot it recorded.
+    fn load(dir: &Path, start: Start<C, SM>, must_be_new: bool) -> io::Result<Self> {
+        let dir = dir.to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        let mut log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(dir.join(LOG_FILE))?;
+
+        let mut bytes = Vec::new();
+        log.read_to_end(&mut bytes)?;
+        match bytes.first() {
+            None => {
+                log.write_all(&[CODEC_VERSION])?;
+                let mut storage = match start {
+                    Start::Genesis(genesis_state) => {
+                        let genesis_entries = genesis_state.to_entries();
+                        let mut storage = FileStorage {
+                            dir,
+                            log,
+                            memory: BasicStorage::with_genesis(genesis_state),
+                            temporary: false,
+                        };
+                        storage.append(GENESIS, genesis_entries);
+                        storage
+                    }
+                    Start::Snapshot(block, state) => {
+                        let entries = state.to_entries();
+                        let mut storage = FileStorage {
+                            dir,
+                            log,
+                            memory: BasicStorage::from_snapshot(block, state),
+                            temporary: false,
+                        };
+                        let block = storage.memory.get_last_block();
+                        storage.append(SNAPSHOT, (&block, entries));
+                        storage
+                    }
+                };
+                storage.sync();
+                return Ok(storage);
+            }
+            Some(_) if must_be_new => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "the directory already holds a storage",
+                ))
+            }
+            Some(&CODEC_VERSION) => (),
+            Some(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "storage log written by an unsupported codec version",
+                ))
+            }
+        }
+
+        // The genesis or snapshot comes first, so that everything after it is replayed on top
+        // of it.
+        let mut remaining = &bytes[1..];
+        let mut start = start;
+        let mut after_start = remaining;
+        match next_record(&mut after_start)? {
+            Some((GENESIS, payload)) => {
+                let entries = Vec::decode_all(payload).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "unreadable genesis record")
+                })?;
+                start = Start::Genesis(SM::State::from_entries(entries));
+                remaining = after_start;
+            }
+            Some((SNAPSHOT, payload)) => {
+                let (block, entries) =
+                    <(Block<C, SM>, Vec<_>)>::decode_all(payload).map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidData, "unreadable snapshot record")
+                    })?;
+                start = Start::Snapshot(block, SM::State::from_entries(entries));
+                remaining = after_start;
+            }
+            _ => (),
+        }
+
+        let mut memory = match start {
+            Start::Genesis(genesis_state) => BasicStorage::with_genesis(genesis_state),
+            Start::Snapshot(block, state) => BasicStorage::from_snapshot(block, state),
+        };
+        while let Some((kind, payload)) = next_record(&mut remaining)? {
+            Self::replay(&mut memory, kind, payload).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "unreadable storage record")
+            })?;
+        }
+
+        // Whatever is left is a record that was cut short. Drop it so new records follow
+        // straight on from the last complete one.
+        if !remaining.is_empty() {
+            log.set_len((bytes.len() - remaining.len()) as u64)?;
+        }
+
+        Ok(FileStorage {
+            dir,
+            log,
+            memory,
+            temporary: false,
+        })
+    }
+
+    /// The directory this storage keeps its log in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Apply a single record from the log to the in-memory storage.
+    /// Returns None if the record could not be decoded.
+    fn replay(memory: &mut BasicStorage<C, SM>, kind: u8, payload: &[u8]) -> Option<()> {
+        match kind {
+            ADD_BLOCK => memory.add_block(Block::decode_all(payload).ok()?),
+            REMOVE_BLOCKS => memory.remove_blocks(&Vec::<Hash>::decode_all(payload).ok()?),
+            FINALIZED_BLOCK => memory.set_finalized_block(Hash::decode_all(payload).ok()?),
+            LAST_BLOCK => memory.set_last_block(Block::decode_all(payload).ok()?),
+            CURRENT_STATE => {
+                let state = memory.get_state(Hash::decode_all(payload).ok()?)?;
+                memory.set_current_state(state);
+            }
+            STATE => {
+                let entries = Vec::decode_all(payload).ok()?;
+                memory.set_state(SM::State::from_entries(entries));
+            }
+            RECEIPTS => {
+                let (block_hash, receipts) =
+                    <(Hash, Vec<Receipt<SM::Event>>)>::decode_all(payload).ok()?;
+                memory.set_receipts(block_hash, receipts);
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    /// Append a record to the log. The storage trait has no way to report failures, and a node
+    /// that cannot write its database cannot go on, so this panics if the write fails.
+    fn append(&mut self, kind: u8, payload: impl Encode) {
+        let mut body = vec![kind];
+        payload.encode_to(&mut body);
+
+        let mut record = (body.len() as u32).encode();
+        record.extend_from_slice(&body);
+        record.extend_from_slice(&checksum(&body));
+        self.log
+            .write_all(&record)
+            .expect("failed to append to the storage log");
+    }
+
+    /// Make sure everything appended so far is on disk. Like `append`, this panics on failure.
+    fn sync(&mut self) {
+        self.log
+            .sync_data()
+            .expect("failed to sync the storage log");
+    }
+}
+
+impl<C: Consensus, SM: StateMachine> Drop for FileStorage<C, SM>
+where
+    SM::State: TrieState,
+{
+    fn drop(&mut self) {
+        if self.temporary {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+}
+
+impl<C, SM> Storage<C, SM> for FileStorage<C, SM>
+where
+    C: Consensus,
+    SM: StateMachine,
+    BasicStorage<C, SM>: Storage<C, SM>,
+    Block<C, SM>: std::hash::Hash + Encode + Decode,
+    SM::State: TrieState + Clone,
+    SM::Event: Encode + Decode,
+    StateKey<SM>: Encode + Decode,
+    StateValue<SM>: Encode + Decode,
+{
+    /// Create a storage in a new directory under the system's temporary directory, which is
+    /// removed when the storage is dropped. Use `FileStorage::create` to keep the data.
+    fn with_genesis(genesis_state: SM::State) -> Self {
+        Self::create_temporary(Start::Genesis(genesis_state))
+            .expect("failed to create storage in the temporary directory")
+    }
+
+    /// Like `with_genesis`, the storage goes in a new temporary directory. Use
+    /// `FileStorage::create_from_snapshot` to keep the data.
+    fn from_snapshot(block: Block<C, SM>, state: SM::State) -> Self {
+        Self::create_temporary(Start::Snapshot(block, state))
+            .expect("failed to create storage in the temporary directory")
+    }
+
+    fn add_block(&mut self, block: Block<C, SM>) {
+        if self.memory.get_block(block.hash()).is_some() {
+            return;
+        }
+        self.append(ADD_BLOCK, &block);
+        self.sync();
+        self.memory.add_block(block);
+    }
+
+    fn get_block(&self, block_hash: Hash) -> Option<Block<C, SM>> {
+        self.memory.get_block(block_hash)
+    }
+
+    fn children(&self, block_hash: Hash) -> Vec<Hash> {
+        self.memory.children(block_hash)
+    }
+
+    fn leaves(&self) -> Vec<Hash> {
+        self.memory.leaves()
+    }
+
+    fn remove_blocks(&mut self, block_hashes: &[Hash]) {
+        self.append(REMOVE_BLOCKS, block_hashes.to_vec());
+        self.sync();
+        self.memory.remove_blocks(block_hashes);
+    }
+
+    fn finalized_block(&self) -> Hash {
+        self.memory.finalized_block()
+    }
+
+    fn set_finalized_block(&mut self, block_hash: Hash) {
+        self.append(FINALIZED_BLOCK, block_hash);
+        self.sync();
+        self.memory.set_finalized_block(block_hash);
+    }
+
+    fn get_last_block(&self) -> Block<C, SM> {
+        self.memory.get_last_block()
+    }
+
+    fn set_last_block(&mut self, block: Block<C, SM>) {
+        self.append(LAST_BLOCK, &block);
+        self.sync();
+        self.memory.set_last_block(block);
+    }
+
+    fn canonical_hash(&self, height: u64) -> Option<Hash> {
+        self.memory.canonical_hash(height)
+    }
+
+    fn base_height(&self) -> u64 {
+        self.memory.base_height()
+    }
+
+    fn current_state(&self) -> SM::State {
+        self.memory.current_state()
+    }
+
+    fn set_current_state(&mut self, state: SM::State) {
+        let root = state_root(&state);
+        if !self.memory.has_state(root) {
+            self.set_state(state.clone());
+        }
+        self.append(CURRENT_STATE, root);
+        self.memory.set_current_state(state);
+    }
+
+    fn get_state(&self, state_root: Hash) -> Option<SM::State> {
+        self.memory.get_state(state_root)
+    }
+
+    fn set_state(&mut self, state: SM::State) {
+        self.append(STATE, state.to_entries());
+        self.memory.set_state(state);
+    }
+
+    fn state_proof(
+        &self,
+        state_root: Hash,
+        key: &StateKey<SM>,
+    ) -> Option<StateProof<StateKey<SM>, StateValue<SM>>> {
+        self.memory.state_proof(state_root, key)
+    }
+
+    fn get_receipts(&self, block_hash: Hash) -> Option<Vec<Receipt<SM::Event>>> {
+        self.memory.get_receipts(block_hash)
+    }
+
+    fn set_receipts(&mut self, block_hash: Hash, receipts: Vec<Receipt<SM::Event>>) {
+        self.append(RECEIPTS, (block_hash, &receipts));
+        self.memory.set_receipts(block_hash, receipts);
+    }
+}
+
+// --- TESTS ---
+
+#[cfg(test)]
+type TestStorage = FileStorage<(), AccountedCurrency>;
+#[cfg(test)]
+type TestClient = test_helpers::TestClient<TestStorage>;
+
+/// A fresh, empty directory for a test to keep its storage in, removed when the test is done.
+#[cfg(test)]
+fn temp_dir(test_name: &str) -> TempDir {
+    TempDir::new(&format!("test-{}", test_name))
+}
+
+#[cfg(test)]
+fn init_client_for_test(dir: &Path) -> TestClient {
+    client_with_storage_for_test(FileStorage::open(dir).unwrap())
+}
+
+#[test]
+fn cl9_new_directory_starts_at_genesis() {
+    let dir = temp_dir("new_directory_starts_at_genesis");
+    let storage = TestStorage::open(&dir).unwrap();
+    let basic = BasicStorage::<(), AccountedCurrency>::new();
+
+    assert_eq!(
+        storage.get_last_block().hash(),
+        basic.get_last_block().hash()
+    );
+    assert_eq!(storage.leaves(), basic.leaves());
+    assert!(dir.join(LOG_FILE).exists());
+}
+
+#[test]
+fn cl9_reopens_at_the_same_best_block() {
+    let dir = temp_dir("reopens_at_the_same_best_block");
+    let mut client = init_client_for_test(&dir);
+    let genesis = client.get_last_block();
+    let a = import_chain(&mut client, &genesis, User::Alice, 10, 3);
+    let best_state = client.current_state();
+    drop(client);
+
+    let client = init_client_for_test(&dir);
+    assert_eq!(client.get_last_block().hash(), a[2].hash());
+    assert_eq!(client.current_state().balances, best_state.balances);
+    for block in &a {
+        assert_eq!(client.get_block(block.hash()).unwrap().hash(), block.hash());
+        assert_eq!(client.get_receipts(block.hash()).unwrap().len(), 1);
+        assert!(client.get_state(block.hash()).is_some());
+    }
+    assert_eq!(client.canonical_block_hash(3), Some(a[2].hash()));
+    assert_eq!(client.all_leaves(), vec![a[2].hash()]);
+}
+
+#[test]
+fn cl9_reopens_with_forks_and_finality() {
+    let dir = temp_dir("reopens_with_forks_and_finality");
+    let mut client = init_client_for_test(&dir);
+    let genesis = client.get_last_block();
+    let a = import_chain(&mut client, &genesis, User::Alice, 10, 2);
+    let b = import_chain(&mut client, &genesis, User::Bob, 5, 1);
+    let c = import_chain(&mut client, &a[0], User::Charlie, 1, 1);
+    assert!(client.manually_finalize_block(a[0].hash()));
+    let best = client.get_last_block().hash();
+    drop(client);
+
+    let client = init_client_for_test(&dir);
+    assert_eq!(client.finalized_block(), a[0].hash());
+    assert!(client.get_block(b[0].hash()).is_none());
+    assert_eq!(
+        client.storage.children(a[0].hash()),
+        vec![a[1].hash(), c[0].hash()]
+    );
+    assert_eq!(client.get_last_block().hash(), best);
+}
+
+#[test]
+fn cl9_reimporting_a_block_writes_nothing() {
+    let dir = temp_dir("reimporting_a_block_writes_nothing");
+    let mut client = init_client_for_test(&dir);
+    let genesis = client.get_last_block();
+    let a = import_chain(&mut client, &genesis, User::Alice, 10, 2);
+    let log_len = std::fs::metadata(dir.join(LOG_FILE)).unwrap().len();
+
+    assert!(!client.import_block(a[0].clone()));
+    assert_eq!(
+        std::fs::metadata(dir.join(LOG_FILE)).unwrap().len(),
+        log_len
+    );
+    assert_eq!(client.get_last_block().hash(), a[1].hash());
+}
+
+#[test]
+fn cl9_recovers_from_a_torn_write() {
+    let dir = temp_dir("recovers_from_a_torn_write");
+    let mut client = init_client_for_test(&dir);
+    let genesis = client.get_last_block();
+    let a = import_chain(&mut client, &genesis, User::Alice, 10, 2);
+    drop(client);
+
+    // Simulate a crash in the middle of writing a record.
+    let log_path = dir.join(LOG_FILE);
+    let complete_len = std::fs::metadata(&log_path).unwrap().len();
+    let mut log = OpenOptions::new().append(true).open(&log_path).unwrap();
+    log.write_all(&[200, 0, 0, 0, ADD_BLOCK, 1, 2, 3]).unwrap();
+    drop(log);
+
+    let mut client = init_client_for_test(&dir);
+    assert_eq!(client.get_last_block().hash(), a[1].hash());
+    assert_eq!(std::fs::metadata(&log_path).unwrap().len(), complete_len);
+
+    // New records go right after the last complete one, so they survive another restart.
+    let more = import_chain(&mut client, &a[1], User::Alice, 10, 1);
+    drop(client);
+    let client = init_client_for_test(&dir);
+    assert_eq!(client.get_last_block().hash(), more[0].hash());
+}
+
+#[test]
+fn cl9_refuses_a_damaged_record_before_the_end() {
+    let dir = temp_dir("refuses_a_damaged_record_before_the_end");
+    let mut client = init_client_for_test(&dir);
+    let genesis = client.get_last_block();
+    import_chain(&mut client, &genesis, User::Alice, 10, 2);
+    drop(client);
+
+    // Flip a bit in the genesis record, which has plenty of records after it.
+    let log_path = dir.join(LOG_FILE);
+    let mut bytes = std::fs::read(&log_path).unwrap();
+    bytes[6] ^= 1;
+    std::fs::write(&log_path, &bytes).unwrap();
+
+    let error = TestStorage::open(&dir).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    // Nothing was cut off.
+    assert_eq!(std::fs::read(&log_path).unwrap(), bytes);
+}
+
+#[test]
+fn cl9_reopens_at_a_current_state_it_never_stored() {
+    let dir = temp_dir("reopens_at_a_current_state_it_never_stored");
+    let mut storage = TestStorage::open(&dir).unwrap();
+    storage.set_current_state(genesis_state_for_test());
+    drop(storage);
+
+    let storage = TestStorage::open(&dir).unwrap();
+    assert_eq!(storage.current_state().balances[&User::Alice], 100);
+}
+
+#[test]
+fn cl9_temporary_storage_is_removed_when_dropped() {
+    let storage = TestStorage::with_genesis(genesis_state_for_test());
+    let dir = storage.dir().to_path_buf();
+    assert!(dir.join(LOG_FILE).exists());
+
+    drop(storage);
+    assert!(!dir.exists());
+}
+
+#[test]
+fn cl9_refuses_log_it_cannot_understand() {
+    let dir = temp_dir("refuses_log_it_cannot_understand");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // A complete record, checksum and all, of a kind that does not exist.
+    let body = vec![99u8, 1, 2, 3];
+    let mut record = vec![CODEC_VERSION];
+    (body.len() as u32).encode_to(&mut record);
+    record.extend_from_slice(&body);
+    record.extend_from_slice(&checksum(&body));
+    std::fs::write(dir.join(LOG_FILE), record).unwrap();
+
+    let error = TestStorage::open(&dir).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn cl9_refuses_log_of_another_version() {
+    let dir = temp_dir("refuses_log_of_another_version");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(LOG_FILE), [CODEC_VERSION + 1]).unwrap();
+
+    let error = TestStorage::open(&dir).err().unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[cfg(test)]
+fn genesis_state_for_test() -> BalancesB {
+    let mut state = BalancesB::default();
+    state.balances.insert(User::Alice, 100);
+    state.balances.insert(User::Bob, 50);
+    state
+}
+
+#[test]
+fn cl9_reopens_from_its_own_genesis() {
+    let dir = temp_dir("reopens_from_its_own_genesis");
+    let genesis = Block::<(), AccountedCurrency>::genesis(&genesis_state_for_test());
+    {
+        let storage = TestStorage::create(&dir, genesis_state_for_test()).unwrap();
+        assert_eq!(storage.get_last_block().hash(), genesis.hash());
+        assert_eq!(storage.current_state().balances[&User::Alice], 100);
+    }
+
+    // Opening replays onto the recorded genesis rather than the default one.
+    let storage = TestStorage::open(&dir).unwrap();
+    assert_eq!(storage.get_last_block().hash(), genesis.hash());
+    assert_eq!(storage.finalized_block(), genesis.hash());
+    assert_eq!(storage.leaves(), vec![genesis.hash()]);
+    assert_eq!(storage.current_state().balances[&User::Bob], 50);
+    assert!(storage.get_state(genesis.header.state_root).is_some());
+}
+
+#[test]
+fn cl9_create_refuses_an_existing_storage() {
+    let dir = temp_dir("create_refuses_an_existing_storage");
+    drop(TestStorage::open(&dir).unwrap());
+
+    let error = TestStorage::create(&dir, genesis_state_for_test())
+        .err()
+        .unwrap();
+    assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+}
+
+#[test]
+fn cl9_new_client_starts_at_its_genesis() {
+    let mut client = TestClient::new(
+        genesis_state_for_test(),
+        AccountedCurrency::default(),
+        (),
+        LongestChain::default(),
+        SimplePool::default(),
+    );
+    let genesis = client.get_last_block();
+    assert_eq!(
+        genesis.hash(),
+        Block::<(), AccountedCurrency>::genesis(&genesis_state_for_test()).hash()
+    );
+    assert_eq!(client.current_state().balances[&User::Alice], 100);
+
+    let a = import_chain(&mut client, &genesis, User::Charlie, 5, 2);
+    assert_eq!(client.get_last_block().hash(), a[1].hash());
+    assert_eq!(client.current_state().balances[&User::Charlie], 10);
+}
+
+#[test]
+fn cl9_reopens_from_a_snapshot() {
+    let source_dir = temp_dir("reopens_from_a_snapshot_source");
+    let mut source = init_client_for_test(&source_dir);
+    let genesis = source.get_last_block();
+    let a = import_chain(&mut source, &genesis, User::Alice, 5, 3);
+    let state = source.get_state(a[2].hash()).unwrap();
+
+    let dir = temp_dir("reopens_from_a_snapshot");
+    {
+        let storage = TestStorage::create_from_snapshot(&dir, a[2].clone(), state).unwrap();
+        let mut client = client_with_storage_for_test(storage);
+        import_chain(&mut client, &a[2], User::Bob, 7, 2);
+    }
+
+    // Opening replays onto the recorded snapshot, with nothing below it.
+    let client = init_client_for_test(&dir);
+    assert_eq!(client.get_last_block().header.height, 5);
+    assert_eq!(client.finalized_block(), a[2].hash());
+    assert_eq!(client.storage.canonical_hash(3), Some(a[2].hash()));
+    assert_eq!(client.storage.canonical_hash(2), None);
+    assert!(client.get_block(a[1].hash()).is_none());
+    assert_eq!(client.current_state().balances[&User::Alice], 15);
+    assert_eq!(client.current_state().balances[&User::Bob], 14);
+}
@@ -0,0 +1,105 @@
+//! Fixtures shared by the tests of the client sections. Most tests only need a plain client
+//! that runs the accounted currency without any consensus and follows the longest chain, and
+//! a quick way to grow its chain. Tests that keep things on disk get a directory that cleans up
+//! after itself.
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use super::p1_data_structure::Block;
+use super::p2_importing_blocks::ImportBlock;
+use super::p3_fork_choice::LongestChain;
+use super::p4_transaction_pool::SimplePool;
+use super::{BasicStorage, FullClient, Storage};
+use crate::c1_state_machine::{AccountedCurrency, AccountingTransaction, User};
+
+/// The client most tests use. Tests that keep the chain somewhere else choose the storage.
+pub type TestClient<S = BasicStorage<(), AccountedCurrency>> =
+    FullClient<(), AccountedCurrency, LongestChain, SimplePool<AccountedCurrency>, S>;
+
+/// A client whose chain is just the genesis block for the default state.
+pub fn init_client_for_test() -> TestClient {
+    client_with_storage_for_test(BasicStorage::new())
+}
+
+/// A client that carries on with whatever chain the given storage holds.
+pub fn client_with_storage_for_test<S>(storage: S) -> TestClient<S>
+where
+    S: Storage<(), AccountedCurrency>,
+{
+    FullClient::resume(
+        AccountedCurrency::default(),
+        (),
+        LongestChain::default(),
+        SimplePool::default(),
+        storage,
+    )
+}
+
+pub fn mint(minter: User, nonce: u64, amount: u64) -> AccountingTransaction {
+    AccountingTransaction::Mint {
+        minter,
+        amount,
+        nonce,
+    }
+}
+
+/// Build and import `length` blocks on top of the given parent, each minting `amount` for
+/// `minter`.
+pub fn import_chain<S>(
+    client: &mut TestClient<S>,
+    parent: &Block<(), AccountedCurrency>,
+    minter: User,
+    amount: u64,
+    length: usize,
+) -> Vec<Block<(), AccountedCurrency>>
+where
+    S: Storage<(), AccountedCurrency>,
+{
+    let mut blocks = Vec::new();
+    let mut parent = parent.clone();
+    for _ in 0..length {
+        let state = client.get_state(parent.hash()).unwrap();
+        let block = parent
+            .child(&state, vec![mint(minter, state.nonce(&minter), amount)])
+            .unwrap();
+        assert!(client.import_block(block.clone()));
+        parent = block.clone();
+        blocks.push(block);
+    }
+    blocks
+}
+
+/// A directory under the system's temporary directory that is removed, along with everything in
+/// it, when the test is done with it. It is not created, only cleared of what an earlier run left.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// The name must be unique among the tests of a run. The process id keeps runs apart.
+    pub fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("diy-blockchain-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        TempDir(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,615 @@
+//! A chain specification describes everything a node needs to start a chain: the genesis state,
+//! and the consensus rules that the blocks built on top of it must follow. Every node of a
+//! network starts from the same specification, which is how they all agree on the genesis block.
+//!
+//! Specifications are text files like this one:
+//!
+//! ```text
+//! name = devnet
+//! consensus = forked
+//! fork-height = 10
+//! threshold = 1844674407370955161
+//! authorities = alice, bob
+//! fork-choice = longest
+//! pool = simple
+//! block-weight-limit = 10
+//!
+//! [balances]
+//! alice = 100
+//! bob = 50
+//! ```
+//!
+//! Settings that are left out take their defaults: proof of work with a threshold of a tenth
+//! of the hash space, alice as the only authority, the longest chain rule, the simple pool and
+//! the client's default block weight limit.
+//! The genesis is either a `[balances]` section with one balance per account, for the accounted
+//! currency, or a `[bills]` section with one bill per line, for the digital cash system. A
+//! specification without either starts from empty balances. Blank lines and `#` comments are
+//! ignored.
+
+use std::fmt;
+
+use crate::c1_state_machine::{BalancesB, CashState, StateMachine, User};
+use crate::c3_consensus::{Consensus, ConsensusAuthority};
+use crate::c4_client::{Block, Storage, DEFAULT_BLOCK_WEIGHT_LIMIT};
+use crate::{Encode, TrieState};
+
+/// The threshold used when a proof of work chain does not set one.
+pub const DEFAULT_THRESHOLD: u64 = u64::MAX / 10;
+
+/// Everything needed to start a chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainSpec {
+    pub name: String,
+    pub consensus: ConsensusSpec,
+    pub fork_choice: ForkChoiceKind,
+    pub pool: PoolKind,
+    /// The most weight an author puts into a single block.
+    pub block_weight_limit: u64,
+    pub genesis: GenesisSpec,
+}
+
+/// The consensus engine a chain uses, along with its parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConsensusSpec {
+    Pow {
+        threshold: u64,
+    },
+    SimplePoa {
+        authorities: Vec<ConsensusAuthority>,
+    },
+    PoaRoundRobin {
+        authorities: Vec<ConsensusAuthority>,
+    },
+    /// Proof of work until the fork height, and round robin proof of authority from there on.
+    Forked {
+        fork_height: u64,
+        threshold: u64,
+        authorities: Vec<ConsensusAuthority>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ForkChoiceKind {
+    Longest,
+    /// Only makes sense with proof of work.
+    Heaviest,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PoolKind {
+    Simple,
+    /// Larger amounts first.
+    Priority,
+}
+
+/// What the chain holds at genesis.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GenesisSpec {
+    /// The balance of each account.
+    Balances(Vec<(User, u64)>),
+    /// The owner and amount of each bill, in the order their serial numbers are given out.
+    Bills(Vec<(User, u64)>),
+}
+
+/// Why a chain specification was rejected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainSpecError {
+    /// The line the problem is on, counting from 1, or 0 if it is not on any single line.
+    pub line: usize,
+    pub reason: String,
+}
+
+impl ChainSpecError {
+    fn new(line: usize, reason: impl Into<String>) -> Self {
+        ChainSpecError {
+            line,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for ChainSpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{}", self.reason),
+            line => write!(f, "line {}: {}", line, self.reason),
+        }
+    }
+}
+
+/// A state that a chain specification can describe the genesis of.
+pub trait GenesisState: Sized {
+    fn from_spec(genesis: &GenesisSpec) -> Result<Self, ChainSpecError>;
+}
+
+impl GenesisState for BalancesB {
+    fn from_spec(genesis: &GenesisSpec) -> Result<Self, ChainSpecError> {
+        let GenesisSpec::Balances(balances) = genesis else {
+            return Err(ChainSpecError::new(
+                0,
+                "the accounted currency starts from [balances], not [bills]",
+            ));
+        };
+        let mut state = BalancesB::default();
+        state.balances.extend(balances.iter().copied());
+        Ok(state)
+    }
+}
+
+impl GenesisState for CashState {
+    fn from_spec(genesis: &GenesisSpec) -> Result<Self, ChainSpecError> {
+        let GenesisSpec::Bills(bills) = genesis else {
+            return Err(ChainSpecError::new(
+                0,
+                "the digital cash system starts from [bills], not [balances]",
+            ));
+        };
+        let mut state = CashState::new();
+        for (owner, amount) in bills {
+            state.issue_bill(*owner, *amount);
+        }
+        Ok(state)
+    }
+}
+
+impl ChainSpec {
+    /// Read a specification from its text form.
+    pub fn parse(text: &str) -> Result<Self, ChainSpecError> {
+        let mut settings: Vec<(usize, &str, &str)> = Vec::new();
+        let mut section: Option<(usize, &str)> = None;
+        let mut entries = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                if section.is_some() {
+                    return Err(ChainSpecError::new(
+                        line_number,
+                        "only one genesis section is allowed",
+                    ));
+                }
+                if name != "balances" && name != "bills" {
+                    return Err(ChainSpecError::new(
+                        line_number,
+                        format!("unknown section [{}]", name),
+                    ));
+                }
+                section = Some((line_number, name));
+                continue;
+            }
+
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| ChainSpecError::new(line_number, "expected name = value"))?;
+            let (name, value) = (name.trim(), value.trim());
+            match section {
+                None => {
+                    if settings.iter().any(|(_, n, _)| *n == name) {
+                        return Err(ChainSpecError::new(
+                            line_number,
+                            format!("{} is set twice", name),
+                        ));
+                    }
+                    settings.push((line_number, name, value));
+                }
+                Some(_) => {
+                    let account =
+                        parse_user(name).map_err(|e| ChainSpecError::new(line_number, e))?;
+                    let amount =
+                        parse_number(value).map_err(|e| ChainSpecError::new(line_number, e))?;
+                    if amount == 0 {
+                        return Err(ChainSpecError::new(line_number, "amounts must not be zero"));
+                    }
+                    entries.push((line_number, account, amount));
+                }
+            }
+        }
+
+        let genesis = match section {
+            Some((_, "bills")) => {
+                GenesisSpec::Bills(entries.into_iter().map(|(_, a, n)| (a, n)).collect())
+            }
+            _ => {
+                let mut balances: Vec<(User, u64)> = Vec::new();
+                for (line_number, account, amount) in entries {
+                    if balances.iter().any(|(a, _)| *a == account) {
+                        return Err(ChainSpecError::new(
+                            line_number,
+                            format!("{} already has a balance", user_name(account)),
+                        ));
+                    }
+                    balances.push((account, amount));
+                }
+                GenesisSpec::Balances(balances)
+            }
+        };
+        Self::from_settings(&settings, genesis)
+    }
+
+    /// Build a specification from its `name = value` settings, using defaults for those that
+    /// are missing.
+    fn from_settings(
+        settings: &[(usize, &str, &str)],
+        genesis: GenesisSpec,
+    ) -> Result<Self, ChainSpecError> {
+        let known = [
+            "name",
+            "consensus",
+            "threshold",
+            "authorities",
+            "fork-height",
+            "fork-choice",
+            "pool",
+            "block-weight-limit",
+        ];
+        if let Some((line, name, _)) = settings.iter().find(|(_, n, _)| !known.contains(n)) {
+            return Err(ChainSpecError::new(
+                *line,
+                format!("unknown setting {}", name),
+            ));
+        }
+        let get = |name: &str| settings.iter().find(|(_, n, _)| *n == name);
+        let number = |name: &str| match get(name) {
+            Some((line, _, value)) => parse_number(value)
+                .map(Some)
+                .map_err(|e| ChainSpecError::new(*line, e)),
+            None => Ok(None),
+        };
+        // Every setting is checked, even those the chosen engine does not use.
+        let threshold = number("threshold")?.unwrap_or(DEFAULT_THRESHOLD);
+        let fork_height = number("fork-height")?;
+        let block_weight_limit =
+            number("block-weight-limit")?.unwrap_or(DEFAULT_BLOCK_WEIGHT_LIMIT);
+        if block_weight_limit == 0 {
+            let line = get("block-weight-limit").unwrap().0;
+            return Err(ChainSpecError::new(
+                line,
+                "the block weight limit must not be zero",
+            ));
+        }
+        let authorities = match get("authorities") {
+            Some((line, _, value)) => value
+                .split(',')
+                .map(|a| parse_authority(a.trim()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| ChainSpecError::new(*line, e))?,
+            None => vec![ConsensusAuthority::Alice],
+        };
+
+        let consensus = match get("consensus").map_or("pow", |(_, _, v)| *v) {
+            "pow" => ConsensusSpec::Pow { threshold },
+            "simple-poa" => ConsensusSpec::SimplePoa { authorities },
+            "poa-round-robin" => ConsensusSpec::PoaRoundRobin { authorities },
+            "forked" => ConsensusSpec::Forked {
+                fork_height: fork_height
+                    .ok_or_else(|| ChainSpecError::new(0, "a forked chain needs a fork-height"))?,
+                threshold,
+                authorities,
+            },
+            other => {
+                let line = get("consensus").unwrap().0;
+                return Err(ChainSpecError::new(
+                    line,
+                    format!("unknown consensus engine {}", other),
+                ));
+            }
+        };
+        let fork_choice = match get("fork-choice") {
+            None => ForkChoiceKind::Longest,
+            Some((_, _, "longest")) => ForkChoiceKind::Longest,
+            Some((line, _, "heaviest")) => {
+                if !matches!(consensus, ConsensusSpec::Pow { .. }) {
+                    return Err(ChainSpecError::new(
+                        *line,
+                        "the heaviest chain fork choice needs proof of work",
+                    ));
+                }
+                ForkChoiceKind::Heaviest
+            }
+            Some((line, _, other)) => {
+                return Err(ChainSpecError::new(
+                    *line,
+                    format!("unknown fork choice {}", other),
+                ))
+            }
+        };
+        let pool = match get("pool") {
+            None | Some((_, _, "simple")) => PoolKind::Simple,
+            Some((_, _, "priority")) => PoolKind::Priority,
+            Some((line, _, other)) => {
+                return Err(ChainSpecError::new(
+                    *line,
+                    format!("unknown transaction pool {}", other),
+                ))
+            }
+        };
+
+        Ok(ChainSpec {
+            name: get("name").map_or("local", |(_, _, v)| *v).to_string(),
+            consensus,
+            fork_choice,
+            pool,
+            block_weight_limit,
+            genesis,
+        })
+    }
+
+    /// The genesis state this specification describes.
+    pub fn genesis_state<St: GenesisState>(&self) -> Result<St, ChainSpecError> {
+        St::from_spec(&self.genesis)
+    }
+
+    /// The genesis block of the chain this specification describes.
+    pub fn genesis_block<C, SM>(&self) -> Result<Block<C, SM>, ChainSpecError>
+    where
+        C: Consensus,
+        SM: StateMachine,
+        SM::State: GenesisState + TrieState + Clone,
+        SM::Transition: Encode + Clone,
+        SM::Event: Encode,
+    {
+        Ok(Block::genesis(&self.genesis_state::<SM::State>()?))
+    }
+
+    /// A storage holding nothing but the genesis of the chain this specification describes.
+    pub fn storage<C, SM, S>(&self) -> Result<S, ChainSpecError>
+    where
+        C: Consensus,
+        SM: StateMachine,
+        S: Storage<C, SM>,
+        SM::State: GenesisState + TrieState,
+        Block<C, SM>: std::hash::Hash,
+    {
+        Ok(S::with_genesis(self.genesis_state()?))
+    }
+}
+
+/// Writes the specification in the text form that `parse` reads, with every setting spelled out.
+impl fmt::Display for ChainSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let authorities = |authorities: &[ConsensusAuthority]| {
+            authorities
+                .iter()
+                .map(|a| authority_name(*a))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        writeln!(f, "name = {}", self.name)?;
+        match &self.consensus {
+            ConsensusSpec::Pow { threshold } => {
+                writeln!(f, "consensus = pow")?;
+                writeln!(f, "threshold = {}", threshold)?;
+            }
+            ConsensusSpec::SimplePoa { authorities: a } => {
+                writeln!(f, "consensus = simple-poa")?;
+                writeln!(f, "authorities = {}", authorities(a))?;
+            }
+            ConsensusSpec::PoaRoundRobin { authorities: a } => {
+                writeln!(f, "consensus = poa-round-robin")?;
+                writeln!(f, "authorities = {}", authorities(a))?;
+            }
+            ConsensusSpec::Forked {
+                fork_height,
+                threshold,
+                authorities: a,
+            } => {
+                writeln!(f, "consensus = forked")?;
+                writeln!(f, "fork-height = {}", fork_height)?;
+                writeln!(f, "threshold = {}", threshold)?;
+                writeln!(f, "authorities = {}", authorities(a))?;
+            }
+        }
+        let fork_choice = match self.fork_choice {
+            ForkChoiceKind::Longest => "longest",
+            ForkChoiceKind::Heaviest => "heaviest",
+        };
+        writeln!(f, "fork-choice = {}", fork_choice)?;
+        let pool = match self.pool {
+            PoolKind::Simple => "simple",
+            PoolKind::Priority => "priority",
+        };
+        writeln!(f, "pool = {}", pool)?;
+        writeln!(f, "block-weight-limit = {}", self.block_weight_limit)?;
+
+        let (section, entries) = match &self.genesis {
+            GenesisSpec::Balances(balances) => ("balances", balances),
+            GenesisSpec::Bills(bills) => ("bills", bills),
+        };
+        writeln!(f, "\n[{}]", section)?;
+        for (account, amount) in entries {
+            writeln!(f, "{} = {}", user_name(*account), amount)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn parse_number(s: &str) -> Result<u64, String> {
+    s.parse().map_err(|_| format!("not a number: {}", s))
+}
+
+pub(crate) fn parse_user(s: &str) -> Result<User, String> {
+    match s {
+        "alice" => Ok(User::Alice),
+        "bob" => Ok(User::Bob),
+        "charlie" => Ok(User::Charlie),
+        _ => Err(format!("unknown account {}", s)),
+    }
+}
+
+pub(crate) fn user_name(user: User) -> &'static str {
+    match user {
+        User::Alice => "alice",
+        User::Bob => "bob",
+        User::Charlie => "charlie",
+    }
+}
+
+fn parse_authority(s: &str) -> Result<ConsensusAuthority, String> {
+    match s {
+        "alice" => Ok(ConsensusAuthority::Alice),
+        "bob" => Ok(ConsensusAuthority::Bob),
+        "charlie" => Ok(ConsensusAuthority::Charlie),
+        _ => Err(format!("unknown authority {}", s)),
+    }
+}
+
+fn authority_name(authority: ConsensusAuthority) -> &'static str {
+    match authority {
+        ConsensusAuthority::Alice => "alice",
+        ConsensusAuthority::Bob => "bob",
+        ConsensusAuthority::Charlie => "charlie",
+    }
+}
+
+// --- TESTS ---
+
+#[cfg(test)]
+use crate::c1_state_machine::{AccountedCurrency, DigitalCashSystem};
+#[cfg(test)]
+use crate::c3_consensus::Pow;
+#[cfg(test)]
+use crate::c4_client::{BasicStorage, FullClient, LongestChain};
+
+#[cfg(test)]
+type TestStorage = BasicStorage<Pow, AccountedCurrency>;
+#[cfg(test)]
+type TestClient = FullClient<Pow, AccountedCurrency, LongestChain, (), TestStorage>;
+#[cfg(test)]
+type CashStorage = BasicStorage<(), DigitalCashSystem>;
+
+#[cfg(test)]
+const DEVNET: &str = "\
+# A chain that hands over from proof of work to alice and bob at block 10.
+name = devnet
+consensus = forked
+fork-height = 10
+threshold = 1844674407370955161
+authorities = alice, bob
+fork-choice = longest
+pool = simple
+block-weight-limit = 4
+
+[balances]
+alice = 100
+bob = 50
+";
+
+#[test]
+fn spec_parses_and_prints() {
+    let spec = ChainSpec::parse(DEVNET).unwrap();
+    assert_eq!(spec.name, "devnet");
+    assert_eq!(
+        spec.consensus,
+        ConsensusSpec::Forked {
+            fork_height: 10,
+            threshold: 1844674407370955161,
+            authorities: vec![ConsensusAuthority::Alice, ConsensusAuthority::Bob],
+        }
+    );
+    assert_eq!(spec.block_weight_limit, 4);
+    assert_eq!(
+        spec.genesis,
+        GenesisSpec::Balances(vec![(User::Alice, 100), (User::Bob, 50)])
+    );
+    assert_eq!(spec.to_string(), DEVNET.split_once('\n').unwrap().1);
+    assert_eq!(ChainSpec::parse(&spec.to_string()).unwrap(), spec);
+}
+
+#[test]
+fn spec_fills_in_defaults() {
+    let spec = ChainSpec::parse("").unwrap();
+    assert_eq!(spec.name, "local");
+    assert_eq!(
+        spec.consensus,
+        ConsensusSpec::Pow {
+            threshold: DEFAULT_THRESHOLD
+        }
+    );
+    assert_eq!(spec.fork_choice, ForkChoiceKind::Longest);
+    assert_eq!(spec.pool, PoolKind::Simple);
+    assert_eq!(spec.block_weight_limit, DEFAULT_BLOCK_WEIGHT_LIMIT);
+    assert_eq!(spec.genesis, GenesisSpec::Balances(Vec::new()));
+
+    let spec = ChainSpec::parse("consensus = poa-round-robin\npool = priority").unwrap();
+    assert_eq!(
+        spec.consensus,
+        ConsensusSpec::PoaRoundRobin {
+            authorities: vec![ConsensusAuthority::Alice]
+        }
+    );
+    assert_eq!(spec.pool, PoolKind::Priority);
+    assert_eq!(ChainSpec::parse(&spec.to_string()).unwrap(), spec);
+}
+
+#[test]
+fn spec_rejects_invalid_specs() {
+    let error = |text: &str| ChainSpec::parse(text).unwrap_err();
+
+    assert_eq!(error("consensus = dictator").line, 1);
+    assert_eq!(error("\nthreshold = lots").line, 2);
+    assert_eq!(error("colour = blue").reason, "unknown setting colour");
+    assert_eq!(error("pool = simple\npool = priority").line, 2);
+    assert_eq!(error("\nblock-weight-limit = 0").line, 2);
+    assert_eq!(error("consensus = forked").line, 0);
+    assert_eq!(
+        error("consensus = simple-poa\nfork-choice = heaviest").line,
+        2
+    );
+    assert_eq!(error("authorities = alice, dave").line, 1);
+    assert_eq!(error("[accounts]").line, 1);
+    assert_eq!(error("[balances]\n[bills]").line, 2);
+    assert_eq!(error("[balances]\ndave = 1").line, 2);
+    assert_eq!(error("[balances]\nalice = 0").line, 2);
+    assert_eq!(error("[balances]\nalice = 1\nalice = 2").line, 3);
+    assert_eq!(error("[balances]\nalice").line, 2);
+    assert_eq!(
+        error("[balances]\nalice = x").to_string(),
+        "line 2: not a number: x"
+    );
+}
+
+#[test]
+fn spec_builds_a_consistent_genesis() {
+    let spec = ChainSpec::parse(DEVNET).unwrap();
+    let state: BalancesB = spec.genesis_state().unwrap();
+    assert_eq!(state.balances[&User::Alice], 100);
+    assert_eq!(state.balances[&User::Bob], 50);
+
+    let genesis = spec.genesis_block::<Pow, AccountedCurrency>().unwrap();
+    let storage: TestStorage = spec.storage().unwrap();
+    assert_eq!(storage.get_last_block().hash(), genesis.hash());
+    assert_eq!(storage.current_state().balances, state.balances);
+    assert_eq!(storage.canonical_hash(0), Some(genesis.hash()));
+
+    let client = TestClient::new(
+        state,
+        AccountedCurrency::default(),
+        Pow {
+            threshold: DEFAULT_THRESHOLD,
+        },
+        LongestChain::default(),
+        (),
+    );
+    assert_eq!(client.canonical_block_hash(0), Some(genesis.hash()));
+}
+
+#[test]
+fn spec_genesis_of_bills() {
+    let spec = ChainSpec::parse("[bills]\nalice = 20\nbob = 5\nalice = 7\n").unwrap();
+    assert!(spec.genesis_state::<BalancesB>().is_err());
+
+    let state: CashState = spec.genesis_state().unwrap();
+    assert_eq!(state.next_serial(), 3);
+    let genesis = spec.genesis_block::<(), DigitalCashSystem>().unwrap();
+    assert_eq!(genesis.header.state_root, crate::state_root(&state));
+
+    let storage: CashStorage = spec.storage().unwrap();
+    assert_eq!(storage.get_last_block().hash(), genesis.hash());
+    assert_eq!(storage.current_state(), state);
+}
@@ -0,0 +1,419 @@
+//! Turning values into bytes and back.
+//!
+//! Anything a node writes to disk or sends to another node has to be turned into bytes first,
+//! and the other side has to be able to turn them back into exactly the same value. The
+//! `Encode` and `Decode` traits here do that with a compact deterministic format, modelled on
+//! the SCALE codec used by Substrate:
+//! * Integers are fixed width and little-endian.
+//! * Lengths, `usize`s and anything else wrapped in `Compact` use a variable width encoding
+//!   that keeps small numbers small. See `Compact` for the details.
+//! * Sequences are prefixed with their compact length.
+//! * Enums are a one byte tag, counting variants from zero, followed by the variant's fields.
+//! * Structs and tuples are just their fields in order.
+//!
+//! The format is not self describing. Decoding needs to know which type to expect.
+//!
+//! Every value has exactly one encoding, and decoding rejects any other bytes. That is what lets
+//! us hash values over their encoding: two nodes that agree on a value agree on its hash.
+//!
+//! The format may change one day. Bytes that leave the node, for disk or the network, are
+//! therefore written with `encode_versioned`, which puts `CODEC_VERSION` in front of them so
+//! that a future version can tell old bytes apart instead of misreading them. Hashes are taken
+//! over the plain encoding.
+
+use crate::{Hasher, H256};
+
+/// The version of the encoding produced by this module.
+pub const CODEC_VERSION: u8 = 1;
+
+/// The reasons bytes may fail to decode into a value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input ended before the value was complete.
+    UnexpectedEnd,
+    /// An enum tag, or a bool, had a value that does not belong to any variant.
+    InvalidTag(u8),
+    /// The value was complete, but there were bytes left over.
+    TrailingBytes,
+    /// A compact integer was not written in its shortest form, or was too large for its type.
+    NonCanonical,
+    /// Versioned bytes were written by a version of the codec this one does not understand.
+    UnsupportedVersion(u8),
+}
+
+/// A value that can be written as bytes.
+pub trait Encode {
+    /// Append the encoding of this value to the given buffer.
+    fn encode_to(&self, out: &mut Vec<u8>);
+
+    /// The encoding of this value on its own.
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_to(&mut out);
+        out
+    }
+
+    /// The encoding of this value, preceded by the version of the codec.
+    fn encode_versioned(&self) -> Vec<u8> {
+        let mut out = vec![CODEC_VERSION];
+        self.encode_to(&mut out);
+        out
+    }
+
+    /// The hash of this value's encoding, using the crate wide hasher.
+    fn encoded_hash(&self) -> H256 {
+        crate::hashing::DefaultHashing::hash_bytes(&self.encode())
+    }
+}
+
+/// A value that can be read back from bytes written by its `Encode` impl.
+pub trait Decode: Sized {
+    /// Read a value from the front of the input, advancing it past the bytes that were used.
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError>;
+
+    /// Read a value that takes up the entire input.
+    fn decode_all(mut input: &[u8]) -> Result<Self, DecodeError> {
+        let value = Self::decode(&mut input)?;
+        if !input.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+        Ok(value)
+    }
+
+    /// Read a value written by `encode_versioned` that takes up the entire input.
+    fn decode_versioned(mut input: &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(&mut input)? {
+            CODEC_VERSION => Self::decode_all(input),
+            version => Err(DecodeError::UnsupportedVersion(version)),
+        }
+    }
+}
+
+/// Take the next `n` bytes off the front of the input.
+pub fn take<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8], DecodeError> {
+    if input.len() < n {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    let (taken, rest) = input.split_at(n);
+    *input = rest;
+    Ok(taken)
+}
+
+macro_rules! impl_codec_for_int {
+    ($($t:ty),*) => {
+        $(
+            impl Encode for $t {
+                fn encode_to(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl Decode for $t {
+                fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+                    let bytes = take(input, std::mem::size_of::<$t>())?;
+                    Ok(<$t>::from_le_bytes(bytes.try_into().unwrap()))
+                }
+            }
+        )*
+    };
+}
+
+impl_codec_for_int!(u8, u16, u32, u64, u128);
+
+/// An integer written in as few bytes as its value needs. The two lowest bits of the first
+/// byte say how the rest is laid out:
+/// * `00`: the value fits in the upper six bits of this single byte, so it is below 64.
+/// * `01`: the value is in the upper fourteen bits of two little-endian bytes.
+/// * `10`: the value is in the upper thirty bits of four little-endian bytes.
+/// * `11`: the upper six bits of this byte, plus four, is the number of little-endian bytes
+///   that follow and hold the value.
+///
+/// Each value must use the shortest of these forms that fits it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Compact<T>(pub T);
+
+impl Encode for Compact<u64> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        let n = self.0;
+        if n < 1 << 6 {
+            out.push((n as u8) << 2);
+        } else if n < 1 << 14 {
+            out.extend_from_slice(&(((n as u16) << 2) | 0b01).to_le_bytes());
+        } else if n < 1 << 30 {
+            out.extend_from_slice(&(((n as u32) << 2) | 0b10).to_le_bytes());
+        } else {
+            let bytes = 8 - n.leading_zeros() as usize / 8;
+            out.push((((bytes - 4) as u8) << 2) | 0b11);
+            out.extend_from_slice(&n.to_le_bytes()[..bytes]);
+        }
+    }
+}
+
+impl Decode for Compact<u64> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let first = u8::decode(input)?;
+        let (n, min) = match first & 0b11 {
+            0b00 => return Ok(Compact((first >> 2) as u64)),
+            0b01 => {
+                let n = u16::from_le_bytes([first, u8::decode(input)?]) >> 2;
+                (n as u64, 1 << 6)
+            }
+            0b10 => {
+                let mut bytes = [first, 0, 0, 0];
+                bytes[1..].copy_from_slice(take(input, 3)?);
+                (u32::from_le_bytes(bytes) as u64 >> 2, 1 << 14)
+            }
+            _ => {
+                let len = (first >> 2) as usize + 4;
+                if len > 8 {
+                    return Err(DecodeError::NonCanonical);
+                }
+                let mut bytes = [0u8; 8];
+                bytes[..len].copy_from_slice(take(input, len)?);
+                // The top byte must not be zero, or a shorter form would have done.
+                if bytes[len - 1] == 0 {
+                    return Err(DecodeError::NonCanonical);
+                }
+                (u64::from_le_bytes(bytes), 1 << 30)
+            }
+        };
+        if n < min {
+            return Err(DecodeError::NonCanonical);
+        }
+        Ok(Compact(n))
+    }
+}
+
+impl Encode for usize {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        Compact(*self as u64).encode_to(out);
+    }
+}
+
+impl Decode for usize {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let Compact(n) = Compact::<u64>::decode(input)?;
+        usize::try_from(n).map_err(|_| DecodeError::NonCanonical)
+    }
+}
+
+impl Encode for bool {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl Decode for bool {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl Encode for () {
+    fn encode_to(&self, _: &mut Vec<u8>) {}
+}
+
+impl Decode for () {
+    fn decode(_: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(())
+    }
+}
+
+impl Encode for H256 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.0);
+    }
+}
+
+impl Decode for H256 {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(H256(take(input, 32)?.try_into().unwrap()))
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        (**self).encode_to(out);
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode_to(out);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.len().encode_to(out);
+        for item in self {
+            item.encode_to(out);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = usize::decode(input)?;
+        // Do not trust the length for the allocation. Every item takes at least a byte
+        // except for zero sized ones, so this bounds it by the remaining input.
+        let mut items = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            items.push(T::decode(input)?);
+        }
+        Ok(items)
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.0.encode_to(out);
+        self.1.encode_to(out);
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok((A::decode(input)?, B::decode(input)?))
+    }
+}
+
+impl<A: Encode, B: Encode, C: Encode> Encode for (A, B, C) {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.0.encode_to(out);
+        self.1.encode_to(out);
+        self.2.encode_to(out);
+    }
+}
+
+impl<A: Decode, B: Decode, C: Decode> Decode for (A, B, C) {
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok((A::decode(input)?, B::decode(input)?, C::decode(input)?))
+    }
+}
+
+#[cfg(test)]
+fn round_trip<T: Encode + Decode + PartialEq + std::fmt::Debug>(value: T) {
+    let bytes = value.encode();
+    assert_eq!(T::decode_all(&bytes), Ok(value));
+}
+
+#[test]
+fn integers_are_little_endian() {
+    assert_eq!(1u32.encode(), vec![1, 0, 0, 0]);
+    assert_eq!(0x0102u16.encode(), vec![2, 1]);
+}
+
+#[test]
+fn compact_integers_use_the_shortest_form() {
+    assert_eq!(Compact(0u64).encode(), vec![0]);
+    assert_eq!(Compact(63u64).encode(), vec![252]);
+    assert_eq!(Compact(64u64).encode(), vec![1, 1]);
+    assert_eq!(Compact(16383u64).encode(), vec![253, 255]);
+    assert_eq!(Compact(16384u64).encode(), vec![2, 0, 1, 0]);
+    assert_eq!(Compact((1u64 << 30) - 1).encode(), vec![254, 255, 255, 255]);
+    assert_eq!(Compact(1u64 << 30).encode(), vec![3, 0, 0, 0, 64]);
+    assert_eq!(
+        Compact(u64::MAX).encode(),
+        vec![19, 255, 255, 255, 255, 255, 255, 255, 255]
+    );
+    assert_eq!(7usize.encode(), vec![28]);
+
+    for n in [
+        0,
+        1,
+        63,
+        64,
+        16383,
+        16384,
+        1 << 30,
+        1 << 32,
+        u64::MAX - 1,
+        u64::MAX,
+    ] {
+        round_trip(Compact(n));
+    }
+}
+
+#[test]
+fn compact_integers_reject_longer_forms() {
+    // 1 written with two and four bytes.
+    assert_eq!(
+        Compact::<u64>::decode_all(&[5, 0]),
+        Err(DecodeError::NonCanonical)
+    );
+    assert_eq!(
+        Compact::<u64>::decode_all(&[6, 0, 0, 0]),
+        Err(DecodeError::NonCanonical)
+    );
+    // 2^30 written with five bytes where four would do, and with more than eight bytes.
+    assert_eq!(
+        Compact::<u64>::decode_all(&[7, 0, 0, 0, 64, 0]),
+        Err(DecodeError::NonCanonical)
+    );
+    assert_eq!(
+        Compact::<u64>::decode_all(&[23, 1, 1, 1, 1, 1, 1, 1, 1, 1]),
+        Err(DecodeError::NonCanonical)
+    );
+}
+
+#[test]
+fn versioned_encoding() {
+    let bytes = 5u16.encode_versioned();
+    assert_eq!(bytes, vec![CODEC_VERSION, 5, 0]);
+    assert_eq!(u16::decode_versioned(&bytes), Ok(5));
+    assert_eq!(
+        u16::decode_versioned(&[0, 5, 0]),
+        Err(DecodeError::UnsupportedVersion(0))
+    );
+    assert_eq!(u16::decode_versioned(&[]), Err(DecodeError::UnexpectedEnd));
+}
+
+#[test]
+fn primitives_round_trip() {
+    round_trip(0u8);
+    round_trip(u64::MAX);
+    round_trip(12345usize);
+    round_trip(true);
+    round_trip(());
+    round_trip(H256::from(42));
+    round_trip(Some(3u64));
+    round_trip(None::<u64>);
+    round_trip(vec![(1u64, H256::from(1)), (2, H256::from(2))]);
+    round_trip((7u8, true, H256::from(3)));
+}
+
+#[test]
+fn decoding_rejects_bad_input() {
+    assert_eq!(u64::decode_all(&[1, 2, 3]), Err(DecodeError::UnexpectedEnd));
+    assert_eq!(bool::decode_all(&[2]), Err(DecodeError::InvalidTag(2)));
+    assert_eq!(u8::decode_all(&[1, 2]), Err(DecodeError::TrailingBytes));
+
+    // A length prefix promising more items than there are bytes for.
+    let mut bytes = 1000usize.encode();
+    bytes.push(1);
+    assert_eq!(
+        Vec::<u64>::decode_all(&bytes),
+        Err(DecodeError::UnexpectedEnd)
+    );
+}
@@ -0,0 +1,309 @@
+//! Cryptographic hashing for the whole crate.
+//!
+//! Early versions of this codebase hashed everything with the standard library's `DefaultHasher`
+//! and used the resulting `u64` as a block hash. That is fine for a toy, but it is neither
+//! collision resistant nor guaranteed to be stable across Rust versions. A real blockchain needs
+//! both: block hashes are how nodes refer to blocks, and they must agree on them forever.
+//!
+//! Here we define a small `Hasher` abstraction that turns bytes into a fixed size digest, and a
+//! from-scratch implementation of SHA-256 behind it. Any type implementing `std::hash::Hash` can be
+//! fed through it by first serializing its `Hash` impl into bytes with a fixed, platform independent
+//! encoding. Values that nodes must agree on, such as blocks, states and receipts, are hashed over
+//! their codec encoding instead.
+
+use std::fmt;
+
+/// A 32-byte digest as produced by SHA-256 and friends.
+///
+/// Digests are ordered and hashed bytewise so they can be used as map keys just like the old `u64`s.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct H256(pub [u8; 32]);
+
+impl H256 {
+    /// The first eight bytes of the digest read as a big-endian number.
+    ///
+    /// Proof of work compares this against a `u64` difficulty threshold, which keeps the
+    /// thresholds readable while still using the full cryptographic hash.
+    pub fn leading_u64(&self) -> u64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&self.0[..8]);
+        u64::from_be_bytes(bytes)
+    }
+
+    /// The last eight bytes of the digest read as a big-endian number. This is the inverse of
+    /// `H256::from(u64)`, and handy when only a few bits of the hash are needed, like its parity.
+    pub fn low_u64(&self) -> u64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&self.0[24..]);
+        u64::from_be_bytes(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+/// Small numbers make handy placeholder hashes in tests. The number is stored big-endian in
+/// the last eight bytes, so `H256::from(0)` is the all-zero hash.
+impl From<u64> for H256 {
+    fn from(n: u64) -> Self {
+        let mut bytes = [0u8; 32];
+        bytes[24..].copy_from_slice(&n.to_be_bytes());
+        H256(bytes)
+    }
+}
+
+impl fmt::Debug for H256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x")?;
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for H256 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A cryptographic hash function over byte strings.
+pub trait Hasher {
+    type Output: Copy + Eq + Ord + std::hash::Hash + fmt::Debug + Default;
+
+    /// Hash the given bytes in one go.
+    fn hash_bytes(bytes: &[u8]) -> Self::Output;
+
+    /// Hash any value that implements `std::hash::Hash` by first turning its `Hash` impl into bytes.
+    fn hash_of<T: std::hash::Hash + ?Sized>(t: &T) -> Self::Output {
+        let mut sink = ByteSink::default();
+        t.hash(&mut sink);
+        Self::hash_bytes(&sink.bytes)
+    }
+}
+
+/// The hasher used throughout the crate by `crate::hash`. Swap this out to change the hash
+/// function of every chain in the crate at once.
+pub type DefaultHashing = Sha256;
+
+/// Collects everything written to it by a `std::hash::Hash` impl into a byte buffer.
+///
+/// The standard library writes integers in native endianness and lengths as `usize`, which would
+/// make hashes differ between platforms. We override those writes to always use little-endian
+/// fixed width encodings, so the same value produces the same bytes everywhere.
+#[derive(Default)]
+pub struct ByteSink {
+    bytes: Vec<u8>,
+}
+
+impl std::hash::Hasher for ByteSink {
+    fn write(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.bytes.push(i);
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes());
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i8(&mut self, i: i8) {
+        self.write_u8(i as u8);
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_u64(i as u64);
+    }
+
+    /// `std::hash::Hasher` insists on a `u64` result. We give the leading bytes of the SHA-256
+    /// digest so that anyone using this sink as a plain hasher still gets a decent hash.
+    fn finish(&self) -> u64 {
+        Sha256::hash_bytes(&self.bytes).leading_u64()
+    }
+}
+
+/// SHA-256 as specified in FIPS 180-4.
+pub struct Sha256;
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+impl Sha256 {
+    /// Mix a single 64-byte block into the running state.
+    fn compress(state: &mut [u32; 8], block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (word, new) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(new);
+        }
+    }
+}
+
+impl Hasher for Sha256 {
+    type Output = H256;
+
+    fn hash_bytes(bytes: &[u8]) -> H256 {
+        // Pad with a single 1 bit, zeros, and the message length in bits so the total
+        // length is a multiple of 64 bytes.
+        let bit_len = (bytes.len() as u64).wrapping_mul(8);
+        let mut message = bytes.to_vec();
+        message.push(0x80);
+        while message.len() % 64 != 56 {
+            message.push(0);
+        }
+        message.extend_from_slice(&bit_len.to_be_bytes());
+
+        let mut state = INITIAL_STATE;
+        for block in message.chunks_exact(64) {
+            Self::compress(&mut state, block);
+        }
+
+        let mut digest = [0u8; 32];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        H256(digest)
+    }
+}
+
+#[cfg(test)]
+fn hex(digest: &H256) -> String {
+    format!("{:?}", digest)
+}
+
+#[test]
+fn sha256_empty_input() {
+    assert_eq!(
+        hex(&Sha256::hash_bytes(b"")),
+        "0xe3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+}
+
+#[test]
+fn sha256_abc() {
+    assert_eq!(
+        hex(&Sha256::hash_bytes(b"abc")),
+        "0xba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+}
+
+#[test]
+fn sha256_two_block_message() {
+    assert_eq!(
+        hex(&Sha256::hash_bytes(
+            b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+        )),
+        "0x248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+    );
+}
+
+#[test]
+fn sha256_million_as() {
+    let input = vec![b'a'; 1_000_000];
+    assert_eq!(
+        hex(&Sha256::hash_bytes(&input)),
+        "0xcdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+    );
+}
+
+#[test]
+fn hash_of_is_deterministic_and_sensitive() {
+    assert_eq!(
+        Sha256::hash_of(&(1u64, "hello")),
+        Sha256::hash_of(&(1u64, "hello"))
+    );
+    assert_ne!(
+        Sha256::hash_of(&(1u64, "hello")),
+        Sha256::hash_of(&(2u64, "hello"))
+    );
+}
+
+#[test]
+fn small_numbers_make_placeholder_hashes() {
+    assert_eq!(H256::from(0), H256::default());
+    assert_eq!(H256::from(1).0[31], 1);
+    assert!(H256::from(1) < H256::from(2));
+    assert_eq!(H256::from(1234).low_u64(), 1234);
+}
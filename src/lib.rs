@@ -1,14 +1,22 @@
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
-
 mod c1_state_machine;
 mod c2_blockchain;
 mod c3_consensus;
 mod c4_client;
+pub mod chain_spec;
+mod codec;
+mod hashing;
+mod json;
+mod merkle;
+pub mod node;
+mod trie;
+
+pub use codec::{Compact, Decode, DecodeError, Encode, CODEC_VERSION};
+pub use hashing::{Hasher, Sha256, H256};
+pub use merkle::{merkle_proof, merkle_root, MerkleProof};
+pub use trie::{state_root, StateProof, TrieDb, TrieState, EMPTY_ROOT};
 
-// Simple helper to do some hashing.
-fn hash<T: Hash>(t: &T) -> u64 {
-    let mut s = DefaultHasher::new();
-    t.hash(&mut s);
-    s.finish()
+// Simple helper to do some hashing. Uses the crate wide cryptographic hasher on the value's
+// encoding, so that every node computes the same hash for the same value.
+fn hash<T: Encode + ?Sized>(t: &T) -> H256 {
+    t.encoded_hash()
 }
@@ -0,0 +1,191 @@
+//! A binary Merkle tree over a list of items.
+//!
+//! Headers used to commit to their extrinsics with a plain `hash(&body)`. That is a fine commitment,
+//! but the only way to convince someone that a single extrinsic is in a block is to send them the
+//! entire body. With a Merkle root instead, an inclusion proof is just the sibling hashes along the
+//! path from the extrinsic's leaf up to the root, which is logarithmic in the size of the block.
+//!
+//! Items are hashed over their encoding, so every node computes the same root for the same list.
+//! Leaves and inner nodes are hashed with different prefixes so that an inner node can never be
+//! passed off as a leaf. When a level has an odd number of nodes, the last node is promoted to the
+//! next level unchanged rather than being paired with a copy of itself.
+
+use crate::{hash, Encode, H256};
+
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+fn leaf_hash<T: Encode>(item: &T) -> H256 {
+    hash(&(LEAF_PREFIX, item))
+}
+
+fn node_hash(left: &H256, right: &H256) -> H256 {
+    hash(&(NODE_PREFIX, left, right))
+}
+
+/// Compute the next level up of the tree from the given level.
+fn parent_level(level: &[H256]) -> Vec<H256> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [odd] => *odd,
+            _ => unreachable!("chunks of two are never empty"),
+        })
+        .collect()
+}
+
+/// The Merkle root of the given items. The root of an empty list is the all-zero hash.
+pub fn merkle_root<T: Encode>(items: &[T]) -> H256 {
+    let mut level: Vec<H256> = items.iter().map(leaf_hash).collect();
+    if level.is_empty() {
+        return H256::default();
+    }
+    while level.len() > 1 {
+        level = parent_level(&level);
+    }
+    level[0]
+}
+
+/// Proof that an item sits at a given index of a list with a given Merkle root.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MerkleProof {
+    /// The position of the proven item in the list
+    pub index: usize,
+    /// How many items the list has in total. Needed to know where odd nodes were promoted.
+    pub leaf_count: usize,
+    /// The sibling hashes from the leaf level up to just below the root. Levels where the
+    /// node on the path had no sibling are skipped.
+    pub siblings: Vec<H256>,
+}
+
+/// Build a proof that the item at `index` is part of the given list.
+/// Returns None if the index is out of bounds.
+pub fn merkle_proof<T: Encode>(items: &[T], index: usize) -> Option<MerkleProof> {
+    if index >= items.len() {
+        return None;
+    }
+
+    let mut siblings = Vec::new();
+    let mut level: Vec<H256> = items.iter().map(leaf_hash).collect();
+    let mut position = index;
+    while level.len() > 1 {
+        let sibling = position ^ 1;
+        if sibling < level.len() {
+            siblings.push(level[sibling]);
+        }
+        level = parent_level(&level);
+        position /= 2;
+    }
+
+    Some(MerkleProof {
+        index,
+        leaf_count: items.len(),
+        siblings,
+    })
+}
+
+impl MerkleProof {
+    /// Check that `item` is at this proof's index in a list whose Merkle root is `root`.
+    pub fn verify<T: Encode>(&self, root: &H256, item: &T) -> bool {
+        if self.index >= self.leaf_count {
+            return false;
+        }
+
+        let mut siblings = self.siblings.iter();
+        let mut current = leaf_hash(item);
+        let mut position = self.index;
+        let mut level_len = self.leaf_count;
+        while level_len > 1 {
+            let sibling_position = position ^ 1;
+            if sibling_position < level_len {
+                let Some(sibling) = siblings.next() else {
+                    return false;
+                };
+                current = if position.is_multiple_of(2) {
+                    node_hash(&current, sibling)
+                } else {
+                    node_hash(sibling, &current)
+                };
+            }
+            position /= 2;
+            level_len = level_len.div_ceil(2);
+        }
+
+        siblings.next().is_none() && current == *root
+    }
+}
+
+#[test]
+fn merkle_root_of_nothing_is_zero() {
+    assert_eq!(merkle_root::<u64>(&[]), H256::default());
+}
+
+#[test]
+fn merkle_root_of_one_item_is_its_leaf_hash() {
+    assert_eq!(merkle_root(&[7u64]), leaf_hash(&7u64));
+}
+
+#[test]
+fn merkle_leaves_are_hashed_over_their_encoding() {
+    let bytes = [LEAF_PREFIX, 7, 0, 0, 0, 0, 0, 0, 0];
+    assert_eq!(
+        leaf_hash(&7u64),
+        <crate::Sha256 as crate::Hasher>::hash_bytes(&bytes)
+    );
+}
+
+#[test]
+fn merkle_root_depends_on_order() {
+    assert_ne!(merkle_root(&[1u64, 2]), merkle_root(&[2u64, 1]));
+}
+
+#[test]
+fn merkle_proofs_verify_for_every_index() {
+    for len in 1..=9u64 {
+        let items: Vec<u64> = (0..len).collect();
+        let root = merkle_root(&items);
+        for (i, item) in items.iter().enumerate() {
+            let proof = merkle_proof(&items, i).unwrap();
+            assert!(proof.verify(&root, item), "len {} index {}", len, i);
+        }
+    }
+}
+
+#[test]
+fn merkle_proof_is_logarithmic() {
+    let items: Vec<u64> = (0..1000).collect();
+    let proof = merkle_proof(&items, 500).unwrap();
+    assert_eq!(proof.siblings.len(), 10);
+}
+
+#[test]
+fn merkle_proof_rejects_wrong_item() {
+    let items = vec![10u64, 20, 30, 40, 50];
+    let root = merkle_root(&items);
+    let proof = merkle_proof(&items, 2).unwrap();
+    assert!(!proof.verify(&root, &31u64));
+}
+
+#[test]
+fn merkle_proof_rejects_wrong_index() {
+    let items = vec![10u64, 20, 30, 40, 50];
+    let root = merkle_root(&items);
+    let mut proof = merkle_proof(&items, 2).unwrap();
+    proof.index = 3;
+    assert!(!proof.verify(&root, &30u64));
+}
+
+#[test]
+fn merkle_proof_rejects_tampered_sibling() {
+    let items = vec![10u64, 20, 30, 40];
+    let root = merkle_root(&items);
+    let mut proof = merkle_proof(&items, 1).unwrap();
+    proof.siblings[0].0[0] ^= 1;
+    assert!(!proof.verify(&root, &20u64));
+}
+
+#[test]
+fn merkle_proof_out_of_bounds() {
+    assert_eq!(merkle_proof(&[1u64, 2, 3], 3), None);
+}
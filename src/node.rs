@@ -0,0 +1,929 @@
+//! A node brings the pieces of the previous chapters together into a program that can be run:
+//! a full client with storage on disk, an RPC server, a network of peers, and a loop that
+//! authors blocks.
+//!
+//! The node always runs the signed accounted currency state machine, so every transaction
+//! pays a fee, which goes to the author of the block including it. A chain is initialised
+//! from a chain specification, which sets its genesis balances, consensus engine, fork choice,
+//! transaction pool and block weight limit. The specification is saved next to the chain's data so that later
+//! commands on the same directory use it too.
+//!
+//! ```text
+//! node init <dir> --spec <file>
+//! node run <dir> [--rpc ADDRESS] [--listen ADDRESS] [--peer ADDRESS] [--author ACCOUNT]
+//!     [--block-time MS] [--block-weight-limit N] [--rounds N] [--transactions FILE]
+//! node import <dir> <file>
+//! node export <dir> <file>
+//! node balances <dir> [ACCOUNT]
+//! ```
+//!
+//! The transactions file lists transactions to queue before authoring starts, one per line:
+//! `mint alice 10`, `burn alice 5` or `transfer alice bob 3`. The node numbers each account's
+//! transactions in the order they are listed. Block files hold a versioned encoding of the
+//! blocks of a canonical chain, without its genesis. A `--block-weight-limit` given to `run`
+//! takes the place of the specification's for that run.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::c1_state_machine::{
+    AccountingTransaction, BalancesB, SignedAccountedCurrency, SignedExtrinsic, User,
+};
+use crate::c3_consensus::{pow_to_poa, Consensus, PoaRoundRobinByHeight, Pow, SimplePoa};
+use crate::c4_client::{
+    Block, FileStorage, ForkChoice, FullClient, HeaviestChain, ImportBlock, LongestChain, Network,
+    PriorityPool, RpcServer, SimplePool, Storage, TransactionPool,
+};
+use crate::chain_spec::{
+    parse_number, parse_user, user_name, ChainSpec, ConsensusSpec, ForkChoiceKind, PoolKind,
+};
+use crate::{Decode, Encode};
+
+/// How to use the node, printed when it is run without a command.
+pub const USAGE: &str = "\
+usage:
+  node init <dir> --spec <file>
+  node run <dir> [--rpc ADDRESS] [--listen ADDRESS] [--peer ADDRESS] [--author ACCOUNT]
+      [--block-time MS] [--block-weight-limit N] [--rounds N] [--transactions FILE]
+  node import <dir> <file>
+  node export <dir> <file>
+  node balances <dir> [ACCOUNT]
+";
+
+/// The file in a chain's directory holding the specification the chain was initialised from.
+const SPEC_FILE: &str = "chain.spec";
+
+/// The block time used unless configured otherwise.
+const DEFAULT_BLOCK_TIME_MS: u64 = 1000;
+
+/// Run the node with the given command line arguments, not including the program name.
+/// Anything worth reporting is written to `out`.
+pub fn run(args: &[String], out: &mut dyn Write) -> Result<(), String> {
+    let Some((command, rest)) = args.split_first() else {
+        return write_out(out, USAGE);
+    };
+    let args = Args::parse(rest)?;
+    match command.as_str() {
+        "init" => {
+            args.expect(1, &["spec"])?;
+            let spec_file = args.option("spec").ok_or("missing --spec")?;
+            let spec = ChainSpec::parse(&read_file(spec_file)?)
+                .map_err(|e| format!("{}: {}", spec_file, e))?;
+            init(Path::new(args.positional(0, "directory")?), &spec, out)
+        }
+        "run" => {
+            args.expect(
+                1,
+                &[
+                    "rpc",
+                    "listen",
+                    "peer",
+                    "author",
+                    "block-time",
+                    "block-weight-limit",
+                    "rounds",
+                    "transactions",
+                ],
+            )?;
+            let transactions = match args.option("transactions") {
+                Some(path) => parse_lines(&read_file(path)?, parse_transaction)?,
+                None => Vec::new(),
+            };
+            let task = RunTask {
+                rpc: args.option("rpc").map(String::from),
+                listen: args.option("listen").map(String::from),
+                peer: args.option("peer").map(String::from),
+                author: args.option("author").map(parse_user).transpose()?,
+                block_time: Duration::from_millis(
+                    args.number("block-time")?.unwrap_or(DEFAULT_BLOCK_TIME_MS),
+                ),
+                block_weight_limit: args.number("block-weight-limit")?,
+                rounds: args.number("rounds")?,
+                transactions,
+            };
+            with_chain(args.positional(0, "directory")?, task, out)
+        }
+        "import" => {
+            args.expect(2, &[])?;
+            let task = ImportTask {
+                file: args.positional(1, "block file")?.to_string(),
+            };
+            with_chain(args.positional(0, "directory")?, task, out)
+        }
+        "export" => {
+            args.expect(2, &[])?;
+            let task = ExportTask {
+                file: args.positional(1, "block file")?.to_string(),
+            };
+            with_chain(args.positional(0, "directory")?, task, out)
+        }
+        "balances" => {
+            args.expect(2, &[])?;
+            let task = BalancesTask {
+                account: args.positional.get(1).map(|a| parse_user(a)).transpose()?,
+            };
+            with_chain(args.positional(0, "directory")?, task, out)
+        }
+        "help" => write_out(out, USAGE),
+        _ => Err(format!("unknown command {}\n{}", command, USAGE)),
+    }
+}
+
+/// Start a new chain from the given specification in a directory that does not hold one yet.
+fn init(dir: &Path, spec: &ChainSpec, out: &mut dyn Write) -> Result<(), String> {
+    if dir.join(SPEC_FILE).exists() {
+        return Err(format!("{} already holds a chain", dir.display()));
+    }
+    let chain = Chain {
+        dir,
+        spec,
+        new: true,
+    };
+    dispatch(chain, InitTask, out)?;
+    std::fs::write(dir.join(SPEC_FILE), spec.to_string())
+        .map_err(|e| format!("{}: {}", dir.display(), e))
+}
+
+/// Run a task on the chain kept in the given directory, with the specification it was
+/// initialised from.
+fn with_chain<T: Task>(dir: &str, task: T, out: &mut dyn Write) -> Result<(), String> {
+    let dir = Path::new(dir);
+    let spec = std::fs::read_to_string(dir.join(SPEC_FILE))
+        .map_err(|_| format!("{} does not hold a chain, run init first", dir.display()))?;
+    let spec = ChainSpec::parse(&spec).map_err(|e| format!("{}: {}", SPEC_FILE, e))?;
+    let chain = Chain {
+        dir,
+        spec: &spec,
+        new: false,
+    };
+    dispatch(chain, task, out)
+}
+
+/// The priority of a transaction in the priority pool. Larger amounts go first.
+fn amount_priority(t: SignedExtrinsic<AccountingTransaction>) -> u64 {
+    match t.call {
+        AccountingTransaction::Mint { amount, .. }
+        | AccountingTransaction::Burn { amount, .. }
+        | AccountingTransaction::Transfer { amount, .. } => amount,
+    }
+}
+
+// --- Assembling a client ---
+
+type NodeClient<C, FC, P> =
+    FullClient<C, SignedAccountedCurrency, FC, P, FileStorage<C, SignedAccountedCurrency>>;
+
+/// Something to do with a chain's client, whichever consensus engine, fork choice and pool
+/// it was configured with.
+trait Task {
+    fn run<C, FC, P>(self, client: NodeClient<C, FC, P>, out: &mut dyn Write) -> Result<(), String>
+    where
+        C: Consensus + Send + 'static,
+        C::Digest: Send,
+        FC: ForkChoice<C, SignedAccountedCurrency> + Default + Send + 'static,
+        P: TransactionPool<SignedAccountedCurrency> + Send + 'static,
+        Block<C, SignedAccountedCurrency>: std::hash::Hash + Clone;
+}
+
+/// A chain directory along with the specification of the chain in it.
+struct Chain<'a> {
+    dir: &'a Path,
+    spec: &'a ChainSpec,
+    /// Whether the chain is being initialised rather than opened again.
+    new: bool,
+}
+
+/// Build the client the specification asks for and hand it to the task. Rust cannot choose
+/// types at runtime, so every supported combination is spelled out here.
+fn dispatch<T: Task>(chain: Chain, task: T, out: &mut dyn Write) -> Result<(), String> {
+    match chain.spec.consensus.clone() {
+        ConsensusSpec::Pow { threshold } => {
+            let engine = Pow { threshold };
+            match chain.spec.fork_choice {
+                ForkChoiceKind::Longest => {
+                    with_pool(chain, engine, LongestChain::default(), task, out)
+                }
+                ForkChoiceKind::Heaviest => {
+                    with_pool(chain, engine, HeaviestChain::default(), task, out)
+                }
+            }
+        }
+        ConsensusSpec::SimplePoa { authorities } => {
+            let engine = SimplePoa { authorities };
+            with_pool(chain, engine, LongestChain::default(), task, out)
+        }
+        ConsensusSpec::PoaRoundRobin { authorities } => {
+            let engine = PoaRoundRobinByHeight { authorities };
+            with_pool(chain, engine, LongestChain::default(), task, out)
+        }
+        ConsensusSpec::Forked {
+            fork_height,
+            threshold,
+            authorities,
+        } => {
+            let engine = pow_to_poa(fork_height, threshold, authorities);
+            with_pool(chain, engine, LongestChain::default(), task, out)
+        }
+    }
+}
+
+fn with_pool<C, FC, T>(
+    chain: Chain,
+    engine: C,
+    fork_choice: FC,
+    task: T,
+    out: &mut dyn Write,
+) -> Result<(), String>
+where
+    C: Consensus + Send + 'static,
+    C::Digest: Send,
+    FC: ForkChoice<C, SignedAccountedCurrency> + Default + Send + 'static,
+    Block<C, SignedAccountedCurrency>: std::hash::Hash + Clone,
+    T: Task,
+{
+    match chain.spec.pool {
+        PoolKind::Simple => {
+            let pool = SimplePool::default();
+            open_client(chain, engine, fork_choice, pool, task, out)
+        }
+        PoolKind::Priority => {
+            let pool = PriorityPool::new(
+                amount_priority as fn(SignedExtrinsic<AccountingTransaction>) -> u64,
+                0,
+            );
+            open_client(chain, engine, fork_choice, pool, task, out)
+        }
+    }
+}
+
+fn open_client<C, FC, P, T>(
+    chain: Chain,
+    engine: C,
+    fork_choice: FC,
+    pool: P,
+    task: T,
+    out: &mut dyn Write,
+) -> Result<(), String>
+where
+    C: Consensus + Send + 'static,
+    C::Digest: Send,
+    FC: ForkChoice<C, SignedAccountedCurrency> + Default + Send + 'static,
+    P: TransactionPool<SignedAccountedCurrency> + Send + 'static,
+    Block<C, SignedAccountedCurrency>: std::hash::Hash + Clone,
+    T: Task,
+{
+    let genesis_state: BalancesB = chain.spec.genesis_state().map_err(|e| e.to_string())?;
+    let genesis = Block::<C, SignedAccountedCurrency>::genesis(&genesis_state).hash();
+    let storage = match chain.new {
+        true => FileStorage::create(chain.dir, genesis_state),
+        false => FileStorage::open(chain.dir),
+    }
+    .map_err(|e| format!("{}: {}", chain.dir.display(), e))?;
+
+    // A storage that was tampered with, or a specification that was edited after init,
+    // would otherwise go unnoticed until blocks fail to import.
+    if storage.canonical_hash(0) != Some(genesis) {
+        return Err(format!(
+            "the chain in {} does not start from the genesis of its specification",
+            chain.dir.display()
+        ));
+    }
+    let mut client = FullClient::resume(
+        SignedAccountedCurrency::default(),
+        engine,
+        fork_choice,
+        pool,
+        storage,
+    );
+    client.set_block_weight_limit(chain.spec.block_weight_limit);
+    task.run(client, out)
+}
+
+// --- Tasks ---
+
+struct InitTask;
+
+impl Task for InitTask {
+    fn run<C, FC, P>(self, client: NodeClient<C, FC, P>, out: &mut dyn Write) -> Result<(), String>
+    where
+        C: Consensus + Send + 'static,
+        C::Digest: Send,
+        FC: ForkChoice<C, SignedAccountedCurrency> + Default + Send + 'static,
+        P: TransactionPool<SignedAccountedCurrency> + Send + 'static,
+        Block<C, SignedAccountedCurrency>: std::hash::Hash + Clone,
+    {
+        let genesis = client.get_last_block().hash();
+        write_out(
+            out,
+            &format!("initialised chain with genesis {}\n", genesis),
+        )
+    }
+}
+
+/// Author blocks every block time, optionally serving RPC calls and gossiping with peers in
+/// between.
+struct RunTask {
+    rpc: Option<String>,
+    /// Where to listen for peers. Asking for a peer without this listens on a free local port.
+    listen: Option<String>,
+    /// A peer to connect to on startup.
+    peer: Option<String>,
+    author: Option<User>,
+    block_time: Duration,
+    /// The block weight limit to author with instead of the specification's.
+    block_weight_limit: Option<u64>,
+    /// How many times to try to author a block before stopping. Runs forever if None.
+    rounds: Option<u64>,
+    /// Transactions to queue before authoring starts.
+    transactions: Vec<AccountingTransaction>,
+}
+
+impl Task for RunTask {
+    fn run<C, FC, P>(
+        self,
+        mut client: NodeClient<C, FC, P>,
+        out: &mut dyn Write,
+    ) -> Result<(), String>
+    where
+        C: Consensus + Send + 'static,
+        C::Digest: Send,
+        FC: ForkChoice<C, SignedAccountedCurrency> + Default + Send + 'static,
+        P: TransactionPool<SignedAccountedCurrency> + Send + 'static,
+        Block<C, SignedAccountedCurrency>: std::hash::Hash + Clone,
+    {
+        client.set_author(self.author);
+        if let Some(block_weight_limit) = self.block_weight_limit {
+            if block_weight_limit == 0 {
+                return Err("the block weight limit must not be zero".into());
+            }
+            client.set_block_weight_limit(block_weight_limit);
+        }
+        // The file does not number anybody's transactions, so each account's go in the order
+        // they are listed, carrying on from the account's nonce at the best block. The play
+        // users' signatures can be made by anybody, the node included.
+        let state = client.current_state();
+        let mut next_nonces = HashMap::new();
+        let submitted = self.transactions.len();
+        let mut accepted = 0;
+        for t in self.transactions {
+            let origin = t.origin();
+            let nonce = next_nonces
+                .entry(origin)
+                .or_insert_with(|| state.nonce(&origin));
+            if client.submit_transaction(SignedExtrinsic::new(origin, t.with_nonce(*nonce))) {
+                *nonce += 1;
+                accepted += 1;
+            }
+        }
+        if submitted > 0 {
+            write_out(
+                out,
+                &format!("queued {} of {} transactions\n", accepted, submitted),
+            )?;
+        }
+
+        let client = Arc::new(Mutex::new(client));
+        let _server = match self.rpc {
+            Some(address) => {
+                let server = RpcServer::start(client.clone(), address.as_str())
+                    .map_err(|e| format!("{}: {}", address, e))?;
+                write_out(out, &format!("serving RPC on {}\n", server.local_addr()))?;
+                Some(server)
+            }
+            None => None,
+        };
+        let network = match (self.listen, self.peer) {
+            (None, None) => None,
+            (listen, peer) => {
+                let address = listen.unwrap_or_else(|| "127.0.0.1:0".into());
+                let network = Network::start(client.clone(), address.as_str())
+                    .map_err(|e| format!("{}: {}", address, e))?;
+                write_out(
+                    out,
+                    &format!("listening for peers on {}\n", network.local_addr()),
+                )?;
+                if let Some(peer) = peer {
+                    network
+                        .connect(peer.as_str())
+                        .map_err(|e| format!("{}: {}", peer, e))?;
+                    write_out(out, &format!("connected to {}\n", peer))?;
+                }
+                Some(network)
+            }
+        };
+
+        let mut round = 0;
+        while self.rounds.is_none_or(|rounds| round < rounds) {
+            thread::sleep(self.block_time);
+            round += 1;
+
+            let mut client = client.lock().unwrap();
+            let previous = client.get_last_block().hash();
+            if client.author_and_import_automatic_block().is_err() {
+                write_out(out, "could not author a block\n")?;
+                continue;
+            }
+            let best = client.get_last_block();
+            if best.hash() != previous {
+                write_out(
+                    out,
+                    &format!(
+                        "authored block #{} {} with {} transactions\n",
+                        best.header.height,
+                        best.hash(),
+                        best.body.len()
+                    ),
+                )?;
+                // The network needs the client to announce the block.
+                drop(client);
+                if let Some(network) = &network {
+                    network.announce_best_block();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Import the blocks of a block file.
+struct ImportTask {
+    file: String,
+}
+
+impl Task for ImportTask {
+    fn run<C, FC, P>(
+        self,
+        mut client: NodeClient<C, FC, P>,
+        out: &mut dyn Write,
+    ) -> Result<(), String>
+    where
+        C: Consensus + Send + 'static,
+        C::Digest: Send,
+        FC: ForkChoice<C, SignedAccountedCurrency> + Default + Send + 'static,
+        P: TransactionPool<SignedAccountedCurrency> + Send + 'static,
+        Block<C, SignedAccountedCurrency>: std::hash::Hash + Clone,
+    {
+        let bytes = std::fs::read(&self.file).map_err(|e| format!("{}: {}", self.file, e))?;
+        let blocks = Vec::<Block<C, SignedAccountedCurrency>>::decode_versioned(&bytes)
+            .map_err(|e| format!("{}: not a block file ({:?})", self.file, e))?;
+
+        // Blocks the chain already has, such as those of an earlier import, are skipped.
+        let blocks: Vec<_> = blocks
+            .into_iter()
+            .filter(|block| client.get_block(block.hash()).is_none())
+            .collect();
+        let total = blocks.len();
+        let imported = blocks
+            .into_iter()
+            .filter(|block| client.import_block(block.clone()))
+            .count();
+        let best = client.get_last_block();
+        write_out(
+            out,
+            &format!(
+                "imported {} of {} blocks, best is #{} {}\n",
+                imported,
+                total,
+                best.header.height,
+                best.hash()
+            ),
+        )?;
+        if imported < total {
+            return Err(format!("{} blocks were rejected", total - imported));
+        }
+        Ok(())
+    }
+}
+
+/// Write the canonical chain, without its genesis, to a block file.
+struct ExportTask {
+    file: String,
+}
+
+impl Task for ExportTask {
+    fn run<C, FC, P>(self, client: NodeClient<C, FC, P>, out: &mut dyn Write) -> Result<(), String>
+    where
+        C: Consensus + Send + 'static,
+        C::Digest: Send,
+        FC: ForkChoice<C, SignedAccountedCurrency> + Default + Send + 'static,
+        P: TransactionPool<SignedAccountedCurrency> + Send + 'static,
+        Block<C, SignedAccountedCurrency>: std::hash::Hash + Clone,
+    {
+        let blocks: Vec<_> = client.canonical_chain_from(1).collect();
+        std::fs::write(&self.file, blocks.encode_versioned())
+            .map_err(|e| format!("{}: {}", self.file, e))?;
+        write_out(out, &format!("exported {} blocks\n", blocks.len()))
+    }
+}
+
+/// Print the balances after the best block, of every account or of a single one.
+struct BalancesTask {
+    account: Option<User>,
+}
+
+impl Task for BalancesTask {
+    fn run<C, FC, P>(self, client: NodeClient<C, FC, P>, out: &mut dyn Write) -> Result<(), String>
+    where
+        C: Consensus + Send + 'static,
+        C::Digest: Send,
+        FC: ForkChoice<C, SignedAccountedCurrency> + Default + Send + 'static,
+        P: TransactionPool<SignedAccountedCurrency> + Send + 'static,
+        Block<C, SignedAccountedCurrency>: std::hash::Hash + Clone,
+    {
+        let state = client.current_state();
+        let mut balances: Vec<_> = match self.account {
+            Some(account) => vec![(account, state.balances.get(&account).copied().unwrap_or(0))],
+            None => state.balances.into_iter().collect(),
+        };
+        balances.sort();
+        for (account, balance) in balances {
+            write_out(out, &format!("{} {}\n", user_name(account), balance))?;
+        }
+        Ok(())
+    }
+}
+
+// --- Parsing ---
+
+/// The positional arguments and `--name value` options of a command.
+struct Args {
+    positional: Vec<String>,
+    options: Vec<(String, String)>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut positional = Vec::new();
+        let mut options = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some(name) => {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| format!("missing a value for --{}", name))?;
+                    options.push((name.to_string(), value.clone()));
+                }
+                None => positional.push(arg.clone()),
+            }
+        }
+        Ok(Args {
+            positional,
+            options,
+        })
+    }
+
+    /// Reject more than `positional` positional arguments and any option not in `options`.
+    fn expect(&self, positional: usize, options: &[&str]) -> Result<(), String> {
+        if let Some(extra) = self.positional.get(positional) {
+            return Err(format!("unexpected argument {}", extra));
+        }
+        match self
+            .options
+            .iter()
+            .find(|(n, _)| !options.contains(&n.as_str()))
+        {
+            Some((name, _)) => Err(format!("unknown option --{}", name)),
+            None => Ok(()),
+        }
+    }
+
+    fn positional(&self, index: usize, what: &str) -> Result<&str, String> {
+        self.positional
+            .get(index)
+            .map(String::as_str)
+            .ok_or_else(|| format!("missing the {}", what))
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn number(&self, name: &str) -> Result<Option<u64>, String> {
+        self.option(name).map(parse_number).transpose()
+    }
+}
+
+/// Parse every line of a text file that is not blank or a `#` comment.
+fn parse_lines<T>(text: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<Vec<T>, String> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i, line.split('#').next().unwrap().trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(i, line)| parse(line).map_err(|e| format!("line {}: {}", i + 1, e)))
+        .collect()
+}
+
+/// Parse a transaction such as `transfer alice bob 3`.
+fn parse_transaction(line: &str) -> Result<AccountingTransaction, String> {
+    match line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["mint", minter, amount] => Ok(AccountingTransaction::Mint {
+            minter: parse_user(minter)?,
+            amount: parse_number(amount)?,
+            nonce: 0,
+        }),
+        ["burn", burner, amount] => Ok(AccountingTransaction::Burn {
+            burner: parse_user(burner)?,
+            amount: parse_number(amount)?,
+            nonce: 0,
+        }),
+        ["transfer", sender, receiver, amount] => Ok(AccountingTransaction::Transfer {
+            sender: parse_user(sender)?,
+            receiver: parse_user(receiver)?,
+            amount: parse_number(amount)?,
+            nonce: 0,
+        }),
+        _ => Err(format!("not a transaction: {}", line)),
+    }
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn write_out(out: &mut dyn Write, text: &str) -> Result<(), String> {
+    out.write_all(text.as_bytes()).map_err(|e| e.to_string())
+}
+
+// --- TESTS ---
+
+#[cfg(test)]
+use crate::c4_client::test_helpers::TempDir;
+
+/// A new directory under the system's temporary directory, with the given files in it. It is
+/// removed when the test is done with it.
+#[cfg(test)]
+fn temp_dir_for_test(files: &[(&str, &str)]) -> TempDir {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static CREATED: AtomicUsize = AtomicUsize::new(0);
+    let dir = TempDir::new(&format!("node-{}", CREATED.fetch_add(1, Ordering::Relaxed)));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, contents) in files {
+        std::fs::write(dir.join(name), contents).unwrap();
+    }
+    dir
+}
+
+/// Run the node in the given directory, where `{}` in an argument stands for the directory.
+#[cfg(test)]
+fn node(dir: &Path, args: &str) -> Result<String, String> {
+    let args: Vec<String> = args
+        .split_whitespace()
+        .map(|a| a.replace("{}", dir.to_str().unwrap()))
+        .collect();
+    let mut out = Vec::new();
+    run(&args, &mut out)?;
+    Ok(String::from_utf8(out).unwrap())
+}
+
+#[cfg(test)]
+const SPEC: &str = "name = test\n\n[balances]\nalice = 100\nbob = 50\n";
+
+#[test]
+fn nd_init_and_query_balances() {
+    let dir = temp_dir_for_test(&[("spec", SPEC)]);
+    let output = node(&dir, "init {}/chain --spec {}/spec").unwrap();
+    assert!(output.starts_with("initialised chain with genesis 0x"));
+
+    assert_eq!(
+        node(&dir, "balances {}/chain").unwrap(),
+        "alice 100\nbob 50\n"
+    );
+    assert_eq!(node(&dir, "balances {}/chain bob").unwrap(), "bob 50\n");
+    assert_eq!(
+        node(&dir, "balances {}/chain charlie").unwrap(),
+        "charlie 0\n"
+    );
+}
+
+#[test]
+fn nd_author_blocks_and_restart() {
+    let dir = temp_dir_for_test(&[
+        ("spec", SPEC),
+        ("txs", "mint charlie 7\ntransfer alice bob 30\n"),
+    ]);
+    node(&dir, "init {}/chain --spec {}/spec").unwrap();
+
+    let output = node(
+        &dir,
+        "run {}/chain --transactions {}/txs --block-time 0 --rounds 2",
+    )
+    .unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], "queued 2 of 2 transactions");
+    assert!(lines[1].starts_with("authored block #1 "));
+    assert!(lines[1].ends_with(" with 2 transactions"));
+    // Each transaction pays a fee of its weight, which is burned as there is no author.
+    assert_eq!(
+        node(&dir, "balances {}/chain").unwrap(),
+        "alice 68\nbob 80\ncharlie 6\n"
+    );
+
+    // After a restart the node carries on from the best block it left off at.
+    let output = node(
+        &dir,
+        "run {}/chain --transactions {}/txs --block-time 0 --rounds 1",
+    )
+    .unwrap();
+    assert!(output.contains("authored block #2 "));
+    assert_eq!(
+        node(&dir, "balances {}/chain").unwrap(),
+        "alice 36\nbob 110\ncharlie 12\n"
+    );
+}
+
+#[test]
+fn nd_author_is_paid_the_fees() {
+    let dir = temp_dir_for_test(&[("spec", SPEC), ("txs", "transfer alice bob 30\n")]);
+    node(&dir, "init {}/chain --spec {}/spec").unwrap();
+
+    let output = node(
+        &dir,
+        "run {}/chain --transactions {}/txs --author charlie --block-time 0 --rounds 1",
+    )
+    .unwrap();
+    assert!(output.contains("authored block #1 "));
+    // Alice pays a fee of 2 for the transfer, and it goes to Charlie for authoring the block.
+    assert_eq!(
+        node(&dir, "balances {}/chain").unwrap(),
+        "alice 68\nbob 80\ncharlie 2\n"
+    );
+}
+
+#[test]
+fn nd_fills_blocks_up_to_the_weight_limit() {
+    let spec = "block-weight-limit = 2\n[balances]\nalice = 1";
+    let dir = temp_dir_for_test(&[
+        ("spec", spec),
+        ("txs", "mint bob 1\nmint bob 2\nmint bob 3\n"),
+    ]);
+    node(&dir, "init {}/chain --spec {}/spec").unwrap();
+
+    let output = node(
+        &dir,
+        "run {}/chain --transactions {}/txs --block-time 0 --rounds 1",
+    )
+    .unwrap();
+    assert!(output.ends_with(" with 2 transactions\n"));
+
+    // The command line limit takes the place of the specification's.
+    let output = node(
+        &dir,
+        "run {}/chain --block-weight-limit 1 --transactions {}/txs --block-time 0 --rounds 1",
+    )
+    .unwrap();
+    assert!(output.contains("authored block #2 "));
+    assert!(output.ends_with(" with 1 transactions\n"));
+}
+
+#[test]
+fn nd_export_and_import_across_the_fork() {
+    let mint_lines: String = (1..=25).map(|i| format!("mint alice {}\n", i)).collect();
+    let spec = "consensus = forked\nfork-height = 2\nauthorities = alice, bob\n[balances]\nbob = 5";
+    let dir = temp_dir_for_test(&[("spec", spec), ("txs", &mint_lines)]);
+    node(&dir, "init {}/a --spec {}/spec").unwrap();
+    node(&dir, "init {}/b --spec {}/spec").unwrap();
+
+    // 25 transactions of weight 1 make three blocks, the last two after the fork.
+    let output = node(
+        &dir,
+        "run {}/a --transactions {}/txs --block-time 0 --rounds 3",
+    )
+    .unwrap();
+    assert!(output.contains("authored block #3 "));
+    assert_eq!(
+        node(&dir, "export {}/a {}/blocks").unwrap(),
+        "exported 3 blocks\n"
+    );
+
+    let output = node(&dir, "import {}/b {}/blocks").unwrap();
+    assert!(output.starts_with("imported 3 of 3 blocks, best is #3 "));
+    let output = node(&dir, "import {}/b {}/blocks").unwrap();
+    assert!(output.starts_with("imported 0 of 0 blocks, best is #3 "));
+    assert_eq!(
+        node(&dir, "balances {}/b").unwrap(),
+        node(&dir, "balances {}/a").unwrap()
+    );
+}
+
+#[test]
+fn nd_import_rejects_blocks_of_another_chain() {
+    let dir = temp_dir_for_test(&[
+        ("spec", SPEC),
+        ("other", "[balances]\nalice = 1\n"),
+        ("txs", "mint alice 1\n"),
+    ]);
+    node(&dir, "init {}/a --spec {}/spec").unwrap();
+    node(&dir, "init {}/b --spec {}/other").unwrap();
+    node(
+        &dir,
+        "run {}/a --transactions {}/txs --block-time 0 --rounds 1",
+    )
+    .unwrap();
+    node(&dir, "export {}/a {}/blocks").unwrap();
+
+    assert_eq!(
+        node(&dir, "import {}/b {}/blocks"),
+        Err("1 blocks were rejected".to_string())
+    );
+    std::fs::write(dir.join("garbage"), [7, 7, 7]).unwrap();
+    assert!(node(&dir, "import {}/b {}/garbage").is_err());
+}
+
+#[test]
+fn nd_rejects_bad_command_lines() {
+    let dir = temp_dir_for_test(&[
+        ("spec", SPEC),
+        ("bad", "[balances]\nalice = lots\n"),
+        ("bills", "[bills]\nalice = 5\n"),
+    ]);
+    assert_eq!(node(&dir, "").unwrap(), USAGE);
+    assert!(node(&dir, "frobnicate").is_err());
+    assert!(node(&dir, "balances {}/nothing").is_err());
+    assert!(node(&dir, "init {}/chain").is_err());
+    assert!(node(&dir, "init {}/chain --spec {}/bad")
+        .unwrap_err()
+        .ends_with("bad: line 2: not a number: lots"));
+    // The node runs the signed accounted currency, which has no bills.
+    assert!(node(&dir, "init {}/chain --spec {}/bills").is_err());
+    assert!(node(&dir, "init {}/chain --spec {}/spec --colour blue").is_err());
+
+    node(&dir, "init {}/chain --spec {}/spec").unwrap();
+    assert!(node(&dir, "init {}/chain --spec {}/spec").is_err());
+    assert!(node(&dir, "balances {}/chain dave").is_err());
+    assert!(node(&dir, "run {}/chain --rounds").is_err());
+    assert!(node(&dir, "run {}/chain --block-weight-limit 0 --rounds 1").is_err());
+}
+
+#[test]
+fn nd_refuses_a_chain_that_does_not_match_its_spec() {
+    let dir = temp_dir_for_test(&[("spec", SPEC)]);
+    node(&dir, "init {}/chain --spec {}/spec").unwrap();
+    let spec = std::fs::read_to_string(dir.join("chain").join(SPEC_FILE)).unwrap();
+    assert_eq!(ChainSpec::parse(&spec), ChainSpec::parse(SPEC));
+
+    std::fs::write(
+        dir.join("chain").join(SPEC_FILE),
+        spec.replace("alice = 100", "alice = 1000"),
+    )
+    .unwrap();
+    assert!(node(&dir, "balances {}/chain")
+        .unwrap_err()
+        .contains("does not start from the genesis of its specification"));
+}
+
+/// An address on localhost that nothing is listening on right now.
+#[cfg(test)]
+fn free_address_for_test() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+#[test]
+fn nd_gossips_blocks_to_a_peer() {
+    let dir = temp_dir_for_test(&[("spec", SPEC), ("txs", "mint charlie 7\n")]);
+    node(&dir, "init {}/a --spec {}/spec").unwrap();
+    node(&dir, "init {}/b --spec {}/spec").unwrap();
+    let address = free_address_for_test();
+
+    let first_dir = dir.to_path_buf();
+    let first_args = format!(
+        "run {{}}/a --listen {} --transactions {{}}/txs --block-time 100 --rounds 10",
+        address
+    );
+    let first = thread::spawn(move || node(&first_dir, &first_args));
+
+    // The second node only authors empty blocks, so anything it has came from the first.
+    let second_args = format!("run {{}}/b --peer {} --block-time 100 --rounds 5", address);
+    let mut attempts = 0;
+    let output = loop {
+        match node(&dir, &second_args) {
+            Ok(output) => break output,
+            // The first node is not listening yet.
+            Err(_) if attempts < 50 => {
+                attempts += 1;
+                thread::sleep(Duration::from_millis(20));
+            }
+            Err(e) => panic!("the first node never started listening: {}", e),
+        }
+    };
+    assert!(output.contains(&format!("connected to {}\n", address)));
+    let output = first.join().unwrap().unwrap();
+    assert!(output.contains(&format!("listening for peers on {}\n", address)));
+
+    assert_eq!(
+        node(&dir, "balances {}/b").unwrap(),
+        "alice 100\nbob 50\ncharlie 6\n"
+    );
+    // Now that the first node has stopped, there is nobody to connect to.
+    assert!(node(&dir, &format!("run {{}}/b --peer {} --rounds 0", address)).is_err());
+}
@@ -0,0 +1,502 @@
+//! A sparse Merkle trie for storing blockchain state.
+//!
+//! Hashing the whole state gives a fine state root, but it has two problems. Every block has to
+//! store a complete copy of the state, even when only a single account changed, and there is no way
+//! to convince someone of a single account's balance without handing them the entire state.
+//!
+//! Here the state is broken up into key-value entries which are stored in a binary trie. Each entry
+//! lives on the path given by the bits of its key's hash. The trie is compact: a leaf is stored as
+//! high up as possible, at the first depth where no other key shares its path, so most paths are
+//! only as long as needed to tell the keys apart.
+//!
+//! Nodes are stored in a `TrieDb` keyed by their own hash. Inserting into a trie never modifies
+//! existing nodes, it only creates new nodes along the changed path. Every other subtree is simply
+//! referenced by hash, so consecutive states share everything that did not change.
+//!
+//! A `StateProof` is the list of sibling hashes along a key's path together with the node found at
+//! its end. It proves either the value stored under the key, or that the key is absent.
+
+use crate::{hash, Encode, H256};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+
+/// The root of the trie that holds no entries.
+pub const EMPTY_ROOT: H256 = H256([0; 32]);
+
+const LEAF_PREFIX: u8 = 0;
+const BRANCH_PREFIX: u8 = 1;
+
+/// A state that can be broken up into key-value entries and put back together again.
+///
+/// This is what allows a state to live in a trie. Keys must be unique, and rebuilding a state from
+/// its entries must give back an equal state.
+pub trait TrieState: Sized {
+    type Key: Clone + Eq + Encode + Debug;
+    type Value: Clone + Eq + Encode + Debug;
+
+    /// All the entries of this state, in any order.
+    fn to_entries(&self) -> Vec<(Self::Key, Self::Value)>;
+
+    /// Build a state from its entries, in any order.
+    fn from_entries(entries: Vec<(Self::Key, Self::Value)>) -> Self;
+}
+
+/// The root of the trie holding the given state's entries. This is the state root that
+/// headers commit to.
+pub fn state_root<S: TrieState>(state: &S) -> H256 {
+    TrieDb::new().insert_all(EMPTY_ROOT, state.to_entries())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Node<K, V> {
+    Leaf { key: K, value: V },
+    Branch { left: H256, right: H256 },
+}
+
+/// A node is encoded as its prefix followed by its fields, so its hash is exactly what
+/// `leaf_hash` and `branch_hash` compute from the fields alone.
+impl<K: Encode, V: Encode> Encode for Node<K, V> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        match self {
+            Node::Leaf { key, value } => (LEAF_PREFIX, key, value).encode_to(out),
+            Node::Branch { left, right } => (BRANCH_PREFIX, left, right).encode_to(out),
+        }
+    }
+}
+
+fn leaf_hash<K: Encode, V: Encode>(key: &K, value: &V) -> H256 {
+    hash(&(LEAF_PREFIX, key, value))
+}
+
+fn branch_hash(left: &H256, right: &H256) -> H256 {
+    hash(&(BRANCH_PREFIX, left, right))
+}
+
+/// Whether the path goes right at the given depth.
+fn goes_right(path: &H256, depth: usize) -> bool {
+    (path.0[depth / 8] >> (7 - depth % 8)) & 1 == 1
+}
+
+/// A content addressed store of trie nodes. Many tries, typically the states after each block,
+/// live in the same database and are identified by their root hash.
+#[derive(Clone, Debug)]
+pub struct TrieDb<K, V> {
+    nodes: HashMap<H256, Node<K, V>>,
+}
+
+impl<K, V> Default for TrieDb<K, V> {
+    fn default() -> Self {
+        TrieDb {
+            nodes: HashMap::new(),
+        }
+    }
+}
+
+impl<K, V> TrieDb<K, V>
+where
+    K: Clone + Eq + Encode,
+    V: Clone + Eq + Encode,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of distinct nodes stored across all tries in this database.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether a trie with the given root is stored in this database.
+    pub fn contains_root(&self, root: &H256) -> bool {
+        *root == EMPTY_ROOT || self.nodes.contains_key(root)
+    }
+
+    fn store(&mut self, node: Node<K, V>) -> H256 {
+        let node_hash = hash(&node);
+        self.nodes.insert(node_hash, node);
+        node_hash
+    }
+
+    fn is_leaf(&self, node: &H256) -> bool {
+        matches!(self.nodes.get(node), Some(Node::Leaf { .. }))
+    }
+
+    /// The value stored under the given key in the trie with the given root.
+    pub fn get(&self, root: &H256, key: &K) -> Option<&V> {
+        let path = hash(key);
+        let mut current = *root;
+        let mut depth = 0;
+        loop {
+            match self.nodes.get(&current)? {
+                Node::Leaf { key: k, value } => return if k == key { Some(value) } else { None },
+                Node::Branch { left, right } => {
+                    current = if goes_right(&path, depth) {
+                        *right
+                    } else {
+                        *left
+                    };
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// All entries of the trie with the given root, or None if the root is unknown.
+    pub fn entries(&self, root: &H256) -> Option<Vec<(K, V)>> {
+        if !self.contains_root(root) {
+            return None;
+        }
+        let mut entries = Vec::new();
+        let mut to_visit = vec![*root];
+        while let Some(node) = to_visit.pop() {
+            if node == EMPTY_ROOT {
+                continue;
+            }
+            match self.nodes.get(&node)? {
+                Node::Leaf { key, value } => entries.push((key.clone(), value.clone())),
+                Node::Branch { left, right } => {
+                    to_visit.push(*left);
+                    to_visit.push(*right);
+                }
+            }
+        }
+        Some(entries)
+    }
+
+    /// Insert or overwrite an entry, returning the root of the new trie. The trie with the
+    /// old root is left intact.
+    pub fn insert(&mut self, root: H256, key: K, value: V) -> H256 {
+        let path = hash(&key);
+        self.insert_at(root, &path, 0, key, value)
+    }
+
+    /// Insert all the given entries, returning the root of the new trie.
+    pub fn insert_all(&mut self, root: H256, entries: Vec<(K, V)>) -> H256 {
+        entries
+            .into_iter()
+            .fold(root, |root, (key, value)| self.insert(root, key, value))
+    }
+
+    fn insert_at(&mut self, node: H256, path: &H256, depth: usize, key: K, value: V) -> H256 {
+        if node == EMPTY_ROOT {
+            return self.store(Node::Leaf { key, value });
+        }
+        match self.nodes[&node].clone() {
+            Node::Leaf { key: existing, .. } if existing == key => {
+                self.store(Node::Leaf { key, value })
+            }
+            Node::Leaf { key: existing, .. } => {
+                let existing_path = hash(&existing);
+                let new_leaf = self.store(Node::Leaf { key, value });
+                self.split(node, &existing_path, new_leaf, path, depth)
+            }
+            Node::Branch { left, right } => {
+                if goes_right(path, depth) {
+                    let right = self.insert_at(right, path, depth + 1, key, value);
+                    self.store(Node::Branch { left, right })
+                } else {
+                    let left = self.insert_at(left, path, depth + 1, key, value);
+                    self.store(Node::Branch { left, right })
+                }
+            }
+        }
+    }
+
+    /// Two leaves share a path up to the given depth. Add branches until their paths part ways.
+    fn split(
+        &mut self,
+        existing: H256,
+        existing_path: &H256,
+        new_leaf: H256,
+        path: &H256,
+        depth: usize,
+    ) -> H256 {
+        assert!(depth < 256, "two distinct keys with the same path hash");
+        let existing_right = goes_right(existing_path, depth);
+        let new_right = goes_right(path, depth);
+        let (left, right) = if existing_right == new_right {
+            let child = self.split(existing, existing_path, new_leaf, path, depth + 1);
+            if new_right {
+                (EMPTY_ROOT, child)
+            } else {
+                (child, EMPTY_ROOT)
+            }
+        } else if new_right {
+            (existing, new_leaf)
+        } else {
+            (new_leaf, existing)
+        };
+        self.store(Node::Branch { left, right })
+    }
+
+    /// Remove an entry if it exists, returning the root of the new trie.
+    pub fn remove(&mut self, root: H256, key: &K) -> H256 {
+        let path = hash(key);
+        self.remove_at(root, &path, 0, key)
+    }
+
+    fn remove_at(&mut self, node: H256, path: &H256, depth: usize, key: &K) -> H256 {
+        if node == EMPTY_ROOT {
+            return EMPTY_ROOT;
+        }
+        match self.nodes[&node].clone() {
+            Node::Leaf { key: existing, .. } => {
+                if existing == *key {
+                    EMPTY_ROOT
+                } else {
+                    node
+                }
+            }
+            Node::Branch { left, right } => {
+                let (left, right) = if goes_right(path, depth) {
+                    (left, self.remove_at(right, path, depth + 1, key))
+                } else {
+                    (self.remove_at(left, path, depth + 1, key), right)
+                };
+                // A leaf left alone under a branch moves up to take the branch's place. This
+                // keeps the shape of the trie, and therefore its root, independent of history.
+                if left == EMPTY_ROOT && (right == EMPTY_ROOT || self.is_leaf(&right)) {
+                    right
+                } else if right == EMPTY_ROOT && self.is_leaf(&left) {
+                    left
+                } else {
+                    self.store(Node::Branch { left, right })
+                }
+            }
+        }
+    }
+
+    /// Drop every node that is not part of one of the given tries. This is how states that are
+    /// no longer needed are removed, since nodes may be shared by many tries and cannot simply
+    /// be deleted along with one of them.
+    pub fn retain_roots<'a>(&mut self, roots: impl IntoIterator<Item = &'a H256>) {
+        let mut reachable = HashSet::new();
+        let mut to_visit: Vec<H256> = roots.into_iter().copied().collect();
+        while let Some(node) = to_visit.pop() {
+            if !reachable.insert(node) {
+                continue;
+            }
+            if let Some(Node::Branch { left, right }) = self.nodes.get(&node) {
+                to_visit.push(*left);
+                to_visit.push(*right);
+            }
+        }
+        self.nodes.retain(|node, _| reachable.contains(node));
+    }
+
+    /// Prove the value, or absence, of the given key in the trie with the given root.
+    /// Returns None if the root is unknown.
+    pub fn prove(&self, root: &H256, key: &K) -> Option<StateProof<K, V>> {
+        if !self.contains_root(root) {
+            return None;
+        }
+        let path = hash(key);
+        let mut siblings = Vec::new();
+        let mut current = *root;
+        loop {
+            if current == EMPTY_ROOT {
+                return Some(StateProof {
+                    siblings,
+                    leaf: None,
+                });
+            }
+            match self.nodes.get(&current)? {
+                Node::Leaf { key, value } => {
+                    return Some(StateProof {
+                        siblings,
+                        leaf: Some((key.clone(), value.clone())),
+                    });
+                }
+                Node::Branch { left, right } => {
+                    if goes_right(&path, siblings.len()) {
+                        siblings.push(*left);
+                        current = *right;
+                    } else {
+                        siblings.push(*right);
+                        current = *left;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Proof of the value stored under a key, or of its absence, in a trie with a known root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateProof<K, V> {
+    /// The sibling hashes along the key's path, starting from the root.
+    pub siblings: Vec<H256>,
+    /// The leaf at the end of the path. Either the proven entry, a different entry proving
+    /// that the key is absent, or nothing at all if the path ends in an empty subtree.
+    pub leaf: Option<(K, V)>,
+}
+
+impl<K: Eq + Encode, V: Eq + Encode> StateProof<K, V> {
+    /// Check that in the trie with the given root the key holds the expected value, where
+    /// None means that the key is absent.
+    pub fn verify(&self, root: &H256, key: &K, expected: Option<&V>) -> bool {
+        let path = hash(key);
+        let depth = self.siblings.len();
+        if depth > 256 {
+            return false;
+        }
+
+        let mut current = match &self.leaf {
+            None => {
+                if expected.is_some() {
+                    return false;
+                }
+                EMPTY_ROOT
+            }
+            Some((leaf_key, value)) => {
+                if leaf_key == key {
+                    if expected != Some(value) {
+                        return false;
+                    }
+                } else {
+                    // A different leaf proves absence only if it sits on our key's path.
+                    let leaf_path = hash(leaf_key);
+                    let on_path =
+                        (0..depth).all(|d| goes_right(&leaf_path, d) == goes_right(&path, d));
+                    if expected.is_some() || !on_path {
+                        return false;
+                    }
+                }
+                leaf_hash(leaf_key, value)
+            }
+        };
+
+        for (d, sibling) in self.siblings.iter().enumerate().rev() {
+            current = if goes_right(&path, d) {
+                branch_hash(sibling, &current)
+            } else {
+                branch_hash(&current, sibling)
+            };
+        }
+        current == *root
+    }
+}
+
+#[test]
+fn empty_trie_has_the_empty_root() {
+    let db = TrieDb::<u64, u64>::new();
+    assert!(db.contains_root(&EMPTY_ROOT));
+    assert_eq!(db.get(&EMPTY_ROOT, &1), None);
+    assert_eq!(db.entries(&EMPTY_ROOT), Some(vec![]));
+}
+
+#[test]
+fn insert_and_get() {
+    let mut db = TrieDb::new();
+    let mut root = EMPTY_ROOT;
+    for i in 0..100u64 {
+        root = db.insert(root, i, i * 10);
+    }
+    for i in 0..100u64 {
+        assert_eq!(db.get(&root, &i), Some(&(i * 10)));
+    }
+    assert_eq!(db.get(&root, &100), None);
+    assert_eq!(db.entries(&root).unwrap().len(), 100);
+}
+
+#[test]
+fn old_roots_stay_readable() {
+    let mut db = TrieDb::new();
+    let root_1 = db.insert(EMPTY_ROOT, 1u64, 1u64);
+    let root_2 = db.insert(root_1, 1, 2);
+    assert_eq!(db.get(&root_1, &1), Some(&1));
+    assert_eq!(db.get(&root_2, &1), Some(&2));
+}
+
+#[test]
+fn root_is_independent_of_insertion_order() {
+    let mut db = TrieDb::new();
+    let forward = db.insert_all(EMPTY_ROOT, (0..50u64).map(|i| (i, i)).collect());
+    let backward = db.insert_all(EMPTY_ROOT, (0..50u64).rev().map(|i| (i, i)).collect());
+    assert_eq!(forward, backward);
+}
+
+#[test]
+fn removing_restores_the_previous_root() {
+    let mut db = TrieDb::new();
+    let before = db.insert_all(EMPTY_ROOT, (0..20u64).map(|i| (i, i)).collect());
+    let with_extra = db.insert(before, 1000, 1000);
+    assert_ne!(before, with_extra);
+    assert_eq!(db.remove(with_extra, &1000), before);
+    assert_eq!(db.remove(before, &1000), before);
+
+    let mut root = before;
+    for i in 0..20u64 {
+        root = db.remove(root, &i);
+    }
+    assert_eq!(root, EMPTY_ROOT);
+}
+
+#[test]
+fn unchanged_subtrees_are_shared() {
+    let mut db = TrieDb::new();
+    let root = db.insert_all(EMPTY_ROOT, (0..1000u64).map(|i| (i, i)).collect());
+    let nodes_before = db.node_count();
+
+    // Changing a single entry only adds the nodes on its path, which is about log2(1000) long.
+    db.insert(root, 500, 0);
+    assert!(db.node_count() - nodes_before < 30);
+}
+
+#[test]
+fn proves_present_values() {
+    let mut db = TrieDb::new();
+    let root = db.insert_all(EMPTY_ROOT, (0..30u64).map(|i| (i, i * 2)).collect());
+    for i in 0..30u64 {
+        let proof = db.prove(&root, &i).unwrap();
+        assert!(proof.verify(&root, &i, Some(&(i * 2))));
+        assert!(!proof.verify(&root, &i, Some(&(i * 2 + 1))));
+        assert!(!proof.verify(&root, &i, None));
+    }
+}
+
+#[test]
+fn proves_absent_values() {
+    let mut db = TrieDb::new();
+    let root = db.insert_all(EMPTY_ROOT, (0..30u64).map(|i| (i, i)).collect());
+    for i in 100..130u64 {
+        let proof = db.prove(&root, &i).unwrap();
+        assert!(proof.verify(&root, &i, None));
+        assert!(!proof.verify(&root, &i, Some(&i)));
+    }
+}
+
+#[test]
+fn proof_does_not_verify_against_another_root() {
+    let mut db = TrieDb::new();
+    let root_1 = db.insert_all(EMPTY_ROOT, (0..10u64).map(|i| (i, i)).collect());
+    let root_2 = db.insert(root_1, 3, 4);
+    let proof = db.prove(&root_1, &3).unwrap();
+    assert!(proof.verify(&root_1, &3, Some(&3)));
+    assert!(!proof.verify(&root_2, &3, Some(&3)));
+    assert!(!proof.verify(&root_2, &3, Some(&4)));
+}
+
+#[test]
+fn unknown_root_cannot_be_proven() {
+    let db = TrieDb::<u64, u64>::new();
+    assert_eq!(db.prove(&H256::from(7), &1), None);
+    assert_eq!(db.entries(&H256::from(7)), None);
+}
+
+#[test]
+fn retaining_roots_drops_other_tries_only() {
+    let mut db = TrieDb::new();
+    let kept = db.insert_all(EMPTY_ROOT, (0..50u64).map(|i| (i, i)).collect());
+    let dropped = db.insert(kept, 7, 0);
+    db.retain_roots([&kept]);
+
+    assert_eq!(db.entries(&kept).unwrap().len(), 50);
+    assert!(!db.contains_root(&dropped));
+
+    // Only the nodes of the kept trie are left, as if it had been built on its own.
+    let mut fresh = TrieDb::new();
+    fresh.insert_all(EMPTY_ROOT, (0..50u64).map(|i| (i, i)).collect());
+    fresh.retain_roots([&kept]);
+    assert_eq!(db.node_count(), fresh.node_count());
+}